@@ -0,0 +1,387 @@
+//! Per-event cost of the hot paths: libinput line parsing and scanning, routing,
+//! frame batching
+//!
+//! `cargo bench` measures; `cargo bench -- --test` runs each once, for CI. No
+//! timings are asserted, only that steady-state forwarding and libinput
+//! scanning never allocate.
+//! Mapped presses are logged to stderr as in the daemon; `2>/dev/null` hides them.
+
+use anyhow::Result;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use evdev::{EventType, InputEvent, Key, MiscType};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use touchpad_remap::activation::Activation;
+use touchpad_remap::config::Config;
+use touchpad_remap::libinput;
+use touchpad_remap::motion::{Coalescer, LastMotion};
+use touchpad_remap::remap::Remapper;
+use touchpad_remap::sink::{EventSink, Frame};
+use touchpad_remap::trigger::Trigger;
+
+/// Counts allocations, so the forwarding check can see them
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+// SAFETY: every call is passed straight to the system allocator
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// Twenty mapped keys of every common target kind, some with modifier variants
+const MAPPINGS: &str = r#"
+[mappings]
+KEY_F = "BTN_LEFT"
+KEY_D = [
+  { target = "BTN_RIGHT" },
+  { target = "BTN_MIDDLE", modifiers = { shift = "held" } },
+]
+KEY_S = "BTN_MIDDLE"
+KEY_G = { target = "BTN_LEFT", modifiers = { ctrl = "released" } }
+KEY_J = { wheel = -1 }
+KEY_K = { wheel = 1 }
+KEY_H = "BTN_SIDE"
+KEY_L = "BTN_EXTRA"
+KEY_Q = "BTN_LEFT"
+KEY_W = "BTN_RIGHT"
+KEY_E = "BTN_MIDDLE"
+KEY_R = { wheel = -2 }
+KEY_T = { wheel = 2 }
+KEY_Y = "BTN_LEFT"
+KEY_U = "BTN_RIGHT"
+KEY_I = "BTN_MIDDLE"
+KEY_O = "BTN_SIDE"
+KEY_P = "BTN_EXTRA"
+KEY_V = "KEY_PLAYPAUSE"
+KEY_B = "KEY_VOLUMEUP"
+"#;
+
+/// Sink that batches frames as the uinput outputs do, and only counts what they'd write
+#[derive(Default)]
+struct Mock {
+    frame: Frame,
+    frames: usize,
+    pointer: usize,
+}
+
+impl Mock {
+    fn flush(&mut self) -> Result<()> {
+        let frames = &mut self.frames;
+        self.frame.flush(|events| {
+            black_box(events);
+            *frames += 1;
+            Ok(())
+        })
+    }
+}
+
+impl EventSink for Mock {
+    fn forward(&mut self, event: InputEvent) -> Result<()> {
+        if self.frame.push(event) {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn key(&mut self, key: Key, value: i32) -> Result<()> {
+        self.frame
+            .push(InputEvent::new(EventType::KEY, key.code(), value));
+        self.flush()
+    }
+
+    fn button(&mut self, button: Key, value: i32) -> Result<()> {
+        black_box((button, value));
+        self.pointer += 1;
+        Ok(())
+    }
+
+    fn wheel(&mut self, hi_res: i32, legacy: i32) -> Result<()> {
+        black_box((hi_res, legacy));
+        self.pointer += 1;
+        Ok(())
+    }
+
+    fn devices(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// One key frame as a keyboard sends it: MSC_SCAN, the key, SYN_REPORT
+fn frame(key: Key, value: i32) -> [InputEvent; 3] {
+    [
+        InputEvent::new(EventType::MISC, MiscType::MSC_SCAN.0, i32::from(key.code())),
+        InputEvent::new(EventType::KEY, key.code(), value),
+        InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
+    ]
+}
+
+/// Typing with the layer off, then pointing with it on: each entry is a frame and the mode
+fn mixed_stream() -> Vec<(InputEvent, bool)> {
+    let typed = [
+        Key::KEY_H,
+        Key::KEY_E,
+        Key::KEY_L,
+        Key::KEY_L,
+        Key::KEY_O,
+        Key::KEY_SPACE,
+        Key::KEY_W,
+        Key::KEY_O,
+        Key::KEY_R,
+        Key::KEY_L,
+        Key::KEY_D,
+        Key::KEY_ENTER,
+    ];
+    let pointed = [Key::KEY_F, Key::KEY_J, Key::KEY_D, Key::KEY_K, Key::KEY_V];
+    let mut stream = Vec::new();
+    for (keys, active) in [(&typed[..], false), (&pointed[..], true)] {
+        for &key in keys {
+            for value in [1, 0] {
+                stream.extend(frame(key, value).map(|event| (event, active)));
+            }
+        }
+    }
+    stream
+}
+
+fn remapper(config: &Config) -> Remapper {
+    Remapper::new(config, Arc::new(LastMotion::new())).expect("the mappings are valid")
+}
+
+fn route(
+    remapper: &mut Remapper,
+    sink: &mut Mock,
+    stream: &[(InputEvent, bool)],
+    start: Instant,
+) -> Instant {
+    let mut now = start;
+    for &(event, active) in stream {
+        now += Duration::from_millis(7);
+        remapper
+            .handle(event, active, now, sink)
+            .expect("the mock sink never fails");
+        remapper
+            .tick(now, active, sink)
+            .expect("the mock sink never fails");
+    }
+    now
+}
+
+fn parsing(c: &mut Criterion) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/libinput");
+    let mut corpus = Vec::new();
+    for entry in fs::read_dir(&dir).expect("examples/libinput is readable") {
+        let path = entry.expect("examples/libinput is readable").path();
+        if path.extension().is_some_and(|ext| ext == "log") {
+            let bytes = fs::read(&path).expect("the fixture is readable");
+            corpus.extend(String::from_utf8_lossy(&bytes).lines().map(str::to_owned));
+        }
+    }
+    assert!(!corpus.is_empty(), "no libinput fixtures found");
+
+    let mut group = c.benchmark_group("libinput");
+    group.throughput(Throughput::Elements(corpus.len() as u64));
+    group.bench_function("parse", |b| {
+        b.iter(|| {
+            for line in &corpus {
+                if let Some(parsed) = libinput::parse(line) {
+                    black_box(libinput::motion_delta(parsed.payload));
+                    black_box(parsed);
+                }
+            }
+        })
+    });
+    group.finish();
+}
+
+/// Ten seconds of a high-resolution touchpad at 1kHz: mostly pointer motion,
+/// with the touch lines a `debug-events` run also prints
+fn high_rate_log() -> Vec<u8> {
+    let mut log = String::from(
+        "-event9   DEVICE_ADDED                 PIXA3854:00 093A:0274 Touchpad    seat0 default group9  cap:pg\n",
+    );
+    for i in 0..10_000u32 {
+        let time = 101.0 + f64::from(i) / 1000.0;
+        let (dx, dy) = (f64::from(i % 7) * 0.35, -f64::from(i % 5) * 0.2);
+        log.push_str(&format!(
+            " event9   POINTER_MOTION               +{:.3}s\t{:6.2}/{:6.2} ({:+6.2}/{:+6.2})\n",
+            time,
+            dx,
+            dy,
+            dx * 0.75,
+            dy * 0.75
+        ));
+        if i % 4 == 0 {
+            log.push_str(&format!(
+                " event9   TOUCH_MOTION                 +{:.3}s\t0 (0) 43.12/61.08 (43.12/61.08mm)\n",
+                time
+            ));
+        }
+    }
+    log.into_bytes()
+}
+
+/// Lines the monitor acts on, as it picks them
+fn followed(kind: libinput::Kind) -> bool {
+    use libinput::Kind::*;
+    matches!(
+        kind,
+        DeviceAdded
+            | DeviceRemoved
+            | GestureHoldBegin
+            | GestureHoldEnd
+            | GestureSwipeBegin
+            | GestureSwipeUpdate
+            | GestureSwipeEnd
+            | PointerMotion
+    )
+}
+
+/// Motion lines scanned and their summed |dx| + |dy|
+fn scan(log: &[u8]) -> (usize, f64) {
+    let mut scanner = libinput::Scanner::new(log);
+    let (mut lines, mut distance) = (0, 0.0);
+    while scanner.advance(followed).expect("reading from memory") {
+        if let Some(line) = scanner.line() {
+            if let Some((dx, dy)) = libinput::motion_delta(line.payload) {
+                lines += 1;
+                distance += dx.abs() + dy.abs();
+            }
+        }
+    }
+    (lines, distance)
+}
+
+fn scanning(c: &mut Criterion) {
+    let log = high_rate_log();
+    let lines = log.iter().filter(|&&b| b == b'\n').count();
+
+    // The buffer has its capacity after the first line; no more allocations after
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let (motion, _) = scan(&log);
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    assert_eq!(motion, 10_000, "every motion line is scanned");
+    assert!(
+        allocations <= 1,
+        "scanning {} lines allocated {} times",
+        lines,
+        allocations
+    );
+
+    let mut group = c.benchmark_group("libinput");
+    group.throughput(Throughput::Elements(lines as u64));
+    group.bench_function("scan_high_rate", |b| b.iter(|| black_box(scan(&log))));
+    // The whole motion path with the mode on, as the monitor runs it, and as
+    // it ran before motion was coalesced
+    let start = Instant::now();
+    let config = Config::parse("", Path::new("bench.toml")).expect("the defaults are valid");
+    let mut activation = Activation::new(
+        config.activate_when.clone(),
+        config.debounce,
+        &config.adaptive_debounce,
+        Trigger::ToolFinger,
+        config.mouse_idle(),
+        &config.hysteresis,
+    );
+    activation.finger_down(start);
+    group.bench_function("follow_high_rate", |b| {
+        b.iter(|| follow(&log, start, &activation, true))
+    });
+    group.bench_function("follow_every_line", |b| {
+        b.iter(|| follow(&log, start, &activation, false))
+    });
+    group.finish();
+}
+
+/// Scan `log` and pass its motion to `activation` (a copy), on the log's own clock
+fn follow(log: &[u8], start: Instant, activation: &Activation, coalesced: bool) {
+    let activation = Mutex::new(activation.clone());
+    let last_motion = LastMotion::new();
+    let mut coalescer = Coalescer::default();
+    let mut scanner = libinput::Scanner::new(log);
+    while scanner.advance(followed).expect("reading from memory") {
+        let Some(line) = scanner.line() else { continue };
+        let (Some((dx, dy)), Some(time)) = (libinput::motion_delta(line.payload), line.time) else {
+            continue;
+        };
+        let now = start + Duration::from_secs_f64(time);
+        let distance = dx.abs() + dy.abs();
+        if coalesced {
+            black_box(coalescer.motion(now, distance, &activation, &last_motion));
+        } else {
+            last_motion.record(now);
+            black_box(activation.lock().unwrap().motion(now, distance));
+        }
+    }
+}
+
+fn routing(c: &mut Criterion) {
+    let config = Config::parse(MAPPINGS, Path::new("bench.toml")).expect("the mappings are valid");
+    let stream = mixed_stream();
+
+    let mut group = c.benchmark_group("routing");
+    group.throughput(Throughput::Elements(stream.len() as u64));
+    group.bench_function(BenchmarkId::new("mixed", stream.len()), |b| {
+        let mut remapper = remapper(&config);
+        let mut sink = Mock::default();
+        let mut now = Instant::now();
+        b.iter(|| now = route(&mut remapper, &mut sink, &stream, now))
+    });
+    group.finish();
+}
+
+fn batching(c: &mut Criterion) {
+    let config = Config::parse(MAPPINGS, Path::new("bench.toml")).expect("the mappings are valid");
+    // Unmapped typing only: the forward-one-frame case
+    let typed: Vec<(InputEvent, bool)> = [Key::KEY_A, Key::KEY_N, Key::KEY_M, Key::KEY_SPACE]
+        .into_iter()
+        .flat_map(|key| [1, 0].map(|value| frame(key, value)))
+        .flatten()
+        .map(|event| (event, false))
+        .collect();
+
+    let mut remapper = remapper(&config);
+    let mut sink = Mock::default();
+    // Warm up, so maps and buffers have their steady-state capacity
+    let mut now = route(&mut remapper, &mut sink, &typed, Instant::now());
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..100 {
+        now = route(&mut remapper, &mut sink, &typed, now);
+    }
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    assert_eq!(
+        allocations, 0,
+        "forwarding allocated {} times in steady state",
+        allocations
+    );
+
+    let mut group = c.benchmark_group("frames");
+    group.throughput(Throughput::Elements(typed.len() as u64 / 3));
+    group.bench_function("forward", |b| {
+        b.iter(|| now = route(&mut remapper, &mut sink, &typed, now))
+    });
+    group.finish();
+    black_box((sink.frames, sink.pointer));
+}
+
+criterion_group!(benches, parsing, scanning, routing, batching);
+criterion_main!(benches);
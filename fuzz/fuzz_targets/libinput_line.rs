@@ -0,0 +1,48 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../src/libinput.rs"]
+mod libinput;
+
+use libinput::Kind;
+
+fuzz_target!(|data: &[u8]| {
+    // The monitor reads lossy lines from the child, so do the same here
+    let text = String::from_utf8_lossy(data);
+    for line in text.lines() {
+        // Every non-blank line is described, parsed or not
+        assert_eq!(libinput::describe(line).is_some(), !line.trim().is_empty());
+        let Some(parsed) = libinput::parse(line) else {
+            continue;
+        };
+
+        // Columns must come from the input, never be invented
+        assert!(line.contains(parsed.device));
+        assert!(line.contains(parsed.kind_name));
+        assert!(line.contains(parsed.payload));
+        assert!(parsed.device.starts_with("event"));
+        assert!(parsed.time.is_none_or(f64::is_finite));
+
+        // The typed kind round-trips through its canonical name
+        match parsed.kind.name() {
+            Some(name) => assert_eq!(Kind::from_name(name), parsed.kind),
+            None => assert_eq!(Kind::from_name(parsed.kind_name), Kind::Other),
+        }
+        // Motion is only ever recognised from the type column itself
+        if parsed.kind == Kind::PointerMotion {
+            assert_eq!(parsed.kind_name, "POINTER_MOTION");
+        }
+        // Deltas feed the motion threshold, so they are finite or absent
+        if let Some((dx, dy)) = libinput::motion_delta(parsed.payload) {
+            assert!(dx.is_finite() && dy.is_finite());
+        }
+        // Swipes latch sticky mode from these, so the same goes for them
+        if let Some((dx, dy)) = libinput::gesture_delta(parsed.payload) {
+            assert!(dx.is_finite() && dy.is_finite());
+        }
+        libinput::gesture_fingers(parsed.payload);
+        libinput::gesture_cancelled(parsed.payload);
+        libinput::has_hold_gestures(Some(parsed.payload));
+    }
+});
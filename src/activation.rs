@@ -0,0 +1,630 @@
+use crate::condition::{Condition, Signal};
+use crate::config::{AdaptiveDebounceConfig, HysteresisConfig};
+use crate::trigger::Trigger;
+use crate::velocity::{self, MotionWindow};
+use std::time::{Duration, Instant};
+
+/// How a change of mode gets past the hysteresis windows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Gate {
+    /// Held back inside either window
+    Hysteresis,
+    /// Motion large enough to reactivate inside the cooldown
+    LargeMotion,
+    /// An explicit change (typing, sticky, the touchpad going away): never held back
+    Bypass,
+}
+
+/// A change of mode, with the reason reported to hooks and JSON output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transition {
+    pub active: bool,
+    pub reason: &'static str,
+}
+
+/// Touchpad mode state machine; it never reads the clock, callers pass `now`
+///
+/// Inputs update the source signals, and the mode is `condition` over them.
+/// Motion latches until a finger lift plus the debounce (or, with
+/// `timeout_only`, until the debounce passes without motion), which `poll` carries
+/// out. A finger coming back down cancels a pending lift, and motion pushes it
+/// out, so a lift of less than the debounce never deactivates. Losing the
+/// touchpad clears everything at once. Detected typing forces
+/// the mode off: the resting finger stops counting until it lifts, and motion
+/// is ignored for the cooldown. The touch-motion latch is the motion latch
+/// armed only by motion during contact, so inertia and jitter reported after a
+/// lift never arm it; without contact signals (`timeout_only`) any motion does.
+/// A hold gesture latches the same way motion does.
+/// A mouse has no contacts, so its motion latch only ever ends by going idle.
+/// With an adaptive debounce, the wait is picked from the recent motion speed
+/// each time a lift (or timeout) is scheduled. Sticky mode holds the mode on
+/// whatever the condition says, typing included, until it is unlatched.
+/// Hysteresis holds changes back: a reactivation within the cooldown of the
+/// last deactivation waits it out unless the motion is large, and a
+/// deactivation within the minimum hold of the last activation waits that out.
+/// A held-back change is re-evaluated when its window ends, so it happens then
+/// only if the inputs still call for it.
+#[derive(Debug, Clone)]
+pub struct Activation {
+    condition: Condition,
+    debounce: Duration,
+    /// Replaces `debounce` when enabled, with the motion it is judged on
+    adaptive: Option<(AdaptiveDebounceConfig, MotionWindow)>,
+    /// Lifts don't end the motion latch; only the time since the last motion does
+    timeout_only: bool,
+    motion: bool,
+    /// Motion seen while the finger was down; cleared along with `motion`
+    touch_motion: bool,
+    finger_down: bool,
+    /// Set by typing: the current touch no longer counts as finger_down
+    finger_masked: bool,
+    /// Motion before this is ignored (typing cooldown)
+    suppress_until: Option<Instant>,
+    active: bool,
+    /// When a pending finger-up clears the motion latch
+    deactivate_at: Option<Instant>,
+    /// Inside a libinput hold gesture
+    hold: bool,
+    mouse_motion: bool,
+    mouse_idle: Duration,
+    /// When the mouse motion latch clears unless the mouse moves again
+    mouse_until: Option<Instant>,
+    /// Latched on by a swipe (`[sticky]`)
+    sticky: bool,
+    reactivate_after: Duration,
+    reactivate_motion: f64,
+    min_active: Duration,
+    /// When the mode last changed
+    changed_at: Option<Instant>,
+    /// A change held back by hysteresis, re-evaluated then with its reason
+    settle: Option<(Instant, &'static str)>,
+}
+
+impl Activation {
+    pub fn new(
+        condition: Condition,
+        debounce: Duration,
+        adaptive: &AdaptiveDebounceConfig,
+        trigger: Trigger,
+        mouse_idle: Duration,
+        hysteresis: &HysteresisConfig,
+    ) -> Self {
+        Self {
+            condition,
+            debounce,
+            adaptive: adaptive.enabled.then(|| {
+                let window = MotionWindow::new(Duration::from_millis(adaptive.window_ms));
+                (adaptive.clone(), window)
+            }),
+            timeout_only: trigger == Trigger::TimeoutOnly,
+            motion: false,
+            touch_motion: false,
+            finger_down: false,
+            finger_masked: false,
+            suppress_until: None,
+            active: false,
+            deactivate_at: None,
+            hold: false,
+            mouse_motion: false,
+            mouse_idle,
+            mouse_until: None,
+            sticky: false,
+            reactivate_after: Duration::from_millis(hysteresis.reactivate_after_ms),
+            reactivate_motion: hysteresis.reactivate_motion,
+            min_active: Duration::from_millis(hysteresis.min_active_ms),
+            changed_at: None,
+            settle: None,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Latch the mode on, or let the condition decide again
+    pub fn sticky(&mut self, now: Instant, on: bool) -> Option<Transition> {
+        self.sticky = on;
+        self.change(now, "sticky", Gate::Bypass)
+    }
+
+    /// Whether a finger is on the pad as far as the trigger signal goes
+    pub fn is_finger_down(&self) -> bool {
+        self.finger_down
+    }
+
+    /// When `poll` has work to do next
+    pub fn next_deadline(&self) -> Option<Instant> {
+        [
+            self.deactivate_at,
+            self.mouse_until,
+            self.settle.map(|(at, _)| at),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+
+    /// Whether a finger lift is waiting out the debounce
+    pub fn lift_pending(&self) -> bool {
+        self.deactivate_at.is_some()
+    }
+
+    /// How long the pending lift still waits, for the log
+    pub fn lift_remaining(&self, now: Instant) -> Option<Duration> {
+        self.deactivate_at
+            .map(|at| at.saturating_duration_since(now))
+    }
+
+    /// The debounce for a lift at `now`
+    fn debounce(&self, now: Instant) -> Duration {
+        match &self.adaptive {
+            Some((config, window)) => velocity::debounce(config, window.speed(now)),
+            None => self.debounce,
+        }
+    }
+
+    /// Pointer motion of `distance` (|dx| + |dy|), past the threshold
+    pub fn motion(&mut self, now: Instant, distance: f64) -> Option<Transition> {
+        if self.suppressed(now) {
+            return None;
+        }
+        if let Some((_, window)) = &mut self.adaptive {
+            window.record(now, distance);
+        }
+        self.motion = true;
+        if self.finger_down || self.timeout_only {
+            self.touch_motion = true;
+        }
+        // Late motion after a lift restarts the window rather than cancelling
+        // it, so a lift that libinput reports out of order can't latch the mode
+        if self.timeout_only || self.deactivate_at.is_some() {
+            self.deactivate_at = Some(now + self.debounce(now));
+        }
+        let gate = if distance >= self.reactivate_motion {
+            Gate::LargeMotion
+        } else {
+            Gate::Hysteresis
+        };
+        self.change(now, "pointer_motion", gate)
+    }
+
+    /// Whether motion may be held back for a while (`motion::Coalescer`)
+    ///
+    /// Only while it would change nothing but the lift deadline, which
+    /// `motion_seen` catches up on: the mode is on by the motion latches, no
+    /// change is waiting out hysteresis, and the debounce doesn't depend on
+    /// the distance moved.
+    pub fn coalesces_motion(&self) -> bool {
+        self.active
+            && self.motion
+            && (self.touch_motion || !self.finger_down)
+            && self.settle.is_none()
+            && self.adaptive.is_none()
+    }
+
+    /// Motion at `at` that may have been held back: a pending lift or timeout
+    /// waits from then, as `motion` would have made it
+    pub fn motion_seen(&mut self, at: Instant) {
+        if self.adaptive.is_some() || self.suppressed(at) {
+            return;
+        }
+        if let Some(deadline) = &mut self.deactivate_at {
+            *deadline = (*deadline).max(at + self.debounce);
+        }
+    }
+
+    /// A hold gesture began (fingers resting still) or ended (lifted, or moving)
+    ///
+    /// The end only matters without contact signals, where it starts the
+    /// timeout; otherwise the hold stays latched until the lift, so fingers
+    /// that start moving out of a hold never flicker the mode off.
+    pub fn hold(&mut self, now: Instant, begin: bool) -> Option<Transition> {
+        if !begin {
+            if self.timeout_only && self.hold {
+                self.deactivate_at = Some(now + self.debounce(now));
+            }
+            return None;
+        }
+        if self.suppressed(now) {
+            return None;
+        }
+        self.hold = true;
+        if self.timeout_only {
+            self.deactivate_at = None;
+        }
+        self.update(now, "hold")
+    }
+
+    /// Motion on the activation mouse, past its threshold
+    pub fn mouse_motion(&mut self, now: Instant) -> Option<Transition> {
+        if self.suppressed(now) {
+            return None;
+        }
+        self.mouse_motion = true;
+        self.mouse_until = Some(now + self.mouse_idle);
+        self.update(now, "mouse_motion")
+    }
+
+    pub fn finger_down(&mut self, now: Instant) -> Option<Transition> {
+        self.finger_down = true;
+        self.finger_masked = self.suppressed(now);
+        if !self.timeout_only {
+            self.deactivate_at = None;
+        }
+        self.update(now, "finger_down")
+    }
+
+    /// Start the debounce; with none, the motion latch clears immediately
+    pub fn finger_up(&mut self, now: Instant) -> Option<Transition> {
+        self.finger_down = false;
+        self.finger_masked = false;
+        if (self.motion || self.hold) && self.deactivate_at.is_none() && !self.timeout_only {
+            self.deactivate_at = Some(now + self.debounce(now));
+        }
+        self.expire(now);
+        self.update(now, "finger_up")
+    }
+
+    /// Typing was detected: off now, and motion ignored until `now + cooldown`
+    pub fn typing(&mut self, now: Instant, cooldown: Duration) -> Option<Transition> {
+        self.motion = false;
+        self.touch_motion = false;
+        self.finger_masked = true;
+        self.deactivate_at = None;
+        self.hold = false;
+        self.mouse_motion = false;
+        self.mouse_until = None;
+        self.suppress_until = Some(now + cooldown);
+        self.clear_window();
+        self.change(now, "typing", Gate::Bypass)
+    }
+
+    fn suppressed(&self, now: Instant) -> bool {
+        self.suppress_until.is_some_and(|until| now < until)
+    }
+
+    pub fn touchpad_gone(&mut self, now: Instant) -> Option<Transition> {
+        self.motion = false;
+        self.touch_motion = false;
+        self.finger_down = false;
+        self.hold = false;
+        self.deactivate_at = None;
+        self.clear_window();
+        self.change(now, "touchpad_gone", Gate::Bypass)
+    }
+
+    /// Motion from before the mode ended says nothing about the next lift
+    fn clear_window(&mut self) {
+        if let Some((_, window)) = &mut self.adaptive {
+            window.clear();
+        }
+    }
+
+    /// Apply whatever timer has expired by `now`
+    pub fn poll(&mut self, now: Instant) -> Option<Transition> {
+        let mouse_idle = self.mouse_until.is_some_and(|at| at <= now);
+        if mouse_idle {
+            self.mouse_until = None;
+            self.mouse_motion = false;
+        }
+        if self.expire(now) {
+            let reason = if self.timeout_only {
+                "timeout"
+            } else {
+                "finger_up"
+            };
+            self.update(now, reason)
+        } else if mouse_idle {
+            self.update(now, "mouse_idle")
+        } else if let Some((_, reason)) = self.settle.take_if(|&mut (at, _)| at <= now) {
+            self.update(now, reason)
+        } else {
+            None
+        }
+    }
+
+    fn expire(&mut self, now: Instant) -> bool {
+        if self.deactivate_at.is_some_and(|at| at <= now) {
+            self.deactivate_at = None;
+            self.motion = false;
+            self.touch_motion = false;
+            self.hold = false;
+            self.clear_window();
+            return true;
+        }
+        false
+    }
+
+    fn update(&mut self, now: Instant, reason: &'static str) -> Option<Transition> {
+        self.change(now, reason, Gate::Hysteresis)
+    }
+
+    fn change(&mut self, now: Instant, reason: &'static str, gate: Gate) -> Option<Transition> {
+        let active = self.sticky
+            || self.condition.eval(&|signal| match signal {
+                Signal::Motion => self.motion,
+                Signal::TouchMotion => self.touch_motion,
+                Signal::FingerDown => self.finger_down && !self.finger_masked,
+                Signal::MouseMotion => self.mouse_motion,
+                Signal::Hold => self.hold,
+            });
+        if self.active == active {
+            self.settle = None;
+            return None;
+        }
+        let window = match gate {
+            Gate::Hysteresis if active => self.reactivate_after,
+            Gate::Hysteresis | Gate::LargeMotion if !active => self.min_active,
+            _ => Duration::ZERO,
+        };
+        if let Some(until) = self
+            .changed_at
+            .map(|at| at + window)
+            .filter(|&until| now < until)
+        {
+            self.settle = Some((until, reason));
+            return None;
+        }
+        self.settle = None;
+        self.active = active;
+        self.changed_at = Some(now);
+        Some(Transition { active, reason })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use proptest::prelude::*;
+    use std::path::Path;
+
+    /// One timed input; `Tick` lets time pass, firing timers as the monitor would
+    #[derive(Debug, Clone)]
+    enum Input {
+        Motion(f64),
+        FingerDown,
+        FingerUp,
+        Hold(bool),
+        Typing(u64),
+        Sticky(bool),
+        TouchpadGone,
+        Tick(u64),
+    }
+
+    fn input(large_motion: bool, bypass: bool) -> impl Strategy<Value = Input> {
+        let max_motion = if large_motion { 40.0 } else { 7.9 };
+        let mut inputs = vec![
+            (0.1..max_motion).prop_map(Input::Motion).boxed(),
+            Just(Input::FingerDown).boxed(),
+            Just(Input::FingerUp).boxed(),
+            any::<bool>().prop_map(Input::Hold).boxed(),
+            (0..80u64).prop_map(Input::Tick).boxed(),
+            (0..400u64).prop_map(Input::Tick).boxed(),
+        ];
+        if bypass {
+            inputs.push((0..300u64).prop_map(Input::Typing).boxed());
+            inputs.push(any::<bool>().prop_map(Input::Sticky).boxed());
+            inputs.push(Just(Input::TouchpadGone).boxed());
+        }
+        proptest::strategy::Union::new(inputs)
+    }
+
+    #[derive(Debug, Clone)]
+    struct Windows {
+        debounce: Duration,
+        reactivate_after: Duration,
+        min_active: Duration,
+    }
+
+    fn activation(windows: &Windows, trigger: Trigger) -> Activation {
+        let text = format!(
+            "debounce_ms = {}\n[hysteresis]\nreactivate_after_ms = {}\nmin_active_ms = {}\n",
+            windows.debounce.as_millis(),
+            windows.reactivate_after.as_millis(),
+            windows.min_active.as_millis()
+        );
+        let config = Config::parse(&text, Path::new("test.toml")).unwrap();
+        Activation::new(
+            config.activate_when.clone(),
+            config.debounce,
+            &config.adaptive_debounce,
+            trigger,
+            config.mouse_idle(),
+            &config.hysteresis,
+        )
+    }
+
+    fn windows() -> impl Strategy<Value = Windows> {
+        (0..300u64, 0..100u64, 0..100u64).prop_map(|(debounce, reactivate, min_active)| Windows {
+            debounce: Duration::from_millis(debounce),
+            reactivate_after: Duration::from_millis(reactivate),
+            min_active: Duration::from_millis(min_active),
+        })
+    }
+
+    fn trigger() -> impl Strategy<Value = Trigger> {
+        prop_oneof![Just(Trigger::ToolFinger), Just(Trigger::TimeoutOnly)]
+    }
+
+    /// Drives an `Activation` over a clock that only moves on `Tick`
+    struct Run {
+        activation: Activation,
+        now: Instant,
+        transitions: Vec<(Instant, Transition)>,
+        /// What is still going on, as far as the inputs say
+        finger_down: bool,
+        holding: bool,
+        sticky: bool,
+        /// The last input that could keep the mode on
+        last_activity: Option<Instant>,
+    }
+
+    impl Run {
+        fn new(activation: Activation) -> Self {
+            Self {
+                activation,
+                now: Instant::now(),
+                transitions: Vec::new(),
+                finger_down: false,
+                holding: false,
+                sticky: false,
+                last_activity: None,
+            }
+        }
+
+        fn apply(&mut self, input: &Input) {
+            let now = self.now;
+            let transition = match *input {
+                Input::Motion(distance) => {
+                    self.last_activity = Some(now);
+                    self.activation.motion(now, distance)
+                }
+                Input::FingerDown => {
+                    self.finger_down = true;
+                    self.last_activity = Some(now);
+                    self.activation.finger_down(now)
+                }
+                Input::FingerUp => {
+                    self.finger_down = false;
+                    self.last_activity = Some(now);
+                    self.activation.finger_up(now)
+                }
+                Input::Hold(begin) => {
+                    self.holding = begin;
+                    self.last_activity = Some(now);
+                    self.activation.hold(now, begin)
+                }
+                Input::Typing(cooldown) => {
+                    self.activation.typing(now, Duration::from_millis(cooldown))
+                }
+                Input::Sticky(on) => {
+                    self.sticky = on;
+                    self.activation.sticky(now, on)
+                }
+                Input::TouchpadGone => {
+                    self.finger_down = false;
+                    self.holding = false;
+                    self.activation.touchpad_gone(now)
+                }
+                Input::Tick(ms) => return self.tick(Duration::from_millis(ms)),
+            };
+            self.record(transition);
+        }
+
+        /// Let `by` pass, polling at every deadline on the way
+        fn tick(&mut self, by: Duration) {
+            let until = self.now + by;
+            while let Some(deadline) = self.activation.next_deadline().filter(|&at| at <= until) {
+                // Nothing is due before the deadline
+                let before = deadline.max(self.now);
+                if before > self.now {
+                    let early = self
+                        .activation
+                        .clone()
+                        .poll(before - Duration::from_nanos(1));
+                    assert_eq!(early, None, "a transition before any deadline");
+                }
+                self.now = before;
+                let transition = self.activation.poll(self.now);
+                self.record(transition);
+            }
+            self.now = until;
+            let transition = self.activation.poll(until);
+            self.record(transition);
+            assert_eq!(
+                self.activation.poll(until),
+                None,
+                "poll twice at the same time"
+            );
+        }
+
+        fn record(&mut self, transition: Option<Transition>) {
+            let Some(transition) = transition else {
+                return;
+            };
+            let was = self.transitions.last().is_some_and(|(_, t)| t.active);
+            assert_ne!(transition.active, was, "a transition that changes nothing");
+            assert_eq!(self.activation.is_active(), transition.active);
+            self.transitions.push((self.now, transition));
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn the_mode_only_stays_on_with_recent_activity(
+            windows in windows(),
+            trigger in trigger(),
+            inputs in prop::collection::vec(input(true, true), 1..200),
+        ) {
+            let mut run = Run::new(activation(&windows, trigger));
+            let quiet = windows.debounce + windows.reactivate_after + windows.min_active;
+            for input in &inputs {
+                let (was_active, seen) = (run.activation.is_active(), run.transitions.len());
+                run.apply(input);
+                // Only a returned transition changes the mode
+                prop_assert!(run.activation.is_active() == was_active || run.transitions.len() > seen);
+                let idle = run.last_activity.is_none_or(|at| run.now > at + quiet);
+                if !run.finger_down && !run.holding && !run.sticky && idle {
+                    prop_assert!(!run.activation.is_active(), "on with nothing going on: {:?}", input);
+                }
+            }
+        }
+
+        #[test]
+        fn every_activation_is_matched_by_a_deactivation(
+            windows in windows(),
+            trigger in trigger(),
+            inputs in prop::collection::vec(input(true, true), 1..200),
+        ) {
+            let mut run = Run::new(activation(&windows, trigger));
+            for input in &inputs {
+                run.apply(input);
+            }
+            for input in [Input::Sticky(false), Input::Hold(false), Input::FingerUp, Input::Tick(1000)] {
+                run.apply(&input);
+            }
+            prop_assert!(!run.activation.is_active());
+            prop_assert_eq!(run.activation.next_deadline(), None);
+            let ons = run.transitions.iter().filter(|(_, t)| t.active).count();
+            prop_assert_eq!(ons, run.transitions.len() - ons);
+        }
+
+        /// synth-177: small motion can't flap the mode faster than the hysteresis windows
+        #[test]
+        fn hysteresis_bounds_the_flap_rate(
+            windows in windows(),
+            trigger in trigger(),
+            inputs in prop::collection::vec(input(false, false), 1..300),
+        ) {
+            let mut run = Run::new(activation(&windows, trigger));
+            for input in &inputs {
+                run.apply(input);
+            }
+            for pair in run.transitions.windows(2) {
+                let ((before, _), (at, transition)) = (pair[0], pair[1]);
+                let window = if transition.active { windows.reactivate_after } else { windows.min_active };
+                prop_assert!(at - before >= window, "{:?} after {:?}", transition, at - before);
+            }
+        }
+
+        /// Large motion may reactivate at once, but the minimum hold still bounds each cycle
+        #[test]
+        fn large_motion_still_holds_each_activation(
+            windows in windows(),
+            trigger in trigger(),
+            inputs in prop::collection::vec(input(true, false), 1..300),
+        ) {
+            let mut run = Run::new(activation(&windows, trigger));
+            for input in &inputs {
+                run.apply(input);
+            }
+            for pair in run.transitions.windows(2) {
+                let ((before, _), (at, transition)) = (pair[0], pair[1]);
+                if !transition.active {
+                    prop_assert!(at - before >= windows.min_active);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use crate::config::{Config, Target};
+use crate::output::Outputs;
+use crate::preflight;
+use crate::sink::EventSink;
+use anyhow::{bail, Result};
+use evdev::raw_stream::RawDevice;
+
+/// What a backend can deliver, so config asking for more gets a warning
+#[derive(Debug, Clone, Copy)]
+pub struct Capabilities {
+    /// REL_WHEEL_HI_RES, i.e. 120ths of a detent reach applications as such
+    pub hi_res_wheel: bool,
+    /// Partial detents get through at all (smooth scrolling, kinetic tails)
+    pub fractional_wheel: bool,
+    /// Non-key events (scancodes, LEDs, switches) are forwarded
+    pub raw_events: bool,
+    /// Pointer and keyboard can be separate devices (pointer_on_keyboard applies)
+    pub separate_devices: bool,
+    /// An absolute pointer can be created, for `[warp]` jumps
+    pub absolute_pointer: bool,
+    /// Key codes above 247 can be typed; X keycodes (evdev + 8) stop at 255
+    pub wide_keycodes: bool,
+}
+
+/// One entry in the backend registry
+pub struct Backend {
+    pub name: &'static str,
+    pub capabilities: Capabilities,
+    /// Whether `auto` should pick this, judged without side effects
+    probe: fn() -> bool,
+    create: fn(&Config, &RawDevice) -> Result<Box<dyn EventSink>>,
+}
+
+/// Registered backends, in `auto` probe order
+pub const BACKENDS: &[Backend] = &[
+    Backend {
+        name: "uinput",
+        capabilities: Capabilities {
+            hi_res_wheel: true,
+            fractional_wheel: true,
+            raw_events: true,
+            separate_devices: true,
+            absolute_pointer: true,
+            wide_keycodes: true,
+        },
+        probe: || preflight::check_uinput().ok,
+        create: |config, keyboard| Ok(Box::new(Outputs::create(config, keyboard)?)),
+    },
+    #[cfg(feature = "wayland")]
+    Backend {
+        name: "wayland",
+        capabilities: Capabilities {
+            hi_res_wheel: false,
+            fractional_wheel: true,
+            raw_events: false,
+            separate_devices: false,
+            absolute_pointer: false,
+            wide_keycodes: true,
+        },
+        probe: || std::env::var_os("WAYLAND_DISPLAY").is_some_and(|d| !d.is_empty()),
+        create: |config, _| {
+            eprintln!("🪟 Connecting to the Wayland compositor...");
+            Ok(Box::new(crate::wayland::WaylandSink::connect(
+                &config.xkb_layout,
+            )?))
+        },
+    },
+    #[cfg(feature = "xtest")]
+    Backend {
+        name: "xtest",
+        capabilities: Capabilities {
+            hi_res_wheel: false,
+            fractional_wheel: false,
+            raw_events: false,
+            separate_devices: false,
+            absolute_pointer: false,
+            wide_keycodes: false,
+        },
+        probe: || std::env::var_os("DISPLAY").is_some_and(|d| !d.is_empty()),
+        create: |_, _| {
+            eprintln!("🪟 Connecting to the X server...");
+            Ok(Box::new(crate::xtest::XtestSink::connect()?))
+        },
+    },
+];
+
+/// Names accepted by `--backend`
+pub fn names() -> Vec<&'static str> {
+    std::iter::once("auto")
+        .chain(BACKENDS.iter().map(|b| b.name))
+        .collect()
+}
+
+/// Resolve a `--backend` value; `auto` takes the first backend whose probe passes
+pub fn select(name: &str) -> Result<&'static Backend> {
+    if name == "auto" {
+        return match BACKENDS.iter().find(|b| (b.probe)()) {
+            Some(backend) => {
+                eprintln!("🔌 Output backend: {} (auto)", backend.name);
+                Ok(backend)
+            }
+            None => bail!(
+                "No usable output backend (tried {})",
+                BACKENDS
+                    .iter()
+                    .map(|b| b.name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+    }
+    match BACKENDS.iter().find(|b| b.name == name) {
+        Some(backend) => {
+            eprintln!("🔌 Output backend: {}", backend.name);
+            Ok(backend)
+        }
+        None => bail!(
+            "Unknown backend '{}' (built with: {})",
+            name,
+            names().join(", ")
+        ),
+    }
+}
+
+impl Backend {
+    pub fn create(&self, config: &Config, keyboard: &RawDevice) -> Result<Box<dyn EventSink>> {
+        (self.create)(config, keyboard)
+    }
+
+    /// Warn about config this backend can only partly honour
+    pub fn check(&self, config: &Config) {
+        let caps = self.capabilities;
+        let wheel = config
+            .targets()
+            .any(|target| matches!(target, Target::Wheel(_)));
+
+        if wheel && !caps.hi_res_wheel {
+            eprintln!(
+                "⚠️  {} has no high-resolution wheel; scroll keys send plain detents",
+                self.name
+            );
+        }
+        if config.scroll.kinetic.enabled && !caps.fractional_wheel {
+            eprintln!(
+                "⚠️  {} can't send partial detents; kinetic scrolling will be coarse",
+                self.name
+            );
+        }
+        if config.pointer_on_keyboard && !caps.separate_devices {
+            eprintln!("⚠️  pointer_on_keyboard has no effect with {}", self.name);
+        }
+        if config.warp.is_some() && !caps.absolute_pointer {
+            eprintln!(
+                "⚠️  {} can't warp the pointer; [warp] jumps are dropped",
+                self.name
+            );
+        }
+        if !caps.wide_keycodes {
+            for key in config.key_targets().iter().filter(|key| key.code() > 247) {
+                eprintln!(
+                    "⚠️  {} can't type {:?}: X keycodes stop at 255",
+                    self.name, key
+                );
+            }
+        }
+        if !caps.raw_events {
+            eprintln!(
+                "   {} forwards key events only (no scancodes, LEDs or switches)",
+                self.name
+            );
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! Exponential backoff for reconnect loops, with quiet logging
+//!
+//! A device unplugged for the day would otherwise retry, and log, forever.
+//! The delay doubles up to a cap, and "still missing" is only logged after
+//! the first failure and then each time the wait passes another power of ten
+//! seconds (10s, 100s, ...). The caller passes the clock in, so the
+//! schedule is a pure function of the times it is given.
+
+use std::time::{Duration, Instant};
+
+/// Delay before the first retry
+pub const MIN_DELAY: Duration = Duration::from_millis(250);
+
+pub struct Backoff {
+    label: String,
+    max: Duration,
+    delay: Duration,
+    attempts: u32,
+    since: Instant,
+    /// log10 of the seconds waited at the last "still missing" log
+    logged: Option<u32>,
+}
+
+impl Backoff {
+    pub fn new(label: &str, max: Duration, now: Instant) -> Self {
+        Self {
+            label: label.to_string(),
+            max: max.max(MIN_DELAY),
+            delay: MIN_DELAY,
+            attempts: 0,
+            since: now,
+            logged: None,
+        }
+    }
+
+    /// Failed attempts so far
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// How long to sleep before the next attempt
+    pub fn delay(&self) -> Duration {
+        self.delay
+    }
+
+    /// Record a failed attempt and grow the delay; logs if it is time to
+    pub fn failed(&mut self, now: Instant) {
+        if let Some(message) = self.fail(now) {
+            eprintln!("{}", message);
+        }
+    }
+
+    /// `failed`, returning the "still missing" line if one is due
+    fn fail(&mut self, now: Instant) -> Option<String> {
+        self.attempts += 1;
+        self.delay = (self.delay * 2).min(self.max);
+        let elapsed = now.saturating_duration_since(self.since);
+        // The first failure logs even under 10s; after that, each new power of ten
+        let order = magnitude(elapsed);
+        if self.logged.is_some_and(|logged| logged >= order) {
+            return None;
+        }
+        self.logged = Some(order);
+        Some(format!(
+            "   {} still missing after {} attempt(s) over {}, next try in {:?}",
+            self.label,
+            self.attempts,
+            human(elapsed),
+            self.delay
+        ))
+    }
+
+    /// Log the single recovery line
+    pub fn recovered(&self, now: Instant) {
+        eprintln!("{}", self.recovery(now));
+    }
+
+    fn recovery(&self, now: Instant) -> String {
+        format!(
+            "🔌 {} recovered after {} attempt(s) / {}",
+            self.label,
+            self.attempts + 1,
+            human(now.saturating_duration_since(self.since))
+        )
+    }
+}
+
+/// log10 of `elapsed` in whole seconds, 0 under a second
+fn magnitude(elapsed: Duration) -> u32 {
+    elapsed.as_secs().max(1).ilog10()
+}
+
+/// "850ms", "42s" or "17 min"
+fn human(elapsed: Duration) -> String {
+    if elapsed < Duration::from_secs(1) {
+        format!("{}ms", elapsed.as_millis())
+    } else if elapsed < Duration::from_secs(60) {
+        format!("{}s", elapsed.as_secs())
+    } else {
+        format!("{} min", elapsed.as_secs() / 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A backoff on a mock clock, with times in milliseconds from `start`
+    struct Clock {
+        start: Instant,
+        backoff: Backoff,
+    }
+
+    impl Clock {
+        fn new(max_s: u64) -> Self {
+            let start = Instant::now();
+            Self {
+                start,
+                backoff: Backoff::new("keyboard", Duration::from_secs(max_s), start),
+            }
+        }
+
+        fn at(&self, ms: u64) -> Instant {
+            self.start + Duration::from_millis(ms)
+        }
+
+        fn fail(&mut self, ms: u64) -> Option<String> {
+            let now = self.at(ms);
+            self.backoff.fail(now)
+        }
+    }
+
+    #[test]
+    fn the_delay_doubles_up_to_the_cap() {
+        let mut clock = Clock::new(2);
+        assert_eq!(clock.backoff.delay(), MIN_DELAY);
+        let mut delays = Vec::new();
+        for i in 0..6 {
+            clock.fail(i * 100);
+            delays.push(clock.backoff.delay().as_millis());
+        }
+        assert_eq!(delays, [500, 1000, 2000, 2000, 2000, 2000]);
+        assert_eq!(clock.backoff.attempts(), 6);
+    }
+
+    #[test]
+    fn a_cap_under_the_first_delay_is_raised_to_it() {
+        let mut clock = Clock::new(0);
+        clock.fail(0);
+        assert_eq!(clock.backoff.delay(), MIN_DELAY);
+    }
+
+    #[test]
+    fn still_missing_is_logged_once_per_power_of_ten_seconds() {
+        let mut clock = Clock::new(60);
+        assert_eq!(
+            clock.fail(250).unwrap(),
+            "   keyboard still missing after 1 attempt(s) over 250ms, next try in 500ms"
+        );
+        for ms in [750, 1_750, 3_750, 9_999] {
+            assert_eq!(clock.fail(ms), None, "at {}ms", ms);
+        }
+        assert_eq!(
+            clock.fail(10_000).unwrap(),
+            "   keyboard still missing after 6 attempt(s) over 10s, next try in 16s"
+        );
+        for ms in [26_000, 58_000, 99_000] {
+            assert_eq!(clock.fail(ms), None, "at {}ms", ms);
+        }
+        assert_eq!(
+            clock.fail(159_000).unwrap(),
+            "   keyboard still missing after 10 attempt(s) over 2 min, next try in 60s"
+        );
+    }
+
+    #[test]
+    fn a_new_outage_after_recovery_starts_over() {
+        let mut clock = Clock::new(60);
+        for ms in [0, 500, 1_500, 3_500] {
+            clock.fail(ms);
+        }
+        assert_eq!(
+            clock.backoff.recovery(clock.at(7_500)),
+            "🔌 keyboard recovered after 5 attempt(s) / 7s"
+        );
+        // Callers drop the recovered backoff; the next outage gets a fresh one
+        let later = clock.at(600_000);
+        let mut backoff = Backoff::new("keyboard", Duration::from_secs(60), later);
+        assert_eq!(backoff.delay(), MIN_DELAY);
+        assert!(
+            backoff.fail(later + MIN_DELAY).is_some(),
+            "logs the first failure"
+        );
+        assert_eq!(backoff.attempts(), 1);
+    }
+}
@@ -0,0 +1,100 @@
+//! Mappings checked against what the physical keyboard can send
+//!
+//! Targets need no check here: config validation already limits them to the
+//! buttons the virtual devices declare (see `Config::mouse_buttons`).
+
+use crate::config::Config;
+use crate::device::{self, DeviceKind};
+use anyhow::Result;
+use evdev::raw_stream::RawDevice;
+use evdev::{AttributeSetRef, Key};
+use std::path::Path;
+
+/// Mapped keys missing from `supported`, in key code order
+pub fn unmatched_sources(config: &Config, supported: Option<&AttributeSetRef<Key>>) -> Vec<Key> {
+    let mut missing: Vec<Key> = config
+        .mapped_keys()
+        .filter(|key| !supported.is_some_and(|keys| keys.contains(**key)))
+        .copied()
+        .collect();
+    missing.sort_by_key(|key| key.code());
+    missing
+}
+
+/// Warn about every mapping `keyboard` can never trigger; returns how many
+pub fn warn_unmatched(config: &Config, keyboard: &RawDevice, path: &Path) -> usize {
+    let missing = unmatched_sources(config, keyboard.supported_keys());
+    for key in &missing {
+        eprintln!(
+            "⚠️  {:?} is mapped, but {} ({}) never sends it",
+            key,
+            path.display(),
+            keyboard.name().unwrap_or("unnamed")
+        );
+    }
+    missing.len()
+}
+
+/// `check`: the config loaded already; cross-check it against the keyboard if reachable
+pub fn check(config: &Config) -> Result<()> {
+    eprintln!("✅ Config is valid");
+    for (keysym, key) in &config.keysyms {
+        eprintln!("🔤 Keysym {} → {:?}", keysym, key);
+    }
+    let typed: Vec<String> = config
+        .key_targets()
+        .iter()
+        .map(|key| format!("{:?}", key))
+        .collect();
+    if !typed.is_empty() {
+        eprintln!(
+            "⌨️  Key targets, always declared on the virtual keyboard: {}",
+            typed.join(", ")
+        );
+    }
+    let in_use = match device::reopen(DeviceKind::Keyboard, &config.keyboard) {
+        Some((keyboard, path)) => {
+            check_keyboard(config, &keyboard, &path);
+            Some(path)
+        }
+        None => {
+            eprintln!("   No keyboard reachable; skipped checking mapped keys against it");
+            None
+        }
+    };
+    // A set can be for a keyboard other than the one in use, e.g. a docked board
+    for (i, set) in config.mapping_sets.iter().enumerate() {
+        match device::reopen(DeviceKind::Keyboard, &set.candidates) {
+            Some((_, path)) if in_use.as_ref() == Some(&path) => {}
+            Some((keyboard, path)) if config.mapping_set_for(&path) == Some(i) => {
+                check_keyboard(config, &keyboard, &path)
+            }
+            Some((_, path)) => eprintln!(
+                "   mapping_sets[{}]: {} is taken by an earlier set",
+                i,
+                path.display()
+            ),
+            None => eprintln!(
+                "   mapping_sets[{}]: no keyboard reachable; skipped checking its keys",
+                i
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Check the table that applies to `keyboard` against the keys it has
+fn check_keyboard(config: &Config, keyboard: &RawDevice, path: &Path) {
+    eprintln!(
+        "🗂️  Mappings for {}: {}",
+        path.display(),
+        config.mapping_source(path)
+    );
+    if warn_unmatched(&config.for_keyboard(path), keyboard, path) == 0 {
+        eprintln!(
+            "✅ Every mapped key exists on {} ({})",
+            path.display(),
+            keyboard.name().unwrap_or("unnamed")
+        );
+    }
+}
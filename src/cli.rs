@@ -0,0 +1,247 @@
+use crate::backend;
+use crate::presets;
+use clap::builder::PossibleValuesParser;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Remap keyboard keys to mouse buttons and scrolling while the touchpad is in use
+#[derive(Debug, Parser)]
+#[command(version)]
+pub struct Cli {
+    /// Stop a running instance (SIGTERM) and take over once it has exited
+    #[arg(long)]
+    pub replace: bool,
+
+    /// Start with remapping off until SIGUSR1 turns it on (as `start_enabled = false`)
+    #[arg(long)]
+    pub start_disabled: bool,
+
+    /// Detach into the background once the keyboard is grabbed (for non-systemd init)
+    #[arg(long, conflicts_with = "output")]
+    pub daemonize: bool,
+
+    /// Where remapped input goes; auto probes uinput, wayland, xtest in that order
+    #[arg(long, default_value = "uinput", value_parser = PossibleValuesParser::new(backend::names()))]
+    pub backend: String,
+
+    /// Show debug output, such as what mode hooks print
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    /// Also write machine-readable records to stdout
+    #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+    pub output: OutputFormat,
+
+    /// Config layer applied on top of /usr/share, /etc and the user's config
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Seat to serve on a multi-seat machine: only its devices are auto-detected,
+    /// and device names, socket, PID and state files get it as a suffix
+    #[arg(long, global = true, value_name = "NAME")]
+    pub seat: Option<String>,
+
+    /// Built-in mappings to start from, below every config layer (see list-presets)
+    #[arg(long, global = true, value_name = "NAME", value_parser = PossibleValuesParser::new(presets::names()))]
+    pub preset: Option<String>,
+
+    /// Also write the log to this file, rotating it by size; SIGHUP reopens it
+    #[arg(long, value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+
+    /// Size at which --log-file is rotated, in bytes or with a K, M or G suffix
+    #[arg(long, value_name = "SIZE", default_value = "10M", value_parser = parse_size)]
+    pub log_max_size: u64,
+
+    /// Rotated log files kept besides the current one
+    #[arg(long, value_name = "N", default_value_t = 5, value_parser = clap::value_parser!(u16).range(0..=100))]
+    pub log_files: u16,
+
+    /// Don't print `STATE ...` lines on stdout for mode and enabled changes
+    #[arg(long)]
+    pub no_state_output: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run activation and routing against a script of timed synthetic events
+    Simulate {
+        /// Lines like `+100ms key KEY_F 1`, `+0ms touchpad motion`, `+200ms finger up`
+        script: PathBuf,
+        /// Fail unless the printed output matches this file line for line
+        #[arg(long = "assert", value_name = "EXPECTED")]
+        expected: Option<PathBuf>,
+    },
+    /// Show how captured `libinput debug-events` output is parsed, one line per input line
+    ParseLibinput {
+        /// The captured output; `-` reads stdin
+        input: PathBuf,
+        /// Fail unless the printed output matches this file line for line
+        #[arg(long = "assert", value_name = "EXPECTED")]
+        expected: Option<PathBuf>,
+    },
+    /// Write a commented starter config with the detected devices filled in
+    InitConfig {
+        /// Where to write it; defaults to --config, TOUCH_LAYER_CONFIG or /etc/touch-layer/config.toml
+        path: Option<PathBuf>,
+        /// Overwrite an existing file
+        #[arg(long)]
+        force: bool,
+    },
+    /// Rewrite a config from an older schema version, keeping a backup
+    MigrateConfig {
+        /// Defaults to --config, TOUCH_LAYER_CONFIG or /etc/touch-layer/config.toml
+        path: Option<PathBuf>,
+    },
+    /// Print the config layers and which one set each value
+    ShowConfig,
+    /// Validate the config, and the mapped keys against the keyboard if it is reachable
+    Check,
+    /// Check permissions, devices, uinput, competing grabs and libinput, without grabbing anything
+    Doctor {
+        /// Print the findings as one JSON document on stdout
+        #[arg(long)]
+        json: bool,
+    },
+    /// Validate the built-in presets and print what each one maps
+    ListPresets,
+    /// List input devices with the paths and vidpid: selectors the config accepts
+    ListDevices,
+    /// Print one device's IDs and capabilities, then stream its events (like evtest)
+    DumpDevice {
+        /// Event node, e.g. /dev/input/event7
+        path: PathBuf,
+        /// Take the device exclusively; fails if another process already has it
+        #[arg(long)]
+        grab: bool,
+    },
+    /// Watch a running instance over its control socket (no device access needed)
+    Monitor {
+        /// Socket to connect to instead of the usual locations
+        #[arg(long)]
+        socket: Option<PathBuf>,
+    },
+    /// Print a running instance's event stream, one line per event
+    Watch {
+        /// Socket to connect to instead of the usual locations
+        #[arg(long)]
+        socket: Option<PathBuf>,
+        /// Print a status snapshot every this many seconds (default: twice a second)
+        #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..=3600))]
+        interval: Option<u64>,
+    },
+    /// Send synthetic input through a running instance's virtual devices (needs control.inject)
+    Inject {
+        /// Socket to connect to instead of the usual locations
+        #[arg(long)]
+        socket: Option<PathBuf>,
+        /// `key NAME [press|release|tap]`, `button NAME [press|release|tap]` or `wheel DETENTS`
+        #[arg(required = true, allow_hyphen_values = true, num_args = 1..)]
+        command: Vec<String>,
+    },
+    /// Soak activation and routing with random high-rate input and check invariants (no devices)
+    Stress {
+        /// Wall-clock time to keep running rounds for, e.g. `30s` or `10m`
+        #[arg(long, default_value = "10s", value_parser = humantime::parse_duration)]
+        duration: Duration,
+        /// Patterns to mix, comma-separated
+        #[arg(
+            long,
+            value_enum,
+            value_delimiter = ',',
+            default_value = "burst,flap,motion"
+        )]
+        patterns: Vec<Pattern>,
+        /// Input events per simulated second
+        #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(u32).range(1..=100_000))]
+        rate: u32,
+        /// Input events per round
+        #[arg(long, default_value_t = 2000, value_parser = clap::value_parser!(u64).range(1..=1_000_000))]
+        events: u64,
+        /// Seed of the first round, to reproduce a run; later rounds count up from it
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Where a failing round's script is written, for `simulate`
+        #[arg(long, value_name = "PATH", default_value = "stress-failure.sim")]
+        save_script: PathBuf,
+    },
+    /// Pause, resume, remap or ask about a running instance over its control socket
+    Ctl {
+        /// Socket to connect to instead of the usual locations
+        #[arg(long)]
+        socket: Option<PathBuf>,
+        #[command(subcommand)]
+        command: CtlCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CtlCommand {
+    /// Turn remapping off now and held buttons up, and back on after DURATION or at `resume`
+    Pause {
+        /// e.g. `30m`, `1h30m` or `90s`, up to a week; none lasts until `resume`
+        duration: Option<String>,
+    },
+    /// End a pause, or turn the layer back on however it was turned off
+    Resume,
+    /// Print whether the layer is on, off or paused (and for how much longer), and the mode
+    Status,
+    /// Map KEY to a button, key or wheel step until exit, or until `save`
+    Map {
+        /// e.g. `KEY_G` or `g`
+        key: String,
+        /// e.g. `BTN_MIDDLE`, `KEY_PLAYPAUSE` or `wheel_up`; only what the virtual devices declare
+        target: String,
+    },
+    /// Type KEY as itself again
+    Unmap { key: String },
+    /// Print every key the base table maps, and to what
+    ListMap,
+    /// Write `map`/`unmap` edits into [mappings] of the highest config layer
+    Save,
+}
+
+/// Smallest --log-max-size, so rotation can't spin on every line
+const MIN_LOG_SIZE: u64 = 4096;
+
+/// `4096`, `512K`, `10M` or `1G`
+fn parse_size(value: &str) -> Result<u64, String> {
+    let (digits, unit) = match value.char_indices().last() {
+        Some((at, 'K' | 'k')) => (&value[..at], 1 << 10),
+        Some((at, 'M' | 'm')) => (&value[..at], 1 << 20),
+        Some((at, 'G' | 'g')) => (&value[..at], 1 << 30),
+        _ => (value, 1),
+    };
+    let size = digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .ok_or_else(|| format!("expected a size like 4096, 512K or 10M, not '{}'", value))?;
+    if size < MIN_LOG_SIZE {
+        return Err(format!("must be at least {} bytes", MIN_LOG_SIZE));
+    }
+    Ok(size)
+}
+
+/// What `stress` feeds in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Pattern {
+    /// Overlapping key presses and releases, with repeats, mapped and unmapped
+    Burst,
+    /// Finger down and up, so the mode turns on and off under held keys
+    Flap,
+    /// Touchpad motion between everything else
+    Motion,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Log lines on stderr only
+    Human,
+    /// JSON lines on stdout (see `report::Record`), logs still on stderr
+    Json,
+}
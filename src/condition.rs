@@ -0,0 +1,355 @@
+//! `activate_when` expressions: source names combined with `&&`, `||` and parentheses
+//!
+//! `&&` binds tighter than `||`, so `motion || finger_down && motion` groups
+//! as `motion || (finger_down && motion)`. There is no negation, so with
+//! every source false the mode is always off.
+
+use anyhow::{bail, Result};
+use std::fmt;
+use std::str::FromStr;
+
+/// A named boolean the activation state machine keeps current
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// Pointer motion seen, latched until a finger lift (plus debounce)
+    Motion,
+    /// Like Motion, but only motion arriving while a finger is down arms it
+    TouchMotion,
+    /// A finger is on the touchpad (BTN_TOOL_FINGER)
+    FingerDown,
+    /// Motion on `[activation_mouse]`, latched until it has been idle for idle_ms
+    MouseMotion,
+    /// Fingers resting still on the pad (libinput's GESTURE_HOLD_BEGIN), latched like Motion
+    Hold,
+}
+
+const SIGNALS: [(&str, Signal); 5] = [
+    ("motion", Signal::Motion),
+    ("touch_motion", Signal::TouchMotion),
+    ("finger_down", Signal::FingerDown),
+    ("mouse_motion", Signal::MouseMotion),
+    ("hold", Signal::Hold),
+];
+
+impl Signal {
+    pub fn name(self) -> &'static str {
+        SIGNALS
+            .iter()
+            .find(|(_, signal)| *signal == self)
+            .map_or("?", |(name, _)| name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    Signal(Signal),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+impl Default for Condition {
+    fn default() -> Self {
+        Condition::Signal(Signal::TouchMotion)
+    }
+}
+
+impl Condition {
+    /// Evaluate with `value` giving each signal's current state
+    pub fn eval(&self, value: &impl Fn(Signal) -> bool) -> bool {
+        match self {
+            Condition::Signal(signal) => value(*signal),
+            Condition::And(a, b) => a.eval(value) && b.eval(value),
+            Condition::Or(a, b) => a.eval(value) || b.eval(value),
+        }
+    }
+
+    pub fn mentions(&self, signal: Signal) -> bool {
+        match self {
+            Condition::Signal(own) => *own == signal,
+            Condition::And(a, b) | Condition::Or(a, b) => a.mentions(signal) || b.mentions(signal),
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Condition::Signal(signal) => write!(f, "{}", signal.name()),
+            Condition::And(a, b) => {
+                // Only an Or operand needs brackets under &&
+                for (i, side) in [a, b].into_iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " && ")?;
+                    }
+                    match **side {
+                        Condition::Or(..) => write!(f, "({})", side)?,
+                        _ => write!(f, "{}", side)?,
+                    }
+                }
+                Ok(())
+            }
+            Condition::Or(a, b) => write!(f, "{} || {}", a, b),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token<'a> {
+    Name(&'a str),
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+fn tokenize(text: &str) -> Result<Vec<(usize, Token<'_>)>> {
+    let mut tokens = Vec::new();
+    let mut rest = text.char_indices().peekable();
+    while let Some((at, c)) = rest.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '&' | '|' => {
+                if rest.next_if(|&(_, next)| next == c).is_none() {
+                    bail!("column {}: expected '{}{}'", at + 1, c, c);
+                }
+                if c == '&' {
+                    Token::And
+                } else {
+                    Token::Or
+                }
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut end = at + 1;
+                while let Some((i, _)) =
+                    rest.next_if(|&(_, next)| next.is_ascii_alphanumeric() || next == '_')
+                {
+                    end = i + 1;
+                }
+                Token::Name(&text[at..end])
+            }
+            c => bail!("column {}: unexpected '{}'", at + 1, c),
+        };
+        tokens.push((at, token));
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over the token list: or := and ('||' and)*, and := atom ('&&' atom)*
+struct Parser<'a> {
+    tokens: Vec<(usize, Token<'a>)>,
+    pos: usize,
+    len: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<Token<'a>> {
+        self.tokens.get(self.pos).map(|&(_, token)| token)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.len, |&(at, _)| at) + 1
+    }
+
+    fn or(&mut self) -> Result<Condition> {
+        let mut left = self.and()?;
+        while self.peek() == Some(Token::Or) {
+            self.pos += 1;
+            left = Condition::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Condition> {
+        let mut left = self.atom()?;
+        while self.peek() == Some(Token::And) {
+            self.pos += 1;
+            left = Condition::And(Box::new(left), Box::new(self.atom()?));
+        }
+        Ok(left)
+    }
+
+    fn atom(&mut self) -> Result<Condition> {
+        let column = self.column();
+        match self.peek() {
+            Some(Token::Name(name)) => {
+                self.pos += 1;
+                match SIGNALS.iter().find(|(known, _)| *known == name) {
+                    Some((_, signal)) => Ok(Condition::Signal(*signal)),
+                    None => bail!(
+                        "column {}: unknown source '{}' (known: {})",
+                        column,
+                        name,
+                        SIGNALS.map(|(name, _)| name).join(", ")
+                    ),
+                }
+            }
+            Some(Token::Open) => {
+                self.pos += 1;
+                let inner = self.or()?;
+                if self.peek() != Some(Token::Close) {
+                    bail!("column {}: expected ')'", self.column());
+                }
+                self.pos += 1;
+                Ok(inner)
+            }
+            Some(_) => bail!("column {}: expected a source name or '('", column),
+            None => bail!("column {}: unexpected end of expression", column),
+        }
+    }
+}
+
+impl FromStr for Condition {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            pos: 0,
+            len: text.len(),
+        };
+        let condition = parser.or()?;
+        if parser.peek().is_some() {
+            bail!("column {}: expected '&&' or '||'", parser.column());
+        }
+        Ok(condition)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Condition {
+        text.parse().unwrap()
+    }
+
+    fn error(text: &str) -> String {
+        text.parse::<Condition>().unwrap_err().to_string()
+    }
+
+    fn signal(signal: Signal) -> Box<Condition> {
+        Box::new(Condition::Signal(signal))
+    }
+
+    /// Whether `a` and `b` agree for every combination of signal values
+    fn equivalent(a: &Condition, b: &Condition) -> bool {
+        (0..1u32 << SIGNALS.len()).all(|bits| {
+            let value = |signal| {
+                let index = SIGNALS.iter().position(|&(_, s)| s == signal).unwrap();
+                bits & (1 << index) != 0
+            };
+            a.eval(&value) == b.eval(&value)
+        })
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            parse("motion || finger_down && hold"),
+            Condition::Or(
+                signal(Signal::Motion),
+                Box::new(Condition::And(
+                    signal(Signal::FingerDown),
+                    signal(Signal::Hold)
+                ))
+            )
+        );
+    }
+
+    #[test]
+    fn parentheses_override_precedence() {
+        let grouped = parse("(motion || finger_down) && hold");
+        assert_eq!(
+            grouped,
+            Condition::And(
+                Box::new(Condition::Or(
+                    signal(Signal::Motion),
+                    signal(Signal::FingerDown)
+                )),
+                signal(Signal::Hold)
+            )
+        );
+        let motion_only = |signal| signal == Signal::Motion;
+        assert!(!grouped.eval(&motion_only));
+        assert!(parse("motion || finger_down && hold").eval(&motion_only));
+    }
+
+    #[test]
+    fn operators_chain_left_to_right() {
+        assert_eq!(
+            parse("motion && hold && finger_down"),
+            Condition::And(
+                Box::new(Condition::And(signal(Signal::Motion), signal(Signal::Hold))),
+                signal(Signal::FingerDown)
+            )
+        );
+        assert_eq!(parse("((hold))"), Condition::Signal(Signal::Hold));
+    }
+
+    #[test]
+    fn unknown_sources_are_rejected_with_their_column() {
+        let message = error("motion && trackpoint");
+        assert!(
+            message.starts_with("column 11: unknown source 'trackpoint'"),
+            "{}",
+            message
+        );
+        assert!(
+            message.contains("known: motion, touch_motion"),
+            "{}",
+            message
+        );
+
+        let config = r#"activate_when = "motion && trackpoint""#;
+        let err = crate::config::Config::parse(config, std::path::Path::new("test.toml"));
+        let chain = format!("{:#}", err.unwrap_err());
+        assert!(chain.contains("unknown source 'trackpoint'"), "{}", chain);
+    }
+
+    #[test]
+    fn unbalanced_parentheses_are_rejected() {
+        assert_eq!(error("(motion || hold"), "column 16: expected ')'");
+        assert_eq!(error("motion || hold)"), "column 15: expected '&&' or '||'");
+        assert_eq!(error("()"), "column 2: expected a source name or '('");
+    }
+
+    #[test]
+    fn malformed_operators_are_rejected() {
+        assert_eq!(error("motion & hold"), "column 8: expected '&&'");
+        assert_eq!(
+            error("motion ||"),
+            "column 10: unexpected end of expression"
+        );
+        assert_eq!(error("motion hold"), "column 8: expected '&&' or '||'");
+        assert_eq!(error("motion || !hold"), "column 11: unexpected '!'");
+    }
+
+    #[test]
+    fn display_parses_back_to_the_same_condition() {
+        for text in [
+            "touch_motion",
+            "motion || finger_down && hold",
+            "(motion || finger_down) && hold",
+            "hold && (motion || mouse_motion) && finger_down",
+            "motion || (hold || finger_down)",
+            "(motion && hold) || (touch_motion && (finger_down || mouse_motion))",
+        ] {
+            let condition = parse(text);
+            let shown = condition.to_string();
+            let again = parse(&shown);
+            assert!(
+                equivalent(&condition, &again),
+                "{} shown as {}",
+                text,
+                shown
+            );
+            assert_eq!(again.to_string(), shown, "{}", text);
+        }
+        assert_eq!(
+            parse("(motion || hold) && finger_down").to_string(),
+            "(motion || hold) && finger_down"
+        );
+    }
+}
@@ -0,0 +1,2409 @@
+use crate::condition::{Condition, Signal};
+use crate::device::{self, VidPid, NAME_PREFIX};
+use crate::exit::{self, Class};
+use crate::keys;
+use crate::layers;
+use crate::migrate;
+use crate::modifiers::{self, Requirement};
+use crate::output;
+use crate::overrides::{self, Override};
+use crate::presets::{self, Preset};
+use crate::scroll::{Curve, Ramp, HI_RES_PER_DETENT};
+use crate::seat;
+use crate::tablet::TabletAction;
+use crate::trigger::Trigger;
+use crate::warp;
+use anyhow::{anyhow, bail, Context, Result};
+use evdev::{AttributeSet, Key, PropType};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use toml_edit::DocumentMut;
+
+/// Default config location, overridable with TOUCH_LAYER_CONFIG
+const DEFAULT_CONFIG_PATH: &str = "/etc/touch-layer/config.toml";
+
+pub const DEFAULT_TOUCHPAD: &str = "/dev/input/by-path/platform-AMDI0010:03-event-mouse";
+pub const DEFAULT_KEYBOARD: &str = "/dev/input/by-path/platform-i8042-serio-0-event-kbd";
+
+/// Fastest allowed scroll repeat, so a typo can't flood the compositor
+pub const MIN_REPEAT_INTERVAL_MS: u64 = 10;
+
+/// Largest wheel step, in detents, of a mapping or script result
+pub const MAX_WHEEL_STEP: i32 = 30;
+
+/// Largest hi-res step per detent: ten detents' worth
+const MAX_HI_RES_STEP: i32 = 10 * HI_RES_PER_DETENT;
+
+/// Fastest allowed turbo clicking, for the same reason
+const MAX_TURBO_RATE_HZ: u32 = 50;
+
+/// Least time between two runs of a key's command, unless it sets its own
+const DEFAULT_COMMAND_COOLDOWN_MS: u64 = 500;
+
+/// Fastest accelerated scroll repeat; past 1/MIN_REPEAT_INTERVAL_MS steps grow instead
+const MAX_ACCEL_RATE_HZ: u32 = 1000;
+
+/// Longest frame gap in a click sequence; toolkits want a triple click within ~400ms
+const MAX_CLICK_GAP_MS: u64 = 80;
+
+/// Longest after_letter_ms; past this, a pause mid-sentence would still type clicks
+const MAX_AFTER_LETTER_MS: u64 = 1000;
+
+/// Most startup grab attempts, so a stuck grab can't hold startup up for minutes
+const MAX_GRAB_ATTEMPTS: u32 = 100;
+
+/// Longest pause between grab attempts; the doubling stops here
+pub const MAX_GRAB_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Bounds of watchdog.max_button_hold_ms: past a click, short of forgetting the option
+const MIN_BUTTON_HOLD_MS: u64 = 1000;
+const MAX_BUTTON_HOLD_MS: u64 = 3_600_000;
+
+/// Raw config as written in the TOML file
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawConfig {
+    touchpad: Option<RawPaths>,
+    keyboard: Option<RawPaths>,
+    device_wait_ms: u64,
+    reconnect_max_delay_ms: u64,
+    grab_attempts: u32,
+    grab_retry_ms: u64,
+    virtual_keyboard_name: String,
+    pointer_on_keyboard: bool,
+    mouse_buttons: Option<Vec<String>>,
+    xkb_layout: String,
+    mapping_keys: MappingKeys,
+    xkb_variant: String,
+    xkb_options: String,
+    xkb_keymap: Option<PathBuf>,
+    debounce_ms: u64,
+    adaptive_debounce: AdaptiveDebounceConfig,
+    chatter_ms: u64,
+    activate_when: Option<String>,
+    motion_threshold: f64,
+    deactivate_on: Trigger,
+    persist_state: bool,
+    start_enabled: bool,
+    on_activate: Option<String>,
+    on_deactivate: Option<String>,
+    /// Whether `command` mapping targets are allowed at all
+    allow_commands: bool,
+    mapping_condition: MappingCondition,
+    mappings: HashMap<String, RawMapping>,
+    mapping_sets: Vec<RawMappingSet>,
+    finger_layers: HashMap<String, HashMap<String, RawMapping>>,
+    scroll: ScrollConfig,
+    script: ScriptConfig,
+    typing: TypingConfig,
+    scheduling: SchedConfig,
+    control: ControlConfig,
+    mirror: MirrorConfig,
+    virtual_devices: RawVirtualDevices,
+    external_mouse: Option<RawExternalMouse>,
+    touchpad_buttons: Option<RawTouchpadButtons>,
+    activation_mouse: Option<RawActivationMouse>,
+    warp: Option<RawWarp>,
+    tablet_mode: RawTabletMode,
+    session_lock: SessionLockConfig,
+    suspend: SuspendConfig,
+    sticky: StickyConfig,
+    hysteresis: HysteresisConfig,
+    watchdog: WatchdogConfig,
+}
+
+impl Default for RawConfig {
+    fn default() -> Self {
+        Self {
+            touchpad: None,
+            keyboard: None,
+            device_wait_ms: 10_000,
+            reconnect_max_delay_ms: 5_000,
+            grab_attempts: 8,
+            grab_retry_ms: 100,
+            virtual_keyboard_name: "my-virtual-keyboard".into(),
+            pointer_on_keyboard: false,
+            mouse_buttons: None,
+            xkb_layout: "us".into(),
+            mapping_keys: MappingKeys::Evdev,
+            xkb_variant: String::new(),
+            xkb_options: String::new(),
+            xkb_keymap: None,
+            debounce_ms: 0,
+            adaptive_debounce: AdaptiveDebounceConfig::default(),
+            chatter_ms: 0,
+            activate_when: None,
+            motion_threshold: 0.0,
+            deactivate_on: Trigger::ToolFinger,
+            persist_state: false,
+            start_enabled: true,
+            on_activate: None,
+            on_deactivate: None,
+            allow_commands: false,
+            mappings: HashMap::from([
+                (
+                    "KEY_F".into(),
+                    RawMapping::Plain(RawTarget::Button("BTN_LEFT".into())),
+                ),
+                (
+                    "KEY_D".into(),
+                    RawMapping::Plain(RawTarget::Button("BTN_RIGHT".into())),
+                ),
+            ]),
+            scroll: ScrollConfig::default(),
+            script: ScriptConfig::default(),
+            typing: TypingConfig::default(),
+            scheduling: SchedConfig::default(),
+            control: ControlConfig::default(),
+            mirror: MirrorConfig::default(),
+            virtual_devices: RawVirtualDevices::default(),
+            external_mouse: None,
+            touchpad_buttons: None,
+            activation_mouse: None,
+            warp: None,
+            tablet_mode: RawTabletMode::default(),
+            session_lock: SessionLockConfig::default(),
+            suspend: SuspendConfig::default(),
+            sticky: StickyConfig::default(),
+            hysteresis: HysteresisConfig::default(),
+            watchdog: WatchdogConfig::default(),
+            mapping_condition: MappingCondition::Active,
+            mapping_sets: Vec::new(),
+            finger_layers: HashMap::new(),
+        }
+    }
+}
+
+/// How the names on the left of `[mappings]` are read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum MappingKeys {
+    /// Evdev key names or codes, independent of the layout
+    Evdev,
+    /// XKB keysyms, resolved against the keymap to the key that types them
+    Keysym,
+}
+
+/// Device path, or an ordered list of candidate paths/globs
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RawPaths {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl RawPaths {
+    fn into_candidates(self, field: &str) -> Result<Vec<String>> {
+        let candidates = match self {
+            RawPaths::One(path) => vec![path],
+            RawPaths::Many(paths) => paths,
+        };
+        if candidates.is_empty() {
+            bail!("{}: at least one device path is required", field);
+        }
+        for candidate in &candidates {
+            if let Some(Err(err)) = VidPid::parse(candidate) {
+                return Err(err.context(field.to_string()));
+            }
+            if candidate.strip_prefix(NAME_PREFIX) == Some("") {
+                bail!("{}: '{}' needs a device name", field, candidate);
+            }
+        }
+        Ok(candidates)
+    }
+}
+
+/// `[external_mouse]`: a mouse grabbed and forwarded through its own proxy
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawExternalMouse {
+    device: RawPaths,
+    #[serde(default = "default_proxy_name")]
+    name: String,
+    #[serde(default)]
+    follow_activation: bool,
+    #[serde(default)]
+    mappings: HashMap<String, RawTarget>,
+}
+
+/// `[[mapping_sets]]`: a mapping table for the keyboards `device` selects
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawMappingSet {
+    device: RawPaths,
+    #[serde(default)]
+    condition: MappingCondition,
+    #[serde(default)]
+    mappings: HashMap<String, RawMapping>,
+}
+
+/// `[activation_mouse]`: a mouse whose motion turns the mode on, never grabbed
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawActivationMouse {
+    device: RawPaths,
+    #[serde(default = "default_mouse_threshold")]
+    threshold: u32,
+    #[serde(default = "default_mouse_idle_ms")]
+    idle_ms: u64,
+}
+
+/// `[virtual_devices]`: phys and input properties of the devices we create
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawVirtualDevices {
+    phys: Option<String>,
+    keyboard_properties: Vec<String>,
+    mouse_properties: Vec<String>,
+}
+
+impl Default for RawVirtualDevices {
+    fn default() -> Self {
+        Self {
+            phys: None,
+            keyboard_properties: Vec::new(),
+            mouse_properties: vec!["INPUT_PROP_POINTER".into()],
+        }
+    }
+}
+
+/// `[tablet_mode]`: what SW_TABLET_MODE does, and where to read it
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawTabletMode {
+    action: TabletAction,
+    device: Option<RawPaths>,
+}
+
+/// `[warp]`: grid pointer warping while `key` is held with the mode active
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawWarp {
+    key: String,
+    width: Option<i32>,
+    height: Option<i32>,
+}
+
+fn default_mouse_threshold() -> u32 {
+    10
+}
+
+fn default_mouse_idle_ms() -> u64 {
+    1000
+}
+
+fn default_proxy_name() -> String {
+    "touch-layer-mouse".into()
+}
+
+/// `[touchpad_buttons]`: the touchpad's own buttons, remapped while active
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawTouchpadButtons {
+    #[serde(default = "default_touchpad_proxy_name")]
+    name: String,
+    mappings: HashMap<String, RawTarget>,
+}
+
+fn default_touchpad_proxy_name() -> String {
+    "touch-layer-touchpad".into()
+}
+
+/// Mapping target: `"BTN_LEFT"`, `{ button = "BTN_LEFT", chatter_ms = 30 }`,
+/// `{ turbo = "BTN_LEFT", rate_hz = 10 }`, `{ double_click = "BTN_LEFT" }`,
+/// `{ triple_click = "BTN_LEFT", gap_ms = 40 }` or `{ wheel = 1, repeat_interval_ms = 30 }`
+/// A `[mappings]` value: a target, or targets that depend on the held modifiers
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RawMapping {
+    Plain(RawTarget),
+    Variant(RawVariant),
+    Variants(Vec<RawVariant>),
+}
+
+/// `{ target = ..., modifiers = { ctrl = "released" } }`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawVariant {
+    target: RawTarget,
+    #[serde(default)]
+    modifiers: HashMap<String, Requirement>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RawTarget {
+    Button(String),
+    ButtonTable(RawButton),
+    Turbo(RawTurbo),
+    DoubleClick(RawDoubleClick),
+    TripleClick(RawTripleClick),
+    Wheel(RawWheel),
+    Command(RawCommand),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawButton {
+    button: String,
+    chatter_ms: Option<u64>,
+    min_press_ms: Option<u64>,
+    /// Overrides typing.after_letter_ms for this key
+    after_letter_ms: Option<u64>,
+    trigger: Option<ClickTrigger>,
+    /// With trigger = "release": a longer hold is a cancel
+    max_hold_ms: Option<u64>,
+}
+
+/// Whether a button mapping clicks on key press (holding it down) or release
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ClickTrigger {
+    Press,
+    Release,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawTurbo {
+    turbo: String,
+    rate_hz: u32,
+    max_clicks: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawDoubleClick {
+    double_click: String,
+    gap_ms: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawTripleClick {
+    triple_click: String,
+    gap_ms: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawWheel {
+    wheel: i32,
+    /// Overrides scroll.step for this key
+    step: Option<i32>,
+    repeat_delay_ms: Option<u64>,
+    repeat_interval_ms: Option<u64>,
+    chatter_ms: Option<u64>,
+    min_press_ms: Option<u64>,
+    /// Scroll keys ignore typing.after_letter_ms unless they set this
+    after_letter_ms: Option<u64>,
+    /// Overrides scroll.natural for this key
+    natural: Option<bool>,
+}
+
+/// `{ command = "grim", cooldown_ms = 1000 }`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawCommand {
+    command: String,
+    cooldown_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScrollConfig {
+    /// Hold time before a scroll key starts repeating
+    pub repeat_delay_ms: u64,
+    /// Interval between repeated detents while held
+    pub repeat_interval_ms: u64,
+    /// Hi-res units (1/120 detent) per detent of a key's `wheel`
+    pub step: i32,
+    /// Flip every key-driven wheel step (and its inertia), as touchpad natural scrolling does
+    pub natural: bool,
+    pub acceleration: AccelConfig,
+    pub kinetic: KineticConfig,
+}
+
+impl Default for ScrollConfig {
+    fn default() -> Self {
+        Self {
+            repeat_delay_ms: 250,
+            repeat_interval_ms: 40,
+            step: HI_RES_PER_DETENT,
+            natural: false,
+            acceleration: AccelConfig::default(),
+            kinetic: KineticConfig::default(),
+        }
+    }
+}
+
+impl ScrollConfig {
+    /// The repeat ramp for a key repeating every `interval` when not accelerated
+    pub fn ramp(&self, interval: Duration) -> Option<Ramp> {
+        let accel = &self.acceleration;
+        accel.enabled.then(|| Ramp {
+            initial_hz: accel
+                .initial_rate_hz
+                .unwrap_or_else(|| (1000 / interval.as_millis().max(1)) as u32)
+                .min(accel.max_rate_hz),
+            max_hz: accel.max_rate_hz,
+            ramp: Duration::from_millis(accel.ramp_ms),
+            curve: accel.curve,
+        })
+    }
+}
+
+/// Scroll repeat speeding up while the key is held
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccelConfig {
+    pub enabled: bool,
+    /// Repeat rate when repeating starts; by default the key's repeat interval
+    pub initial_rate_hz: Option<u32>,
+    pub max_rate_hz: u32,
+    /// Hold time, after the repeat delay, until max_rate_hz is reached
+    pub ramp_ms: u64,
+    pub curve: Curve,
+}
+
+impl Default for AccelConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            initial_rate_hz: None,
+            max_rate_hz: 100,
+            ramp_ms: 2000,
+            curve: Curve::Quadratic,
+        }
+    }
+}
+
+/// Inertia applied after a scroll key is released
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KineticConfig {
+    pub enabled: bool,
+    /// Time for the velocity to decay to ~37% (exponential time constant)
+    pub time_constant_ms: u64,
+    /// Inertia stops once velocity falls below this (detents per second)
+    pub min_velocity: f64,
+    /// Velocity cap (detents per second)
+    pub max_velocity: f64,
+    /// Interval between emitted inertia frames
+    pub tick_ms: u64,
+}
+
+impl Default for KineticConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            time_constant_ms: 325,
+            min_velocity: 2.0,
+            max_velocity: 60.0,
+            tick_ms: 16,
+        }
+    }
+}
+
+/// Debounce scaled by how fast the finger moved just before lifting
+///
+/// Slow, precise motion gets `min_ms` so typing can start right away; a fast
+/// flick gets `max_ms` to bridge the lift between strokes. `min_ms == max_ms`
+/// is a static debounce.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdaptiveDebounceConfig {
+    /// Replaces `debounce_ms`
+    pub enabled: bool,
+    pub min_ms: u64,
+    pub max_ms: u64,
+    /// How much recent motion the speed is averaged over
+    pub window_ms: u64,
+    /// Speeds (|dx| + |dy| per second) at or below which `min_ms` applies,
+    /// and at or above which `max_ms` does; linear in between
+    pub slow: f64,
+    pub fast: f64,
+}
+
+impl Default for AdaptiveDebounceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_ms: 0,
+            max_ms: 300,
+            window_ms: 200,
+            slow: 200.0,
+            fast: 2000.0,
+        }
+    }
+}
+
+/// Optional rhai script consulted on every key press
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScriptConfig {
+    /// Script defining `route(event)`; relative paths are against the config file
+    pub path: Option<PathBuf>,
+    /// Per-call operation budget; a script exceeding it falls back to the mappings
+    pub max_operations: u64,
+}
+
+impl Default for ScriptConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            max_operations: 10_000,
+        }
+    }
+}
+
+/// Deactivate when ordinary typing shows up while the mode is active
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TypingConfig {
+    /// Unmapped, non-modifier presses that count as typing; 0 turns this off
+    pub presses: usize,
+    /// Window those presses must fall within
+    pub window_ms: u64,
+    /// How long motion is ignored afterwards
+    pub cooldown_ms: u64,
+    /// A click key pressed this soon after a passed-through letter types
+    /// itself instead; 0 turns this off
+    pub after_letter_ms: u64,
+}
+
+impl Default for TypingConfig {
+    fn default() -> Self {
+        Self {
+            presses: 0,
+            window_ms: 1000,
+            cooldown_ms: 0,
+            after_letter_ms: 0,
+        }
+    }
+}
+
+/// Scheduling for the keyboard thread, to keep forwarding snappy under load
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchedConfig {
+    pub policy: SchedPolicy,
+    /// SCHED_FIFO priority, 1-99
+    pub priority: i32,
+    /// Nice value for policy = "nice", -20-19
+    pub nice: i32,
+}
+
+impl Default for SchedConfig {
+    fn default() -> Self {
+        Self {
+            policy: SchedPolicy::Normal,
+            priority: 10,
+            nice: -10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SchedPolicy {
+    Normal,
+    Fifo,
+    Nice,
+}
+
+/// Control socket (`touchpad-remap monitor` connects to it)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ControlConfig {
+    pub enabled: bool,
+    /// Group allowed to connect; without one, only the daemon's own user can
+    pub group: Option<String>,
+    /// Accept `inject` commands, synthetic input on our virtual devices
+    pub inject: bool,
+    /// Injected commands allowed per second, across all clients
+    pub inject_rate: u32,
+    /// Accept `map`, `unmap` and `save`, which edit mappings and the config file
+    pub map: bool,
+}
+
+impl Default for ControlConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            group: None,
+            inject: false,
+            inject_rate: 20,
+            map: false,
+        }
+    }
+}
+
+/// Capability classes copied from the keyboard to the virtual keyboard
+///
+/// Keys, relative axes and switches always are; each of these can be turned
+/// off if it confuses a compositor.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MirrorConfig {
+    /// Lock indicators, so LED writes to the virtual keyboard succeed
+    pub leds: bool,
+    /// MSC_SCAN and friends
+    pub misc: bool,
+    /// Absolute axes with their ranges, e.g. volume sliders
+    pub abs: bool,
+    /// EV_REP with the keyboard's delay and rate; the kernel then generates
+    /// repeats itself and forwarded ones are dropped
+    pub repeat: bool,
+}
+
+impl Default for MirrorConfig {
+    fn default() -> Self {
+        Self {
+            leds: true,
+            misc: true,
+            abs: true,
+            repeat: true,
+        }
+    }
+}
+
+/// `[session_lock]`: what a locked logind session does to the layer
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionLockConfig {
+    /// Follow the session's Lock/Unlock signals and LockedHint
+    pub enabled: bool,
+    /// Also give the keyboard back while locked, so the lock screen reads it
+    pub ungrab: bool,
+}
+
+impl Default for SessionLockConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ungrab: false,
+        }
+    }
+}
+
+/// `[suspend]`: letting go of the keyboard while the system sleeps
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SuspendConfig {
+    /// Ungrab before sleep and re-find and re-grab the keyboard after resume
+    pub enabled: bool,
+    /// Longest we hold up suspend waiting for the keyboard to be released
+    pub max_delay_ms: u64,
+}
+
+impl Default for SuspendConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_delay_ms: 1000,
+        }
+    }
+}
+
+/// `[sticky]`: latching the layer on with a swipe up, off with a swipe down
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StickyConfig {
+    pub enabled: bool,
+    /// Fingers a latching swipe uses; libinput only reports swipes of 3 or more
+    pub fingers: u32,
+    /// Least vertical travel, in libinput's accelerated units
+    pub min_distance: f64,
+    /// Longest a swipe may take to count
+    pub max_duration_ms: u64,
+}
+
+impl Default for StickyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            fingers: 3,
+            min_distance: 100.0,
+            max_duration_ms: 500,
+        }
+    }
+}
+
+/// `[hysteresis]`: keeping a hovering finger from flapping the mode
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HysteresisConfig {
+    /// After a deactivation, reactivation waits this long...
+    pub reactivate_after_ms: u64,
+    /// ...unless one motion event moves at least this far (|dx| + |dy|)
+    pub reactivate_motion: f64,
+    /// After an activation, deactivation waits this long
+    pub min_active_ms: u64,
+}
+
+impl Default for HysteresisConfig {
+    fn default() -> Self {
+        Self {
+            reactivate_after_ms: 30,
+            reactivate_motion: 8.0,
+            min_active_ms: 30,
+        }
+    }
+}
+
+/// `[watchdog]`: ungrab and abort if the keyboard loop stalls with input waiting
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WatchdogConfig {
+    pub enabled: bool,
+    /// How long the loop may go without a beat while keys are waiting
+    pub stall_ms: u64,
+    /// Longest a routed button stays down with none of its keys down; 0 is off.
+    /// Applies whether or not `enabled` is set.
+    pub max_button_hold_ms: u64,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            stall_ms: 3000,
+            max_button_hold_ms: 0,
+        }
+    }
+}
+
+impl WatchdogConfig {
+    pub fn max_button_hold(&self) -> Option<Duration> {
+        (self.max_button_hold_ms > 0).then(|| Duration::from_millis(self.max_button_hold_ms))
+    }
+}
+
+/// What a mapped key turns into while the mode is active
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Button(Key),
+    /// Another key, typed on the virtual keyboard
+    Key(Key),
+    /// Full click when the key comes back up; a press never holds the button
+    OnRelease(ReleaseTarget),
+    Turbo(TurboTarget),
+    Clicks(ClickTarget),
+    Wheel(WheelTarget),
+    Command(CommandTarget),
+}
+
+impl Target {
+    /// The mouse button it clicks, if any
+    pub fn button(&self) -> Option<Key> {
+        match self {
+            Target::Button(button)
+            | Target::OnRelease(ReleaseTarget { button, .. })
+            | Target::Turbo(TurboTarget { button, .. })
+            | Target::Clicks(ClickTarget { button, .. }) => Some(*button),
+            Target::Key(_) | Target::Wheel(_) | Target::Command(_) => None,
+        }
+    }
+}
+
+/// Short form, as `ctl list-map` and `list-presets` show it
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Target::Button(key) | Target::Key(key) => write!(f, "{:?}", key),
+            Target::OnRelease(release) => write!(f, "{:?} on release", release.button),
+            Target::Turbo(turbo) => write!(f, "turbo {:?}", turbo.button),
+            Target::Clicks(clicks) => write!(f, "{:?} x{}", clicks.button, clicks.count),
+            Target::Wheel(wheel) => write!(f, "wheel {:+}", wheel.detents),
+            Target::Command(_) => f.write_str("command"),
+        }
+    }
+}
+
+/// Several full clicks from one press (double/triple click)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClickTarget {
+    pub button: Key,
+    pub count: u32,
+    /// Between every press and release frame of the sequence
+    pub gap: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReleaseTarget {
+    pub button: Key,
+    /// Holds longer than this click nothing
+    pub max_hold: Option<Duration>,
+}
+
+/// Autofire key: full clicks at a fixed rate while held
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TurboTarget {
+    pub button: Key,
+    /// Time from one press to the next; the button is down for the first half
+    pub interval: Duration,
+    /// Stop after this many clicks even if the key is still held
+    pub max_clicks: Option<u32>,
+}
+
+/// A shell command run on press (`sh -c`); the key itself is swallowed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandTarget {
+    /// Leaked, so Target stays Copy; a config is loaded once per process
+    pub command: &'static str,
+    /// Presses closer together than this run it only once
+    pub cooldown: Duration,
+}
+
+/// Scroll key: one step on press, then repeating on our own timer while held
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WheelTarget {
+    /// Vertical wheel detents per step, positive scrolls up
+    pub detents: i32,
+    /// Hi-res units per detent, 120 for whole detents
+    pub step: i32,
+    pub repeat_delay: Duration,
+    pub repeat_interval: Duration,
+    /// Repeat speeding up with hold time, replacing the fixed interval
+    pub acceleration: Option<Ramp>,
+}
+
+impl WheelTarget {
+    /// Hi-res units one step emits, signed
+    pub fn hi_res(&self) -> i32 {
+        self.detents * self.step
+    }
+
+    /// One step's movement in (possibly fractional) detents, for inertia
+    pub fn distance(&self) -> f64 {
+        f64::from(self.hi_res()) / f64::from(HI_RES_PER_DETENT)
+    }
+}
+
+/// A grabbed external mouse, remapped on a virtual proxy that copies it
+#[derive(Debug, Clone)]
+pub struct ExternalMouse {
+    /// Candidate paths/globs, tried in order
+    pub candidates: Vec<String>,
+    /// Name of the proxy device
+    pub name: String,
+    /// Remap only while the layer is active, as keyboard mappings do; otherwise always
+    pub follow_activation: bool,
+    /// Source buttons of this mouse only; keyboard mappings never apply to it
+    pub mappings: HashMap<Key, Target>,
+    pub chatter: HashMap<Key, Duration>,
+    pub min_press: HashMap<Key, Duration>,
+}
+
+impl ExternalMouse {
+    fn from_raw(raw: RawExternalMouse, scroll: &ScrollConfig) -> Result<Self> {
+        let (mappings, chatter, min_press) =
+            pointer_mappings("external_mouse", raw.mappings, scroll)?;
+        Ok(Self {
+            candidates: raw.device.into_candidates("external_mouse.device")?,
+            name: seat::suffixed(&raw.name),
+            follow_activation: raw.follow_activation,
+            mappings,
+            chatter,
+            min_press,
+        })
+    }
+
+    /// The touchpad behind a proxy of its own, remapped only while active
+    fn for_touchpad(
+        raw: RawTouchpadButtons,
+        candidates: &[String],
+        scroll: &ScrollConfig,
+    ) -> Result<Self> {
+        let (mappings, chatter, min_press) =
+            pointer_mappings("touchpad_buttons", raw.mappings, scroll)?;
+        if mappings.is_empty() {
+            bail!("touchpad_buttons.mappings: map at least one button");
+        }
+        Ok(Self {
+            candidates: candidates.to_vec(),
+            name: seat::suffixed(&raw.name),
+            follow_activation: true,
+            mappings,
+            chatter,
+            min_press,
+        })
+    }
+}
+
+/// Mappings, chatter windows and minimum presses of a grabbed pointer's buttons
+type PointerMappings = (
+    HashMap<Key, Target>,
+    HashMap<Key, Duration>,
+    HashMap<Key, Duration>,
+);
+
+fn pointer_mappings(
+    section: &str,
+    raw: HashMap<String, RawTarget>,
+    scroll: &ScrollConfig,
+) -> Result<PointerMappings> {
+    let mut mappings = HashMap::new();
+    let mut chatter = HashMap::new();
+    let mut min_press = HashMap::new();
+    let mut names: Vec<_> = raw.into_iter().collect();
+    names.sort_by(|a, b| a.0.cmp(&b.0));
+    for (name, target) in names {
+        let field = format!("{}.mappings.{}", section, name);
+        let key = keys::resolve(&name).with_context(|| field.clone())?;
+        if mappings.contains_key(&key) {
+            bail!("{}: {:?} is mapped twice", field, key);
+        }
+        // chatter_ms is for keyboard switches; mouse buttons set their own
+        let parsed = parse_target(&field, target, 0, scroll)?;
+        if let Target::Key(target) = parsed.target {
+            bail!(
+                "{}: {:?} is a keyboard key; pointer mappings only take buttons and wheels",
+                field,
+                target
+            );
+        }
+        if let Some(window) = parsed.chatter {
+            chatter.insert(key, window);
+        }
+        if let Some(min) = parsed.min_press {
+            min_press.insert(key, min);
+        }
+        mappings.insert(key, parsed.target);
+    }
+    Ok((mappings, chatter, min_press))
+}
+
+/// How our virtual devices present themselves to udev, libinput and compositors
+#[derive(Debug, Clone)]
+pub struct VirtualDevicesConfig {
+    /// Phys of the virtual keyboard; otherwise the source keyboard's plus `PHYS_SUFFIX`
+    pub phys: Option<String>,
+    pub keyboard_properties: Vec<PropType>,
+    pub mouse_properties: Vec<PropType>,
+}
+
+/// Appended to a source device's phys for the virtual device standing in for it
+pub const PHYS_SUFFIX: &str = "/touch-layer";
+
+impl VirtualDevicesConfig {
+    /// Phys of the virtual keyboard copying a keyboard with phys `source`
+    pub fn keyboard_phys(&self, source: Option<&str>) -> String {
+        match &self.phys {
+            Some(phys) => phys.clone(),
+            None => match source {
+                Some(source) => format!("{}{}", source, PHYS_SUFFIX),
+                None => "touch-layer".into(),
+            },
+        }
+    }
+}
+
+/// `INPUT_PROP_POINTER` or `POINTER`
+fn parse_property(field: &str, name: &str) -> Result<PropType> {
+    name.strip_prefix("INPUT_PROP_")
+        .unwrap_or(name)
+        .parse()
+        .map_err(|_| {
+            anyhow!(
+                "{}: unknown input property '{}' (e.g. INPUT_PROP_POINTER, INPUT_PROP_DIRECT)",
+                field,
+                name
+            )
+        })
+}
+
+/// Reaction to a convertible folding into a tablet
+#[derive(Debug, Clone)]
+pub struct TabletModeConfig {
+    pub action: TabletAction,
+    /// Switch device candidates; empty means the keyboard, else the first node with the switch
+    pub candidates: Vec<String>,
+}
+
+/// Grid warping: `key` held turns U I O / J K L / M , . into pointer jumps
+#[derive(Debug, Clone, Copy)]
+pub struct WarpConfig {
+    pub key: Key,
+    /// Range of the absolute device's ABS_X and ABS_Y
+    pub width: i32,
+    pub height: i32,
+}
+
+/// A mouse counted as an activation source (`mouse_motion`)
+#[derive(Debug, Clone)]
+pub struct ActivationMouse {
+    /// Candidate paths/globs, tried in order
+    pub candidates: Vec<String>,
+    /// Smallest |dx| + |dy| of one frame that counts, in mouse counts
+    pub threshold: u32,
+    /// How long after its last motion the mouse stops counting
+    pub idle: Duration,
+}
+
+/// Where a setting came from, so errors can say what to fix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    File(PathBuf),
+    /// Name of the TOUCH_LAYER_* variable
+    Env(String),
+    /// A built-in preset, by name
+    Preset(&'static str),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "built-in default"),
+            Source::File(path) => write!(f, "config file {}", path.display()),
+            Source::Env(var) => write!(f, "environment variable {}", var),
+            Source::Preset(name) => write!(f, "preset {}", name),
+        }
+    }
+}
+
+/// A value set by the file or the environment, for the startup printout
+#[derive(Debug, Clone)]
+pub struct Setting {
+    /// Dotted key, e.g. `scroll.natural` or `mappings.KEY_F`
+    pub key: String,
+    pub value: String,
+    pub source: Source,
+}
+
+/// Validated configuration
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Candidate touchpad paths/globs, tried in order
+    pub touchpad: Vec<String>,
+    pub touchpad_source: Source,
+    /// Candidate keyboard paths/globs, tried in order
+    pub keyboard: Vec<String>,
+    pub keyboard_source: Source,
+    /// How long a `name:` candidate matching nothing is waited for at startup
+    pub device_wait: Duration,
+    /// Longest pause between attempts to get back a device that went away
+    pub reconnect_max_delay: Duration,
+    /// Grabs tried at startup while another process holds the device (EBUSY)
+    pub grab_attempts: u32,
+    /// Pause before the second grab attempt, doubling up to `MAX_GRAB_RETRY_DELAY`
+    pub grab_retry: Duration,
+    pub virtual_keyboard_name: String,
+    /// Declare buttons/axes on the virtual keyboard instead of creating a separate mouse
+    pub pointer_on_keyboard: bool,
+    /// Explicit `mouse_buttons`; otherwise buttons are declared as mappings need them
+    pub declared_buttons: Option<Vec<Key>>,
+    /// Layout announced in the keymap of the Wayland backend's keyboard
+    #[cfg_attr(not(feature = "wayland"), allow(dead_code))]
+    pub xkb_layout: String,
+    /// Keysym mapping sources and the keys they resolved to, in config order
+    pub keysyms: Vec<(String, Key)>,
+    pub debounce: Duration,
+    pub adaptive_debounce: AdaptiveDebounceConfig,
+    /// Which touchpad signals turn the mode on
+    pub activate_when: Condition,
+    /// Smallest |dx| + |dy| of a libinput motion event that counts
+    pub motion_threshold: f64,
+    /// Which touchpad signal counts as the finger lifting
+    pub deactivate_on: Trigger,
+    /// Restore the enabled flag and sticky latch saved by the previous run
+    pub persist_state: bool,
+    /// Off: start with remapping disabled until SIGUSR1 turns it on
+    pub start_enabled: bool,
+    /// Shell commands run (via sh -c) when the mode turns on / off
+    pub on_activate: Option<String>,
+    pub on_deactivate: Option<String>,
+    /// When `mappings` apply
+    pub mapping_condition: MappingCondition,
+    pub mappings: HashMap<Key, Target>,
+    /// Mapped keys whose target depends on the held modifiers
+    pub conditional: HashMap<Key, Vec<Variant>>,
+    /// Mapped keys whose release waits this long for a bouncing re-press
+    pub chatter: HashMap<Key, Duration>,
+    /// Mapped keys whose presses shorter than this are dropped (palm grazes)
+    pub min_press: HashMap<Key, Duration>,
+    /// Mapped keys with their own typing.after_letter_ms; zero is off
+    pub after_letter: HashMap<Key, Duration>,
+    /// Chatter window of mappings that set none, kept for ones added at runtime
+    pub chatter_ms: u64,
+    /// Tables that replace the three above on the keyboards they select
+    pub mapping_sets: Vec<MappingSet>,
+    /// Tables over `mappings` by the fingers on the touchpad; 3 stands for 3 or more
+    pub finger_layers: BTreeMap<usize, MappingTable>,
+    pub scroll: ScrollConfig,
+    pub script: ScriptConfig,
+    pub typing: TypingConfig,
+    pub scheduling: SchedConfig,
+    pub control: ControlConfig,
+    pub mirror: MirrorConfig,
+    pub virtual_devices: VirtualDevicesConfig,
+    pub external_mouse: Option<ExternalMouse>,
+    /// The touchpad grabbed behind a proxy, its buttons remapped while active
+    pub touchpad_buttons: Option<ExternalMouse>,
+    pub activation_mouse: Option<ActivationMouse>,
+    pub warp: Option<WarpConfig>,
+    pub tablet_mode: TabletModeConfig,
+    pub session_lock: SessionLockConfig,
+    pub suspend: SuspendConfig,
+    pub sticky: StickyConfig,
+    pub hysteresis: HysteresisConfig,
+    pub watchdog: WatchdogConfig,
+    /// Everything not at its built-in default, with where it came from
+    pub settings: Vec<Setting>,
+    /// Config files merged, lowest layer first; empty with built-in defaults
+    pub layers: Vec<PathBuf>,
+    /// The built-in preset below them, from `--preset` or `preset = "NAME"`
+    pub preset: Option<&'static str>,
+}
+
+/// When a mapping table applies, against the touchpad mode
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MappingCondition {
+    /// While the touchpad is in use
+    #[default]
+    Active,
+    /// Except while the touchpad is in use, e.g. home-row clicks for a trackball
+    Inactive,
+    Always,
+}
+
+impl MappingCondition {
+    /// Whether mappings apply; never while not `engaged` (disabled or paused)
+    pub fn applies(self, engaged: bool, active: bool) -> bool {
+        engaged
+            && match self {
+                MappingCondition::Active => active,
+                MappingCondition::Inactive => !active,
+                MappingCondition::Always => true,
+            }
+    }
+}
+
+/// Mapped keys with their bounce and palm filters
+#[derive(Debug, Clone, Default)]
+pub struct MappingTable {
+    pub condition: MappingCondition,
+    pub mappings: HashMap<Key, Target>,
+    pub conditional: HashMap<Key, Vec<Variant>>,
+    pub chatter: HashMap<Key, Duration>,
+    pub min_press: HashMap<Key, Duration>,
+    pub after_letter: HashMap<Key, Duration>,
+}
+
+/// One of a mapping's targets, for the modifier states `when` matches
+#[derive(Debug, Clone, Copy)]
+pub struct Variant {
+    pub when: modifiers::Condition,
+    pub target: Target,
+}
+
+/// A `[[mapping_sets]]` entry: `table` applies on the keyboards `candidates` select
+#[derive(Debug, Clone)]
+pub struct MappingSet {
+    pub candidates: Vec<String>,
+    pub table: MappingTable,
+}
+
+/// Buttons always declared on the virtual mouse
+pub const MOUSE_BUTTONS: [Key; 3] = [Key::BTN_LEFT, Key::BTN_RIGHT, Key::BTN_MIDDLE];
+
+/// Back/forward style buttons, declared only when a mapping targets them
+/// (BTN_SIDE 0x113, BTN_EXTRA 0x114, BTN_FORWARD 0x115, BTN_BACK 0x116)
+pub const EXTRA_MOUSE_BUTTONS: [Key; 4] = [
+    Key::BTN_SIDE,
+    Key::BTN_EXTRA,
+    Key::BTN_FORWARD,
+    Key::BTN_BACK,
+];
+
+impl Config {
+    /// Load and merge every config layer present (see `layers`), lowest first
+    ///
+    /// `extra` is the `--config` file, which must exist. TOUCH_LAYER_* overrides
+    /// (see `overrides`) are applied on top of whatever was found, and the
+    /// preset, if one is chosen (see `presets`), goes below it all.
+    pub fn load(extra: Option<&Path>) -> Result<Self> {
+        Self::read(extra).map_err(|err| exit::tag(Class::Config, err))
+    }
+
+    fn read(extra: Option<&Path>) -> Result<Self> {
+        let overrides = overrides::from_env()?;
+        let mut files = Vec::new();
+        for path in layers::paths(extra) {
+            if !path.exists() && Some(path.as_path()) != extra {
+                continue;
+            }
+            let text = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read config {}", path.display()))?;
+            files.push((path, text));
+        }
+        Self::build(&files, &overrides, presets::chosen())
+    }
+
+    /// TOUCH_LAYER_CONFIG, or the default /etc path
+    pub fn path() -> PathBuf {
+        std::env::var_os("TOUCH_LAYER_CONFIG")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH))
+    }
+
+    /// Validate `text` as if it were the contents of `path`, alone
+    ///
+    /// Older schema versions are migrated first, with a warning per change.
+    pub fn parse(text: &str, path: &Path) -> Result<Self> {
+        Self::build(
+            &[(path.to_path_buf(), text.to_string())],
+            &[],
+            presets::chosen(),
+        )
+        .map_err(|err| exit::tag(Class::Config, err))
+    }
+
+    /// A built-in preset alone, validated as any config file is
+    pub fn preset(preset: &'static Preset) -> Result<Self> {
+        Self::build(&[], &[], Some(preset)).map_err(|err| exit::tag(Class::Config, err))
+    }
+
+    /// Merge the files, lowest layer first, with the overrides applied on top
+    ///
+    /// `preset` is `--preset`; without it, a `preset = "NAME"` in the merged
+    /// layers picks one. Either way it goes in below the first file.
+    fn build(
+        files: &[(PathBuf, String)],
+        overrides: &[Override],
+        preset: Option<&'static Preset>,
+    ) -> Result<Self> {
+        let mut parsed = Vec::new();
+        for (path, text) in files {
+            let migrated = migrate::migrate(text)
+                .with_context(|| format!("Failed to parse config {}", path.display()))?;
+            for note in &migrated.notes {
+                eprintln!("⚠️  Config {}: {}", path.display(), note);
+            }
+            if !migrated.notes.is_empty() {
+                eprintln!("   Run `touchpad-remap migrate-config` to update the file");
+            }
+            let mut layer = migrated.doc;
+            layer.remove("version");
+            parsed.push((path, layer));
+        }
+
+        let (mut doc, mut settings) = merge_layers(None, &parsed, overrides)?;
+        let preset = match (preset, doc.get("preset")) {
+            (Some(preset), _) => Some(preset),
+            (None, None) => None,
+            (None, Some(item)) => Some(presets::find(
+                item.as_str().context("preset: expected a preset name")?,
+            )?),
+        };
+        if let Some(preset) = preset {
+            // Again with the preset at the bottom, so every file overrides it
+            (doc, settings) = merge_layers(Some(preset), &parsed, overrides)?;
+        }
+        doc.remove("preset");
+
+        let mut described = match preset {
+            None if files.is_empty() => "built-in defaults".to_string(),
+            Some(preset) if files.is_empty() => format!("preset {}", preset.name),
+            _ => {
+                let paths: Vec<String> =
+                    files.iter().map(|(p, _)| p.display().to_string()).collect();
+                format!("config {}", paths.join(" + "))
+            }
+        };
+        if let (false, Some(preset)) = (files.is_empty(), preset) {
+            described = format!("{} on preset {}", described, preset.name);
+        }
+        if !overrides.is_empty() {
+            let vars: Vec<&str> = overrides.iter().map(|o| o.var.as_str()).collect();
+            described = format!("{} with {}", described, vars.join(", "));
+        }
+
+        let raw: RawConfig = toml::from_str(&doc.to_string())
+            .with_context(|| format!("Failed to parse {}", described))?;
+        let layers = files.iter().map(|(path, _)| path.clone()).collect();
+        let mut config = Self::from_raw(raw, layers, settings)
+            .with_context(|| format!("Invalid {}", described))?;
+        config.preset = preset.map(|preset| preset.name);
+        Ok(config)
+    }
+
+    fn from_raw(raw: RawConfig, layers: Vec<PathBuf>, settings: Vec<Setting>) -> Result<Self> {
+        let source_of = |key: &str| {
+            settings
+                .iter()
+                .find(|s| s.key == key)
+                .map_or(Source::Default, |s| s.source.clone())
+        };
+        let touchpad_source = source_of("touchpad");
+        let keyboard_source = source_of("keyboard");
+
+        let keymap = match raw.mapping_keys {
+            MappingKeys::Evdev => None,
+            MappingKeys::Keysym => {
+                let file = raw.xkb_keymap.as_ref().map(|path| {
+                    match (path.is_relative(), source_of("xkb_keymap")) {
+                        (true, Source::File(config)) => config
+                            .parent()
+                            .map_or_else(|| path.clone(), |dir| dir.join(path)),
+                        _ => path.clone(),
+                    }
+                });
+                Some(keysym_resolver(
+                    file.as_deref(),
+                    &raw.xkb_layout,
+                    &raw.xkb_variant,
+                    &raw.xkb_options,
+                )?)
+            }
+        };
+
+        let mut keysyms = Vec::new();
+        let MappingTable {
+            mappings,
+            conditional,
+            mut chatter,
+            mut min_press,
+            mut after_letter,
+            ..
+        } = parse_mappings(
+            raw.mappings,
+            keymap.as_ref(),
+            raw.chatter_ms,
+            &raw.scroll,
+            &mut keysyms,
+        )?;
+        let mut mapping_sets = Vec::new();
+        for (i, set) in raw.mapping_sets.into_iter().enumerate() {
+            let field = format!("mapping_sets[{}]", i);
+            let candidates = set.device.into_candidates(&format!("{}.device", field))?;
+            let mut table = parse_mappings(
+                set.mappings,
+                keymap.as_ref(),
+                raw.chatter_ms,
+                &raw.scroll,
+                &mut keysyms,
+            )
+            .with_context(|| field.clone())?;
+            table.condition = set.condition;
+            mapping_sets.push(MappingSet { candidates, table });
+        }
+        let mut raw_layers: Vec<_> = raw.finger_layers.into_iter().collect();
+        raw_layers.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut finger_layers = BTreeMap::new();
+        for (name, layer) in raw_layers {
+            let fingers = match name.as_str() {
+                "1" => 1,
+                "2" => 2,
+                "3+" => 3,
+                _ => bail!("finger_layers.{}: layers are \"1\", \"2\" and \"3+\"", name),
+            };
+            let table = parse_mappings(
+                layer,
+                keymap.as_ref(),
+                raw.chatter_ms,
+                &raw.scroll,
+                &mut keysyms,
+            )
+            .with_context(|| format!("finger_layers.{}", name))?;
+            finger_layers.insert(fingers, table);
+        }
+        // The filters are the key's: [mappings] decides, then the layer for fewer fingers
+        for table in finger_layers.values_mut() {
+            for (key, window) in std::mem::take(&mut table.chatter) {
+                chatter.entry(key).or_insert(window);
+            }
+            for (key, min) in std::mem::take(&mut table.min_press) {
+                min_press.entry(key).or_insert(min);
+            }
+            for (key, window) in std::mem::take(&mut table.after_letter) {
+                after_letter.entry(key).or_insert(window);
+            }
+        }
+        if !finger_layers.is_empty() && mappings.is_empty() && conditional.is_empty() {
+            bail!("finger_layers needs a default layer: [mappings] applies to finger counts without a layer, and must not be empty");
+        }
+
+        let declared_buttons = match raw.mouse_buttons {
+            Some(names) => Some(
+                names
+                    .iter()
+                    .map(|name| parse_button("mouse_buttons", name))
+                    .collect::<Result<Vec<_>>>()?,
+            ),
+            None => None,
+        };
+        if let Some(declared) = &declared_buttons {
+            let top = MappingTable {
+                mappings: mappings.clone(),
+                conditional: conditional.clone(),
+                ..MappingTable::default()
+            };
+            let mut undeclared: Vec<(&Key, Key)> = top
+                .targets()
+                .chain(mapping_sets.iter().flat_map(|set| set.table.targets()))
+                .chain(finger_layers.values().flat_map(MappingTable::targets))
+                .filter_map(|(key, target)| Some((key, target.button()?)))
+                .filter(|(_, button)| !declared.contains(button))
+                .collect();
+            undeclared.sort_by_key(|(key, _)| key.code());
+            if let Some((key, button)) = undeclared.first() {
+                bail!(
+                    "{:?}: {:?} is not in mouse_buttons; add it there, or remove mouse_buttons to declare mapped buttons automatically",
+                    key,
+                    button
+                );
+            }
+        }
+
+        let activation_mouse = match raw.activation_mouse {
+            Some(mouse) => {
+                if mouse.idle_ms == 0 {
+                    bail!("activation_mouse: idle_ms must be positive");
+                }
+                Some(ActivationMouse {
+                    candidates: mouse.device.into_candidates("activation_mouse.device")?,
+                    threshold: mouse.threshold,
+                    idle: Duration::from_millis(mouse.idle_ms),
+                })
+            }
+            None => None,
+        };
+        if !(raw.motion_threshold >= 0.0 && raw.motion_threshold.is_finite()) {
+            bail!("motion_threshold must be a non-negative number");
+        }
+
+        let activate_when = match &raw.activate_when {
+            Some(text) => text
+                .parse()
+                .with_context(|| format!("activate_when = \"{}\"", text))?,
+            // A configured mouse counts unless activate_when says otherwise
+            None if activation_mouse.is_some() => Condition::Or(
+                Box::default(),
+                Box::new(Condition::Signal(Signal::MouseMotion)),
+            ),
+            None => Condition::default(),
+        };
+        if activation_mouse.is_some() && !activate_when.mentions(Signal::MouseMotion) {
+            eprintln!("⚠️  activation_mouse is set, but activate_when never reads mouse_motion");
+        }
+
+        let warp = match raw.warp {
+            Some(warp) => {
+                let key = keys::resolve(&warp.key).context("warp.key")?;
+                if warp::GRID_KEYS.contains(&key) {
+                    bail!("warp.key: {:?} is one of the grid keys", key);
+                }
+                let size = |field: &str, value: Option<i32>| match value {
+                    None => Ok(warp::NORMALIZED_RANGE),
+                    Some(value) if (1..=warp::NORMALIZED_RANGE).contains(&value) => Ok(value),
+                    Some(_) => bail!(
+                        "warp.{} must be between 1 and {}",
+                        field,
+                        warp::NORMALIZED_RANGE
+                    ),
+                };
+                Some(WarpConfig {
+                    key,
+                    width: size("width", warp.width)?,
+                    height: size("height", warp.height)?,
+                })
+            }
+            None => None,
+        };
+        // Its own namespace: a click mapping on the key would never fire
+        if let Some(warp) = &warp {
+            let tables = std::iter::once(("[mappings]".to_string(), &mappings, &conditional))
+                .chain(mapping_sets.iter().enumerate().map(|(i, set)| {
+                    (
+                        format!("mapping_sets[{}]", i),
+                        &set.table.mappings,
+                        &set.table.conditional,
+                    )
+                }))
+                .chain(finger_layers.iter().map(|(&fingers, table)| {
+                    (
+                        format!("finger_layers.{}", layer_name(fingers)),
+                        &table.mappings,
+                        &table.conditional,
+                    )
+                }));
+            for (name, mappings, conditional) in tables {
+                if mappings.contains_key(&warp.key) || conditional.contains_key(&warp.key) {
+                    bail!("warp.key {:?} is also mapped in {}", warp.key, name);
+                }
+            }
+        }
+
+        if raw.suspend.max_delay_ms == 0 {
+            bail!("suspend.max_delay_ms must be positive");
+        }
+        if raw.control.inject && !raw.control.enabled {
+            bail!("control.inject needs the control socket (control.enabled)");
+        }
+        if raw.control.map && !raw.control.enabled {
+            bail!("control.map needs the control socket (control.enabled)");
+        }
+        if raw.control.inject_rate == 0 {
+            bail!("control.inject_rate must be positive");
+        }
+        let sticky = &raw.sticky;
+        if !(3..=5).contains(&sticky.fingers) {
+            bail!("sticky.fingers must be 3, 4 or 5");
+        }
+        if !(sticky.min_distance > 0.0 && sticky.min_distance.is_finite()) {
+            bail!("sticky.min_distance must be positive");
+        }
+        if sticky.max_duration_ms == 0 {
+            bail!("sticky.max_duration_ms must be positive");
+        }
+        let hysteresis = &raw.hysteresis;
+        if !(hysteresis.reactivate_motion >= 0.0 && hysteresis.reactivate_motion.is_finite()) {
+            bail!("hysteresis.reactivate_motion must be 0 or more");
+        }
+        if hysteresis.reactivate_after_ms > 1000 || hysteresis.min_active_ms > 1000 {
+            bail!("hysteresis windows are at most 1000ms");
+        }
+        if raw.watchdog.stall_ms < 500 {
+            bail!("watchdog.stall_ms must be at least 500");
+        }
+        let hold = raw.watchdog.max_button_hold_ms;
+        if hold != 0 && !(MIN_BUTTON_HOLD_MS..=MAX_BUTTON_HOLD_MS).contains(&hold) {
+            bail!(
+                "watchdog.max_button_hold_ms must be 0 (off) or {} to {}",
+                MIN_BUTTON_HOLD_MS,
+                MAX_BUTTON_HOLD_MS
+            );
+        }
+        if !(1..=MAX_GRAB_ATTEMPTS).contains(&raw.grab_attempts) {
+            bail!("grab_attempts must be 1 to {}", MAX_GRAB_ATTEMPTS);
+        }
+        if raw.grab_retry_ms == 0 || raw.grab_retry_ms > MAX_GRAB_RETRY_DELAY.as_millis() as u64 {
+            bail!(
+                "grab_retry_ms must be 1 to {}",
+                MAX_GRAB_RETRY_DELAY.as_millis()
+            );
+        }
+        let adaptive = &raw.adaptive_debounce;
+        if adaptive.enabled {
+            if adaptive.min_ms > adaptive.max_ms {
+                bail!("adaptive_debounce: need min_ms <= max_ms");
+            }
+            if adaptive.window_ms == 0 {
+                bail!("adaptive_debounce: window_ms must be positive");
+            }
+            if !(adaptive.slow >= 0.0 && adaptive.fast > adaptive.slow && adaptive.fast.is_finite())
+            {
+                bail!("adaptive_debounce: need 0 <= slow < fast");
+            }
+            if raw.deactivate_on == Trigger::TimeoutOnly && adaptive.min_ms == 0 {
+                bail!("deactivate_on = \"timeout_only\" needs a positive adaptive_debounce.min_ms");
+            }
+            if raw.debounce_ms != 0 {
+                eprintln!("⚠️  debounce_ms is ignored while adaptive_debounce is enabled");
+            }
+        } else if raw.deactivate_on == Trigger::TimeoutOnly && raw.debounce_ms == 0 {
+            bail!("deactivate_on = \"timeout_only\" needs a positive debounce_ms");
+        }
+
+        if !(1..=MAX_HI_RES_STEP).contains(&raw.scroll.step) {
+            bail!("scroll.step must be between 1 and {}", MAX_HI_RES_STEP);
+        }
+
+        let kinetic = &raw.scroll.kinetic;
+        if kinetic.enabled {
+            if kinetic.time_constant_ms == 0 || kinetic.tick_ms == 0 {
+                bail!("scroll.kinetic: time_constant_ms and tick_ms must be positive");
+            }
+            if !(kinetic.min_velocity > 0.0 && kinetic.max_velocity >= kinetic.min_velocity) {
+                bail!("scroll.kinetic: need 0 < min_velocity <= max_velocity");
+            }
+        }
+
+        let accel = &raw.scroll.acceleration;
+        if accel.enabled {
+            if !(1..=MAX_ACCEL_RATE_HZ).contains(&accel.max_rate_hz) {
+                bail!(
+                    "scroll.acceleration: max_rate_hz must be between 1 and {}",
+                    MAX_ACCEL_RATE_HZ
+                );
+            }
+            if accel
+                .initial_rate_hz
+                .is_some_and(|initial| initial == 0 || initial > accel.max_rate_hz)
+            {
+                bail!("scroll.acceleration: need 0 < initial_rate_hz <= max_rate_hz");
+            }
+        }
+
+        if raw.typing.presses > 0 && raw.typing.window_ms == 0 {
+            bail!("typing: window_ms must be positive");
+        }
+        if raw.typing.after_letter_ms > MAX_AFTER_LETTER_MS {
+            bail!(
+                "typing: after_letter_ms must be at most {}",
+                MAX_AFTER_LETTER_MS
+            );
+        }
+        // Typing turns the mode off, which would turn these tables on mid-word
+        let inverted = std::iter::once(raw.mapping_condition)
+            .chain(mapping_sets.iter().map(|set| set.table.condition))
+            .any(|condition| condition != MappingCondition::Active);
+        if raw.typing.presses > 0 && inverted {
+            bail!("typing detection only works with mappings that apply while the touchpad is active (condition = \"active\")");
+        }
+
+        let sched = &raw.scheduling;
+        if !(1..=99).contains(&sched.priority) {
+            bail!("scheduling: priority must be between 1 and 99");
+        }
+        if !(-20..=19).contains(&sched.nice) {
+            bail!("scheduling: nice must be between -20 and 19");
+        }
+
+        let mut script = raw.script;
+        if script.path.is_some() && script.max_operations == 0 {
+            bail!("script: max_operations must be positive");
+        }
+        // Relative to the file that named it
+        if let (Some(script_path), Source::File(file)) =
+            (&mut script.path, source_of("script.path"))
+        {
+            if let Some(config_dir) = file.parent() {
+                *script_path = config_dir.join(&*script_path);
+            }
+        }
+
+        let external_mouse = raw
+            .external_mouse
+            .map(|mouse| ExternalMouse::from_raw(mouse, &raw.scroll))
+            .transpose()?;
+        let touchpad = raw
+            .touchpad
+            .unwrap_or_else(|| RawPaths::One(DEFAULT_TOUCHPAD.into()))
+            .into_candidates("touchpad")?;
+        let touchpad_buttons = raw
+            .touchpad_buttons
+            .map(|buttons| ExternalMouse::for_touchpad(buttons, &touchpad, &raw.scroll))
+            .transpose()?;
+
+        let commands = mappings
+            .values()
+            .chain(
+                conditional
+                    .values()
+                    .flatten()
+                    .map(|variant| &variant.target),
+            )
+            .chain(
+                mapping_sets
+                    .iter()
+                    .flat_map(|set| set.table.targets().map(|(_, target)| target)),
+            )
+            .chain(
+                finger_layers
+                    .values()
+                    .flat_map(|table| table.targets().map(|(_, target)| target)),
+            )
+            .chain(
+                external_mouse
+                    .iter()
+                    .chain(&touchpad_buttons)
+                    .flat_map(|mouse| mouse.mappings.values()),
+            )
+            .any(|target| matches!(target, Target::Command(_)));
+        if commands && !raw.allow_commands {
+            bail!("command mappings run shell commands; set allow_commands = true to use them");
+        }
+
+        Ok(Self {
+            touchpad,
+            touchpad_source,
+            keyboard: raw
+                .keyboard
+                .unwrap_or_else(|| RawPaths::One(DEFAULT_KEYBOARD.into()))
+                .into_candidates("keyboard")?,
+            keyboard_source,
+            device_wait: Duration::from_millis(raw.device_wait_ms),
+            reconnect_max_delay: Duration::from_millis(raw.reconnect_max_delay_ms),
+            grab_attempts: raw.grab_attempts,
+            grab_retry: Duration::from_millis(raw.grab_retry_ms),
+            virtual_keyboard_name: seat::suffixed(&raw.virtual_keyboard_name),
+            pointer_on_keyboard: raw.pointer_on_keyboard,
+            declared_buttons,
+            xkb_layout: raw.xkb_layout,
+            keysyms,
+            debounce: Duration::from_millis(raw.debounce_ms),
+            adaptive_debounce: raw.adaptive_debounce,
+            activate_when,
+            motion_threshold: raw.motion_threshold,
+            deactivate_on: raw.deactivate_on,
+            persist_state: raw.persist_state,
+            start_enabled: raw.start_enabled,
+            on_activate: raw.on_activate,
+            on_deactivate: raw.on_deactivate,
+            mapping_condition: raw.mapping_condition,
+            mappings,
+            conditional,
+            chatter,
+            min_press,
+            after_letter,
+            chatter_ms: raw.chatter_ms,
+            mapping_sets,
+            finger_layers,
+            scroll: raw.scroll,
+            script,
+            typing: raw.typing,
+            scheduling: raw.scheduling,
+            control: raw.control,
+            mirror: raw.mirror,
+            virtual_devices: VirtualDevicesConfig {
+                phys: raw.virtual_devices.phys,
+                keyboard_properties: raw
+                    .virtual_devices
+                    .keyboard_properties
+                    .iter()
+                    .map(|name| parse_property("virtual_devices.keyboard_properties", name))
+                    .collect::<Result<_>>()?,
+                mouse_properties: raw
+                    .virtual_devices
+                    .mouse_properties
+                    .iter()
+                    .map(|name| parse_property("virtual_devices.mouse_properties", name))
+                    .collect::<Result<_>>()?,
+            },
+            external_mouse,
+            touchpad_buttons,
+            activation_mouse,
+            warp,
+            tablet_mode: TabletModeConfig {
+                action: raw.tablet_mode.action,
+                candidates: match raw.tablet_mode.device {
+                    Some(device) => device.into_candidates("tablet_mode.device")?,
+                    None => Vec::new(),
+                },
+            },
+            session_lock: raw.session_lock,
+            suspend: raw.suspend,
+            sticky: raw.sticky,
+            hysteresis: raw.hysteresis,
+            watchdog: raw.watchdog,
+            settings,
+            layers,
+            preset: None,
+        })
+    }
+
+    /// Key set for the virtual mouse: `mouse_buttons` if given, else the mapped
+    /// buttons plus BTN_LEFT, which udev needs to see a mouse at all
+    ///
+    /// A script may click any standard button, so with one they are all declared.
+    pub fn mouse_buttons(&self) -> AttributeSet<Key> {
+        let mut buttons = AttributeSet::new();
+        if let Some(declared) = &self.declared_buttons {
+            for button in declared {
+                buttons.insert(*button);
+            }
+            return buttons;
+        }
+        buttons.insert(Key::BTN_LEFT);
+        if self.script.path.is_some() {
+            for button in MOUSE_BUTTONS {
+                buttons.insert(button);
+            }
+        }
+        for button in self.targets().filter_map(Target::button) {
+            buttons.insert(button);
+        }
+        buttons
+    }
+
+    /// Targets of the top-level mappings and of every mapping set
+    pub fn targets(&self) -> impl Iterator<Item = &Target> {
+        let variants = self.conditional.values().flatten();
+        self.mappings
+            .values()
+            .chain(variants.map(|variant| &variant.target))
+            .chain(
+                self.mapping_sets
+                    .iter()
+                    .map(|set| &set.table)
+                    .chain(self.finger_layers.values())
+                    .flat_map(|table| table.targets().map(|(_, target)| target)),
+            )
+    }
+
+    /// Every key the top-level mappings and the finger layers remap, plain or conditional
+    pub fn mapped_keys(&self) -> impl Iterator<Item = &Key> {
+        let layers = self
+            .finger_layers
+            .values()
+            .flat_map(|table| table.mappings.keys().chain(table.conditional.keys()));
+        self.mappings
+            .keys()
+            .chain(self.conditional.keys())
+            .chain(layers)
+    }
+
+    /// Index of the first mapping set that selects the keyboard at `path`
+    pub fn mapping_set_for(&self, path: &Path) -> Option<usize> {
+        self.mapping_sets
+            .iter()
+            .position(|set| device::selects(&set.candidates, path))
+    }
+
+    /// This config as the keyboard at `path` sees it: its mapping set, if
+    /// one selects it, in place of the top-level mappings
+    pub fn for_keyboard(&self, path: &Path) -> Config {
+        let mut config = self.clone();
+        if let Some(index) = self.mapping_set_for(path) {
+            let table = config.mapping_sets.swap_remove(index).table;
+            config.mapping_condition = table.condition;
+            config.mappings = table.mappings;
+            config.conditional = table.conditional;
+            config.chatter = table.chatter;
+            config.min_press = table.min_press;
+            config.after_letter = table.after_letter;
+            // Layers sit on the top-level table only
+            config.finger_layers.clear();
+        }
+        config.mapping_sets.clear();
+        config
+    }
+
+    /// Which table applies to the keyboard at `path`, for status output
+    pub fn mapping_source(&self, path: &Path) -> String {
+        match self.mapping_set_for(path) {
+            Some(index) => format!(
+                "mapping_sets[{}] ({})",
+                index,
+                self.mapping_sets[index].candidates.join(", ")
+            ),
+            None if self.finger_layers.is_empty() => "[mappings]".to_string(),
+            None => format!(
+                "[mappings], with finger_layers {}",
+                self.finger_layers
+                    .keys()
+                    .map(|&fingers| layer_name(fingers))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+
+    /// Names of the virtual devices a run with this config creates
+    pub fn own_device_names(&self) -> Vec<String> {
+        let mut names = vec![self.virtual_keyboard_name.clone(), output::mouse_name()];
+        names.extend(self.external_mouse.iter().map(|mouse| mouse.name.clone()));
+        names.extend(self.touchpad_buttons.iter().map(|proxy| proxy.name.clone()));
+        if self.warp.is_some() {
+            names.push(output::warp_name());
+        }
+        names
+    }
+
+    /// How long `mouse_motion` outlasts the last motion, if there is an activation mouse
+    pub fn mouse_idle(&self) -> Duration {
+        self.activation_mouse
+            .as_ref()
+            .map_or(Duration::ZERO, |mouse| mouse.idle)
+    }
+
+    /// Whether anything can click or scroll; without, no virtual mouse is made
+    pub fn needs_pointer(&self) -> bool {
+        self.targets()
+            .any(|target| !matches!(target, Target::Key(_) | Target::Command(_)))
+            || self.script.path.is_some()
+    }
+
+    /// Keys mappings type, declared on the virtual keyboard whatever the real one has
+    pub fn key_targets(&self) -> AttributeSet<Key> {
+        let mut keys = AttributeSet::new();
+        for target in self.targets() {
+            if let Target::Key(key) = target {
+                keys.insert(*key);
+            }
+        }
+        keys
+    }
+
+    /// A plain `KEY = "TARGET"` mapping, as `ctl map` adds one, with its chatter window
+    pub fn parse_mapping(
+        &self,
+        key: &str,
+        target: &str,
+    ) -> Result<(Key, Target, Option<Duration>)> {
+        let key = keys::resolve(key)?;
+        let parsed = parse_target(
+            &format!("{:?}", key),
+            RawTarget::Button(target.to_string()),
+            self.chatter_ms,
+            &self.scroll,
+        )?;
+        Ok((key, parsed.target, parsed.chatter))
+    }
+
+    /// Whether anything can scroll, so the wheel axes are worth declaring
+    pub fn needs_wheel(&self) -> bool {
+        self.script.path.is_some()
+            || self
+                .targets()
+                .any(|target| matches!(target, Target::Wheel(_)))
+    }
+}
+
+/// A finger layer's name in the config: "1", "2" or "3+"
+pub fn layer_name(fingers: usize) -> String {
+    match fingers {
+        3.. => "3+".to_string(),
+        fingers => fingers.to_string(),
+    }
+}
+
+impl MappingTable {
+    /// Whether `key` is mapped here, plainly or by modifiers
+    pub fn maps(&self, key: Key) -> bool {
+        self.mappings.contains_key(&key) || self.conditional.contains_key(&key)
+    }
+
+    /// Every key with each target it can have
+    pub fn targets(&self) -> impl Iterator<Item = (&Key, &Target)> {
+        let variants = self
+            .conditional
+            .iter()
+            .flat_map(|(key, variants)| variants.iter().map(move |v| (key, &v.target)));
+        self.mappings.iter().chain(variants)
+    }
+}
+
+/// Resolve a `[mappings]`-style table, noting keysym sources in `keysyms`
+fn parse_mappings(
+    raw: HashMap<String, RawMapping>,
+    keymap: Option<&KeysymLookup>,
+    default_chatter_ms: u64,
+    scroll: &ScrollConfig,
+    keysyms: &mut Vec<(String, Key)>,
+) -> Result<MappingTable> {
+    let mut table = MappingTable::default();
+    let mut names: Vec<_> = raw.into_iter().collect();
+    // Sorted so keysym messages and errors come out the same every run
+    names.sort_by(|a, b| a.0.cmp(&b.0));
+    for (name, mapping) in names {
+        let key = match keymap {
+            Some(keymap) => {
+                let key = keymap(&name).with_context(|| format!("Mapping '{}'", name))?;
+                keysyms.push((name.clone(), key));
+                key
+            }
+            None => keys::resolve(&name)?,
+        };
+        // `f` and `KEY_F` are the same key; which one wins must not depend on hash order
+        if table.mappings.contains_key(&key) || table.conditional.contains_key(&key) {
+            bail!("{:?} is mapped twice ('{}' names it too)", key, name);
+        }
+        let raw_variants = match mapping {
+            RawMapping::Plain(target) => {
+                let parsed = parse_target(&name, target, default_chatter_ms, scroll)?;
+                table.add_filters(key, &parsed);
+                table.mappings.insert(key, parsed.target);
+                continue;
+            }
+            RawMapping::Variant(variant) => vec![variant],
+            RawMapping::Variants(variants) => variants,
+        };
+        if raw_variants.is_empty() {
+            bail!("{}: needs at least one target", name);
+        }
+        let mut variants = Vec::new();
+        for (i, variant) in raw_variants.into_iter().enumerate() {
+            let field = format!("{}[{}]", name, i);
+            let when =
+                modifiers::Condition::parse(&variant.modifiers).with_context(|| field.clone())?;
+            let parsed = parse_target(&field, variant.target, default_chatter_ms, scroll)?;
+            // The filters are the key's, not a variant's: the first to set one decides
+            table.add_filters(key, &parsed);
+            variants.push(Variant {
+                when,
+                target: parsed.target,
+            });
+        }
+        let conditions: Vec<_> = variants.iter().map(|variant| variant.when).collect();
+        if let Some(&i) = modifiers::unreachable(&conditions).first() {
+            bail!(
+                "{}[{}] ({}) never applies: a more specific or earlier variant always wins",
+                name,
+                i,
+                conditions[i]
+            );
+        }
+        table.conditional.insert(key, variants);
+    }
+    Ok(table)
+}
+
+impl MappingTable {
+    fn add_filters(&mut self, key: Key, parsed: &ParsedTarget) {
+        if let Some(window) = parsed.chatter {
+            self.chatter.entry(key).or_insert(window);
+        }
+        if let Some(min) = parsed.min_press {
+            self.min_press.entry(key).or_insert(min);
+        }
+        if let Some(window) = parsed.after_letter {
+            self.after_letter.entry(key).or_insert(window);
+        }
+    }
+}
+
+/// A mapped key's target, with the bounce and palm filters it asks for
+struct ParsedTarget {
+    target: Target,
+    chatter: Option<Duration>,
+    min_press: Option<Duration>,
+    after_letter: Option<Duration>,
+}
+
+/// Validate the target of mapping `name`; `default_chatter_ms` applies unless it sets its own
+fn parse_target(
+    name: &str,
+    target: RawTarget,
+    default_chatter_ms: u64,
+    scroll: &ScrollConfig,
+) -> Result<ParsedTarget> {
+    // `wheel_up` and friends are a plain one-detent wheel table
+    let target = match target {
+        RawTarget::Button(button) => match keys::wheel_alias(&button) {
+            Some(step) => RawTarget::Wheel(RawWheel {
+                wheel: step,
+                step: None,
+                repeat_delay_ms: None,
+                repeat_interval_ms: None,
+                chatter_ms: None,
+                min_press_ms: None,
+                after_letter_ms: None,
+                natural: None,
+            }),
+            None => RawTarget::Button(button),
+        },
+        other => other,
+    };
+    let chatter_ms = match &target {
+        RawTarget::Button(_)
+        | RawTarget::Turbo(_)
+        | RawTarget::DoubleClick(_)
+        | RawTarget::TripleClick(_)
+        | RawTarget::Command(_) => None,
+        RawTarget::ButtonTable(table) => table.chatter_ms,
+        RawTarget::Wheel(wheel) => wheel.chatter_ms,
+    }
+    .unwrap_or(default_chatter_ms);
+    let min_press_ms = match &target {
+        RawTarget::ButtonTable(table) => table.min_press_ms,
+        RawTarget::Wheel(wheel) => wheel.min_press_ms,
+        _ => None,
+    };
+    let after_letter_ms = match &target {
+        RawTarget::ButtonTable(table) => table.after_letter_ms,
+        RawTarget::Wheel(wheel) => wheel.after_letter_ms,
+        _ => None,
+    };
+    if after_letter_ms.is_some_and(|ms| ms > MAX_AFTER_LETTER_MS) {
+        bail!(
+            "{}: after_letter_ms must be at most {}",
+            name,
+            MAX_AFTER_LETTER_MS
+        );
+    }
+    let target = match target {
+        RawTarget::Button(target) => parse_plain(name, &target)?,
+        RawTarget::ButtonTable(table) => {
+            let button = parse_button(name, &table.button)?;
+            match table.trigger.unwrap_or(ClickTrigger::Press) {
+                ClickTrigger::Press => {
+                    if table.max_hold_ms.is_some() {
+                        bail!("{}: max_hold_ms needs trigger = \"release\"", name);
+                    }
+                    Target::Button(button)
+                }
+                ClickTrigger::Release => {
+                    // A deferred release and a deferred click would fight over the same window
+                    if table.chatter_ms.is_some() {
+                        bail!(
+                            "{}: chatter_ms can't be combined with trigger = \"release\"",
+                            name
+                        );
+                    }
+                    Target::OnRelease(ReleaseTarget {
+                        button,
+                        max_hold: table.max_hold_ms.map(Duration::from_millis),
+                    })
+                }
+            }
+        }
+        RawTarget::Turbo(turbo) => {
+            if !(1..=MAX_TURBO_RATE_HZ).contains(&turbo.rate_hz) {
+                bail!(
+                    "{}: rate_hz must be between 1 and {}",
+                    name,
+                    MAX_TURBO_RATE_HZ
+                );
+            }
+            if turbo.max_clicks == Some(0) {
+                bail!("{}: max_clicks must be positive", name);
+            }
+            Target::Turbo(TurboTarget {
+                button: parse_button(name, &turbo.turbo)?,
+                interval: Duration::from_secs(1) / turbo.rate_hz,
+                max_clicks: turbo.max_clicks,
+            })
+        }
+        RawTarget::DoubleClick(RawDoubleClick {
+            double_click: button,
+            gap_ms,
+        }) => Target::Clicks(click_target(name, &button, 2, gap_ms)?),
+        RawTarget::TripleClick(RawTripleClick {
+            triple_click: button,
+            gap_ms,
+        }) => Target::Clicks(click_target(name, &button, 3, gap_ms)?),
+        RawTarget::Wheel(wheel) => {
+            if wheel.wheel == 0 {
+                bail!("{}: wheel step must not be zero", name);
+            }
+            if wheel.wheel.abs() > MAX_WHEEL_STEP {
+                bail!(
+                    "{}: wheel step must be at most {} detents",
+                    name,
+                    MAX_WHEEL_STEP
+                );
+            }
+            let step = wheel.step.unwrap_or(scroll.step);
+            if !(1..=MAX_HI_RES_STEP).contains(&step) {
+                bail!("{}: step must be between 1 and {}", name, MAX_HI_RES_STEP);
+            }
+            let delay = wheel.repeat_delay_ms.unwrap_or(scroll.repeat_delay_ms);
+            let interval = wheel
+                .repeat_interval_ms
+                .unwrap_or(scroll.repeat_interval_ms);
+            if interval < MIN_REPEAT_INTERVAL_MS {
+                bail!(
+                    "{}: repeat_interval_ms must be at least {}",
+                    name,
+                    MIN_REPEAT_INTERVAL_MS
+                );
+            }
+            // Flipped here, once, so repeat and kinetic inherit the direction
+            let natural = wheel.natural.unwrap_or(scroll.natural);
+            Target::Wheel(WheelTarget {
+                detents: if natural { -wheel.wheel } else { wheel.wheel },
+                step,
+                repeat_delay: Duration::from_millis(delay),
+                repeat_interval: Duration::from_millis(interval),
+                acceleration: scroll.ramp(Duration::from_millis(interval)),
+            })
+        }
+        RawTarget::Command(command) => {
+            if command.command.trim().is_empty() {
+                bail!("{}: command must not be empty", name);
+            }
+            Target::Command(CommandTarget {
+                command: Box::leak(command.command.into_boxed_str()),
+                cooldown: Duration::from_millis(
+                    command.cooldown_ms.unwrap_or(DEFAULT_COMMAND_COOLDOWN_MS),
+                ),
+            })
+        }
+    };
+    Ok(ParsedTarget {
+        target,
+        chatter: (chatter_ms > 0).then(|| Duration::from_millis(chatter_ms)),
+        min_press: min_press_ms.filter(|&ms| ms > 0).map(Duration::from_millis),
+        after_letter: after_letter_ms.map(Duration::from_millis),
+    })
+}
+
+fn click_target(key: &str, button: &str, count: u32, gap_ms: Option<u64>) -> Result<ClickTarget> {
+    let gap_ms = gap_ms.unwrap_or(30);
+    if !(1..=MAX_CLICK_GAP_MS).contains(&gap_ms) {
+        bail!("{}: gap_ms must be between 1 and {}", key, MAX_CLICK_GAP_MS);
+    }
+    Ok(ClickTarget {
+        button: parse_button(key, button)?,
+        count,
+        gap: Duration::from_millis(gap_ms),
+    })
+}
+
+/// Lookup from keysym name to the key typing it, against the configured keymap
+type KeysymLookup = Box<dyn Fn(&str) -> Result<Key>>;
+
+#[cfg(feature = "xkb")]
+fn keysym_resolver(
+    file: Option<&Path>,
+    layout: &str,
+    variant: &str,
+    options: &str,
+) -> Result<KeysymLookup> {
+    use crate::xkb::{KeymapSource, Resolver};
+    let source = match file {
+        Some(path) => KeymapSource::File(path),
+        None => KeymapSource::Names {
+            layout,
+            variant,
+            options,
+        },
+    };
+    let resolver = Resolver::new(source)?;
+    Ok(Box::new(move |name| resolver.resolve(name)))
+}
+
+#[cfg(not(feature = "xkb"))]
+fn keysym_resolver(_: Option<&Path>, _: &str, _: &str, _: &str) -> Result<KeysymLookup> {
+    bail!("mapping_keys = \"keysym\" needs a build with the xkb feature")
+}
+
+/// The preset, then each file, then the overrides, and who set each value
+fn merge_layers(
+    preset: Option<&'static Preset>,
+    files: &[(&PathBuf, DocumentMut)],
+    overrides: &[Override],
+) -> Result<(DocumentMut, Vec<Setting>)> {
+    let mut doc = DocumentMut::new();
+    let mut settings = Vec::new();
+    if let Some(preset) = preset {
+        let mut layer = migrate::migrate(preset.text)
+            .with_context(|| format!("Failed to parse preset {}", preset.name))?
+            .doc;
+        layer.remove("version");
+        layers::merge(
+            doc.as_table_mut(),
+            layer.as_table(),
+            "",
+            &Source::Preset(preset.name),
+            &mut settings,
+        )?;
+    }
+    for (path, layer) in files {
+        layers::merge(
+            doc.as_table_mut(),
+            layer.as_table(),
+            "",
+            &Source::File(path.to_path_buf()),
+            &mut settings,
+        )
+        .with_context(|| format!("Failed to merge config {}", path.display()))?;
+    }
+    overrides::apply(&mut doc, overrides)?;
+    for o in overrides {
+        layers::forget(&mut settings, o.key);
+        settings.push(Setting {
+            key: o.key.to_string(),
+            value: o.display(),
+            source: Source::Env(o.var.clone()),
+        });
+    }
+    Ok((doc, settings))
+}
+
+/// A mapping's button name, limited to what the virtual mouse can declare
+/// A plain string target: a mouse button, or a key to type instead
+fn parse_plain(key: &str, target: &str) -> Result<Target> {
+    let resolved = keys::resolve(target).with_context(|| format!("{}: bad target", key))?;
+    if MOUSE_BUTTONS.contains(&resolved) || EXTRA_MOUSE_BUTTONS.contains(&resolved) {
+        return Ok(Target::Button(resolved));
+    }
+    // BTN_MISC..KEY_OK, the D-pad and BTN_TRIGGER_HAPPY are buttons, not keys
+    let code = resolved.code();
+    if (0x100..0x160).contains(&code)
+        || (0x220..=0x223).contains(&code)
+        || (0x2c0..=0x2e7).contains(&code)
+    {
+        bail!("{}: '{}' is not a supported mouse button", key, target);
+    }
+    Ok(Target::Key(resolved))
+}
+
+fn parse_button(key: &str, button: &str) -> Result<Key> {
+    let resolved = keys::resolve(button).with_context(|| format!("{}: bad button", key))?;
+    if !(MOUSE_BUTTONS.contains(&resolved) || EXTRA_MOUSE_BUTTONS.contains(&resolved)) {
+        bail!("{}: '{}' is not a supported mouse button", key, button);
+    }
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `files` merged in order, lowest first, with no overrides or preset
+    fn layered(files: &[&str]) -> Result<Config> {
+        let files: Vec<_> = files
+            .iter()
+            .enumerate()
+            .map(|(i, text)| (PathBuf::from(format!("layer{}.toml", i)), text.to_string()))
+            .collect();
+        Config::build(&files, &[], None)
+    }
+
+    #[test]
+    fn layers_deep_merge_into_the_config() {
+        let config = layered(&[
+            "keyboard = \"/dev/input/event3\"\n[scroll]\nnatural = true\nstep = 60\n\
+             [scroll.kinetic]\nenabled = true\n[mappings]\nKEY_F = { wheel = 1, natural = true }\n",
+            "keyboard = { append = [\"/dev/input/event7\"] }\n[scroll]\nstep = 120\n\
+             [mappings]\nKEY_F = { wheel = 2 }\nKEY_D = \"BTN_RIGHT\"\n",
+        ])
+        .unwrap();
+        assert_eq!(config.keyboard, ["/dev/input/event3", "/dev/input/event7"]);
+        assert!(config.scroll.natural);
+        assert_eq!(config.scroll.step, 120);
+        assert!(config.scroll.kinetic.enabled);
+        assert_eq!(config.mappings.len(), 2);
+        let setting = |key: &str| {
+            let s = config.settings.iter().find(|s| s.key == key).unwrap();
+            (s.value.clone(), s.source.clone())
+        };
+        assert_eq!(
+            setting("mappings.KEY_F"),
+            (
+                "{ wheel = 2 }".to_string(),
+                Source::File(PathBuf::from("layer1.toml"))
+            )
+        );
+        assert_eq!(
+            setting("scroll.natural").1,
+            Source::File(PathBuf::from("layer0.toml"))
+        );
+    }
+
+    #[test]
+    fn an_upper_array_replaces_the_lower_one() {
+        let config = layered(&[
+            "keyboard = [\"/dev/input/event3\", \"/dev/input/event4\"]\n",
+            "keyboard = [\"/dev/input/event7\"]\n",
+        ])
+        .unwrap();
+        assert_eq!(config.keyboard, ["/dev/input/event7"]);
+    }
+
+    #[test]
+    fn a_conflicting_type_fails_validation() {
+        let error = layered(&["[scroll]\nstep = 60\n", "scroll = 1\n"]).unwrap_err();
+        assert!(format!("{:#}", error).contains("scroll"), "{:#}", error);
+        let error = layered(&[
+            "[scroll]\nstep = 60\n",
+            "[scroll]\nstep = { append = [1] }\n",
+        ])
+        .unwrap_err();
+        assert!(
+            format!("{:#}", error).contains("Failed to merge config layer1.toml"),
+            "{:#}",
+            error
+        );
+    }
+}
@@ -0,0 +1,616 @@
+//! Control socket: one command per connection, on its first line
+//!
+//! `subscribe [SECONDS]` streams records as JSON lines (see `report`): a
+//! `status` snapshot on connect and every `SECONDS` (default
+//! `STATUS_INTERVAL`), plus everything the daemon emits except `forward`. Any
+//! number of clients may subscribe; each has its own bounded queue, and one
+//! that lets it fill up is sent an `error` line and disconnected, so a slow
+//! reader never holds up the daemon. `inject ...` queues synthetic input (see `inject`)
+//! and answers `ok`. `pause [DURATION]` and `resume` switch the layer (see
+//! `snooze`), and `status` answers with one line about it. `map KEY TARGET`,
+//! `unmap KEY`, `list-map` and `save` edit the mappings (see `live_map`).
+//! Unknown commands get `error ...` and a hang-up.
+//!
+//! Under systemd socket activation the listener is the one passed in
+//! LISTEN_FDS (named `control` in LISTEN_FDNAMES, or the only one), and the
+//! socket file belongs to systemd: it is neither created nor removed here.
+
+use crate::activation::Activation;
+use crate::config::ControlConfig;
+use crate::inject;
+use crate::instance;
+use crate::live_map;
+use crate::motion::LastMotion;
+use crate::report;
+use crate::seat;
+use crate::snooze::{self, Request};
+use anyhow::{anyhow, bail, Context, Result};
+use std::ffi::CString;
+use std::fs::{self, DirBuilder};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::io::FromRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// FileDescriptorName= of the socket unit, to pick ours among several passed
+const FD_NAME: &str = "control";
+
+const STATUS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Longest `subscribe SECONDS` interval
+const MAX_STATUS_SECONDS: u64 = 3600;
+
+/// Lines queued per subscriber; one that falls further behind is disconnected
+const QUEUE_LEN: usize = 256;
+
+/// How long a client gets to send its command
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+static SUBSCRIBERS: Mutex<Vec<SyncSender<Arc<str>>>> = Mutex::new(Vec::new());
+
+/// Fast check for the hot path, kept in step with SUBSCRIBERS
+static ANY_SUBSCRIBER: AtomicBool = AtomicBool::new(false);
+
+/// `touch-layer.sock`, or `touch-layer-SEAT.sock` with `--seat`
+fn socket_name() -> String {
+    seat::file_name("touch-layer", "sock")
+}
+
+pub fn socket_path() -> PathBuf {
+    instance::runtime_path(&socket_name())
+}
+
+/// Where a client should look: ours, then the system service's
+pub fn candidate_paths() -> Vec<PathBuf> {
+    let mut paths = vec![socket_path()];
+    let system = PathBuf::from("/run").join(socket_name());
+    if !paths.contains(&system) {
+        paths.push(system);
+    }
+    paths
+}
+
+/// Send one command and return what follows the `ok` of its reply
+pub fn request(socket: Option<&Path>, command: &str) -> Result<String> {
+    let mut stream = connect(socket)?;
+    writeln!(stream, "{}", command).context("Failed to send the command")?;
+    let mut reply = String::new();
+    BufReader::new(stream)
+        .read_line(&mut reply)
+        .context("Lost the connection to the daemon")?;
+    let reply = reply.trim();
+    if reply == "ok" {
+        return Ok(String::new());
+    }
+    if let Some(text) = reply.strip_prefix("ok ") {
+        return Ok(text.to_string());
+    }
+    match reply.strip_prefix("error ") {
+        Some(message) => bail!("Daemon refused: {}", message),
+        None => bail!("Unexpected reply from daemon: {}", reply),
+    }
+}
+
+/// Connect to `socket`, else the first of `candidate_paths` that answers
+pub fn connect(socket: Option<&Path>) -> Result<UnixStream> {
+    if let Some(path) = socket {
+        return UnixStream::connect(path)
+            .with_context(|| format!("Failed to connect to {}", path.display()));
+    }
+    let candidates = candidate_paths();
+    for path in &candidates {
+        if let Ok(stream) = UnixStream::connect(path) {
+            return Ok(stream);
+        }
+    }
+    let tried: Vec<String> = candidates.iter().map(|p| p.display().to_string()).collect();
+    bail!(
+        "No running instance found (tried {}); is the control socket enabled and readable?",
+        tried.join(", ")
+    )
+}
+
+pub fn has_subscribers() -> bool {
+    ANY_SUBSCRIBER.load(Ordering::Relaxed)
+}
+
+/// Queue a line for every subscriber without ever blocking the caller
+///
+/// A subscriber whose queue is full is dropped; its thread sees the channel
+/// disconnect once it has written what was queued, and hangs up.
+pub fn broadcast(line: &str) {
+    let line: Arc<str> = line.into();
+    let mut subscribers = SUBSCRIBERS.lock().unwrap();
+    subscribers.retain(|tx| match tx.try_send(line.clone()) {
+        Ok(()) => true,
+        Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) => false,
+    });
+    ANY_SUBSCRIBER.store(!subscribers.is_empty(), Ordering::Relaxed);
+}
+
+/// What the periodic `status` record is built from
+pub struct Status {
+    pub activation: Arc<Mutex<Activation>>,
+    pub enabled: Arc<AtomicBool>,
+    pub last_motion: Arc<LastMotion>,
+}
+
+impl Status {
+    /// `status`'s answer: the switch, then the mode
+    fn line(&self) -> String {
+        let layer = snooze::describe().unwrap_or_else(|| {
+            if self.enabled.load(Ordering::Relaxed) {
+                "enabled".to_string()
+            } else {
+                "disabled".to_string()
+            }
+        });
+        let active = self.activation.lock().unwrap().is_active();
+        let mut line = format!(
+            "layer {}; mode {}",
+            layer,
+            if active { "active" } else { "inactive" }
+        );
+        if live_map::modified() {
+            line.push_str("; mappings modified from config");
+        }
+        line
+    }
+
+    fn record(&self) -> report::Record {
+        let (active, finger_down) = {
+            let activation = self.activation.lock().unwrap();
+            (activation.is_active(), activation.is_finger_down())
+        };
+        let since_motion = self.last_motion.elapsed(Instant::now());
+        report::status(
+            active,
+            self.enabled.load(Ordering::Relaxed),
+            finger_down,
+            since_motion.map(|elapsed| elapsed.as_millis() as u64),
+        )
+    }
+}
+
+/// The socket being served; dropping it removes the file if we bound it
+pub struct Server {
+    /// None when systemd owns the socket file
+    path: Option<PathBuf>,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Which of `count` passed descriptors is ours, by its LISTEN_FDNAMES entry
+fn pick_fd(count: usize, names: Option<&str>) -> Option<usize> {
+    if let Some(index) = names.and_then(|names| names.split(':').position(|name| name == FD_NAME)) {
+        return (index < count).then_some(index);
+    }
+    (count == 1).then_some(0)
+}
+
+/// The listening socket systemd passed us, if any
+fn inherited() -> Result<Option<UnixListener>> {
+    // Left in the environment: daemonize checks it, and hooks can't use it (LISTEN_PID)
+    let fds = systemd::daemon::listen_fds(false).context("Failed to read LISTEN_FDS")?;
+    if fds.is_empty() {
+        return Ok(None);
+    }
+    let names = std::env::var("LISTEN_FDNAMES").ok();
+    let Some(index) = pick_fd(fds.len() as usize, names.as_deref()) else {
+        eprintln!(
+            "⚠️  systemd passed {} sockets, none named '{}'; binding our own",
+            fds.len(),
+            FD_NAME
+        );
+        return Ok(None);
+    };
+    let fd = fds
+        .iter()
+        .nth(index)
+        .expect("index is within the passed range");
+    let unix_stream = systemd::daemon::is_socket_unix(
+        fd,
+        Some(systemd::daemon::SocketType::Stream),
+        systemd::daemon::Listening::IsListening,
+        None::<&str>,
+    )
+    .context("Failed to inspect the socket passed by systemd")?;
+    if !unix_stream {
+        bail!(
+            "The socket systemd passed (fd {}) is not a listening unix stream socket",
+            fd
+        );
+    }
+    // SAFETY: systemd handed this descriptor to this process, and nothing else wraps it
+    Ok(Some(unsafe { UnixListener::from_raw_fd(fd) }))
+}
+
+/// Serve the socket systemd passed, or bind our own, accepting clients on a background thread
+///
+/// Called with the instance lock held, so a socket file left behind can only
+/// be stale and is replaced.
+pub fn serve(config: &ControlConfig, status: Status) -> Result<Option<Server>> {
+    if !config.enabled {
+        return Ok(None);
+    }
+    let status = Arc::new(status);
+    if let Some(listener) = inherited()? {
+        let path = listener
+            .local_addr()
+            .ok()
+            .and_then(|addr| addr.as_pathname().map(|path| path.display().to_string()))
+            .unwrap_or_else(|| "unnamed".to_string());
+        accept(listener, status);
+        eprintln!("🔌 Control socket: {} (from systemd)", path);
+        return Ok(Some(Server { path: None }));
+    }
+
+    let path = socket_path();
+    let _ = fs::remove_file(&path);
+
+    let gid = config
+        .group
+        .as_deref()
+        .map(|group| group_id(group).with_context(|| format!("control: unknown group '{}'", group)))
+        .transpose()?;
+    let listener = bind_private(&path, gid)
+        .with_context(|| format!("Failed to bind control socket {}", path.display()))?;
+    let server = Server {
+        path: Some(path.clone()),
+    };
+
+    accept(listener, status);
+    eprintln!("🔌 Control socket: {}", path.display());
+    Ok(Some(server))
+}
+
+/// Bind `path` with its final group and mode (0600, or 0660 with `gid`)
+///
+/// The socket is bound in a directory only we can enter, set up there and
+/// then renamed into place, so no client can ever reach it with looser
+/// permissions. The umask would do that too, but it is the whole process's,
+/// and other threads are already creating files.
+fn bind_private(path: &Path, gid: Option<u32>) -> Result<UnixListener> {
+    let staging = path.with_file_name(format!(".{}.bind", socket_name()));
+    // Stale: the instance lock is held
+    let _ = fs::remove_dir_all(&staging);
+    DirBuilder::new()
+        .mode(0o700)
+        .create(&staging)
+        .with_context(|| format!("Failed to create {}", staging.display()))?;
+    let staged = staging.join("socket");
+    let bound = (|| -> Result<UnixListener> {
+        let listener = UnixListener::bind(&staged)?;
+        if let Some(gid) = gid {
+            std::os::unix::fs::chown(&staged, None, Some(gid))
+                .context("Failed to set its group")?;
+        }
+        let mode = if gid.is_some() { 0o660 } else { 0o600 };
+        fs::set_permissions(&staged, fs::Permissions::from_mode(mode))
+            .context("Failed to set its permissions")?;
+        fs::rename(&staged, path).context("Failed to move it into place")?;
+        Ok(listener)
+    })();
+    let _ = fs::remove_dir_all(&staging);
+    bound
+}
+
+fn accept(listener: UnixListener, status: Arc<Status>) {
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            let status = status.clone();
+            thread::spawn(move || {
+                // A client hanging up mid-stream is normal
+                let _ = handle(stream, &status);
+            });
+        }
+    });
+}
+
+fn group_id(name: &str) -> Option<u32> {
+    let name = CString::new(name).ok()?;
+    // SAFETY: name is NUL-terminated; the result is read before any other getgr* call
+    let group = unsafe { libc::getgrnam(name.as_ptr()) };
+    // SAFETY: non-null pointers from getgrnam point to a valid struct group
+    (!group.is_null()).then(|| unsafe { (*group).gr_gid })
+}
+
+fn handle(mut stream: UnixStream, status: &Status) -> io::Result<()> {
+    stream.set_read_timeout(Some(COMMAND_TIMEOUT))?;
+    let mut command = String::new();
+    BufReader::new(stream.try_clone()?).read_line(&mut command)?;
+    let command = command.trim();
+    if let Some(interval) = command
+        .strip_prefix("subscribe")
+        .filter(|rest| rest.is_empty() || rest.starts_with(' '))
+    {
+        return match parse_interval(interval.trim()) {
+            Ok(interval) => subscribe(stream, status, interval),
+            Err(err) => writeln!(stream, "error {:#}", err),
+        };
+    }
+    if let Some(line) = command.strip_prefix("inject ") {
+        return match inject::submit(line) {
+            Ok(()) => writeln!(stream, "ok"),
+            Err(err) => writeln!(stream, "error {:#}", err),
+        };
+    }
+    let words: Vec<&str> = command.split_whitespace().collect();
+    let reply = match words.as_slice() {
+        ["pause"] => pause(snooze::parse(None)),
+        ["pause", duration @ ..] => pause(snooze::parse(Some(&duration.join(" ")))),
+        ["resume"] => snooze::submit(Request::Resume).map(|()| "resumed".to_string()),
+        ["status"] => Ok(status.line()),
+        ["map", key, target] => live_map::map(key, target),
+        ["unmap", key] => live_map::unmap(key),
+        ["list-map"] => live_map::list(),
+        ["save"] => live_map::save(),
+        _ => Err(anyhow!("unknown command '{}'", command)),
+    };
+    match reply {
+        Ok(text) => writeln!(stream, "ok {}", text),
+        Err(err) => writeln!(stream, "error {:#}", err),
+    }
+}
+
+fn pause(request: Result<Request>) -> Result<String> {
+    let request = request?;
+    snooze::submit(request)?;
+    Ok(match request {
+        Request::Pause(Some(until)) => format!(
+            "paused for {}",
+            snooze::remaining(until.duration_since(SystemTime::now()).unwrap_or_default())
+        ),
+        _ => "paused until resumed".to_string(),
+    })
+}
+
+/// `subscribe`'s optional status interval, in whole seconds
+fn parse_interval(word: &str) -> Result<Duration> {
+    if word.is_empty() {
+        return Ok(STATUS_INTERVAL);
+    }
+    match word.parse::<u64>() {
+        Ok(seconds) if (1..=MAX_STATUS_SECONDS).contains(&seconds) => {
+            Ok(Duration::from_secs(seconds))
+        }
+        _ => bail!(
+            "subscribe takes a status interval of 1 to {} seconds",
+            MAX_STATUS_SECONDS
+        ),
+    }
+}
+
+fn subscribe(mut stream: UnixStream, status: &Status, interval: Duration) -> io::Result<()> {
+    let send_status = |stream: &mut UnixStream| match report::to_json(status.record()) {
+        Some(json) => writeln!(stream, "{}", json),
+        None => Ok(()),
+    };
+    send_status(&mut stream)?;
+
+    let (tx, rx) = mpsc::sync_channel(QUEUE_LEN);
+    {
+        let mut subscribers = SUBSCRIBERS.lock().unwrap();
+        subscribers.push(tx);
+        ANY_SUBSCRIBER.store(true, Ordering::Relaxed);
+    }
+    // Dropping rx on return unregisters us at the next broadcast
+    let mut next_status = Instant::now() + interval;
+    loop {
+        match rx.recv_timeout(next_status.saturating_duration_since(Instant::now())) {
+            Ok(line) => writeln!(stream, "{}", line)?,
+            Err(RecvTimeoutError::Timeout) => {}
+            // Only broadcast drops the sender, for a full queue
+            Err(RecvTimeoutError::Disconnected) => {
+                return writeln!(
+                    stream,
+                    "error fell more than {} records behind; disconnected",
+                    QUEUE_LEN
+                );
+            }
+        }
+        // Due even while records keep coming, not only once they pause
+        let now = Instant::now();
+        if now >= next_status {
+            send_status(&mut stream)?;
+            next_status = (next_status + interval).max(now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::trigger::Trigger;
+
+    fn status() -> Status {
+        let config = Config::parse("", Path::new("test.toml")).unwrap();
+        let activation = Activation::new(
+            config.activate_when.clone(),
+            config.debounce,
+            &config.adaptive_debounce,
+            Trigger::ToolFinger,
+            config.mouse_idle(),
+            &config.hysteresis,
+        );
+        Status {
+            activation: Arc::new(Mutex::new(activation)),
+            enabled: Arc::new(AtomicBool::new(true)),
+            last_motion: Arc::new(LastMotion::new()),
+        }
+    }
+
+    #[test]
+    fn status_snapshots_keep_coming_under_steady_traffic() {
+        let (client, server) = UnixStream::pair().unwrap();
+        thread::spawn(move || subscribe(server, &status(), Duration::from_millis(100)));
+        let mut lines = BufReader::new(client).lines();
+        assert!(lines
+            .next()
+            .unwrap()
+            .unwrap()
+            .contains(r#""type":"status""#));
+
+        // A record every 10ms, well inside the interval, for half a second
+        let sender = thread::spawn(|| {
+            for _ in 0..50 {
+                broadcast(r#"{"type":"test"}"#);
+                thread::sleep(Duration::from_millis(10));
+            }
+        });
+        let deadline = Instant::now() + Duration::from_millis(400);
+        let mut statuses = 0;
+        while Instant::now() < deadline {
+            if lines
+                .next()
+                .unwrap()
+                .unwrap()
+                .contains(r#""type":"status""#)
+            {
+                statuses += 1;
+            }
+        }
+        sender.join().unwrap();
+        assert!(statuses >= 2, "{} status lines in 400ms", statuses);
+    }
+
+    #[test]
+    fn the_passed_descriptor_is_picked_by_name() {
+        assert_eq!(pick_fd(1, None), Some(0));
+        assert_eq!(pick_fd(2, None), None, "unnamed and ambiguous");
+        assert_eq!(pick_fd(0, None), None);
+        assert_eq!(pick_fd(3, Some("metrics:control:other")), Some(1));
+        assert_eq!(
+            pick_fd(1, Some("metrics")),
+            Some(0),
+            "a lone socket is ours"
+        );
+        assert_eq!(pick_fd(2, Some("metrics:other")), None);
+        assert_eq!(
+            pick_fd(1, Some("metrics:control")),
+            None,
+            "name past the count"
+        );
+    }
+
+    /// Set in the re-executed test binary, naming the case it runs
+    const CHILD_CASE: &str = "TOUCH_LAYER_TEST_LISTEN";
+
+    /// Run this test again in a child process with `socket` as its fd 3, the way systemd passes it
+    fn run_child(case: &str, socket: std::os::unix::io::RawFd, listen_pid: Option<u32>) {
+        use std::os::unix::process::CommandExt;
+        let mut command = std::process::Command::new(std::env::current_exe().unwrap());
+        command
+            .args([
+                "--exact",
+                "control::tests::inherits_the_socket_systemd_passes",
+                "--test-threads=1",
+                "--nocapture",
+            ])
+            .env(CHILD_CASE, case)
+            .env("LISTEN_FDS", "1")
+            .env_remove("LISTEN_FDNAMES")
+            .env_remove("LISTEN_PID");
+        if let Some(pid) = listen_pid {
+            command.env("LISTEN_PID", pid.to_string());
+        }
+        // SAFETY: dup2 and fcntl are async-signal-safe. The copy drops the
+        // close-on-exec flag; dup2 onto itself doesn't, so clear it by hand.
+        unsafe {
+            command.pre_exec(move || {
+                let result = if socket == 3 {
+                    libc::fcntl(3, libc::F_SETFD, 0)
+                } else {
+                    libc::dup2(socket, 3)
+                };
+                if result < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let output = command.output().unwrap();
+        assert!(
+            output.status.success(),
+            "case '{}' failed: {}\n{}{}",
+            case,
+            output.status,
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    /// The child side: LISTEN_PID names this process unless the parent set another
+    fn child(case: &str) {
+        if std::env::var_os("LISTEN_PID").is_none() {
+            std::env::set_var("LISTEN_PID", std::process::id().to_string());
+        }
+        match case {
+            "listener" => {
+                let listener = inherited().unwrap().expect("the passed socket is used");
+                let path = listener.local_addr().unwrap();
+                assert_eq!(
+                    path.as_pathname().unwrap().file_name().unwrap(),
+                    socket_name().as_str()
+                );
+            }
+            "other-pid" => assert!(inherited().unwrap().is_none()),
+            "pair" => {
+                let error = inherited().unwrap_err().to_string();
+                assert!(
+                    error.contains("not a listening unix stream socket"),
+                    "{}",
+                    error
+                );
+            }
+            _ => panic!("unknown case {}", case),
+        }
+    }
+
+    #[test]
+    fn inherits_the_socket_systemd_passes() {
+        use std::os::unix::io::AsRawFd;
+        if let Ok(case) = std::env::var(CHILD_CASE) {
+            return child(&case);
+        }
+        let dir =
+            std::env::temp_dir().join(format!("touch-layer-test-listen-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let listener = UnixListener::bind(dir.join(socket_name())).unwrap();
+        run_child("listener", listener.as_raw_fd(), None);
+        // Meant for another process: e.g. the parent that spawned us
+        run_child("other-pid", listener.as_raw_fd(), Some(std::process::id()));
+        let (end, _other) = UnixStream::pair().unwrap();
+        run_child("pair", end.as_raw_fd(), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn the_socket_is_bound_owner_only() {
+        let dir =
+            std::env::temp_dir().join(format!("touch-layer-test-bind-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(socket_name());
+        let _listener = bind_private(&path, None).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let left: Vec<_> = fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(left.len(), 1, "the staging directory is removed");
+        UnixStream::connect(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
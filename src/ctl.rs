@@ -0,0 +1,45 @@
+//! `touchpad-remap ctl ...`: switch or remap a running instance over its control socket
+
+use crate::cli::CtlCommand;
+use crate::control;
+use crate::keys;
+use crate::snooze;
+use anyhow::Result;
+use std::path::Path;
+
+pub fn run(socket: Option<&Path>, command: &CtlCommand) -> Result<()> {
+    let line = match command {
+        CtlCommand::Pause { duration } => {
+            // Checked here too, for errors that don't need a round trip
+            snooze::parse(duration.as_deref())?;
+            match duration {
+                Some(duration) => format!("pause {}", duration),
+                None => "pause".to_string(),
+            }
+        }
+        CtlCommand::Resume => "resume".to_string(),
+        CtlCommand::Status => "status".to_string(),
+        CtlCommand::Map { key, target } => {
+            // Checked here too, for errors that don't need a round trip
+            keys::resolve(key)?;
+            format!("map {} {}", key, target)
+        }
+        CtlCommand::Unmap { key } => {
+            keys::resolve(key)?;
+            format!("unmap {}", key)
+        }
+        CtlCommand::ListMap => {
+            // One entry per line; the daemon joins them to fit its one-line reply
+            for entry in control::request(socket, "list-map")?.split("; ") {
+                println!("{}", entry);
+            }
+            return Ok(());
+        }
+        CtlCommand::Save => "save".to_string(),
+    };
+    let reply = control::request(socket, &line)?;
+    if !reply.is_empty() {
+        println!("{}", reply);
+    }
+    Ok(())
+}
@@ -0,0 +1,120 @@
+use crate::logfile;
+use anyhow::{bail, Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Readiness byte meaning "started"; anything else is the child's exit code
+const READY: u8 = 0;
+
+static DAEMONIZED: AtomicBool = AtomicBool::new(false);
+
+/// Write end of the readiness pipe while a daemonized child is still starting up
+static READY_PIPE: Mutex<Option<File>> = Mutex::new(None);
+
+/// True when running detached via `--daemonize`, so sd_notify is skipped
+pub fn is_daemon() -> bool {
+    DAEMONIZED.load(Ordering::SeqCst)
+}
+
+/// Classic double-fork detach; returns only in the daemon process
+///
+/// The original process stays in the foreground until the daemon reports
+/// readiness (devices opened, keyboard grabbed) or failure through a pipe, and
+/// exits with the daemon's startup status. Must run before any threads exist.
+pub fn daemonize() -> Result<()> {
+    if std::env::var_os("LISTEN_FDS").is_some() {
+        bail!("--daemonize cannot be used under socket activation");
+    }
+    // chdir("/") below would break a relative config path
+    if let Some(path) = std::env::var_os("TOUCH_LAYER_CONFIG") {
+        let absolute =
+            std::path::absolute(&path).context("Failed to resolve TOUCH_LAYER_CONFIG")?;
+        std::env::set_var("TOUCH_LAYER_CONFIG", absolute);
+    }
+
+    let mut fds = [0; 2];
+    // SAFETY: fds has room for the two descriptors pipe2 writes
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error()).context("Failed to create readiness pipe");
+    }
+    // SAFETY: pipe2 just returned these descriptors and nothing else owns them
+    let (read, write) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+
+    if fork()? {
+        drop(write);
+        std::process::exit(wait_for_child(File::from(read)));
+    }
+    drop(read);
+
+    // SAFETY: setsid has no memory-safety preconditions
+    if unsafe { libc::setsid() } < 0 {
+        return Err(io::Error::last_os_error()).context("setsid failed");
+    }
+    // Second fork: the session leader exits so the daemon can never reacquire a terminal
+    if fork()? {
+        // SAFETY: exit without running the parent's atexit handlers or flushing its buffers twice
+        unsafe { libc::_exit(0) };
+    }
+
+    std::env::set_current_dir("/").context("Failed to chdir to /")?;
+    DAEMONIZED.store(true, Ordering::SeqCst);
+    *READY_PIPE.lock().unwrap() = Some(File::from(write));
+    Ok(())
+}
+
+/// true in the parent
+fn fork() -> Result<bool> {
+    // SAFETY: called before the runtime starts, while the process is single-threaded
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()).context("fork failed"),
+        0 => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+/// Block until the daemon reports in; its status becomes ours
+fn wait_for_child(mut pipe: File) -> i32 {
+    let mut status = [0u8; 1];
+    match pipe.read(&mut status) {
+        Ok(1) if status[0] == READY => 0,
+        Ok(1) => status[0] as i32,
+        // Pipe closed without a word: the daemon died during startup
+        _ => 1,
+    }
+}
+
+/// Tell the waiting parent we are up, then leave the terminal
+pub fn notify_ready() -> Result<()> {
+    let Some(mut pipe) = READY_PIPE.lock().unwrap().take() else {
+        return Ok(());
+    };
+    // Keep the terminal until now so startup diagnostics are seen by whoever ran us
+    redirect_stdio()?;
+    pipe.write_all(&[READY])
+        .context("Failed to report readiness")
+}
+
+/// Pass a startup failure's exit code to the waiting parent; no-op once ready
+pub fn report_exit(code: i32) {
+    if let Some(mut pipe) = READY_PIPE.lock().unwrap().take() {
+        let _ = pipe.write_all(&[code.clamp(1, 255) as u8]);
+    }
+}
+
+fn redirect_stdio() -> Result<()> {
+    let null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")
+        .context("Failed to open /dev/null")?;
+    for fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        // SAFETY: both descriptors are valid; dup2 atomically replaces the standard stream
+        if unsafe { libc::dup2(null.as_raw_fd(), fd) } < 0 {
+            return Err(io::Error::last_os_error()).context("Failed to redirect stdio");
+        }
+    }
+    logfile::detach(null.as_raw_fd()).context("Failed to detach the log file from the terminal")
+}
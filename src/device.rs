@@ -0,0 +1,496 @@
+use crate::config::Source;
+use crate::diagnose;
+use crate::exit::{self, Class};
+use crate::preflight::AccessError;
+use crate::seat;
+use anyhow::{anyhow, Result};
+use evdev::raw_stream::{self, RawDevice};
+use evdev::{Key, RelativeAxisType};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Candidate prefix selecting by input_id instead of by path
+const VIDPID_PREFIX: &str = "vidpid:";
+
+/// Candidate prefix selecting by exact device name, e.g. `name:keyd virtual keyboard`
+pub const NAME_PREFIX: &str = "name:";
+
+/// How often a `name:` candidate is looked for while waiting on it
+const NAME_POLL: Duration = Duration::from_millis(250);
+
+/// `vidpid:VVVV:PPPP[:N]`: the Nth (from 0) qualifying device with that vendor and product
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VidPid {
+    pub vendor: u16,
+    pub product: u16,
+    pub index: usize,
+}
+
+impl VidPid {
+    /// None when `candidate` is not a vidpid selector at all
+    pub fn parse(candidate: &str) -> Option<Result<Self>> {
+        let spec = candidate.strip_prefix(VIDPID_PREFIX)?;
+        let mut parts = spec.split(':');
+        let mut hex = || {
+            parts
+                .next()
+                .filter(|part| part.len() == 4)
+                .and_then(|part| u16::from_str_radix(part, 16).ok())
+        };
+        let ids = (hex(), hex());
+        let index = match parts.next() {
+            None => Some(0),
+            Some(index) => index.parse().ok(),
+        };
+        Some(match (ids, index, parts.next()) {
+            ((Some(vendor), Some(product)), Some(index), None) => Ok(Self {
+                vendor,
+                product,
+                index,
+            }),
+            _ => Err(anyhow::anyhow!(
+                "'{}': expected vidpid:VVVV:PPPP or vidpid:VVVV:PPPP:N (hex IDs)",
+                candidate
+            )),
+        })
+    }
+
+    pub fn of(device: &RawDevice, index: usize) -> Self {
+        let id = device.input_id();
+        Self {
+            vendor: id.vendor(),
+            product: id.product(),
+            index,
+        }
+    }
+
+    fn matches(&self, device: &RawDevice) -> bool {
+        let id = device.input_id();
+        id.vendor() == self.vendor && id.product() == self.product
+    }
+}
+
+/// The selector as written in a config; the index only when it isn't 0
+impl std::fmt::Display for VidPid {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}{:04x}:{:04x}",
+            VIDPID_PREFIX, self.vendor, self.product
+        )?;
+        if self.index > 0 {
+            write!(f, ":{}", self.index)?;
+        }
+        Ok(())
+    }
+}
+
+/// Every /dev/input/event* node that opens, in event-number order
+pub fn event_nodes() -> Vec<(PathBuf, RawDevice)> {
+    let number = |path: &PathBuf| {
+        path.file_name()
+            .and_then(|name| name.to_str()?.strip_prefix("event")?.parse::<u32>().ok())
+            .unwrap_or(u32::MAX)
+    };
+    let mut nodes: Vec<_> = raw_stream::enumerate().collect();
+    nodes.sort_by_key(|(path, _)| number(path));
+    nodes
+}
+
+/// Bus type of uinput and other software devices, ours included
+pub const BUS_VIRTUAL: u16 = 0x06;
+
+/// Names of the virtual devices this process creates
+static OWN_NAMES: OnceLock<Vec<String>> = OnceLock::new();
+
+/// --verbose, for detail such as each grab attempt
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_verbose(verbose: bool) {
+    VERBOSE.store(verbose, Ordering::Relaxed);
+}
+
+pub fn verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+
+/// Remember our virtual device names, so no candidate ever matches one of them
+pub fn set_own_names(names: Vec<String>) {
+    let _ = OWN_NAMES.set(names);
+}
+
+/// Whether `device` is one of our own virtual devices; other tools' count as real
+pub fn is_own(device: &RawDevice) -> bool {
+    device.input_id().bus_type().0 == BUS_VIRTUAL
+        && device.name().is_some_and(|name| {
+            OWN_NAMES
+                .get()
+                .is_some_and(|names| names.iter().any(|own| own == name))
+        })
+}
+
+/// What a candidate device must look like to be accepted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceKind {
+    Touchpad,
+    Keyboard,
+    /// The external mouse of `[external_mouse]`
+    Mouse,
+}
+
+impl DeviceKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            DeviceKind::Touchpad => "touchpad",
+            DeviceKind::Keyboard => "keyboard",
+            DeviceKind::Mouse => "mouse",
+        }
+    }
+
+    /// Whether a device of this kind may be grabbed, so another process's grab disqualifies it
+    pub fn grabbed(self) -> bool {
+        matches!(self, DeviceKind::Keyboard | DeviceKind::Mouse)
+    }
+
+    /// Why `device` doesn't qualify, if it doesn't
+    pub fn reject_reason(self, device: &RawDevice) -> Option<&'static str> {
+        let keys = device.supported_keys();
+        let has = |key| keys.is_some_and(|keys| keys.contains(key));
+        let axes = device.supported_relative_axes();
+        let has_rel = |axis| axes.is_some_and(|axes| axes.contains(axis));
+        match self {
+            // The release monitor relies on BTN_TOOL_FINGER
+            DeviceKind::Touchpad if !has(Key::BTN_TOOL_FINGER) => Some("no BTN_TOOL_FINGER"),
+            DeviceKind::Keyboard if !(has(Key::KEY_A) && has(Key::KEY_ENTER)) => {
+                Some("no letter keys")
+            }
+            DeviceKind::Mouse if !(has(Key::BTN_LEFT) && has_rel(RelativeAxisType::REL_X)) => {
+                Some("no BTN_LEFT and REL_X")
+            }
+            _ => None,
+        }
+    }
+}
+
+/// What a failed read from an input device means for the reader loop
+#[derive(Debug)]
+pub enum ReadFailure {
+    /// Interrupted or nothing to read after all: just try again
+    Retry,
+    /// The device went away (unplug, suspend): reconnect
+    Gone,
+    Fatal(io::Error),
+}
+
+impl ReadFailure {
+    pub fn classify(err: io::Error) -> Self {
+        match err.raw_os_error() {
+            Some(libc::EINTR | libc::EAGAIN) => ReadFailure::Retry,
+            Some(libc::ENODEV) => ReadFailure::Gone,
+            _ if err.kind() == io::ErrorKind::Interrupted => ReadFailure::Retry,
+            _ => ReadFailure::Fatal(err),
+        }
+    }
+}
+
+/// Name plus phys: stable across re-enumeration even when the event node changes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub name: String,
+    pub phys: String,
+}
+
+impl Identity {
+    pub fn of(device: &RawDevice) -> Self {
+        Self {
+            name: device.name().unwrap_or_default().to_string(),
+            phys: device.physical_path().unwrap_or_default().to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for Identity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "'{}' ({})", self.name, self.phys)
+    }
+}
+
+/// Find the device with `identity` anywhere under /dev/input, on our seat
+pub fn find_by_identity(identity: &Identity) -> Option<(RawDevice, PathBuf)> {
+    raw_stream::enumerate()
+        .find(|(path, device)| Identity::of(device) == *identity && seat::accepts(path))
+        .map(|(path, device)| (device, path))
+}
+
+/// Expand a candidate into paths, plus how many qualifying ones to pass over
+///
+/// Globs match in sorted order, plain paths pass through, and vidpid
+/// selectors match event nodes in order of their number.
+fn expand(candidate: &str) -> (Vec<PathBuf>, usize) {
+    if let Some(name) = candidate.strip_prefix(NAME_PREFIX) {
+        let paths = event_nodes()
+            .into_iter()
+            .filter(|(_, device)| device.name() == Some(name))
+            .map(|(path, _)| path)
+            .collect();
+        return (paths, 0);
+    }
+    if let Some(Ok(selector)) = VidPid::parse(candidate) {
+        let paths = event_nodes()
+            .into_iter()
+            .filter(|(_, device)| selector.matches(device))
+            .map(|(path, _)| path)
+            .collect();
+        return (paths, selector.index);
+    }
+    if !candidate.contains(['*', '?', '[']) {
+        return (vec![PathBuf::from(candidate)], 0);
+    }
+    match glob::glob(candidate) {
+        Ok(paths) => {
+            let mut paths: Vec<_> = paths.filter_map(Result::ok).collect();
+            paths.sort();
+            (paths, 0)
+        }
+        Err(_) => (Vec::new(), 0),
+    }
+}
+
+/// Every existing node the candidates expand to, in the order they are tried
+pub fn candidate_nodes(candidates: &[String]) -> Vec<PathBuf> {
+    let mut nodes = Vec::new();
+    for candidate in candidates {
+        for path in expand(candidate).0 {
+            if path.exists() && !nodes.contains(&path) {
+                nodes.push(path);
+            }
+        }
+    }
+    nodes
+}
+
+/// Whether any of `candidates` names the node at `path` (links resolved)
+pub fn selects(candidates: &[String], path: &Path) -> bool {
+    let Ok(target) = std::fs::canonicalize(path) else {
+        return false;
+    };
+    candidate_nodes(candidates)
+        .iter()
+        .any(|node| std::fs::canonicalize(node).is_ok_and(|node| node == target))
+}
+
+/// Whether another process holds an exclusive grab on `device`, tested by
+/// grabbing it for a moment
+pub fn grabbed_elsewhere(device: &mut RawDevice) -> bool {
+    match device.grab() {
+        Ok(()) => {
+            let _ = device.ungrab();
+            false
+        }
+        Err(err) => err.raw_os_error() == Some(libc::EBUSY),
+    }
+}
+
+/// At startup, give a `name:` candidate that matches nothing yet up to
+/// `timeout` to appear: keyd and the like create theirs when they start
+pub fn wait_for_named(label: &str, candidates: &[String], timeout: Duration) {
+    let named = candidates.iter().any(|c| c.starts_with(NAME_PREFIX));
+    if !named || timeout.is_zero() || !candidate_nodes(candidates).is_empty() {
+        return;
+    }
+    eprintln!(
+        "⏳ Waiting up to {}ms for the {} to appear...",
+        timeout.as_millis(),
+        label
+    );
+    let started = Instant::now();
+    while started.elapsed() < timeout {
+        thread::sleep(NAME_POLL);
+        if !candidate_nodes(candidates).is_empty() {
+            eprintln!(
+                "   ✓ {} appeared after {}ms",
+                label,
+                started.elapsed().as_millis()
+            );
+            return;
+        }
+    }
+    eprintln!("   {} still missing", label);
+}
+
+/// Try candidates in order, returning the first match or every open failure
+///
+/// Without `probe_grabs`, a pattern doesn't skip devices another process has
+/// grabbed, since finding out takes a grab of our own.
+fn try_candidates(
+    kind: DeviceKind,
+    candidates: &[String],
+    verbose: bool,
+    probe_grabs: bool,
+) -> std::result::Result<(RawDevice, PathBuf), Vec<(PathBuf, io::Error)>> {
+    let mut failures: Vec<(PathBuf, io::Error)> = Vec::new();
+
+    for candidate in candidates {
+        let (paths, mut skip) = expand(candidate);
+        if paths.is_empty() && verbose {
+            eprintln!("   ⏭  {}: no matches", candidate);
+        }
+
+        // An explicit path or name is taken as meant; a pattern skips what is already grabbed
+        let pattern = VidPid::parse(candidate).is_some() || candidate.contains(['*', '?', '[']);
+        // Only an explicit path may name another seat's device
+        let detected = pattern || candidate.starts_with(NAME_PREFIX);
+        for path in paths {
+            let mut device = match RawDevice::open(&path) {
+                Ok(device) => device,
+                Err(err) => {
+                    if verbose {
+                        eprintln!("   ⏭  {}: {}", path.display(), err);
+                    }
+                    failures.push((path, err));
+                    continue;
+                }
+            };
+            let reason = if is_own(&device) {
+                Some("our own virtual device")
+            } else if detected && !seat::accepts(&path) {
+                Some("on another seat")
+            } else if let Some(reason) = kind.reject_reason(&device) {
+                Some(reason)
+            } else if probe_grabs && pattern && kind.grabbed() && grabbed_elsewhere(&mut device) {
+                Some("grabbed by another process")
+            } else {
+                None
+            };
+            if let Some(reason) = reason {
+                if verbose {
+                    eprintln!(
+                        "   ⏭  {} ({}): {}",
+                        path.display(),
+                        device.name().unwrap_or("unnamed"),
+                        reason
+                    );
+                }
+                continue;
+            }
+            if skip > 0 {
+                skip -= 1;
+                if verbose {
+                    eprintln!(
+                        "   ⏭  {}: not the requested index of {}",
+                        path.display(),
+                        candidate
+                    );
+                }
+                continue;
+            }
+
+            eprintln!(
+                "   ✓ Using {} ({})",
+                path.display(),
+                device.name().unwrap_or("unnamed")
+            );
+            return Ok((device, path));
+        }
+    }
+
+    Err(failures)
+}
+
+/// Re-run candidate matching after a device went away, without the diagnostics
+pub fn reopen(kind: DeviceKind, candidates: &[String]) -> Option<(RawDevice, PathBuf)> {
+    try_candidates(kind, candidates, false, true).ok()
+}
+
+/// As `reopen`, but without grabbing anything, not even for a moment
+pub fn resolve_passive(kind: DeviceKind, candidates: &[String]) -> Option<(RawDevice, PathBuf)> {
+    try_candidates(kind, candidates, false, false).ok()
+}
+
+/// Open the first candidate that exists and has the expected capabilities
+///
+/// When none qualifies, explains each open failure and lists what is available.
+pub fn open_first(
+    kind: DeviceKind,
+    candidates: &[String],
+    source: &Source,
+) -> Result<(RawDevice, PathBuf)> {
+    let failures = match try_candidates(kind, candidates, true, true) {
+        Ok(found) => return Ok(found),
+        Err(failures) => failures,
+    };
+
+    eprintln!();
+    for (path, err) in &failures {
+        diagnose::explain_open_failure(kind.label(), path, err, &source.to_string());
+    }
+    diagnose::list_available_devices();
+    eprintln!();
+
+    let denied = failures
+        .iter()
+        .find(|(_, err)| matches!(err.raw_os_error(), Some(libc::EACCES) | Some(libc::EPERM)));
+    if let Some((path, _)) = denied {
+        return Err(
+            anyhow::Error::new(AccessError::EventNode(path.clone())).context(format!(
+                "No usable {} among candidates: {}",
+                kind.label(),
+                candidates.join(", ")
+            )),
+        );
+    }
+
+    Err(exit::tag(
+        Class::DeviceNotFound,
+        anyhow!(
+            "No usable {} among candidates: {}",
+            kind.label(),
+            candidates.join(", ")
+        ),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classify(errno: i32) -> ReadFailure {
+        ReadFailure::classify(io::Error::from_raw_os_error(errno))
+    }
+
+    #[test]
+    fn interrupted_and_empty_reads_are_retried() {
+        assert!(matches!(classify(libc::EINTR), ReadFailure::Retry));
+        assert!(matches!(classify(libc::EAGAIN), ReadFailure::Retry));
+        assert!(matches!(classify(libc::EWOULDBLOCK), ReadFailure::Retry));
+        let interrupted = io::Error::from(io::ErrorKind::Interrupted);
+        assert!(matches!(
+            ReadFailure::classify(interrupted),
+            ReadFailure::Retry
+        ));
+    }
+
+    #[test]
+    fn a_vanished_device_is_gone() {
+        assert!(matches!(classify(libc::ENODEV), ReadFailure::Gone));
+    }
+
+    #[test]
+    fn any_other_errno_is_fatal_and_kept() {
+        for errno in [libc::EIO, libc::EBADF, libc::EINVAL, libc::EACCES] {
+            match classify(errno) {
+                ReadFailure::Fatal(err) => assert_eq!(err.raw_os_error(), Some(errno)),
+                other => panic!("errno {} classified as {:?}", errno, other),
+            }
+        }
+        let other = io::Error::other("not from the kernel");
+        assert!(matches!(
+            ReadFailure::classify(other),
+            ReadFailure::Fatal(_)
+        ));
+    }
+}
@@ -0,0 +1,229 @@
+use crate::device::{self, DeviceKind, VidPid};
+use crate::preflight;
+use crate::seat;
+use evdev::raw_stream::RawDevice;
+use evdev::{Key, RelativeAxisType};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+/// Directories of stable symlinks users should put in their config
+const LINK_DIRS: [&str; 2] = ["/dev/input/by-path", "/dev/input/by-id"];
+
+/// Owner, mode and group membership of a device node
+#[derive(Debug, Clone)]
+pub struct NodeAccess {
+    pub mode: u32,
+    pub gid: u32,
+    pub group: String,
+    /// Whether this process currently has the owning group
+    pub member: bool,
+}
+
+impl NodeAccess {
+    pub fn of(path: &Path) -> io::Result<Self> {
+        let meta = fs::metadata(path)?;
+        let gid = meta.gid();
+        Ok(Self {
+            mode: meta.permissions().mode() & 0o777,
+            gid,
+            group: group_name(gid),
+            member: has_group(gid),
+        })
+    }
+}
+
+/// Look up a group name in /etc/group, falling back to the numeric id
+pub fn group_name(gid: u32) -> String {
+    fs::read_to_string("/etc/group")
+        .ok()
+        .and_then(|groups| {
+            groups.lines().find_map(|line| {
+                let mut fields = line.split(':');
+                let name = fields.next()?;
+                let id = fields.nth(1)?.parse::<u32>().ok()?;
+                (id == gid).then(|| name.to_string())
+            })
+        })
+        .unwrap_or_else(|| gid.to_string())
+}
+
+/// Whether the running process has `gid` as its primary or a supplementary group
+pub fn has_group(gid: u32) -> bool {
+    // SAFETY: getegid has no preconditions; getgroups writes at most `len` entries
+    unsafe {
+        if libc::getegid() == gid {
+            return true;
+        }
+        let len = libc::getgroups(0, std::ptr::null_mut());
+        if len <= 0 {
+            return false;
+        }
+        let mut groups = vec![0 as libc::gid_t; len as usize];
+        let len = libc::getgroups(len, groups.as_mut_ptr());
+        groups.truncate(len.max(0) as usize);
+        groups.contains(&gid)
+    }
+}
+
+/// Rough classification of a device, to help pick the right link
+fn capability_hint(device: &RawDevice) -> &'static str {
+    let keys = device.supported_keys();
+    let has = |key| keys.is_some_and(|keys| keys.contains(key));
+    if has(Key::BTN_TOOL_FINGER) {
+        "touchpad"
+    } else if has(Key::KEY_A) && has(Key::KEY_ENTER) {
+        "keyboard"
+    } else if device
+        .supported_relative_axes()
+        .is_some_and(|axes| axes.contains(RelativeAxisType::REL_X))
+    {
+        "mouse"
+    } else {
+        "other"
+    }
+}
+
+fn links() -> Vec<PathBuf> {
+    let mut links: Vec<_> = LINK_DIRS
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .collect();
+    links.sort();
+    links
+}
+
+/// Links in the same directory whose names share most of the failing name
+fn near_misses(path: &Path) -> Vec<PathBuf> {
+    let name = path.file_name().and_then(|n| n.to_str());
+    let (Some(dir), Some(name)) = (path.parent(), name) else {
+        return Vec::new();
+    };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut misses: Vec<_> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            let other = entry.file_name();
+            let other = other.to_string_lossy();
+            let common = name
+                .chars()
+                .zip(other.chars())
+                .take_while(|(a, b)| a == b)
+                .count();
+            other != name && common >= 8 && common * 2 >= name.len()
+        })
+        .map(|entry| entry.path())
+        .collect();
+    misses.sort();
+    misses
+}
+
+/// Print everything useful about a failed open: why, where the path came from, and what exists
+pub fn explain_open_failure(label: &str, path: &Path, err: &io::Error, source: &str) {
+    eprintln!("❌ Could not open {} {}: {}", label, path.display(), err);
+    eprintln!("   Path came from: {}", source);
+
+    match err.kind() {
+        io::ErrorKind::PermissionDenied => {
+            preflight::print_check(&preflight::check_event_node(path));
+        }
+        io::ErrorKind::NotFound => {
+            let misses = near_misses(path);
+            if !misses.is_empty() {
+                eprintln!("   Similar names exist:");
+                for miss in misses {
+                    eprintln!("     {}", miss.display());
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// List the by-path/by-id links with device names and a capability hint
+pub fn list_available_devices() {
+    let links = links();
+    if links.is_empty() {
+        eprintln!("   No links found under {}", LINK_DIRS.join(" or "));
+        return;
+    }
+
+    eprintln!("   Available devices:");
+    for link in links {
+        match RawDevice::open(&link) {
+            Ok(device) => eprintln!(
+                "     {} → {} [{}]",
+                link.display(),
+                device.name().unwrap_or("unnamed"),
+                capability_hint(&device)
+            ),
+            Err(err) => eprintln!("     {} (cannot open: {})", link.display(), err),
+        }
+    }
+    eprintln!("   `touchpad-remap list-devices` also shows vidpid: selectors");
+}
+
+/// `list-devices`: every event node, with a selector that can go straight into the config
+///
+/// Touchpads and keyboards get the vidpid selector that picks exactly that
+/// node, index included, plus any by-path/by-id links to it.
+pub fn list_devices() {
+    let mut links_to: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+    for link in links() {
+        if let Ok(target) = fs::canonicalize(&link) {
+            links_to.entry(target).or_default().push(link);
+        }
+    }
+
+    let nodes = device::event_nodes();
+    if nodes.is_empty() {
+        println!("No readable event devices under /dev/input (try as root or in group input)");
+        return;
+    }
+    let mut seen: HashMap<(u16, u16, &str), usize> = HashMap::new();
+    for (path, device) in &nodes {
+        let kind = [DeviceKind::Touchpad, DeviceKind::Keyboard]
+            .into_iter()
+            .find(|kind| kind.reject_reason(device).is_none());
+        let hint = kind.map_or(capability_hint(device), DeviceKind::label);
+        let id = VidPid::of(device, 0);
+        // The index counts only devices of the same kind, as matching does
+        let index = match kind {
+            Some(kind) => {
+                let count = seen
+                    .entry((id.vendor, id.product, kind.label()))
+                    .or_default();
+                *count += 1;
+                *count - 1
+            }
+            None => 0,
+        };
+        println!(
+            "{}  {}  [{}]  {}",
+            path.display(),
+            VidPid { index, ..id },
+            hint,
+            device.name().unwrap_or("unnamed")
+        );
+        for link in links_to.get(path).into_iter().flatten() {
+            println!("    {}", link.display());
+        }
+        // Virtual devices (keyd, kmonad) have no links, and their name is stable
+        if device.input_id().bus_type().0 == device::BUS_VIRTUAL {
+            if let Some(name) = device.name() {
+                println!("    {}{}", device::NAME_PREFIX, name);
+            }
+        }
+        let seat = seat::seat_of(Path::new(seat::UDEV_DATA), path);
+        if seat != seat::DEFAULT_SEAT {
+            println!("    on {}", seat);
+        }
+    }
+}
@@ -0,0 +1,435 @@
+//! `doctor`: the daemon's whole environment checked in one go
+//!
+//! Runs the startup checks, resolves the configured devices, creates and
+//! destroys a throwaway uinput device, looks for other programs that may hold
+//! a grab, and tries the libinput backend on the touchpad. Devices are only
+//! opened to identify them: nothing is ever grabbed, not even for a moment,
+//! and the test device is gone before the next check runs.
+
+use crate::condition::Signal;
+use crate::config::Config;
+use crate::device::{self, DeviceKind};
+use crate::instance;
+use crate::libinput::{self, Kind};
+use crate::preflight::{self, Check};
+use anyhow::{bail, Result};
+use evdev::uinput::VirtualDeviceBuilder;
+use evdev::{AttributeSet, Key};
+use serde::Serialize;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// How long libinput gets to report the touchpad
+const LIBINPUT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Programs that grab keyboards or touchpads themselves, by /proc comm prefix
+const GRABBERS: &[&str] = &[
+    "keyd",
+    "kmonad",
+    "kanata",
+    "udevmon",
+    "intercept",
+    "evremap",
+    "xremap",
+    "input-remapper",
+    "evsieve",
+    "makima",
+    "touchpad-remap",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Level {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// One check's outcome, with what was found and how to fix it
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    pub name: String,
+    pub level: Level,
+    pub details: Vec<String>,
+}
+
+impl Finding {
+    fn new(name: impl Into<String>, level: Level, details: Vec<String>) -> Self {
+        Self {
+            name: name.into(),
+            level,
+            details,
+        }
+    }
+
+    /// A preflight check, which either passes or fails
+    fn from_check(check: Check) -> Self {
+        let level = if check.ok { Level::Pass } else { Level::Fail };
+        Self::new(check.name, level, check.details)
+    }
+}
+
+/// The `--json` document
+#[derive(Debug, Serialize)]
+struct Report<'a> {
+    findings: &'a [Finding],
+    passed: usize,
+    warnings: usize,
+    failed: usize,
+}
+
+/// Run every check and print the findings; fails if any check failed
+pub fn run(
+    config: Result<Config>,
+    backend: &str,
+    libinput_version: Option<String>,
+    json: bool,
+) -> Result<()> {
+    let mut findings = Vec::new();
+    let config = match config {
+        Ok(config) => {
+            let details = if config.layers.is_empty() {
+                vec!["built-in defaults (no config file found)".into()]
+            } else {
+                config
+                    .layers
+                    .iter()
+                    .map(|layer| format!("layer {}", layer.display()))
+                    .collect()
+            };
+            findings.push(Finding::new("load config", Level::Pass, details));
+            Some(config)
+        }
+        Err(err) => {
+            findings.push(Finding::new(
+                "load config",
+                Level::Fail,
+                vec![
+                    format!("{:#}", err),
+                    "`touchpad-remap check` validates it; device checks are skipped".into(),
+                ],
+            ));
+            None
+        }
+    };
+
+    let touchpad = config.as_ref().and_then(|config| {
+        let touchpad = resolve(&mut findings, DeviceKind::Touchpad, &config.touchpad);
+        let keyboard = resolve(&mut findings, DeviceKind::Keyboard, &config.keyboard);
+        for path in [&touchpad, &keyboard].into_iter().flatten() {
+            findings.push(competing_grabs(path));
+        }
+        touchpad
+    });
+    findings.push(running_instance());
+
+    // The mouse and touchpad proxies are uinput devices whatever the backend
+    let needs_uinput = backend == "uinput"
+        || backend == "auto"
+        || config.as_ref().is_some_and(|config| {
+            config.external_mouse.is_some() || config.touchpad_buttons.is_some()
+        });
+    findings.push(uinput(needs_uinput));
+    findings.push(session(backend));
+    findings.extend(backend_libinput(
+        config.as_ref(),
+        libinput_version,
+        touchpad.as_deref(),
+    ));
+
+    let count = |level| findings.iter().filter(|f| f.level == level).count();
+    let (passed, warnings, failed) = (count(Level::Pass), count(Level::Warn), count(Level::Fail));
+    if json {
+        let report = Report {
+            findings: &findings,
+            passed,
+            warnings,
+            failed,
+        };
+        println!("{}", serde_json::to_string(&report)?);
+    } else {
+        eprintln!("🩺 Doctor:");
+        for finding in &findings {
+            let mark = match finding.level {
+                Level::Pass => "✓",
+                Level::Warn => "⚠",
+                Level::Fail => "✗",
+            };
+            eprintln!("   {} {}", mark, finding.name);
+            for detail in &finding.details {
+                eprintln!("       {}", detail);
+            }
+        }
+        eprintln!(
+            "🩺 {} passed, {} warning{}, {} failed",
+            passed,
+            warnings,
+            if warnings == 1 { "" } else { "s" },
+            failed
+        );
+    }
+    if failed > 0 {
+        bail!(
+            "{} doctor check{} failed",
+            failed,
+            if failed == 1 { "" } else { "s" }
+        );
+    }
+    Ok(())
+}
+
+/// Read access to every candidate node, then which one would be used
+fn resolve(
+    findings: &mut Vec<Finding>,
+    kind: DeviceKind,
+    candidates: &[String],
+) -> Option<PathBuf> {
+    let name = format!("find {}", kind.label());
+    let nodes = device::candidate_nodes(candidates);
+    if nodes.is_empty() {
+        findings.push(Finding::new(
+            name,
+            Level::Fail,
+            vec![
+                format!("no node matches {}", candidates.join(", ")),
+                "`touchpad-remap list-devices` shows what is available".into(),
+            ],
+        ));
+        return None;
+    }
+    let checks: Vec<Check> = nodes
+        .iter()
+        .map(|node| preflight::check_event_node(node))
+        .collect();
+    // Unreadable fallbacks don't matter once one candidate works
+    if !checks.iter().any(|check| check.ok) {
+        findings.extend(checks.into_iter().map(Finding::from_check));
+        return None;
+    }
+    match device::resolve_passive(kind, candidates) {
+        Some((device, path)) => {
+            findings.push(Finding::new(
+                name,
+                Level::Pass,
+                vec![format!(
+                    "{} ({})",
+                    path.display(),
+                    device.name().unwrap_or("unnamed")
+                )],
+            ));
+            Some(path)
+        }
+        None => {
+            findings.push(Finding::new(
+                name,
+                Level::Fail,
+                vec![
+                    format!(
+                        "none of {} looks like a {}",
+                        candidates.join(", "),
+                        kind.label()
+                    ),
+                    "`touchpad-remap list-devices` shows what each device is".into(),
+                ],
+            ));
+            None
+        }
+    }
+}
+
+/// Other grabbers with the node open; who actually holds a grab can only be
+/// learned by grabbing, which doctor never does
+fn competing_grabs(path: &Path) -> Finding {
+    let name = format!("other grabbers of {}", path.display());
+    let grabbers: Vec<String> = preflight::device_holders(path)
+        .into_iter()
+        .filter(|(_, comm)| GRABBERS.iter().any(|grabber| comm.starts_with(grabber)))
+        .map(|(pid, comm)| format!("{} {}", pid, comm))
+        .collect();
+    if grabbers.is_empty() {
+        return Finding::new(name, Level::Pass, Vec::new());
+    }
+    let mut details = vec!["held open by a program that grabs input devices:".into()];
+    details.extend(grabbers.into_iter().map(|grabber| format!("  {}", grabber)));
+    details.push("stop it, or point the config at the device it creates instead".into());
+    Finding::new(name, Level::Warn, details)
+}
+
+fn running_instance() -> Finding {
+    let name = "no instance running";
+    match instance::running() {
+        None => Finding::new(name, Level::Pass, Vec::new()),
+        Some(pid) => Finding::new(
+            name,
+            Level::Warn,
+            vec![
+                match pid {
+                    Some(pid) => format!("already running as PID {}", pid),
+                    None => format!("{} is locked", instance::pid_file_path().display()),
+                },
+                "it holds the keyboard grab; a new start needs --replace".into(),
+            ],
+        ),
+    }
+}
+
+/// The preflight check, then a real device created and destroyed
+fn uinput(needed: bool) -> Finding {
+    let mut finding = Finding::from_check(preflight::check_uinput());
+    if finding.level == Level::Pass {
+        if let Err(err) = create_test_device() {
+            finding.level = Level::Fail;
+            finding
+                .details
+                .push(format!("creating a test device failed: {}", err));
+        } else {
+            finding
+                .details
+                .push("created and destroyed a test device".into());
+        }
+    }
+    if finding.level == Level::Fail && !needed {
+        finding.level = Level::Warn;
+        finding
+            .details
+            .push("not needed with this backend and config".into());
+    }
+    finding
+}
+
+fn create_test_device() -> std::io::Result<()> {
+    let keys = AttributeSet::from_iter([Key::KEY_A]);
+    // Destroyed when dropped, at the end of this statement
+    VirtualDeviceBuilder::new()?
+        .name("touch-layer doctor")
+        .with_keys(&keys)?
+        .build()?;
+    Ok(())
+}
+
+/// What the session offers the output backend
+fn session(backend: &str) -> Finding {
+    let var = |name| std::env::var(name).ok().filter(|value| !value.is_empty());
+    let (wayland, display) = (var("WAYLAND_DISPLAY"), var("DISPLAY"));
+    let mut details = vec![format!(
+        "XDG_SESSION_TYPE={}, WAYLAND_DISPLAY={}, DISPLAY={}",
+        var("XDG_SESSION_TYPE").as_deref().unwrap_or("(unset)"),
+        wayland.as_deref().unwrap_or("(unset)"),
+        display.as_deref().unwrap_or("(unset)")
+    )];
+    let level = match backend {
+        "wayland" if wayland.is_none() => {
+            details.push("the wayland backend needs WAYLAND_DISPLAY".into());
+            Level::Fail
+        }
+        "xtest" if display.is_none() => {
+            details.push("the xtest backend needs DISPLAY".into());
+            Level::Fail
+        }
+        "uinput" | "auto" if wayland.is_some() => {
+            details.push(
+                "if clicks never arrive, the compositor may be ignoring virtual devices; \
+                 try --backend wayland"
+                    .into(),
+            );
+            Level::Pass
+        }
+        _ => Level::Pass,
+    };
+    Finding::new(
+        format!("session for the {} backend", backend),
+        level,
+        details,
+    )
+}
+
+/// libinput is installed, new enough for the config, and reports the touchpad
+fn backend_libinput(
+    config: Option<&Config>,
+    version: Option<String>,
+    touchpad: Option<&Path>,
+) -> Vec<Finding> {
+    let Some(version) = version else {
+        return vec![Finding::new(
+            "libinput",
+            Level::Fail,
+            vec![
+                "`libinput` not found: install libinput-tools (or your distro's equivalent)".into(),
+            ],
+        )];
+    };
+    let hold = config.is_some_and(|config| config.activate_when.mentions(Signal::Hold));
+    let mut findings = vec![if hold && !libinput::has_hold_gestures(Some(&version)) {
+        Finding::new(
+            "libinput",
+            Level::Warn,
+            vec![
+                format!("version {}", version),
+                "hold gestures need 1.19; `hold` in activate_when never turns the mode on".into(),
+            ],
+        )
+    } else {
+        Finding::new(
+            "libinput",
+            Level::Pass,
+            vec![format!("version {}", version)],
+        )
+    }];
+    if let Some(touchpad) = touchpad {
+        findings.push(libinput_sees(touchpad));
+    }
+    findings
+}
+
+/// Run `libinput debug-events` on the touchpad until it reports the device
+fn libinput_sees(touchpad: &Path) -> Finding {
+    let name = format!("libinput reads {}", touchpad.display());
+    let child = Command::new("libinput")
+        .arg("debug-events")
+        .arg("--device")
+        .arg(touchpad)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(err) => {
+            return Finding::new(name, Level::Fail, vec![format!("failed to run: {}", err)])
+        }
+    };
+    let (tx, rx) = mpsc::channel();
+    if let Some(stdout) = child.stdout.take() {
+        thread::spawn(move || {
+            let added = BufReader::new(stdout)
+                .lines()
+                .map_while(|line| line.ok())
+                .any(|line| {
+                    libinput::parse(&line).is_some_and(|line| line.kind == Kind::DeviceAdded)
+                });
+            let _ = tx.send(added);
+        });
+    }
+    let added = rx.recv_timeout(LIBINPUT_TIMEOUT).unwrap_or(false);
+    let _ = child.kill();
+    let _ = child.wait();
+    if added {
+        Finding::new(name, Level::Pass, Vec::new())
+    } else {
+        Finding::new(
+            name,
+            Level::Fail,
+            vec![
+                format!("no DEVICE_ADDED within {}s", LIBINPUT_TIMEOUT.as_secs()),
+                format!(
+                    "try `libinput debug-events --device {}`",
+                    touchpad.display()
+                ),
+            ],
+        )
+    }
+}
@@ -0,0 +1,162 @@
+//! `dump-device PATH`: what the kernel reports for one event node, evtest-style
+//!
+//! Prints the name, IDs, properties and capabilities, then every event with
+//! the time since the previous one until interrupted. `--grab` takes the
+//! device exclusively, which fails if another process is already consuming it.
+
+use crate::device::{ReadFailure, VidPid};
+use crate::exit::{self, Class};
+use crate::preflight::AccessError;
+use anyhow::{anyhow, Context, Result};
+use evdev::raw_stream::RawDevice;
+use evdev::{InputEvent, InputEventKind};
+use std::fmt::Debug;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+pub fn run(path: &Path, grab: bool) -> Result<()> {
+    let mut device = RawDevice::open(path).map_err(|err| {
+        let message = format!("Failed to open {}", path.display());
+        match err.kind() {
+            io::ErrorKind::NotFound => {
+                exit::tag(Class::DeviceNotFound, anyhow!(err).context(message))
+            }
+            io::ErrorKind::PermissionDenied => {
+                anyhow::Error::new(AccessError::EventNode(path.to_path_buf())).context(message)
+            }
+            _ => anyhow!(err).context(message),
+        }
+    })?;
+
+    print_header(path, &device);
+
+    if grab {
+        if let Err(err) = device.grab() {
+            let hint = if err.raw_os_error() == Some(libc::EBUSY) {
+                "another process holds the grab and gets every event first"
+            } else {
+                "EVIOCGRAB failed"
+            };
+            return Err(anyhow::Error::new(AccessError::Grab(path.to_path_buf()))
+                .context(format!("{}: {}", hint, err)));
+        }
+        println!("Grabbed: other readers see nothing until this exits");
+    }
+
+    println!("Events (Ctrl-C to stop):");
+    let mut previous = None;
+    loop {
+        match device.fetch_events() {
+            Ok(events) => {
+                for event in events {
+                    println!("{}", describe(&event, previous));
+                    previous = Some(event.timestamp());
+                }
+            }
+            Err(err) => match ReadFailure::classify(err) {
+                ReadFailure::Retry => continue,
+                ReadFailure::Gone => return Err(anyhow!("{} went away", path.display())),
+                ReadFailure::Fatal(err) => return Err(err).context("Failed to fetch events"),
+            },
+        }
+    }
+}
+
+fn print_header(path: &Path, device: &RawDevice) {
+    let id = device.input_id();
+    println!("Device:     {}", device.name().unwrap_or("unnamed"));
+    println!("Path:       {}", path.display());
+    if let Some(phys) = device.physical_path().filter(|phys| !phys.is_empty()) {
+        println!("Phys:       {}", phys);
+    }
+    if let Some(uniq) = device.unique_name().filter(|uniq| !uniq.is_empty()) {
+        println!("Uniq:       {}", uniq);
+    }
+    println!(
+        "ID:         bus {:#x} vendor {:#06x} product {:#06x} version {:#x} ({})",
+        id.bus_type().0,
+        id.vendor(),
+        id.product(),
+        id.version(),
+        VidPid::of(device, 0)
+    );
+    print_set("Properties:", device.properties().iter());
+    print_set("Events:", device.supported_events().iter());
+    if let Some(keys) = device.supported_keys() {
+        print_set("Keys:", keys.iter());
+    }
+    if let Some(axes) = device.supported_relative_axes() {
+        print_set("Relative:", axes.iter());
+    }
+    if let Some(axes) = device.supported_absolute_axes() {
+        println!("Absolute:");
+        let state = device.get_abs_state().ok();
+        for axis in axes.iter() {
+            match state.as_ref().map(|state| state[axis.0 as usize]) {
+                Some(info) => println!(
+                    "  {:<24} value {} min {} max {} fuzz {} flat {} resolution {}",
+                    format!("{:?}", axis),
+                    info.value,
+                    info.minimum,
+                    info.maximum,
+                    info.fuzz,
+                    info.flat,
+                    info.resolution
+                ),
+                None => println!("  {:?}", axis),
+            }
+        }
+    }
+    if let Some(switches) = device.supported_switches() {
+        print_set("Switches:", switches.iter());
+    }
+    if let Some(leds) = device.supported_leds() {
+        print_set("LEDs:", leds.iter());
+    }
+    if let Some(misc) = device.misc_properties() {
+        print_set("Misc:", misc.iter());
+    }
+}
+
+/// `label` and the names in `set`, or `(none)`
+fn print_set<T: Debug>(label: &str, set: impl Iterator<Item = T>) {
+    let names: Vec<String> = set.map(|item| format!("{:?}", item)).collect();
+    let names = if names.is_empty() {
+        "(none)".to_string()
+    } else {
+        names.join(" ")
+    };
+    println!("{:<11} {}", label, names);
+}
+
+/// One event as `+0.008012s  EV_REL  REL_X  -3`, timed from the `previous` one
+///
+/// The first event of a dump gets `+0.000000s`.
+pub fn describe(event: &InputEvent, previous: Option<SystemTime>) -> String {
+    let delta = previous
+        .and_then(|previous| event.timestamp().duration_since(previous).ok())
+        .unwrap_or_default();
+    let (kind, code) = match event.kind() {
+        InputEventKind::Synchronization(sync) => ("EV_SYN", format!("{:?}", sync)),
+        InputEventKind::Key(key) => ("EV_KEY", format!("{:?}", key)),
+        InputEventKind::RelAxis(axis) => ("EV_REL", format!("{:?}", axis)),
+        InputEventKind::AbsAxis(axis) => ("EV_ABS", format!("{:?}", axis)),
+        InputEventKind::Misc(misc) => ("EV_MSC", format!("{:?}", misc)),
+        InputEventKind::Switch(switch) => ("EV_SW", format!("{:?}", switch)),
+        InputEventKind::Led(led) => ("EV_LED", format!("{:?}", led)),
+        InputEventKind::Sound(sound) => ("EV_SND", format!("{:?}", sound)),
+        _ => (
+            "EV_OTHER",
+            format!("type {} code {}", event.event_type().0, event.code()),
+        ),
+    };
+    format!(
+        "+{}.{:06}s  {:<6}  {:<24}  {}",
+        delta.as_secs(),
+        delta.subsec_micros(),
+        kind,
+        code,
+        event.value()
+    )
+}
@@ -0,0 +1,205 @@
+//! Exit statuses, one per failure class (sysexits.h values)
+//!
+//! Every run ends in `finish`, which reads the class off the error chain:
+//! [`AccessError`] for the permission walls, a [`tag`] for the other classes.
+//! Anything unclassified, such as a monitor giving up mid-run, is 1.
+
+use crate::daemon;
+use crate::logfile;
+use crate::preflight::AccessError;
+use crate::report::{self, Record};
+use anyhow::Result;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_DEVICE_NOT_FOUND: i32 = 69;
+pub const EXIT_INTERNAL: i32 = 70;
+pub const EXIT_UINPUT_UNAVAILABLE: i32 = 72;
+pub const EXIT_ALREADY_RUNNING: i32 = 73;
+pub const EXIT_GRAB_CONFLICT: i32 = 75;
+pub const EXIT_PERMISSION: i32 = 77;
+pub const EXIT_CONFIG: i32 = 78;
+
+/// Failure classes marked where the error starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    /// The config layers or environment overrides don't load; restarting won't help
+    Config,
+    /// No candidate device exists
+    DeviceNotFound,
+    /// A panic, ours or a monitor's
+    Internal,
+}
+
+impl Class {
+    fn exit_code(self) -> i32 {
+        match self {
+            Class::Config => EXIT_CONFIG,
+            Class::DeviceNotFound => EXIT_DEVICE_NOT_FOUND,
+            Class::Internal => EXIT_INTERNAL,
+        }
+    }
+}
+
+/// `inner` marked with its class; reads exactly like `inner`
+struct Tagged {
+    class: Class,
+    inner: anyhow::Error,
+}
+
+impl fmt::Debug for Tagged {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.inner, f)
+    }
+}
+
+impl fmt::Display for Tagged {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.inner, f)
+    }
+}
+
+impl Error for Tagged {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.inner.source()
+    }
+}
+
+/// Mark `err` as `class` without changing its message
+pub fn tag(class: Class, err: anyhow::Error) -> anyhow::Error {
+    anyhow::Error::new(Tagged { class, inner: err })
+}
+
+/// The exit status for `err`: the outermost classified cause wins
+pub fn code(err: &anyhow::Error) -> i32 {
+    for cause in err.chain() {
+        if let Some(access) = cause.downcast_ref::<AccessError>() {
+            return access.exit_code();
+        }
+        if let Some(tagged) = cause.downcast_ref::<Tagged>() {
+            return tagged.class.exit_code();
+        }
+    }
+    let denied = err.chain().any(|cause| {
+        cause
+            .downcast_ref::<io::Error>()
+            .is_some_and(|err| err.kind() == io::ErrorKind::PermissionDenied)
+    });
+    if denied {
+        EXIT_PERMISSION
+    } else {
+        EXIT_FAILURE
+    }
+}
+
+/// Report the outcome and exit with its status
+///
+/// Exits explicitly: dropping the runtime would wait on the blocked monitor threads.
+pub fn finish(result: std::thread::Result<Result<()>>) -> ! {
+    let err = match result {
+        Ok(Ok(())) => {
+            logfile::finish();
+            std::process::exit(EXIT_OK)
+        }
+        Ok(Err(err)) => err,
+        // The panic message is already on stderr
+        Err(_) => tag(Class::Internal, anyhow::anyhow!("Internal error (panic)")),
+    };
+    eprintln!("Error: {:?}", err);
+    report::emit(Record::Error {
+        message: format!("{:#}", err),
+    });
+    let code = code(&err);
+    daemon::report_exit(code);
+    logfile::finish();
+    std::process::exit(code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use anyhow::{anyhow, Context};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn an_invalid_config_is_a_config_error() {
+        let err = Config::parse("debounce_ms = \"soon\"", Path::new("test.toml"))
+            .context("Failed to start")
+            .unwrap_err();
+        assert_eq!(code(&err), EXIT_CONFIG);
+    }
+
+    #[test]
+    fn a_missing_device_is_device_not_found() {
+        let err = tag(Class::DeviceNotFound, anyhow!("No touchpad found"))
+            .context("Failed to open devices");
+        assert_eq!(code(&err), EXIT_DEVICE_NOT_FOUND);
+    }
+
+    #[test]
+    fn permission_walls_are_permission_errors() {
+        let node = anyhow::Error::new(AccessError::EventNode(PathBuf::from("/dev/input/event7")))
+            .context("Failed to open the keyboard");
+        assert_eq!(code(&node), EXIT_PERMISSION);
+
+        // A bare EACCES anywhere in the chain counts too
+        let denied = Err::<(), _>(io::Error::from(io::ErrorKind::PermissionDenied))
+            .context("Failed to open /dev/input/event7")
+            .context("Failed to start")
+            .unwrap_err();
+        assert_eq!(code(&denied), EXIT_PERMISSION);
+    }
+
+    #[test]
+    fn a_failed_grab_is_a_grab_conflict() {
+        let err = anyhow::Error::new(AccessError::Grab(PathBuf::from("/dev/input/event3")))
+            .context(io::Error::from_raw_os_error(libc::EBUSY));
+        assert_eq!(code(&err), EXIT_GRAB_CONFLICT);
+    }
+
+    #[test]
+    fn uinput_and_a_second_instance_have_their_own_codes() {
+        let uinput = anyhow::Error::new(AccessError::Uinput).context("Failed to create outputs");
+        assert_eq!(code(&uinput), EXIT_UINPUT_UNAVAILABLE);
+        let running = anyhow::Error::new(AccessError::AlreadyRunning(Some(42)));
+        assert_eq!(code(&running), EXIT_ALREADY_RUNNING);
+    }
+
+    #[test]
+    fn a_panic_is_an_internal_error() {
+        let err = tag(Class::Internal, anyhow!("Internal error (panic)"));
+        assert_eq!(code(&err), EXIT_INTERNAL);
+    }
+
+    #[test]
+    fn the_outermost_class_wins() {
+        // An unreadable config is a config error, not a permission one
+        let err = Err::<(), _>(io::Error::from(io::ErrorKind::PermissionDenied))
+            .context("Failed to read config /etc/touch-layer/config.toml")
+            .map_err(|err| tag(Class::Config, err))
+            .unwrap_err();
+        assert_eq!(code(&err), EXIT_CONFIG);
+    }
+
+    #[test]
+    fn tagging_keeps_the_message() {
+        let err = tag(
+            Class::Config,
+            anyhow!("bad key").context("Failed to parse config"),
+        );
+        assert_eq!(format!("{:#}", err), "Failed to parse config: bad key");
+    }
+
+    #[test]
+    fn anything_unclassified_is_1() {
+        let err = Err::<(), _>(io::Error::from(io::ErrorKind::BrokenPipe))
+            .context("The libinput monitor gave up")
+            .unwrap_err();
+        assert_eq!(code(&err), EXIT_FAILURE);
+        assert_eq!(code(&anyhow!("something else")), EXIT_FAILURE);
+    }
+}
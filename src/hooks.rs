@@ -0,0 +1,258 @@
+use crate::sched;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Output, Stdio};
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::thread;
+
+/// Hooks running at once; transitions past that wait, collapsed into the latest
+const MAX_RUNNING: usize = 4;
+
+/// Commands run on mode transitions, started by a single worker so flapping can't fork-bomb
+struct Hooks {
+    on_activate: Option<String>,
+    on_deactivate: Option<String>,
+    verbose: bool,
+    /// Latest state not yet handed to a command; newer transitions overwrite it
+    pending: Mutex<Option<(bool, String)>>,
+    wake: Condvar,
+    /// Hooks started and not yet reaped
+    running: Mutex<usize>,
+    finished: Condvar,
+}
+
+static HOOKS: OnceLock<Hooks> = OnceLock::new();
+
+/// Start the hook worker if either command is configured
+pub fn install(on_activate: Option<String>, on_deactivate: Option<String>, verbose: bool) {
+    if on_activate.is_none() && on_deactivate.is_none() {
+        return;
+    }
+    let hooks = HOOKS.get_or_init(|| Hooks {
+        on_activate,
+        on_deactivate,
+        verbose,
+        pending: Mutex::new(None),
+        wake: Condvar::new(),
+        running: Mutex::new(0),
+        finished: Condvar::new(),
+    });
+    thread::spawn(move || hooks.run());
+}
+
+/// Run `command` via sh -c in its own process group, reaped on a throwaway thread
+///
+/// For `command` mappings: nothing waits for it, and its output goes nowhere.
+pub fn spawn_detached(command: &str, env: &[(&str, String)]) {
+    let mut child = Command::new("sh");
+    child
+        .arg("-c")
+        .arg(command)
+        .envs(env.iter().map(|(name, value)| (name, value)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        // Out of our group, so a Ctrl-C meant for us doesn't kill it
+        .process_group(0);
+    // SAFETY: reset_for_child only makes async-signal-safe syscalls
+    unsafe {
+        child.pre_exec(|| {
+            sched::reset_for_child();
+            Ok(())
+        });
+    }
+    let child = child.spawn();
+    match child {
+        Ok(mut child) => {
+            thread::spawn(move || {
+                if let Ok(status) = child.wait() {
+                    if !status.success() {
+                        eprintln!("⚠️  Mapped command exited with {}", status);
+                    }
+                }
+            });
+        }
+        Err(err) => eprintln!("⚠️  Failed to run mapped command: {}", err),
+    }
+}
+
+/// Queue the hook for a transition; never blocks the caller
+pub fn mode_changed(active: bool, reason: &str) {
+    if let Some(hooks) = HOOKS.get() {
+        *hooks.pending.lock().unwrap() = Some((active, reason.to_string()));
+        hooks.wake.notify_one();
+    }
+}
+
+impl Hooks {
+    fn run(&'static self) {
+        // The state the last command ran for, so a flap that settles back is a no-op
+        let mut last = None;
+        loop {
+            // A free slot first, so transitions meanwhile collapse into the latest
+            self.wait_for_slot();
+            let (active, reason) = {
+                let mut pending = self.pending.lock().unwrap();
+                loop {
+                    if let Some(next) = pending.take() {
+                        break next;
+                    }
+                    pending = self.wake.wait(pending).unwrap();
+                }
+            };
+            if last == Some(active) {
+                continue;
+            }
+            last = Some(active);
+
+            let command = if active {
+                &self.on_activate
+            } else {
+                &self.on_deactivate
+            };
+            if let Some(command) = command {
+                self.spawn(command, active, &reason);
+            }
+        }
+    }
+
+    fn wait_for_slot(&self) {
+        let mut running = self.running.lock().unwrap();
+        if *running >= MAX_RUNNING {
+            eprintln!(
+                "⚠️  {} hooks still running; later ones wait for one to exit",
+                *running
+            );
+        }
+        while *running >= MAX_RUNNING {
+            running = self.finished.wait(running).unwrap();
+        }
+    }
+
+    /// Start the hook and leave it to a thread of its own, which reports how it went
+    fn spawn(&'static self, command: &str, active: bool, reason: &str) {
+        let hook = if active {
+            "on_activate"
+        } else {
+            "on_deactivate"
+        };
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .env(
+                "TOUCH_LAYER_STATE",
+                if active { "active" } else { "inactive" },
+            )
+            .env("TOUCH_LAYER_REASON", reason)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn();
+        let child = match child {
+            Ok(child) => child,
+            Err(err) => {
+                eprintln!("⚠️  Failed to run {} hook: {}", hook, err);
+                return;
+            }
+        };
+        *self.running.lock().unwrap() += 1;
+        thread::spawn(move || {
+            self.report(hook, child.wait_with_output());
+            *self.running.lock().unwrap() -= 1;
+            self.finished.notify_one();
+        });
+    }
+
+    fn report(&self, hook: &str, output: io::Result<Output>) {
+        match output {
+            Ok(output) => {
+                if !output.status.success() {
+                    eprintln!("⚠️  {} hook exited with {}", hook, output.status);
+                }
+                if self.verbose {
+                    for stream in [&output.stdout, &output.stderr] {
+                        for line in String::from_utf8_lossy(stream).lines() {
+                            eprintln!("   [{}] {}", hook, line);
+                        }
+                    }
+                }
+            }
+            Err(err) => eprintln!("⚠️  Failed to wait for {} hook: {}", hook, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use std::time::{Duration, Instant};
+
+    fn start(on_activate: &str, on_deactivate: &str) -> &'static Hooks {
+        let hooks = Box::leak(Box::new(Hooks {
+            on_activate: Some(on_activate.to_string()),
+            on_deactivate: Some(on_deactivate.to_string()),
+            verbose: false,
+            pending: Mutex::new(None),
+            wake: Condvar::new(),
+            running: Mutex::new(0),
+            finished: Condvar::new(),
+        }));
+        thread::spawn(|| hooks.run());
+        hooks
+    }
+
+    fn queue(hooks: &Hooks, active: bool) {
+        *hooks.pending.lock().unwrap() = Some((active, "test".to_string()));
+        hooks.wake.notify_one();
+    }
+
+    fn appears(path: &Path, within: Duration) -> bool {
+        let deadline = Instant::now() + within;
+        while Instant::now() < deadline {
+            if path.exists() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    #[test]
+    fn a_hung_hook_does_not_hold_up_the_next() {
+        let done =
+            std::env::temp_dir().join(format!("touch-layer-test-hook-{}", std::process::id()));
+        let _ = std::fs::remove_file(&done);
+        let hooks = start("sleep 3", &format!("touch '{}'", done.display()));
+        queue(hooks, true);
+        thread::sleep(Duration::from_millis(100));
+        queue(hooks, false);
+        assert!(
+            appears(&done, Duration::from_secs(2)),
+            "on_deactivate waited for on_activate"
+        );
+        std::fs::remove_file(&done).unwrap();
+    }
+
+    #[test]
+    fn transitions_past_the_limit_collapse_into_the_latest() {
+        let log =
+            std::env::temp_dir().join(format!("touch-layer-test-hooks-{}", std::process::id()));
+        let _ = std::fs::remove_file(&log);
+        let append = |state: &str| format!("echo {} >> '{}'; sleep 1", state, log.display());
+        let hooks = start(&append("on"), &append("off"));
+        for i in 0..MAX_RUNNING {
+            queue(hooks, i % 2 == 0);
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert_eq!(*hooks.running.lock().unwrap(), MAX_RUNNING);
+        // While every slot is busy: two flaps that end where the last hook left it
+        for active in [true, false, true, false] {
+            queue(hooks, active);
+        }
+        thread::sleep(Duration::from_millis(1500));
+        let lines = std::fs::read_to_string(&log).unwrap();
+        assert_eq!(lines, "on\noff\non\noff\n", "nothing more ran");
+        std::fs::remove_file(&log).unwrap();
+    }
+}
@@ -0,0 +1,393 @@
+//! `init-config`: write a commented starter config with the detected devices
+
+use crate::config::{Config, DEFAULT_KEYBOARD, DEFAULT_TOUCHPAD};
+use crate::device::{self, DeviceKind, VidPid, BUS_VIRTUAL};
+use anyhow::{bail, Context, Result};
+use evdev::raw_stream::RawDevice;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Stable link directories, most preferred first
+const LINK_DIRS: [&str; 2] = ["/dev/input/by-path", "/dev/input/by-id"];
+
+/// Every option, commented out at its default; `{touchpad}` and `{keyboard}` are filled in
+const TEMPLATE: &str = r#"# touch-layer configuration, generated by `touchpad-remap init-config`.
+# Commented-out options show their defaults.
+
+# Schema version; see `touchpad-remap migrate-config`
+version = 1
+
+# Devices: a path, a glob, `vidpid:VVVV:PPPP`, `name:Device Name`, or a list of
+# these tried in order. `touchpad-remap list-devices` shows what is available.
+{touchpad}
+{keyboard}
+# How long a `name:` device that doesn't exist yet is waited for at startup
+#device_wait_ms = 10000
+# Cap on the doubling pause between reconnect attempts for a device that went away
+#reconnect_max_delay_ms = 5000
+# Grabs tried at startup while another process briefly holds a device, and the
+# pause before the second, doubling up to 1s
+#grab_attempts = 8
+#grab_retry_ms = 100
+
+# Name of the virtual keyboard that typing is forwarded through
+#virtual_keyboard_name = "my-virtual-keyboard"
+# Declare mouse buttons on the virtual keyboard instead of a separate mouse
+#pointer_on_keyboard = false
+# Buttons the virtual mouse declares; by default the standard three plus any
+# that mappings use. When set, mapping another button is an error.
+#mouse_buttons = ["BTN_LEFT", "BTN_RIGHT", "BTN_MIDDLE", "BTN_SIDE"]
+# Keymap layout announced by the wayland backend
+#xkb_layout = "us"
+# Read mapping keys as XKB keysyms ("f", "semicolon") typed on xkb_layout,
+# xkb_variant and xkb_options, or on a compiled keymap file, instead of as
+# evdev key names. Needs the xkb cargo feature.
+#mapping_keys = "evdev"
+#xkb_variant = "dvorak"
+#xkb_options = "caps:escape"
+#xkb_keymap = "keymap.xkb"
+
+# Wait this long after the finger lifts before deactivating
+#debounce_ms = 0
+# Bounce filter for mapped keys: hold releases back this long
+#chatter_ms = 0
+# Which touchpad signals keep the mode on: touch_motion, motion, finger_down,
+# hold (resting fingers, libinput 1.19+), mouse_motion, && and ||
+#activate_when = "touch_motion"
+# Smallest |dx| + |dy| of a touchpad motion event that counts; 0 counts any
+#motion_threshold = 0.0
+# Lift signal: "auto", "tool_finger", "touch", "all_contacts_up" or "timeout_only"
+#deactivate_on = "tool_finger"
+# Remember the SIGUSR1 on/off switch (and the sticky latch) across restarts
+#persist_state = false
+# Start with remapping disabled until SIGUSR1 turns it on (also --start-disabled)
+#start_enabled = true
+# Shell commands run when the mode turns on / off
+#on_activate = "notify-send 'layer on'"
+#on_deactivate = "notify-send 'layer off'"
+# Allow `command` mappings, which run shell commands when their key is pressed
+#allow_commands = false
+# When [mappings] applies: "active" (touchpad in use), "inactive" or "always"
+#mapping_condition = "active"
+
+# Start from a built-in preset (see `touchpad-remap list-presets`); the
+# [mappings] below add to its mappings and replace the keys both name
+#preset = "vim"
+
+# Keys that act as mouse buttons or scroll while the touchpad is in use
+[mappings]
+KEY_F = "BTN_LEFT"
+KEY_D = "BTN_RIGHT"
+KEY_S = "BTN_MIDDLE"
+#KEY_E = { wheel = -1 }                         # scroll down
+#KEY_R = { wheel = 1, repeat_interval_ms = 30 } # scroll up, faster repeat
+#KEY_A = { target = "BTN_SIDE", modifiers = { ctrl = "released" } }  # Ctrl+A still types
+#KEY_G = { command = "grim -g \"$(slurp)\"", cooldown_ms = 1000 }    # needs allow_commands
+#KEY_V = "KEY_PLAYPAUSE"                        # type another key instead
+#KEY_SPACE = "BTN_LEFT"                         # a second key may share a button
+
+# A table of its own for the keyboards a set selects, in place of [mappings];
+# the first set that selects the keyboard in use wins
+#[[mapping_sets]]
+#device = "name:Keychron K2"
+#mappings = {}   # nothing remapped on this board
+#condition = "active"
+
+# Tables over [mappings] by the fingers resting on the touchpad: "1", "2", "3+"
+#[finger_layers]
+#2 = { KEY_F = { wheel = 1 }, KEY_D = { wheel = -1 } }   # two fingers scroll
+
+[scroll]
+# Hold time before a scroll key repeats, and the repeat interval (minimum 10)
+#repeat_delay_ms = 250
+#repeat_interval_ms = 40
+# Hi-res units (1/120 detent) per detent of a key's `wheel`; lower is smoother
+#step = 120
+# Flip key scrolling, as touchpad natural scrolling does
+#natural = false
+
+[scroll.acceleration]
+# Repeat faster the longer a scroll key is held
+#enabled = false
+#initial_rate_hz = 25     # default: from repeat_interval_ms
+#max_rate_hz = 100
+#ramp_ms = 2000           # hold time (after the delay) to reach max_rate_hz
+#curve = "quadratic"      # "linear", "quadratic" or "cubic"
+
+[scroll.kinetic]
+# Keep scrolling after a held scroll key is released
+#enabled = false
+#time_constant_ms = 325   # velocity decays to ~37% after this long
+#min_velocity = 2.0       # detents/s at which inertia stops
+#max_velocity = 60.0      # detents/s cap on the release velocity
+#tick_ms = 16
+
+[script]
+# rhai script deciding routes per key press, relative to this file
+#path = "route.rhai"
+# Operation budget per call before falling back to the mappings
+#max_operations = 10000
+
+[typing]
+# Deactivate after this many unmapped presses within window_ms; 0 is off
+#presses = 0
+#window_ms = 1000
+# Ignore touchpad motion this long afterwards
+#cooldown_ms = 0
+# A click key pressed this soon after a typed letter types itself; 0 is off
+#after_letter_ms = 0
+
+[scheduling]
+# Keyboard thread priority: "normal", "fifo" or "nice" (needs CAP_SYS_NICE)
+#policy = "normal"
+#priority = 10   # SCHED_FIFO, 1-99
+#nice = -10      # -20-19
+
+[control]
+# Socket for `touchpad-remap monitor`
+#enabled = true
+# Group allowed to connect; otherwise only the daemon's user
+#group = "wheel"
+# Let anyone who can connect send synthetic input (`touchpad-remap inject`)
+#inject = false
+#inject_rate = 20   # commands per second
+# Let anyone who can connect edit mappings and save them to the config (`ctl map`)
+#map = false
+
+[mirror]
+# Keyboard capabilities copied to the virtual keyboard besides keys and axes
+#leds = true
+#misc = true
+#abs = true
+#repeat = true   # the kernel repeats keys at the keyboard's rate
+
+[virtual_devices]
+# Phys of the virtual keyboard; default: the keyboard's own plus "/touch-layer".
+# The virtual mouse and warp tablet append "/mouse" and "/tablet" to it.
+#phys = "isa0060/serio0/input0/touch-layer"
+#keyboard_properties = []
+#mouse_properties = ["INPUT_PROP_POINTER"]
+
+# Grab another mouse and remap its buttons on a virtual copy of it. Motion and
+# unmapped buttons pass through; remaps apply always, or only while the layer
+# is active with follow_activation.
+#[external_mouse]
+#device = "vidpid:046d:c52b"
+#name = "touch-layer-mouse"
+#follow_activation = false
+#
+#[external_mouse.mappings]
+#BTN_SIDE = "BTN_MIDDLE"
+#BTN_EXTRA = { double_click = "BTN_LEFT" }
+
+# Remap the touchpad's own buttons while the layer is active, on a virtual
+# copy of the touchpad.
+#[touchpad_buttons]
+#name = "touch-layer-touchpad"
+#
+#[touchpad_buttons.mappings]
+#BTN_LEFT = "BTN_MIDDLE"
+
+# A mouse whose motion also turns the mode on (mouse_motion), read without a
+# grab; it counts until it has been still for idle_ms.
+#[activation_mouse]
+#device = "vidpid:046d:c52b"
+#threshold = 10   # smallest |dx| + |dy| of one report
+#idle_ms = 1000
+
+# In tablet mode (SW_TABLET_MODE): "disable" forces the layer off, "ungrab"
+# also hands the keyboard back, "ignore" keeps remapping
+#[tablet_mode]
+#action = "disable"
+#device = "/dev/input/by-path/platform-INT33D5:00-event"
+
+# While the logind session is locked the layer is forced off; with ungrab the
+# lock screen reads the keyboard directly. No system bus just means no tracking.
+#[session_lock]
+#enabled = true
+#ungrab = false
+
+# Latch the layer on with a quick swipe up, and off again with a swipe down.
+#[sticky]
+#enabled = false
+#fingers = 3
+#min_distance = 100.0
+#max_duration_ms = 500
+
+# Space out mode changes from a finger hovering at the edge of contact:
+# reactivating waits reactivate_after_ms after a deactivation, unless one motion
+# event moves reactivate_motion; deactivating waits min_active_ms after an activation.
+#[hysteresis]
+#reactivate_after_ms = 30
+#reactivate_motion = 8.0
+#min_active_ms = 30
+
+# Let go of the keyboard before suspend (logind PrepareForSleep) and find and
+# grab it again after resume, for hardware that re-enumerates across sleep.
+# Suspend is never held up longer than max_delay_ms.
+#[suspend]
+#enabled = false
+#max_delay_ms = 1000
+
+# If the keyboard loop goes stall_ms without checking in while keys are waiting,
+# release the keyboard grab and abort, for the service manager to restart us.
+#[watchdog]
+#enabled = true
+#stall_ms = 3000
+# Force up a mouse button held this long while none of its keys is down, as a
+# backstop against routing bugs; 0 is off, 30000 is a sensible value
+#max_button_hold_ms = 0
+
+# Pick the debounce from the speed just before the lift, instead of debounce_ms:
+# min_ms at or below `slow`, max_ms at or above `fast` (|dx| + |dy| per second)
+#[adaptive_debounce]
+#enabled = false
+#min_ms = 0
+#max_ms = 300
+#window_ms = 200
+#slow = 200.0
+#fast = 2000.0
+
+# Hold key with the mode active to warp the pointer on a 3x3 grid: U I O /
+# J K L / M , . jump to a cell's center and narrow the grid to it. The jumps
+# go out on an absolute device spanning width x height (default 65536 each).
+#[warp]
+#key = "KEY_SPACE"
+#width = 1920
+#height = 1080
+"#;
+
+pub fn run(path: Option<&Path>, force: bool) -> Result<()> {
+    let path = path.map_or_else(Config::path, Path::to_path_buf);
+    if path.exists() && !force {
+        bail!(
+            "{} already exists; pass --force to overwrite it",
+            path.display()
+        );
+    }
+
+    // The running daemon's devices must not be mistaken for real ones
+    if let Ok(config) = Config::load(None) {
+        device::set_own_names(config.own_device_names());
+    }
+
+    eprintln!("🔎 Detecting devices...");
+    let touchpad = detect(DeviceKind::Touchpad);
+    let keyboard = detect(DeviceKind::Keyboard);
+    let text = render(touchpad, keyboard);
+
+    // Never hand out a file the daemon would refuse
+    Config::parse(&text, &path).context("Generated config does not validate")?;
+
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    fs::write(&path, text).with_context(|| format!("Failed to write {}", path.display()))?;
+    eprintln!("📝 Wrote {}", path.display());
+    Ok(())
+}
+
+/// A stable way to name the first physical device of `kind`
+fn detect(kind: DeviceKind) -> Option<String> {
+    let mut nodes = device::event_nodes();
+    // A keyboard keyd has grabbed is behind its virtual one, which is the one to use
+    let qualifies = |device: &mut RawDevice| {
+        !device::is_own(device)
+            && kind.reject_reason(device).is_none()
+            && !(kind.grabbed() && device::grabbed_elsewhere(device))
+    };
+    let index = nodes.iter_mut().position(|(_, device)| qualifies(device))?;
+    let (node, device) = &nodes[index];
+    eprintln!(
+        "   ✓ {}: {} ({})",
+        kind.label(),
+        node.display(),
+        device.name().unwrap_or("unnamed")
+    );
+
+    if let Some(link) = link_to(node) {
+        return Some(link.display().to_string());
+    }
+    // Other tools' virtual devices have no links, and their IDs are often shared
+    if device.input_id().bus_type().0 == BUS_VIRTUAL {
+        if let Some(name) = device.name() {
+            return Some(format!("{}{}", device::NAME_PREFIX, name));
+        }
+    }
+    // Same counting as matching, so an identical earlier device gets skipped
+    let selector = VidPid::of(device, 0);
+    let index = nodes
+        .iter()
+        .take_while(|(path, _)| path != node)
+        .filter(|(_, other)| {
+            kind.reject_reason(other).is_none() && VidPid::of(other, 0) == selector
+        })
+        .count();
+    Some(VidPid { index, ..selector }.to_string())
+}
+
+fn link_to(node: &Path) -> Option<PathBuf> {
+    LINK_DIRS.iter().find_map(|dir| {
+        let mut links: Vec<PathBuf> = fs::read_dir(dir)
+            .ok()?
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|link| fs::canonicalize(link).is_ok_and(|target| target == node))
+            .collect();
+        links.sort();
+        links.into_iter().next()
+    })
+}
+
+/// The template with the detected devices, or the defaults and a note
+fn render(touchpad: Option<String>, keyboard: Option<String>) -> String {
+    TEMPLATE
+        .replace(
+            "{touchpad}",
+            &setting("touchpad", touchpad, DEFAULT_TOUCHPAD),
+        )
+        .replace(
+            "{keyboard}",
+            &setting("keyboard", keyboard, DEFAULT_KEYBOARD),
+        )
+}
+
+fn setting(field: &str, detected: Option<String>, fallback: &str) -> String {
+    match detected {
+        Some(path) => format!("{} = {}", field, toml::Value::String(path)),
+        None => format!(
+            "# No {} detected; replace this with one from `touchpad-remap list-devices`\n{} = {}",
+            field,
+            field,
+            toml::Value::String(fallback.into())
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(text: &str) -> Config {
+        Config::parse(text, Path::new("test.toml"))
+            .unwrap_or_else(|err| panic!("{:#}\n{}", err, text))
+    }
+
+    #[test]
+    fn the_template_validates_with_nothing_detected() {
+        let text = render(None, None);
+        assert!(text.contains("# No touchpad detected"));
+        let config = validate(&text);
+        assert_eq!(config.touchpad, [DEFAULT_TOUCHPAD]);
+        assert_eq!(config.keyboard, [DEFAULT_KEYBOARD]);
+    }
+
+    #[test]
+    fn the_template_validates_with_detected_devices() {
+        let touchpad = "/dev/input/by-path/platform-i8042-serio-1-event-mouse";
+        // A name selector with the characters TOML needs escaped
+        let keyboard = r#"name:Kbd "K2" \ v2"#;
+        let config = validate(&render(Some(touchpad.into()), Some(keyboard.into())));
+        assert_eq!(config.touchpad, [touchpad]);
+        assert_eq!(config.keyboard, [keyboard]);
+    }
+}
@@ -0,0 +1,305 @@
+//! Synthetic input from the control socket (`[control] inject = true`)
+//!
+//! `inject key KEY_PLAYPAUSE tap`, `inject button left press` and
+//! `inject wheel -3` go out on the virtual devices we already own, so scripts
+//! need no uinput access of their own. A command is checked against what the
+//! virtual devices declare and against a rate limit on the socket thread,
+//! then queued for the keyboard loop, which emits it through the same outputs
+//! as remapped keys and releases whatever it still holds at shutdown.
+
+use crate::config::{Config, ControlConfig, Target};
+use crate::control;
+use crate::keys;
+use crate::shutdown;
+use crate::sink::EventSink;
+use anyhow::{bail, Result};
+use evdev::raw_stream::RawDevice;
+use evdev::Key;
+use std::collections::{HashSet, VecDeque};
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+/// Commands waiting for the keyboard loop; more are refused
+const QUEUE_LEN: usize = 64;
+
+/// Most wheel detents one command may send
+const MAX_DETENTS: i32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stroke {
+    Down,
+    Up,
+    /// Press and release
+    Tap,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Key(Key, Stroke),
+    Button(Key, Stroke),
+    /// Detents, positive up
+    Wheel(i32),
+}
+
+fn parse_stroke(word: &str) -> Result<Stroke> {
+    match word {
+        "press" | "down" => Ok(Stroke::Down),
+        "release" | "up" => Ok(Stroke::Up),
+        "tap" | "click" => Ok(Stroke::Tap),
+        _ => bail!("expected press, release or tap, not '{}'", word),
+    }
+}
+
+/// One command, without the leading `inject`
+pub fn parse(line: &str) -> Result<Action> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        ["key", name] => Ok(Action::Key(keys::resolve(name)?, Stroke::Tap)),
+        ["key", name, press] => Ok(Action::Key(keys::resolve(name)?, parse_stroke(press)?)),
+        ["button", name] => Ok(Action::Button(keys::resolve(name)?, Stroke::Tap)),
+        ["button", name, press] => Ok(Action::Button(keys::resolve(name)?, parse_stroke(press)?)),
+        ["wheel", detents] => match detents.parse::<i32>() {
+            Ok(detents) if detents != 0 && detents.abs() <= MAX_DETENTS => {
+                Ok(Action::Wheel(detents))
+            }
+            _ => bail!("wheel takes 1 to {} detents, up or down (-)", MAX_DETENTS),
+        },
+        _ => bail!("expected 'key NAME [press|release|tap]', 'button NAME [press|release|tap]' or 'wheel DETENTS'"),
+    }
+}
+
+/// What the virtual devices can send, set by the keyboard loop once it has them
+pub struct Declared {
+    keys: HashSet<Key>,
+    buttons: HashSet<Key>,
+    wheel: bool,
+}
+
+impl Declared {
+    /// The virtual keyboard mirrors `keyboard`'s keys plus the mapped ones; the
+    /// pointer has the configured buttons
+    pub fn new(config: &Config, keyboard: &RawDevice) -> Self {
+        let pointer = config.needs_pointer();
+        Self {
+            keys: keyboard
+                .supported_keys()
+                .into_iter()
+                .flat_map(|keys| keys.iter())
+                .chain(config.key_targets().iter())
+                .collect(),
+            buttons: if pointer {
+                config.mouse_buttons().iter().collect()
+            } else {
+                HashSet::new()
+            },
+            wheel: pointer && config.needs_wheel(),
+        }
+    }
+
+    fn check(&self, action: Action) -> Result<()> {
+        match action {
+            Action::Key(key, _) if !self.keys.contains(&key) => {
+                bail!("the virtual keyboard has no {:?}", key)
+            }
+            Action::Button(button, _) if !self.buttons.contains(&button) => {
+                bail!("the virtual pointer has no {:?}", button)
+            }
+            Action::Wheel(_) if !self.wheel => bail!("the virtual pointer has no wheel"),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the virtual devices can send what `target` does, for `ctl map`
+    pub fn check_target(&self, target: Target) -> Result<()> {
+        match target {
+            Target::Key(key) => self.check(Action::Key(key, Stroke::Tap)),
+            Target::Wheel(_) => self.check(Action::Wheel(1)),
+            Target::Command(_) => Ok(()),
+            other => match other.button() {
+                Some(button) => self.check(Action::Button(button, Stroke::Tap)),
+                None => Ok(()),
+            },
+        }
+    }
+}
+
+/// Token bucket: `rate` commands a second, in bursts of up to `rate`
+struct Limiter {
+    rate: f64,
+    tokens: f64,
+    at: Instant,
+}
+
+impl Limiter {
+    fn take(&mut self, now: Instant) -> bool {
+        let refill = now.saturating_duration_since(self.at).as_secs_f64() * self.rate;
+        self.tokens = (self.tokens + refill).min(self.rate);
+        self.at = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+struct Injector {
+    declared: Mutex<Option<Declared>>,
+    limiter: Mutex<Limiter>,
+    queue: Mutex<VecDeque<Action>>,
+    read: OwnedFd,
+    write: OwnedFd,
+}
+
+static INJECTOR: OnceLock<Injector> = OnceLock::new();
+
+/// Accept injected commands from now on, if the config allows them
+pub fn init(config: &ControlConfig) -> Result<()> {
+    if !config.inject {
+        return Ok(());
+    }
+    let (read, write) = shutdown::pipe("injection")?;
+    let rate = f64::from(config.inject_rate);
+    let _ = INJECTOR.set(Injector {
+        declared: Mutex::new(None),
+        limiter: Mutex::new(Limiter {
+            rate,
+            tokens: rate,
+            at: Instant::now(),
+        }),
+        queue: Mutex::new(VecDeque::new()),
+        read,
+        write,
+    });
+    eprintln!("💉 Input injection: on, at most {}/s", config.inject_rate);
+    Ok(())
+}
+
+/// Replace what commands are checked against, e.g. after the outputs were rebuilt
+pub fn declare(declared: Declared) {
+    if let Some(injector) = INJECTOR.get() {
+        *injector.declared.lock().unwrap() = Some(declared);
+    }
+}
+
+/// Descriptor that becomes readable when commands are queued, or -1 (which poll ignores)
+pub fn fd() -> RawFd {
+    INJECTOR
+        .get()
+        .map_or(-1, |injector| injector.read.as_raw_fd())
+}
+
+/// Check and queue one command from a client
+pub fn submit(line: &str) -> Result<()> {
+    let Some(injector) = INJECTOR.get() else {
+        bail!("injection is off (control.inject)");
+    };
+    let action = parse(line)?;
+    match &*injector.declared.lock().unwrap() {
+        Some(declared) => declared.check(action)?,
+        None => bail!("the virtual devices are not up yet"),
+    }
+    if !injector.limiter.lock().unwrap().take(Instant::now()) {
+        bail!("rate limited (control.inject_rate)");
+    }
+    {
+        let mut queue = injector.queue.lock().unwrap();
+        if queue.len() >= QUEUE_LEN {
+            bail!("too many commands pending");
+        }
+        queue.push_back(action);
+    }
+    // SAFETY: writing one byte from a valid buffer to our own pipe
+    unsafe { libc::write(injector.write.as_raw_fd(), [1u8].as_ptr().cast(), 1) };
+    Ok(())
+}
+
+/// Everything queued since the last call, emptying the wakeup pipe
+pub fn take() -> Vec<Action> {
+    let Some(injector) = INJECTOR.get() else {
+        return Vec::new();
+    };
+    let mut buf = [0u8; 64];
+    // SAFETY: reading into a valid buffer from our own non-blocking pipe
+    while unsafe {
+        libc::read(
+            injector.read.as_raw_fd(),
+            buf.as_mut_ptr().cast(),
+            buf.len(),
+        )
+    } > 0
+    {}
+    injector.queue.lock().unwrap().drain(..).collect()
+}
+
+/// Keys and buttons pressed by injection and not released yet
+#[derive(Default)]
+pub struct Held {
+    keys: HashSet<Key>,
+    buttons: HashSet<Key>,
+}
+
+impl Held {
+    pub fn apply(&mut self, action: Action, out: &mut dyn EventSink) -> Result<()> {
+        eprintln!("💉 Injected {:?}", action);
+        match action {
+            Action::Key(key, press) => {
+                press_or_release(&mut self.keys, key, press, &mut |key, value| {
+                    out.key(key, value)
+                })
+            }
+            Action::Button(button, press) => {
+                press_or_release(&mut self.buttons, button, press, &mut |button, value| {
+                    out.button(button, value)
+                })
+            }
+            Action::Wheel(detents) => out.wheel(detents * 120, detents),
+        }
+    }
+
+    /// Release everything still held, as at shutdown
+    pub fn release_all(&mut self, out: &mut dyn EventSink) -> Result<()> {
+        for key in self.keys.drain() {
+            out.key(key, 0)?;
+        }
+        for button in self.buttons.drain() {
+            out.button(button, 0)?;
+        }
+        Ok(())
+    }
+}
+
+/// Send `press`, keeping `held` in step; a release of what isn't held goes out anyway
+fn press_or_release(
+    held: &mut HashSet<Key>,
+    key: Key,
+    press: Stroke,
+    send: &mut dyn FnMut(Key, i32) -> Result<()>,
+) -> Result<()> {
+    match press {
+        Stroke::Down => {
+            held.insert(key);
+            send(key, 1)
+        }
+        Stroke::Up => {
+            held.remove(&key);
+            send(key, 0)
+        }
+        Stroke::Tap => {
+            held.remove(&key);
+            send(key, 1)?;
+            send(key, 0)
+        }
+    }
+}
+
+/// `touchpad-remap inject ...`: send one command to the running daemon
+pub fn run(socket: Option<&Path>, command: &[String]) -> Result<()> {
+    let command = command.join(" ");
+    // Checked here too, for errors that don't need a round trip
+    parse(&command)?;
+    control::request(socket, &format!("inject {}", command))?;
+    Ok(())
+}
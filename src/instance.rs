@@ -0,0 +1,136 @@
+use crate::preflight::AccessError;
+use crate::seat;
+use anyhow::{bail, Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long `--replace` waits for the old instance to let go of the lock
+const REPLACE_TIMEOUT: Duration = Duration::from_secs(10);
+const REPLACE_POLL: Duration = Duration::from_millis(100);
+
+pub fn pid_file_path() -> PathBuf {
+    runtime_path(&seat::file_name("touch-layer", "pid"))
+}
+
+/// `name` in /run for the system service, in $XDG_RUNTIME_DIR when running as a user
+pub fn runtime_path(name: &str) -> PathBuf {
+    // SAFETY: geteuid has no preconditions
+    let root = unsafe { libc::geteuid() } == 0;
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !root && !dir.is_empty() => PathBuf::from(dir).join(name),
+        _ => PathBuf::from("/run").join(name),
+    }
+}
+
+/// Exclusive flock on the PID file, held for the life of the process
+///
+/// The kernel drops the lock when the descriptor closes, so it cannot outlive
+/// us even on panic or SIGKILL. The file itself is left in place (emptied on
+/// drop): unlinking it would let a waiting `--replace` lock a dead inode.
+pub struct InstanceLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl InstanceLock {
+    /// Take the lock, or fail with the PID of the instance holding it
+    ///
+    /// With `replace`, the holder is sent SIGTERM and we wait for it to exit.
+    pub fn acquire(replace: bool) -> Result<Self> {
+        let path = pid_file_path();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("Failed to open PID file {}", path.display()))?;
+
+        if !try_lock(&file).with_context(|| format!("Failed to lock {}", path.display()))? {
+            let owner = read_pid(&mut file);
+            if !replace {
+                match owner {
+                    Some(pid) => {
+                        eprintln!("🔒 Already running as PID {} ({})", pid, path.display())
+                    }
+                    None => eprintln!("🔒 Already running ({} is locked)", path.display()),
+                }
+                eprintln!("   Stop it first, or pass --replace to take over");
+                return Err(AccessError::AlreadyRunning(owner).into());
+            }
+
+            let Some(pid) = owner else {
+                bail!("{} is locked but holds no PID to signal", path.display());
+            };
+            eprintln!("🔁 Replacing running instance (PID {})", pid);
+            // SAFETY: kill has no memory-safety preconditions
+            if unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } < 0 {
+                let err = io::Error::last_os_error();
+                return Err(err).with_context(|| format!("Failed to signal PID {}", pid));
+            }
+
+            let deadline = Instant::now() + REPLACE_TIMEOUT;
+            while !try_lock(&file)? {
+                if Instant::now() >= deadline {
+                    bail!(
+                        "PID {} still holds {} after {:?}",
+                        pid,
+                        path.display(),
+                        REPLACE_TIMEOUT
+                    );
+                }
+                thread::sleep(REPLACE_POLL);
+            }
+        }
+
+        file.set_len(0)?;
+        file.rewind()?;
+        writeln!(file, "{}", std::process::id())
+            .with_context(|| format!("Failed to write PID file {}", path.display()))?;
+        Ok(Self { file, path })
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        if let Err(err) = self.file.set_len(0) {
+            eprintln!("⚠️  Failed to clear {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+/// Whether an instance holds the PID file lock, and its PID if readable;
+/// the lock is only tested, never held
+pub fn running() -> Option<Option<u32>> {
+    let mut file = File::open(pid_file_path()).ok()?;
+    // SAFETY: the descriptor is open for the duration of the call
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_SH | libc::LOCK_NB) } == 0 {
+        // Closing the file drops the shared lock again
+        return None;
+    }
+    Some(read_pid(&mut file))
+}
+
+/// Non-blocking exclusive flock; false if someone else holds it
+fn try_lock(file: &File) -> io::Result<bool> {
+    // SAFETY: the descriptor is open for the duration of the call
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EWOULDBLOCK) => Ok(false),
+        _ => Err(err),
+    }
+}
+
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut contents = String::new();
+    file.rewind().ok()?;
+    file.read_to_string(&mut contents).ok()?;
+    contents.trim().parse().ok()
+}
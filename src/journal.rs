@@ -0,0 +1,299 @@
+//! Held synthetic outputs, journaled for the next instance to release
+//!
+//! A SIGKILLed daemon releases nothing. uinput devices are destroyed with it,
+//! and the kernel sends their releases then, but outputs that outlive us
+//! don't get them: the X server's XTest device stays pressed, and a
+//! compositor may keep the pressed state cached. So every press and release
+//! the layer synthesizes (remapped keys and pointer buttons) is appended to a
+//! small journal in the runtime directory. The next instance reads what it
+//! says is still held, releases that through its own outputs before grabbing,
+//! and empties the file. A clean shutdown has released everything and removes
+//! it.
+//!
+//! Passthrough keys are never journaled, so what is typed never reaches the
+//! disk, and the file is only readable by its owner. Each change is a single
+//! append without fsync, formatted into a fixed buffer: the page cache
+//! outlives a killed process, and only a power cut, which takes the session
+//! too, could lose one. A torn last line is skipped.
+
+use crate::instance;
+use crate::seat;
+use crate::sink::EventSink;
+use anyhow::{Context, Result};
+use evdev::Key;
+use std::collections::{BTreeSet, HashSet};
+use std::fs::{self, File, OpenOptions, Permissions};
+use std::io::{self, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::PathBuf;
+
+/// Size past which the journal is emptied the next time nothing is held
+const COMPACT_BYTES: u64 = 4096;
+
+/// Room for the longest line, `-button 65535` and its newline
+const LINE_BYTES: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Output {
+    /// On the virtual keyboard, forwarded or remapped
+    Key,
+    /// On the virtual pointer
+    Button,
+}
+
+impl Output {
+    fn name(self) -> &'static str {
+        match self {
+            Output::Key => "key",
+            Output::Button => "button",
+        }
+    }
+}
+
+pub struct Journal {
+    file: File,
+    path: PathBuf,
+    held: HashSet<(Output, u16)>,
+    /// Bytes written since it was last emptied
+    size: u64,
+    /// Set after a failed write, which stops the journal rather than input
+    failed: bool,
+}
+
+pub fn path() -> PathBuf {
+    instance::runtime_path(&seat::file_name("touch-layer-held", "journal"))
+}
+
+impl Journal {
+    /// Release on `out` whatever a previous instance's journal has held, then
+    /// start an empty one
+    pub fn open(out: &mut dyn EventSink) -> Result<Self> {
+        Self::open_at(path(), out)
+    }
+
+    /// `open`, with the journal at `path`
+    pub(crate) fn open_at(path: PathBuf, out: &mut dyn EventSink) -> Result<Self> {
+        let leftover = match fs::read_to_string(&path) {
+            Ok(text) => held_in(&text),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => BTreeSet::new(),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        };
+        if !leftover.is_empty() {
+            eprintln!(
+                "🩹 Releasing {} output(s) a previous instance left held ({})",
+                leftover.len(),
+                path.display()
+            );
+            for &(output, code) in &leftover {
+                match output {
+                    Output::Key => out.key(Key::new(code), 0)?,
+                    Output::Button => out.button(Key::new(code), 0)?,
+                }
+            }
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        // An older instance may have left it readable by others
+        file.set_permissions(Permissions::from_mode(0o600))
+            .and_then(|()| file.set_len(0))
+            .with_context(|| format!("Failed to empty {}", path.display()))?;
+        Ok(Self {
+            file,
+            path,
+            held: HashSet::new(),
+            size: 0,
+            failed: false,
+        })
+    }
+
+    /// Note a synthetic press (1) or release (0); repeats and no-ops write nothing
+    pub fn record(&mut self, output: Output, key: Key, value: i32) {
+        let entry = (output, key.code());
+        let sign = match value {
+            0 if self.held.remove(&entry) => '-',
+            1 if self.held.insert(entry) => '+',
+            _ => return,
+        };
+        if self.failed {
+            return;
+        }
+        let mut buf = [0; LINE_BYTES];
+        let mut cursor = &mut buf[..];
+        // Fits: the longest line is LINE_BYTES
+        let _ = writeln!(cursor, "{}{} {}", sign, output.name(), key.code());
+        let len = LINE_BYTES - cursor.len();
+        let written = self.file.write_all(&buf[..len]).and_then(|()| {
+            self.size += len as u64;
+            if self.held.is_empty() && self.size >= COMPACT_BYTES {
+                self.size = 0;
+                self.file.set_len(0)?;
+            }
+            Ok(())
+        });
+        if let Err(err) = written {
+            eprintln!(
+                "⚠️  Failed to write {}: {}; held outputs are no longer journaled",
+                self.path.display(),
+                err
+            );
+            self.failed = true;
+        }
+    }
+}
+
+impl Drop for Journal {
+    /// Removed once nothing is held; otherwise left for the next instance
+    fn drop(&mut self) {
+        if self.held.is_empty() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// What a journal's lines leave held, in code order
+fn held_in(text: &str) -> BTreeSet<(Output, u16)> {
+    let mut held = BTreeSet::new();
+    for line in text.lines() {
+        let Some((change, code)) = line.split_once(' ') else {
+            continue;
+        };
+        let (press, name) = if let Some(name) = change.strip_prefix('+') {
+            (true, name)
+        } else if let Some(name) = change.strip_prefix('-') {
+            (false, name)
+        } else {
+            continue;
+        };
+        let output = match name {
+            "key" => Output::Key,
+            "button" => Output::Button,
+            _ => continue,
+        };
+        let Ok(code) = code.parse::<u16>() else {
+            continue;
+        };
+        if press {
+            held.insert((output, code));
+        } else {
+            held.remove(&(output, code));
+        }
+    }
+    held
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recover::Guarded;
+    use evdev::{EventType, InputEvent};
+
+    /// Records the releases and presses it is sent
+    #[derive(Default)]
+    struct Recorder(Vec<(Output, u16, i32)>);
+
+    impl EventSink for Recorder {
+        fn forward(&mut self, _event: InputEvent) -> Result<()> {
+            Ok(())
+        }
+
+        fn key(&mut self, key: Key, value: i32) -> Result<()> {
+            self.0.push((Output::Key, key.code(), value));
+            Ok(())
+        }
+
+        fn button(&mut self, button: Key, value: i32) -> Result<()> {
+            self.0.push((Output::Button, button.code(), value));
+            Ok(())
+        }
+
+        fn wheel(&mut self, _hi_res: i32, _legacy: i32) -> Result<()> {
+            Ok(())
+        }
+
+        fn devices(&self) -> Vec<String> {
+            Vec::new()
+        }
+    }
+
+    fn scratch(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "touch-layer-test-{}-{}.journal",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn restart_releases_what_a_handwritten_journal_holds() {
+        let path = scratch("restart");
+        // KEY_A released again, a torn last line, and one that isn't ours
+        fs::write(
+            &path,
+            "+key 30\n+button 272\n+key 31\n-key 30\n?key 5\n+butt",
+        )
+        .unwrap();
+        let mut out = Recorder::default();
+        let journal = Journal::open_at(path.clone(), &mut out).unwrap();
+        assert_eq!(out.0, [(Output::Key, 31, 0), (Output::Button, 272, 0)]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        drop(journal);
+        assert!(!path.exists(), "nothing held, so the journal is removed");
+    }
+
+    #[test]
+    fn a_killed_instance_leaves_its_held_outputs_for_the_next() {
+        let path = scratch("killed");
+        let mut journal = Journal::open_at(path.clone(), &mut Recorder::default()).unwrap();
+        journal.record(Output::Button, Key::BTN_LEFT, 1);
+        journal.record(Output::Button, Key::BTN_LEFT, 2);
+        journal.record(Output::Key, Key::new(u16::MAX), 1);
+        journal.record(Output::Key, Key::KEY_F13, 1);
+        journal.record(Output::Key, Key::KEY_F13, 0);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "+button 272\n+key 65535\n+key 183\n-key 183\n"
+        );
+        // Dropped with outputs held, as a kill leaves it
+        drop(journal);
+        let mut out = Recorder::default();
+        let next = Journal::open_at(path.clone(), &mut out).unwrap();
+        assert_eq!(
+            out.0,
+            [(Output::Key, u16::MAX, 0), (Output::Button, 272, 0)]
+        );
+        drop(next);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn passthrough_keys_are_never_journaled() {
+        let path = scratch("passthrough");
+        let mut outputs = Guarded::new(Box::new(Recorder::default()));
+        let journal = Journal::open_at(path.clone(), &mut outputs).unwrap();
+        outputs.keep_journal(journal);
+        for value in [1, 2, 0] {
+            let typed = InputEvent::new(EventType::KEY, Key::KEY_P.code(), value);
+            outputs.forward(typed).unwrap();
+        }
+        outputs.key(Key::KEY_PLAYPAUSE, 1).unwrap();
+        outputs.button(Key::BTN_RIGHT, 1).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "+key 164\n+button 273\n"
+        );
+        outputs.key(Key::KEY_PLAYPAUSE, 0).unwrap();
+        outputs.button(Key::BTN_RIGHT, 0).unwrap();
+        drop(outputs);
+        assert!(!path.exists());
+    }
+}
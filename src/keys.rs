@@ -0,0 +1,272 @@
+//! Key and button names as written in configs, scripts and simulations
+//!
+//! Accepted, in this order: a canonical evdev name in any case (`KEY_F`,
+//! `btn_left`), a friendly alias (`left`, `super`, `esc`), the name without its
+//! `KEY_`/`BTN_` prefix (`f`, `leftmeta`), or a numeric code (`0x110`, `272`;
+//! a single digit is the digit key).
+//! Anything else fails with the closest known name as a suggestion.
+
+use anyhow::{bail, Result};
+use evdev::Key;
+use std::str::FromStr;
+
+/// Codes below KEY_MAX + 1
+const KEY_CNT: u16 = 0x300;
+
+/// Friendly names, lowercase; buttons win over keys, so `left` is BTN_LEFT
+const ALIASES: &[(&str, Key)] = &[
+    ("left", Key::BTN_LEFT),
+    ("leftclick", Key::BTN_LEFT),
+    ("lmb", Key::BTN_LEFT),
+    ("right", Key::BTN_RIGHT),
+    ("rightclick", Key::BTN_RIGHT),
+    ("rmb", Key::BTN_RIGHT),
+    ("middle", Key::BTN_MIDDLE),
+    ("middleclick", Key::BTN_MIDDLE),
+    ("mmb", Key::BTN_MIDDLE),
+    ("back", Key::BTN_SIDE),
+    ("forward", Key::BTN_EXTRA),
+    ("esc", Key::KEY_ESC),
+    ("escape", Key::KEY_ESC),
+    ("return", Key::KEY_ENTER),
+    ("ctrl", Key::KEY_LEFTCTRL),
+    ("control", Key::KEY_LEFTCTRL),
+    ("shift", Key::KEY_LEFTSHIFT),
+    ("alt", Key::KEY_LEFTALT),
+    ("altgr", Key::KEY_RIGHTALT),
+    ("super", Key::KEY_LEFTMETA),
+    ("meta", Key::KEY_LEFTMETA),
+    ("win", Key::KEY_LEFTMETA),
+    ("caps", Key::KEY_CAPSLOCK),
+    ("del", Key::KEY_DELETE),
+    ("ins", Key::KEY_INSERT),
+    ("pgup", Key::KEY_PAGEUP),
+    ("pgdn", Key::KEY_PAGEDOWN),
+    ("menu", Key::KEY_COMPOSE),
+];
+
+/// Mapping targets that are a one-detent wheel step rather than a key
+const WHEEL_ALIASES: &[(&str, i32)] = &[
+    ("wheel_up", 1),
+    ("scroll_up", 1),
+    ("wheel_down", -1),
+    ("scroll_down", -1),
+];
+
+/// Resolve a key or button name, see the module docs
+pub fn resolve(name: &str) -> Result<Key> {
+    let trimmed = name.trim();
+    if trimmed.is_empty() {
+        bail!("empty key name");
+    }
+    let upper = trimmed.to_ascii_uppercase();
+    if let Ok(key) = Key::from_str(&upper) {
+        return Ok(key);
+    }
+    let lower = trimmed.to_ascii_lowercase();
+    if let Some((_, key)) = ALIASES.iter().find(|(alias, _)| *alias == lower) {
+        return Ok(*key);
+    }
+    for prefix in ["KEY_", "BTN_"] {
+        if let Ok(key) = Key::from_str(&format!("{}{}", prefix, upper)) {
+            return Ok(key);
+        }
+    }
+    if let Some(code) = parse_code(&lower) {
+        if code < KEY_CNT {
+            return Ok(Key::new(code));
+        }
+        bail!(
+            "key code {} is out of range (at most {:#x})",
+            code,
+            KEY_CNT - 1
+        );
+    }
+
+    match suggestion(&upper) {
+        Some(close) => bail!("unknown key '{}'; did you mean {}?", trimmed, close),
+        None => bail!("unknown key '{}'", trimmed),
+    }
+}
+
+/// The step of a `wheel_up`-style target name
+pub fn wheel_alias(name: &str) -> Option<i32> {
+    let lower = name.trim().to_ascii_lowercase();
+    WHEEL_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == lower)
+        .map(|(_, step)| *step)
+}
+
+/// KEY_A to KEY_Z, which sit in three runs of codes (Q-P, A-L, Z-M)
+pub fn is_letter(key: Key) -> bool {
+    matches!(key.code(), 16..=25 | 30..=38 | 44..=50)
+}
+
+fn parse_code(text: &str) -> Option<u16> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// Every canonical name evdev knows
+fn names() -> impl Iterator<Item = String> {
+    (0..KEY_CNT)
+        .map(|code| format!("{:?}", Key::new(code)))
+        .filter(|name| name.starts_with("KEY_") || name.starts_with("BTN_"))
+}
+
+/// The closest name to `upper`, if any is close enough to be a typo
+fn suggestion(upper: &str) -> Option<String> {
+    let aliases = ALIASES
+        .iter()
+        .map(|(alias, _)| alias.to_string())
+        .chain(WHEEL_ALIASES.iter().map(|(alias, _)| alias.to_string()));
+    let (distance, name) = names()
+        .chain(aliases)
+        .map(|name| {
+            // Compare with the prefix a bare name would get, so `leftmta` finds KEY_LEFTMETA
+            let canonical = name.to_ascii_uppercase();
+            let bare = ["KEY_", "BTN_"]
+                .iter()
+                .find_map(|prefix| canonical.strip_prefix(prefix))
+                .unwrap_or(&canonical);
+            let distance = levenshtein(upper, &canonical).min(levenshtein(upper, bare));
+            (distance, name)
+        })
+        .min_by_key(|(distance, _)| *distance)?;
+    (distance <= (upper.len() / 3).max(1)).then_some(name)
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                diagonal
+            } else {
+                1 + diagonal.min(above).min(row[j])
+            };
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(name: &str) -> String {
+        resolve(name).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn canonical_names_in_any_case() {
+        for name in ["KEY_F", "key_f", "Key_F", "  KEY_F "] {
+            assert_eq!(resolve(name).unwrap(), Key::KEY_F, "{:?}", name);
+        }
+        assert_eq!(resolve("btn_left").unwrap(), Key::BTN_LEFT);
+        assert_eq!(resolve("KEY_LEFTMETA").unwrap(), Key::KEY_LEFTMETA);
+    }
+
+    #[test]
+    fn names_without_their_prefix() {
+        assert_eq!(resolve("f").unwrap(), Key::KEY_F);
+        assert_eq!(resolve("LeftMeta").unwrap(), Key::KEY_LEFTMETA);
+        assert_eq!(resolve("space").unwrap(), Key::KEY_SPACE);
+        assert_eq!(resolve("side").unwrap(), Key::BTN_SIDE);
+        assert_eq!(resolve("playpause").unwrap(), Key::KEY_PLAYPAUSE);
+    }
+
+    #[test]
+    fn aliases_and_buttons_winning_over_keys() {
+        // KEY_LEFT exists too; the alias makes `left` the button
+        assert_eq!(resolve("left").unwrap(), Key::BTN_LEFT);
+        assert_eq!(resolve("LeftClick").unwrap(), Key::BTN_LEFT);
+        assert_eq!(resolve("rmb").unwrap(), Key::BTN_RIGHT);
+        assert_eq!(resolve("middle").unwrap(), Key::BTN_MIDDLE);
+        assert_eq!(resolve("forward").unwrap(), Key::BTN_EXTRA);
+        assert_eq!(resolve("super").unwrap(), Key::KEY_LEFTMETA);
+        assert_eq!(resolve("ESC").unwrap(), Key::KEY_ESC);
+        assert_eq!(resolve("key_left").unwrap(), Key::KEY_LEFT);
+        for (alias, key) in ALIASES {
+            assert_eq!(resolve(alias).unwrap(), *key, "{}", alias);
+        }
+    }
+
+    #[test]
+    fn wheel_aliases_are_steps_not_keys() {
+        assert_eq!(wheel_alias("wheel_up"), Some(1));
+        assert_eq!(wheel_alias(" Scroll_Down "), Some(-1));
+        assert_eq!(wheel_alias("left"), None);
+        assert!(resolve("wheel_up").is_err());
+    }
+
+    #[test]
+    fn numeric_codes() {
+        assert_eq!(resolve("0x110").unwrap(), Key::BTN_LEFT);
+        assert_eq!(resolve("272").unwrap(), Key::BTN_LEFT);
+        assert_eq!(resolve("0x21").unwrap(), Key::KEY_F);
+        // A single digit is the digit key, not a code
+        assert_eq!(resolve("1").unwrap(), Key::KEY_1);
+        assert_eq!(
+            error("0x300"),
+            "key code 768 is out of range (at most 0x2ff)"
+        );
+    }
+
+    #[test]
+    fn typos_get_the_closest_name() {
+        assert_eq!(
+            error("KEY_LEFTMTA"),
+            "unknown key 'KEY_LEFTMTA'; did you mean KEY_LEFTMETA?"
+        );
+        assert_eq!(
+            error("leftmta"),
+            "unknown key 'leftmta'; did you mean KEY_LEFTMETA?"
+        );
+        assert_eq!(
+            error("BTN_LEFY"),
+            "unknown key 'BTN_LEFY'; did you mean BTN_LEFT?"
+        );
+        assert_eq!(
+            error("lefclick"),
+            "unknown key 'lefclick'; did you mean leftclick?"
+        );
+        assert_eq!(
+            error("wheel_upp"),
+            "unknown key 'wheel_upp'; did you mean wheel_up?"
+        );
+    }
+
+    #[test]
+    fn nothing_close_gets_no_suggestion() {
+        assert_eq!(error("xyzzyplugh"), "unknown key 'xyzzyplugh'");
+        // Rather than the digit one edit away
+        assert_eq!(error(" "), "empty key name");
+    }
+
+    #[test]
+    fn levenshtein_distances() {
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("same", "same"), 0);
+    }
+
+    #[test]
+    fn letters_are_the_26_letter_keys() {
+        let letters: Vec<Key> = (0..KEY_CNT)
+            .map(Key::new)
+            .filter(|&k| is_letter(k))
+            .collect();
+        assert_eq!(letters.len(), 26);
+        assert!(letters.iter().all(|key| format!("{:?}", key).len() == 5));
+        assert!(!is_letter(Key::KEY_1) && !is_letter(Key::KEY_SPACE));
+    }
+}
@@ -0,0 +1,332 @@
+//! Config layers: /usr/share, then /etc, then the user's file, then `--config`
+//!
+//! Each layer overrides single keys of the ones below it. Tables merge key by
+//! key; anything else, arrays included, replaces what was there. A mapping
+//! replaces the whole target of its key. `key = { append = [..] }` instead adds
+//! to the list from the layers below.
+
+use crate::config::{Config, Setting, Source};
+use anyhow::{bail, Result};
+use std::path::{Path, PathBuf};
+use toml_edit::{Array, Item, TableLike, Value};
+
+/// Shipped by packages, below the administrator's /etc file
+const VENDOR_CONFIG_PATH: &str = "/usr/share/touch-layer/config.toml";
+
+/// Every layer that is read if present, lowest first
+pub fn paths(extra: Option<&Path>) -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from(VENDOR_CONFIG_PATH), Config::path()];
+    paths.extend(user_path());
+    paths.extend(extra.map(Path::to_path_buf));
+    // TOUCH_LAYER_CONFIG or --config may name a file that is already a layer
+    let mut seen = Vec::new();
+    paths.retain(|path| {
+        let new = !seen.contains(path);
+        seen.push(path.clone());
+        new
+    });
+    paths
+}
+
+/// $XDG_CONFIG_HOME/touch-layer/config.toml, or under ~/.config
+fn user_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| Some(PathBuf::from(std::env::var_os("HOME")?).join(".config")))?;
+    Some(base.join("touch-layer").join("config.toml"))
+}
+
+/// Keys below which a value is never merged into, only replaced
+fn atomic(key: &str) -> bool {
+    key.starts_with("mappings.")
+        || key.starts_with("external_mouse.mappings.")
+        || key
+            .strip_prefix("finger_layers.")
+            .is_some_and(|rest| rest.contains('.'))
+}
+
+/// Merge `upper` into `lower`, recording what `source` set in `settings`
+pub fn merge(
+    lower: &mut dyn TableLike,
+    upper: &dyn TableLike,
+    prefix: &str,
+    source: &Source,
+    settings: &mut Vec<Setting>,
+) -> Result<()> {
+    for (key, item) in upper.iter() {
+        let path = format!("{}{}", prefix, key);
+        if let (Some(below), Some(above)) = (
+            lower.get_mut(key).and_then(Item::as_table_like_mut),
+            item.as_table_like(),
+        ) {
+            if !atomic(&path) && append_marker(item).is_none() {
+                merge(below, above, &format!("{}.", path), source, settings)?;
+                continue;
+            }
+        }
+
+        let merged = match append_marker(item) {
+            Some(added) => {
+                let mut list = match lower.get(key) {
+                    None => Array::new(),
+                    Some(Item::Value(Value::Array(list))) => list.clone(),
+                    Some(Item::Value(value @ Value::String(_))) => {
+                        Array::from_iter([value.clone()])
+                    }
+                    Some(_) => bail!("{}: `append` needs a list in the layers below", path),
+                };
+                list.extend(added.iter().cloned());
+                Item::Value(Value::Array(list))
+            }
+            None => item.clone(),
+        };
+        forget(settings, &path);
+        record(&merged, &path, source, settings);
+        lower.insert(key, merged);
+    }
+    Ok(())
+}
+
+/// `{ append = [..] }`, the marker for adding to a list instead of replacing it
+fn append_marker(item: &Item) -> Option<&Array> {
+    let table = item.as_table_like()?;
+    if table.len() != 1 {
+        return None;
+    }
+    table.get("append")?.as_array()
+}
+
+/// Drop what earlier layers recorded for `key` and everything under it
+pub fn forget(settings: &mut Vec<Setting>, key: &str) {
+    settings.retain(|s| {
+        s.key != key
+            && !s
+                .key
+                .strip_prefix(key)
+                .is_some_and(|rest| rest.starts_with('.'))
+    });
+}
+
+/// Every leaf under `item`, keyed by its dotted path
+fn record(item: &Item, key: &str, source: &Source, settings: &mut Vec<Setting>) {
+    match item.as_table_like() {
+        Some(table) if !atomic(key) => {
+            for (sub, item) in table.iter() {
+                record(item, &format!("{}.{}", key, sub), source, settings);
+            }
+        }
+        _ => {
+            let value = match item {
+                Item::Value(value) => {
+                    let mut value = value.clone().decorated("", "");
+                    if let Value::Array(list) = &mut value {
+                        list.fmt();
+                    }
+                    value.to_string()
+                }
+                // `[mappings.KEY_F]` written as its own table
+                Item::Table(table) => {
+                    let mut inline = table.clone().into_inline_table();
+                    inline.fmt();
+                    inline.to_string()
+                }
+                other => other.to_string(),
+            };
+            settings.push(Setting {
+                key: key.to_string(),
+                value,
+                source: source.clone(),
+            });
+        }
+    }
+}
+
+/// `show-config`: which layers exist and what each one set
+pub fn show(config: &Config, extra: Option<&Path>) {
+    println!("Layers, lowest first:");
+    for path in paths(extra) {
+        let state = if config.layers.contains(&path) {
+            "loaded"
+        } else {
+            "not present"
+        };
+        println!("  {} ({})", path.display(), state);
+    }
+    println!();
+    if config.settings.is_empty() {
+        println!("Everything is at its built-in default.");
+        return;
+    }
+    println!("Values not at their built-in default:");
+    for setting in &config.settings {
+        println!(
+            "  {} = {}  [{}]",
+            setting.key, setting.value, setting.source
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use toml_edit::DocumentMut;
+
+    /// `upper` merged over `lower`, as TOML, and what `upper` was recorded setting
+    fn merged(lower: &str, upper: &str) -> Result<(DocumentMut, Vec<(String, String)>)> {
+        let mut doc: DocumentMut = lower.parse().unwrap();
+        let layer: DocumentMut = upper.parse().unwrap();
+        let mut settings = Vec::new();
+        let source = Source::File(PathBuf::from("upper.toml"));
+        merge(
+            doc.as_table_mut(),
+            layer.as_table(),
+            "",
+            &source,
+            &mut settings,
+        )?;
+        assert!(settings.iter().all(|s| s.source == source));
+        let settings = settings.into_iter().map(|s| (s.key, s.value)).collect();
+        Ok((doc, settings))
+    }
+
+    fn value(doc: &DocumentMut, path: &str) -> String {
+        let mut item = doc.as_item();
+        for key in path.split('.') {
+            item = &item[key];
+        }
+        let mut value = item.as_value().expect(path).clone().decorated("", "");
+        if let Value::Array(list) = &mut value {
+            list.fmt();
+        }
+        value.to_string()
+    }
+
+    #[test]
+    fn tables_merge_key_by_key() {
+        let (doc, settings) = merged(
+            "[scroll]\nnatural = true\nstep = 60\n",
+            "[scroll]\nstep = 120\n",
+        )
+        .unwrap();
+        assert_eq!(value(&doc, "scroll.natural"), "true");
+        assert_eq!(value(&doc, "scroll.step"), "120");
+        assert_eq!(settings, [("scroll.step".to_string(), "120".to_string())]);
+    }
+
+    #[test]
+    fn nested_tables_merge_all_the_way_down() {
+        let (doc, settings) = merged(
+            "[scroll.kinetic]\nenabled = true\nfriction = 2.0\n[scroll]\nstep = 60\n",
+            "[scroll.kinetic]\nfriction = 4.0\n",
+        )
+        .unwrap();
+        assert_eq!(value(&doc, "scroll.kinetic.enabled"), "true");
+        assert_eq!(value(&doc, "scroll.kinetic.friction"), "4.0");
+        assert_eq!(value(&doc, "scroll.step"), "60");
+        assert_eq!(settings.len(), 1);
+        assert_eq!(settings[0].0, "scroll.kinetic.friction");
+    }
+
+    #[test]
+    fn arrays_replace() {
+        let (doc, _) = merged(
+            "keyboard = [\"/dev/input/event3\", \"/dev/input/event4\"]\n",
+            "keyboard = [\"/dev/input/event7\"]\n",
+        )
+        .unwrap();
+        assert_eq!(value(&doc, "keyboard"), "[\"/dev/input/event7\"]");
+    }
+
+    #[test]
+    fn append_adds_to_the_list_below() {
+        let upper = "keyboard = { append = [\"/dev/input/event7\"] }\n";
+        let (doc, settings) = merged("keyboard = [\"/dev/input/event3\"]\n", upper).unwrap();
+        assert_eq!(
+            value(&doc, "keyboard"),
+            "[\"/dev/input/event3\", \"/dev/input/event7\"]"
+        );
+        assert_eq!(
+            settings,
+            [(
+                "keyboard".to_string(),
+                "[\"/dev/input/event3\", \"/dev/input/event7\"]".to_string()
+            )]
+        );
+        // A single path below is a one-element list, and nothing below an empty one
+        let (doc, _) = merged("keyboard = \"/dev/input/event3\"\n", upper).unwrap();
+        assert_eq!(
+            value(&doc, "keyboard"),
+            "[\"/dev/input/event3\", \"/dev/input/event7\"]"
+        );
+        let (doc, _) = merged("", upper).unwrap();
+        assert_eq!(value(&doc, "keyboard"), "[\"/dev/input/event7\"]");
+    }
+
+    #[test]
+    fn append_onto_anything_else_is_an_error() {
+        let upper = "[scroll]\nstep = { append = [1] }\n";
+        let error = merged("[scroll]\nstep = 60\n", upper).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "scroll.step: `append` needs a list in the layers below"
+        );
+        assert!(merged("[scroll.step]\nx = 1\n", upper).is_err());
+    }
+
+    #[test]
+    fn a_conflicting_type_replaces_what_was_below() {
+        let (doc, settings) = merged("[scroll]\nstep = 60\n", "scroll = 1\n").unwrap();
+        assert_eq!(value(&doc, "scroll"), "1");
+        assert_eq!(settings, [("scroll".to_string(), "1".to_string())]);
+        let (doc, _) = merged("scroll = 1\n", "[scroll]\nstep = 60\n").unwrap();
+        assert_eq!(value(&doc, "scroll.step"), "60");
+    }
+
+    #[test]
+    fn a_mapping_replaces_its_whole_target() {
+        let (doc, settings) = merged(
+            "[mappings]\nKEY_F = { wheel = 1, natural = true }\nKEY_D = \"BTN_RIGHT\"\n",
+            "[mappings]\nKEY_F = { wheel = 2 }\n",
+        )
+        .unwrap();
+        assert_eq!(value(&doc, "mappings.KEY_F"), "{ wheel = 2 }");
+        assert_eq!(value(&doc, "mappings.KEY_D"), "\"BTN_RIGHT\"");
+        assert_eq!(
+            settings,
+            [("mappings.KEY_F".to_string(), "{ wheel = 2 }".to_string())]
+        );
+    }
+
+    #[test]
+    fn a_later_layer_forgets_what_it_replaced() {
+        let mut settings = Vec::new();
+        let mut doc: DocumentMut = "".parse().unwrap();
+        for (name, text) in [
+            ("lower.toml", "[scroll]\nstep = 60\nnatural = true\n"),
+            ("upper.toml", "scroll = { step = 120 }\n"),
+        ] {
+            let layer: DocumentMut = text.parse().unwrap();
+            let source = Source::File(PathBuf::from(name));
+            merge(
+                doc.as_table_mut(),
+                layer.as_table(),
+                "",
+                &source,
+                &mut settings,
+            )
+            .unwrap();
+        }
+        let sources: Vec<_> = settings
+            .iter()
+            .map(|s| (s.key.as_str(), s.source.clone()))
+            .collect();
+        assert_eq!(
+            sources,
+            [
+                ("scroll.natural", Source::File(PathBuf::from("lower.toml"))),
+                ("scroll.step", Source::File(PathBuf::from("upper.toml"))),
+            ]
+        );
+    }
+}
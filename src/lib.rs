@@ -0,0 +1,71 @@
+//! touch-layer: keyboard keys become mouse buttons and scrolling while the touchpad is in use
+//!
+//! The `touchpad-remap` binary is a thin layer over these modules; they are a
+//! library so the benchmarks in `benches/` can reach the event path.
+
+pub mod activation;
+pub mod backend;
+pub mod backoff;
+pub mod capabilities;
+pub mod cli;
+pub mod condition;
+pub mod config;
+pub mod control;
+pub mod ctl;
+pub mod daemon;
+pub mod device;
+pub mod diagnose;
+pub mod doctor;
+pub mod dump;
+pub mod exit;
+pub mod hooks;
+pub mod init_config;
+pub mod inject;
+pub mod instance;
+pub mod journal;
+pub mod keys;
+pub mod layers;
+pub mod libinput;
+pub mod live_map;
+pub mod logfile;
+pub mod migrate;
+pub mod modifiers;
+pub mod monitor;
+pub mod motion;
+pub mod mouse_proxy;
+pub mod mt;
+pub mod output;
+pub mod overrides;
+pub mod preflight;
+pub mod presets;
+pub mod quality;
+pub mod reaper;
+pub mod recover;
+pub mod remap;
+pub mod report;
+pub mod resync;
+pub mod sched;
+pub mod script;
+pub mod scroll;
+pub mod seat;
+pub mod session;
+pub mod shutdown;
+pub mod simulate;
+pub mod sink;
+pub mod snooze;
+pub mod state;
+pub mod state_output;
+pub mod stress;
+pub mod swipe;
+pub mod tablet;
+pub mod trigger;
+pub mod typing;
+pub mod velocity;
+pub mod warp;
+pub mod watchdog;
+#[cfg(feature = "wayland")]
+pub mod wayland;
+#[cfg(feature = "xkb")]
+pub mod xkb;
+#[cfg(feature = "xtest")]
+pub mod xtest;
@@ -0,0 +1,382 @@
+//! Parser for `libinput debug-events` output lines
+//!
+//! Lines look like (column widths vary between libinput versions):
+//!
+//! ```text
+//! -event7   DEVICE_ADDED            SynPS/2 Synaptics TouchPad        seat0 default group7  cap:pg
+//!  event7   POINTER_MOTION          +1.234s    1.20/  0.50 ( +1.00/ +0.50)
+//!  event7   GESTURE_SWIPE_BEGIN     +2.000s    3
+//! ```
+//!
+//! Kept free of other crate modules so the fuzz target can include it directly.
+
+use std::fmt;
+use std::io::{self, BufRead};
+
+/// Event type column, for the events we care about; anything else is `Other`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    DeviceAdded,
+    DeviceRemoved,
+    PointerMotion,
+    PointerMotionAbsolute,
+    PointerButton,
+    /// POINTER_AXIS (before 1.19) and POINTER_SCROLL_* (1.19+)
+    PointerScroll,
+    GestureSwipeBegin,
+    GestureSwipeUpdate,
+    GestureSwipeEnd,
+    GesturePinchBegin,
+    GesturePinchUpdate,
+    GesturePinchEnd,
+    GestureHoldBegin,
+    GestureHoldEnd,
+    TouchDown,
+    TouchMotion,
+    TouchUp,
+    KeyboardKey,
+    Other,
+}
+
+const KINDS: &[(&str, Kind)] = &[
+    ("DEVICE_ADDED", Kind::DeviceAdded),
+    ("DEVICE_REMOVED", Kind::DeviceRemoved),
+    ("POINTER_MOTION", Kind::PointerMotion),
+    ("POINTER_MOTION_ABSOLUTE", Kind::PointerMotionAbsolute),
+    ("POINTER_BUTTON", Kind::PointerButton),
+    ("POINTER_AXIS", Kind::PointerScroll),
+    ("POINTER_SCROLL_WHEEL", Kind::PointerScroll),
+    ("POINTER_SCROLL_FINGER", Kind::PointerScroll),
+    ("POINTER_SCROLL_CONTINUOUS", Kind::PointerScroll),
+    ("GESTURE_SWIPE_BEGIN", Kind::GestureSwipeBegin),
+    ("GESTURE_SWIPE_UPDATE", Kind::GestureSwipeUpdate),
+    ("GESTURE_SWIPE_END", Kind::GestureSwipeEnd),
+    ("GESTURE_PINCH_BEGIN", Kind::GesturePinchBegin),
+    ("GESTURE_PINCH_UPDATE", Kind::GesturePinchUpdate),
+    ("GESTURE_PINCH_END", Kind::GesturePinchEnd),
+    ("GESTURE_HOLD_BEGIN", Kind::GestureHoldBegin),
+    ("GESTURE_HOLD_END", Kind::GestureHoldEnd),
+    ("TOUCH_DOWN", Kind::TouchDown),
+    ("TOUCH_MOTION", Kind::TouchMotion),
+    ("TOUCH_UP", Kind::TouchUp),
+    ("KEYBOARD_KEY", Kind::KeyboardKey),
+];
+
+impl Kind {
+    pub fn from_name(name: &str) -> Kind {
+        KINDS
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map_or(Kind::Other, |(_, kind)| *kind)
+    }
+
+    /// Canonical column name; several names map to `PointerScroll`, this is the current one
+    pub fn name(self) -> Option<&'static str> {
+        match self {
+            Kind::PointerScroll => Some("POINTER_SCROLL_FINGER"),
+            Kind::Other => None,
+            kind => KINDS.iter().find(|(_, k)| *k == kind).map(|(n, _)| *n),
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name().unwrap_or("(other)"))
+    }
+}
+
+/// One parsed line, borrowing from the input
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Line<'a> {
+    /// Event node name, e.g. `event7`
+    pub device: &'a str,
+    pub kind: Kind,
+    /// The raw event type column
+    pub kind_name: &'a str,
+    /// Seconds since libinput started, when the line has a `+1.234s` column
+    pub time: Option<f64>,
+    /// Everything after the time (or the type, without one)
+    pub payload: &'a str,
+}
+
+/// Parse one line; None for blank lines and anything without device and type columns
+pub fn parse(line: &str) -> Option<Line<'_>> {
+    let line = line.trim_start();
+    let (device, rest) = split_word(line)?;
+    let device = device.strip_prefix('-').unwrap_or(device);
+    if !device.starts_with("event") {
+        return None;
+    }
+    let (kind_name, rest) = split_word(rest)?;
+    if !kind_name
+        .bytes()
+        .all(|b| b.is_ascii_uppercase() || b == b'_')
+    {
+        return None;
+    }
+
+    let (time, payload) = match split_word(rest) {
+        Some((word, after)) if word.starts_with('+') && word.ends_with('s') => {
+            let time = word[1..word.len() - 1].parse::<f64>().ok();
+            (time.filter(|t| t.is_finite()), after)
+        }
+        _ => (None, rest),
+    };
+
+    Some(Line {
+        device,
+        kind: Kind::from_name(kind_name),
+        kind_name,
+        time,
+        payload: payload.trim(),
+    })
+}
+
+/// The type column of a raw line, found on bytes alone; `Other` for anything `parse` rejects
+pub fn kind_of(line: &[u8]) -> Kind {
+    let mut words = line
+        .split(u8::is_ascii_whitespace)
+        .filter(|word| !word.is_empty());
+    let (Some(device), Some(kind)) = (words.next(), words.next()) else {
+        return Kind::Other;
+    };
+    let device = device.strip_prefix(b"-").unwrap_or(device);
+    if !device.starts_with(b"event") {
+        return Kind::Other;
+    }
+    KINDS
+        .iter()
+        .find(|(name, _)| name.as_bytes() == kind)
+        .map_or(Kind::Other, |(_, kind)| *kind)
+}
+
+/// `debug-events` output read a line at a time into one buffer that is reused
+///
+/// `advance` passes over lines of unwanted kinds on their type column alone,
+/// before any UTF-8 check, so the steady state neither allocates nor decodes.
+pub struct Scanner<R> {
+    reader: R,
+    buf: Vec<u8>,
+}
+
+impl<R: BufRead> Scanner<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buf: Vec::with_capacity(256),
+        }
+    }
+
+    /// Read up to the next line whose kind `wanted` takes; false at the end of input
+    pub fn advance(&mut self, wanted: impl Fn(Kind) -> bool) -> io::Result<bool> {
+        loop {
+            self.buf.clear();
+            if self.reader.read_until(b'\n', &mut self.buf)? == 0 {
+                return Ok(false);
+            }
+            if wanted(kind_of(&self.buf)) {
+                return Ok(true);
+            }
+        }
+    }
+
+    /// The line `advance` stopped at, parsed; None if it isn't UTF-8 or doesn't parse
+    pub fn line(&self) -> Option<Line<'_>> {
+        parse(std::str::from_utf8(&self.buf).ok()?)
+    }
+}
+
+/// The accelerated dx/dy of a POINTER_MOTION payload (`1.20/  0.50 ( +1.00/ +0.50)`)
+pub fn motion_delta(payload: &str) -> Option<(f64, f64)> {
+    let accelerated = payload.split('(').next()?;
+    let (dx, dy) = accelerated.split_once('/')?;
+    let dx = dx.trim().parse::<f64>().ok()?;
+    let dy = dy.trim().parse::<f64>().ok()?;
+    (dx.is_finite() && dy.is_finite()).then_some((dx, dy))
+}
+
+/// The finger count leading a GESTURE_* payload (`3`, `3 cancelled`, `3 12.00/ 0.50 (...)`)
+pub fn gesture_fingers(payload: &str) -> Option<u32> {
+    split_word(payload)?.0.parse().ok()
+}
+
+/// Whether a GESTURE_*_END payload says the gesture was cancelled
+pub fn gesture_cancelled(payload: &str) -> bool {
+    payload.split_whitespace().nth(1) == Some("cancelled")
+}
+
+/// The accelerated dx/dy of a GESTURE_SWIPE_UPDATE payload, after the finger count
+pub fn gesture_delta(payload: &str) -> Option<(f64, f64)> {
+    motion_delta(split_word(payload)?.1)
+}
+
+/// Whether a `libinput --version` string is 1.19 or later, which reports hold gestures
+///
+/// An unknown version is assumed to be recent.
+pub fn has_hold_gestures(version: Option<&str>) -> bool {
+    let Some(version) = version else {
+        return true;
+    };
+    let mut parts = version.split('.').map(|part| part.parse::<u32>().ok());
+    match (parts.next().flatten(), parts.next().flatten()) {
+        (Some(major), Some(minor)) => (major, minor) >= (1, 19),
+        _ => true,
+    }
+}
+
+/// How `parse` reads a line, for `parse-libinput`; None for blank lines
+///
+/// Rejected lines (headers, warnings, kernel-bug notes) read `ignored`.
+pub fn describe(line: &str) -> Option<String> {
+    if line.trim().is_empty() {
+        return None;
+    }
+    let Some(parsed) = parse(line) else {
+        return Some("ignored".to_string());
+    };
+    let mut text = match parsed.kind {
+        Kind::Other => format!("{} Other({})", parsed.device, parsed.kind_name),
+        kind => format!("{} {:?}", parsed.device, kind),
+    };
+    match parsed.time {
+        Some(time) => text.push_str(&format!(" +{:.3}s", time)),
+        None => text.push_str(" -"),
+    }
+    if parsed.kind == Kind::PointerMotion {
+        match motion_delta(parsed.payload) {
+            Some((dx, dy)) => text.push_str(&format!(" delta {:.2}/{:.2}", dx, dy)),
+            None => text.push_str(" no delta"),
+        }
+    }
+    let swipe = [
+        Kind::GestureSwipeBegin,
+        Kind::GestureSwipeUpdate,
+        Kind::GestureSwipeEnd,
+    ];
+    if swipe.contains(&parsed.kind) {
+        match gesture_fingers(parsed.payload) {
+            Some(fingers) => text.push_str(&format!(" fingers {}", fingers)),
+            None => text.push_str(" no fingers"),
+        }
+    }
+    if parsed.kind == Kind::GestureSwipeUpdate {
+        match gesture_delta(parsed.payload) {
+            Some((dx, dy)) => text.push_str(&format!(" delta {:.2}/{:.2}", dx, dy)),
+            None => text.push_str(" no delta"),
+        }
+    }
+    if parsed.kind == Kind::GestureSwipeEnd && gesture_cancelled(parsed.payload) {
+        text.push_str(" cancelled");
+    }
+    Some(text)
+}
+
+/// First whitespace-separated word and the remainder
+fn split_word(text: &str) -> Option<(&str, &str)> {
+    let text = text.trim_start();
+    if text.is_empty() {
+        return None;
+    }
+    let end = text.find(char::is_whitespace).unwrap_or(text.len());
+    Some((&text[..end], &text[end..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURES: &[(&str, &str)] = &[
+        (
+            include_str!("../examples/libinput/synthetic-v1.20.log"),
+            include_str!("../examples/libinput/synthetic-v1.20.expected"),
+        ),
+        (
+            include_str!("../examples/libinput/synthetic-v1.23.log"),
+            include_str!("../examples/libinput/synthetic-v1.23.expected"),
+        ),
+        (
+            include_str!("../examples/libinput/synthetic-v1.26.log"),
+            include_str!("../examples/libinput/synthetic-v1.26.expected"),
+        ),
+        (
+            include_str!("../examples/libinput/swipe.log"),
+            include_str!("../examples/libinput/swipe.expected"),
+        ),
+        (
+            include_str!("../examples/libinput/rejected.log"),
+            include_str!("../examples/libinput/rejected.expected"),
+        ),
+    ];
+    const REJECTED: &str = include_str!("../examples/libinput/rejected.log");
+    const V1_20: &str = include_str!("../examples/libinput/synthetic-v1.20.log");
+    const V1_26: &str = include_str!("../examples/libinput/synthetic-v1.26.log");
+
+    #[test]
+    fn every_fixture_reads_as_expected() {
+        for (log, expected) in FIXTURES {
+            let read: Vec<String> = log.lines().filter_map(describe).collect();
+            let expected: Vec<&str> = expected.lines().filter(|l| !l.starts_with('#')).collect();
+            assert_eq!(read, expected);
+        }
+    }
+
+    #[test]
+    fn lines_parse_into_typed_events() {
+        let lines: Vec<Line> = V1_20.lines().filter_map(parse).collect();
+        assert_eq!(
+            lines[0],
+            Line {
+                device: "event2",
+                kind: Kind::DeviceAdded,
+                kind_name: "DEVICE_ADDED",
+                time: None,
+                payload: "Power Button                      seat0 default group1  cap:k",
+            }
+        );
+        let motion = lines[2];
+        assert_eq!(
+            (motion.device, motion.kind),
+            ("event7", Kind::PointerMotion)
+        );
+        assert_eq!(motion.time, Some(1.204));
+        assert_eq!(motion_delta(motion.payload), Some((1.20, 0.50)));
+        let scroll = lines[6];
+        assert_eq!(
+            (scroll.kind, scroll.kind_name),
+            (Kind::PointerScroll, "POINTER_SCROLL_FINGER")
+        );
+        let update = lines[11];
+        assert_eq!(update.kind, Kind::GestureSwipeUpdate);
+        assert_eq!(gesture_fingers(update.payload), Some(3));
+        assert_eq!(gesture_delta(update.payload), Some((12.00, 0.50)));
+    }
+
+    #[test]
+    fn headers_and_warnings_are_ignored() {
+        for line in REJECTED.lines().filter(|l| !l.trim().is_empty()) {
+            assert_eq!(parse(line), None, "{line:?}");
+            assert_eq!(kind_of(line.as_bytes()), Kind::Other, "{line:?}");
+        }
+    }
+
+    #[test]
+    fn kind_of_agrees_with_parse() {
+        for (log, _) in FIXTURES {
+            for line in log.lines() {
+                let parsed = parse(line).map_or(Kind::Other, |line| line.kind);
+                assert_eq!(kind_of(line.as_bytes()), parsed, "{line:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn the_scanner_stops_only_at_wanted_lines() {
+        let mut scanner = Scanner::new(V1_26.as_bytes());
+        let mut times = Vec::new();
+        while scanner.advance(|kind| kind == Kind::PointerMotion).unwrap() {
+            times.push(scanner.line().unwrap().time.unwrap());
+        }
+        assert_eq!(times, [101.500, 101.512]);
+    }
+}
@@ -0,0 +1,299 @@
+//! `ctl map KEY TARGET`, `ctl unmap KEY`, `ctl list-map` and `ctl save`
+//!
+//! Edits to the keyboard's base table while running, for trying a mapping
+//! out. A target is a plain one (`BTN_MIDDLE`, `KEY_PLAYPAUSE`, `wheel_up`),
+//! checked by the config's own resolver and against what the virtual devices
+//! declare: one they lack is refused, as it would need them recreated. The
+//! socket thread checks and records an edit, then queues it for the keyboard
+//! loop, which applies it; a held key keeps its press-time route, and finger
+//! layers still win where they map the key. Edits are lost at exit unless
+//! `save` writes them into the highest config layer, comments kept. Only
+//! `list-map` works without `control.map`.
+
+use crate::config::{Config, Source, Target};
+use crate::inject::Declared;
+use crate::keys;
+use crate::layers;
+use crate::shutdown;
+use anyhow::{bail, Context, Result};
+use evdev::Key;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::Write;
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use toml_edit::{value, DocumentMut, Item};
+
+/// One change for the keyboard loop: the key's new target and chatter window, or none
+pub struct Edit {
+    pub key: Key,
+    pub mapping: Option<(Target, Option<Duration>)>,
+}
+
+struct Mapped {
+    /// What each mapped key does now, described
+    current: HashMap<Key, String>,
+    /// The same as loaded or last saved, to tell whether anything changed
+    saved: HashMap<Key, String>,
+    /// Targets as given since the last save, by key code; None unmaps
+    edits: BTreeMap<u16, (Key, Option<String>)>,
+}
+
+struct Live {
+    config: Config,
+    /// The keyboard's `[[mapping_sets]]` entry, whose table `save` can't write
+    mapping_set: Option<usize>,
+    declared: Declared,
+    table: Mutex<Mapped>,
+    pending: Mutex<Vec<Edit>>,
+    read: OwnedFd,
+    write: OwnedFd,
+}
+
+static LIVE: OnceLock<Live> = OnceLock::new();
+
+/// Accept edits from now on, against the keyboard's table
+///
+/// `config` is the keyboard's own (`Config::for_keyboard`), from `mapping_set`
+/// if one selected it; `declared` is what its virtual devices were created with.
+pub fn init(config: &Config, mapping_set: Option<usize>, declared: Declared) -> Result<()> {
+    let (read, write) = shutdown::pipe("mapping edits")?;
+    let mut current: HashMap<Key, String> = config
+        .mappings
+        .iter()
+        .map(|(&key, target)| (key, target.to_string()))
+        .collect();
+    for (&key, variants) in &config.conditional {
+        current.insert(key, format!("by modifiers ({} variants)", variants.len()));
+    }
+    let _ = LIVE.set(Live {
+        config: config.clone(),
+        mapping_set,
+        declared,
+        table: Mutex::new(Mapped {
+            saved: current.clone(),
+            current,
+            edits: BTreeMap::new(),
+        }),
+        pending: Mutex::new(Vec::new()),
+        read,
+        write,
+    });
+    Ok(())
+}
+
+fn live() -> Result<&'static Live> {
+    match LIVE.get() {
+        Some(live) => Ok(live),
+        None => bail!("the keyboard is not up yet"),
+    }
+}
+
+/// The live table, for a command that changes it or the config file
+fn editable() -> Result<&'static Live> {
+    let live = live()?;
+    if !live.config.control.map {
+        bail!("map editing is off (control.map)");
+    }
+    Ok(live)
+}
+
+/// `map KEY TARGET`: check, record and queue; returns what to tell the client
+pub fn map(key: &str, target: &str) -> Result<String> {
+    let live = editable()?;
+    let (key, resolved, chatter) = live.config.parse_mapping(key, target)?;
+    live.declared.check_target(resolved).with_context(|| {
+        format!(
+            "{} needs the virtual devices recreated; map it in the config and restart",
+            target
+        )
+    })?;
+    let described = resolved.to_string();
+    {
+        let mut table = live.table.lock().unwrap();
+        table.current.insert(key, described.clone());
+        table
+            .edits
+            .insert(key.code(), (key, Some(target.to_string())));
+    }
+    live.submit(Edit {
+        key,
+        mapping: Some((resolved, chatter)),
+    });
+    eprintln!("✏️  Mapped {:?} → {} (control)", key, described);
+    Ok(format!("{:?} → {}", key, described))
+}
+
+/// `unmap KEY`: the key is typed as itself again
+pub fn unmap(key: &str) -> Result<String> {
+    let live = editable()?;
+    let key = keys::resolve(key)?;
+    {
+        let mut table = live.table.lock().unwrap();
+        if table.current.remove(&key).is_none() {
+            bail!("{:?} is not mapped in the base table", key);
+        }
+        table.edits.insert(key.code(), (key, None));
+    }
+    live.submit(Edit { key, mapping: None });
+    eprintln!("✏️  Unmapped {:?} (control)", key);
+    Ok(format!("{:?} unmapped", key))
+}
+
+/// `list-map`: every mapped key, by code, as `KEY → TARGET` joined with `; `
+pub fn list() -> Result<String> {
+    let live = live()?;
+    let table = live.table.lock().unwrap();
+    let mut entries: Vec<(&Key, &String)> = table.current.iter().collect();
+    entries.sort_by_key(|(key, _)| key.code());
+    if entries.is_empty() {
+        return Ok("nothing mapped".to_string());
+    }
+    Ok(entries
+        .iter()
+        .map(|(key, target)| format!("{:?} → {}", key, target))
+        .collect::<Vec<_>>()
+        .join("; "))
+}
+
+/// Whether the table differs from the config as loaded or last saved
+pub fn modified() -> bool {
+    LIVE.get().is_some_and(|live| {
+        let table = live.table.lock().unwrap();
+        table.current != table.saved
+    })
+}
+
+/// Descriptor that becomes readable when edits are queued, or -1 (which poll ignores)
+pub fn fd() -> RawFd {
+    LIVE.get().map_or(-1, |live| live.read.as_raw_fd())
+}
+
+/// Everything queued since the last call, emptying the wakeup pipe
+pub fn take() -> Vec<Edit> {
+    let Some(live) = LIVE.get() else {
+        return Vec::new();
+    };
+    let (fd, mut buf) = (live.read.as_raw_fd(), [0u8; 64]);
+    // SAFETY: reading into a valid buffer from our own non-blocking pipe
+    while unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) } > 0 {}
+    live.pending.lock().unwrap().drain(..).collect()
+}
+
+impl Live {
+    fn submit(&self, edit: Edit) {
+        self.pending.lock().unwrap().push(edit);
+        // SAFETY: writing one byte from a valid buffer to our own pipe
+        unsafe { libc::write(self.write.as_raw_fd(), [1u8].as_ptr().cast(), 1) };
+    }
+
+    /// The highest config layer, which is where a new file goes too
+    fn save_path(&self) -> Option<PathBuf> {
+        self.config
+            .layers
+            .last()
+            .cloned()
+            .or_else(|| layers::paths(None).pop())
+    }
+}
+
+/// `save`: write the edits into `[mappings]` of the highest config layer
+pub fn save() -> Result<String> {
+    let live = editable()?;
+    if let Some(index) = live.mapping_set {
+        bail!(
+            "the keyboard uses mapping_sets[{}], which save can't write; edit the config",
+            index
+        );
+    }
+    if !live.config.keysyms.is_empty() {
+        bail!("mapping_keys = \"keysym\" configs can't be saved to; edit the config");
+    }
+    let path = live.save_path().context("No config layer to save to")?;
+    let mut table = live.table.lock().unwrap();
+    if table.edits.is_empty() {
+        return Ok(format!("nothing to save to {}", path.display()));
+    }
+
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => {
+            return Err(err).with_context(|| format!("Failed to read {}", path.display()));
+        }
+    };
+    let mut doc: DocumentMut = text
+        .parse()
+        .with_context(|| format!("Failed to parse config {}", path.display()))?;
+    let mappings = doc
+        .entry("mappings")
+        .or_insert_with(|| Item::Table(toml_edit::Table::new()))
+        .as_table_like_mut()
+        .with_context(|| format!("{}: mappings is not a table", path.display()))?;
+    let mut below = Vec::new();
+    for (key, target) in table.edits.values() {
+        // `f` and `KEY_F` are the same key: whichever spelling the file uses goes
+        let names: Vec<String> = mappings
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .filter(|name| keys::resolve(name).is_ok_and(|resolved| resolved == *key))
+            .collect();
+        for name in names {
+            mappings.remove(&name);
+        }
+        match target {
+            Some(target) => {
+                mappings.insert(&format!("{:?}", key), value(target.as_str()));
+            }
+            None => below.extend(mapped_below(&live.config, *key, &path)),
+        }
+    }
+    let updated = doc.to_string();
+    // Never write back something the daemon would refuse
+    Config::parse(&updated, &path).context("The edited config does not validate")?;
+    write(&path, &updated)?;
+
+    let count = table.edits.len();
+    table.edits.clear();
+    table.saved = table.current.clone();
+    eprintln!("📝 Saved {} mapping edit(s) to {}", count, path.display());
+    let mut reply = format!("saved {} edit(s) to {}", count, path.display());
+    for (key, layer) in below {
+        reply.push_str(&format!(
+            "; {:?} is still mapped in {}, which a restart reads",
+            key,
+            layer.display()
+        ));
+    }
+    Ok(reply)
+}
+
+/// Lower layers mapping `key`, which an unmap in `path` can't take away
+fn mapped_below(config: &Config, key: Key, path: &Path) -> Option<(Key, PathBuf)> {
+    config.settings.iter().find_map(|setting| {
+        let name = setting.key.strip_prefix("mappings.")?;
+        let Source::File(layer) = &setting.source else {
+            return None;
+        };
+        (layer != path && keys::resolve(name).is_ok_and(|resolved| resolved == key))
+            .then(|| (key, layer.clone()))
+    })
+}
+
+/// Via a temp file and rename, so a crash never leaves a torn config
+fn write(path: &Path, text: &str) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create config directory {}", dir.display()))?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut file =
+        File::create(&tmp).with_context(|| format!("Failed to write config {}", tmp.display()))?;
+    file.write_all(text.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp, path).with_context(|| format!("Failed to replace config {}", path.display()))
+}
@@ -0,0 +1,268 @@
+//! `--log-file`: the stderr log, also kept in a file that rotates itself
+//!
+//! stderr is replaced by a pipe. A thread passes everything read from it on
+//! to the original stderr, and appends it to the file through a buffer that
+//! is flushed every `FLUSH_INTERVAL` and at exit. Once the file reaches
+//! `--log-max-size` it becomes `PATH.1`, older ones move up to
+//! `PATH.<--log-files>` and the oldest is removed. SIGHUP reopens the file,
+//! for external rotation that moves it away.
+
+use anyhow::{Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+/// Longest a line waits in the buffer; a crash loses at most this much
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long exit waits for the writer to drain the pipe
+const FINISH_TIMEOUT: Duration = Duration::from_secs(1);
+
+struct Writer {
+    /// Our duplicate of the original stderr, which the log still goes to
+    tee: OwnedFd,
+    reopen: AtomicBool,
+    /// Signalled once the writer has flushed for the last time
+    done: Mutex<Option<Receiver<()>>>,
+}
+
+static WRITER: OnceLock<Writer> = OnceLock::new();
+
+/// Where the log goes and when it rotates
+pub struct Options {
+    pub path: PathBuf,
+    pub max_size: u64,
+    /// Rotated files kept besides the current one
+    pub keep: usize,
+}
+
+pub fn active() -> bool {
+    WRITER.get().is_some()
+}
+
+/// Send stderr through the log file from now on
+///
+/// Starts a thread, so a daemon has to fork first.
+pub fn start(options: Options) -> Result<()> {
+    let file = open(&options.path)?;
+    let size = file.metadata().map_or(0, |meta| meta.len());
+    let path = options.path.display().to_string();
+
+    // SAFETY: fcntl only reads the descriptor
+    let tee = unsafe { libc::fcntl(libc::STDERR_FILENO, libc::F_DUPFD_CLOEXEC, 0) };
+    if tee < 0 {
+        return Err(io::Error::last_os_error()).context("Failed to duplicate stderr");
+    }
+    // SAFETY: tee is a fresh descriptor nothing else owns
+    let tee = unsafe { OwnedFd::from_raw_fd(tee) };
+
+    // Blocking, unlike shutdown::pipe: a log line must never fail with EAGAIN
+    let mut fds = [0; 2];
+    // SAFETY: fds has room for the two descriptors pipe2 writes
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error()).context("Failed to create log pipe");
+    }
+    // SAFETY: pipe2 just returned these descriptors and nothing else owns them
+    let (read, write) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+
+    let (done_tx, done_rx) = mpsc::channel();
+    let mut log = Log {
+        path: options.path,
+        max_size: options.max_size,
+        keep: options.keep,
+        out: Some(BufWriter::new(file)),
+        size,
+        tee: tee.as_raw_fd(),
+    };
+    let _ = WRITER.set(Writer {
+        tee,
+        reopen: AtomicBool::new(false),
+        done: Mutex::new(Some(done_rx)),
+    });
+    thread::Builder::new()
+        .name("log file".into())
+        .spawn(move || {
+            log.run(File::from(read));
+            let _ = done_tx.send(());
+        })
+        .context("Failed to start the log writer")?;
+
+    // SAFETY: both descriptors are valid; dup2 atomically replaces stderr,
+    // and the copy it makes is inherited by hooks, so their output is logged too
+    if unsafe { libc::dup2(write.as_raw_fd(), libc::STDERR_FILENO) } < 0 {
+        return Err(io::Error::last_os_error()).context("Failed to redirect stderr");
+    }
+    eprintln!(
+        "📝 Log file: {} (rotated at {} bytes, {} kept)",
+        path, options.max_size, options.keep
+    );
+    Ok(())
+}
+
+/// Reopen the file at its path on the next wakeup (SIGHUP)
+pub fn reopen() {
+    if let Some(writer) = WRITER.get() {
+        writer.reopen.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Keep logging to the file only, now that the terminal is left behind
+pub fn detach(null: RawFd) -> io::Result<()> {
+    let Some(writer) = WRITER.get() else {
+        return Ok(());
+    };
+    // SAFETY: both descriptors are valid; dup2 atomically replaces our copy of stderr
+    if unsafe { libc::dup2(null, writer.tee.as_raw_fd()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Put stderr back and wait for the writer to flush what it was sent
+pub fn finish() {
+    let Some(writer) = WRITER.get() else {
+        return;
+    };
+    let Some(done) = writer.done.lock().unwrap().take() else {
+        return;
+    };
+    // Closes our end of the pipe; the writer sees EOF unless a hook still holds it
+    // SAFETY: both descriptors are valid; dup2 atomically replaces stderr
+    unsafe { libc::dup2(writer.tee.as_raw_fd(), libc::STDERR_FILENO) };
+    let _ = done.recv_timeout(FINISH_TIMEOUT);
+}
+
+fn open(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o640)
+        .open(path)
+        .with_context(|| format!("Failed to open log file {}", path.display()))
+}
+
+struct Log {
+    path: PathBuf,
+    max_size: u64,
+    keep: usize,
+    /// None after the file could not be reopened; retried at the next rotation or SIGHUP
+    out: Option<BufWriter<File>>,
+    size: u64,
+    tee: RawFd,
+}
+
+impl Log {
+    fn run(&mut self, mut pipe: File) {
+        let mut buf = [0u8; 8192];
+        loop {
+            let readable = wait(pipe.as_raw_fd(), FLUSH_INTERVAL);
+            if WRITER
+                .get()
+                .is_some_and(|writer| writer.reopen.swap(false, Ordering::Relaxed))
+            {
+                self.reopen();
+            }
+            if !readable {
+                self.flush();
+                continue;
+            }
+            match pipe.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => self.write(&buf[..n]),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => break,
+            }
+        }
+        self.flush();
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        // SAFETY: writing from a valid buffer; a short or failed write only loses terminal output
+        unsafe { libc::write(self.tee, data.as_ptr().cast(), data.len()) };
+        let Some(out) = &mut self.out else {
+            return;
+        };
+        if let Err(err) = out.write_all(data) {
+            self.complain(&format!("Failed to write the log file: {}", err));
+            self.out = None;
+            return;
+        }
+        self.size += data.len() as u64;
+        if self.size >= self.max_size {
+            self.rotate();
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Some(out) = &mut self.out {
+            let _ = out.flush();
+        }
+    }
+
+    /// PATH → PATH.1 → ... → PATH.<keep>, then a fresh PATH
+    fn rotate(&mut self) {
+        self.flush();
+        self.out = None;
+        let rotated = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        };
+        let result = if self.keep == 0 {
+            fs::remove_file(&self.path)
+        } else {
+            (1..self.keep)
+                .rev()
+                .map(|n| fs::rename(rotated(n), rotated(n + 1)))
+                .filter(
+                    |result| !matches!(result, Err(err) if err.kind() == io::ErrorKind::NotFound),
+                )
+                .collect::<io::Result<()>>()
+                .and_then(|()| fs::rename(&self.path, rotated(1)))
+        };
+        if let Err(err) = result {
+            self.complain(&format!("Failed to rotate the log file: {}", err));
+        }
+        self.reopen();
+    }
+
+    fn reopen(&mut self) {
+        self.flush();
+        match open(&self.path) {
+            Ok(file) => {
+                self.size = file.metadata().map_or(0, |meta| meta.len());
+                self.out = Some(BufWriter::new(file));
+            }
+            Err(err) => {
+                self.complain(&format!("{:#}", err));
+                self.out = None;
+            }
+        }
+    }
+
+    /// Tell the terminal; stderr would only come back to us
+    fn complain(&self, message: &str) {
+        let line = format!("⚠️  {}\n", message);
+        // SAFETY: writing from a valid buffer to our copy of stderr
+        unsafe { libc::write(self.tee, line.as_ptr().cast(), line.len()) };
+    }
+}
+
+/// Whether `fd` became readable within `timeout`
+fn wait(fd: RawFd, timeout: Duration) -> bool {
+    let mut pfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: pfd is a valid pollfd for the duration of the call
+    let ready = unsafe { libc::poll(&mut pfd, 1, timeout.as_millis() as libc::c_int) };
+    ready > 0
+}
@@ -1,208 +1,1928 @@
+use touchpad_remap::{
+    activation, backend, backoff, capabilities, cli, condition, config, control, ctl, daemon,
+    device, diagnose, doctor, dump, exit, hooks, init_config, inject, instance, journal, layers,
+    libinput, live_map, logfile, migrate, monitor, motion, mouse_proxy, mt, preflight, presets,
+    quality, reaper, recover, remap, report, resync, sched, seat, session, shutdown, simulate,
+    sink, snooze, state, state_output, stress, swipe, tablet, trigger, typing, watchdog,
+};
+
+use activation::{Activation, Transition};
 use anyhow::{Context, Result};
-use evdev::uinput::{VirtualDevice, VirtualDeviceBuilder};
-use evdev::{Device, EventType, InputEventKind, Key};
-use std::io::{BufRead, BufReader as StdBufReader};
+use backend::Backend;
+use backoff::Backoff;
+use clap::Parser;
+use cli::{Cli, Command, OutputFormat};
+use condition::Signal;
+use config::{Config, ExternalMouse, Source};
+use device::{DeviceKind, ReadFailure};
+use evdev::raw_stream::RawDevice;
+use evdev::{InputEventKind, RelativeAxisType, Synchronization};
+use instance::InstanceLock;
+use libinput::{Kind, Scanner};
+use motion::{Coalescer, LastMotion};
+use mouse_proxy::MouseProxy;
+use mt::{Fingers, SlotTracker};
+use preflight::AccessError;
+use recover::Guarded;
+use remap::Remapper;
+use report::Record;
+use resync::{DropFilter, Feed};
+use session::{SessionLock, Suspend};
+use shutdown::Shutdown;
+use sink::EventSink;
+use state::PersistedState;
+use std::io::{self, BufRead, BufReader as StdBufReader};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
 use std::process::{Command as StdCommand, Stdio};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use swipe::{Direction, SwipeTracker};
+use tablet::{TabletAction, TabletSwitch};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::task::JoinSet;
+use trigger::{Contacts, Trigger};
+use typing::TypingDetector;
+
+fn main() {
+    let cli = Cli::parse();
+    if cli.output == OutputFormat::Json {
+        report::enable();
+    }
+    // With JSON output, stdout carries only JSON; mode changes are records there
+    if cli.no_state_output || cli.output == OutputFormat::Json {
+        state_output::disable();
+    }
+    // A panic still leaves through exit::finish, so it gets its own status
+    let result = std::panic::catch_unwind(|| dispatch(&cli));
+    // Threads still reading from a child never get to drop its guard
+    reaper::stop_all();
+    exit::finish(result);
+}
+
+fn dispatch(cli: &Cli) -> Result<()> {
+    seat::set(cli.seat.clone())?;
+    presets::choose(cli.preset.as_deref())?;
+    match &cli.command {
+        Some(Command::Simulate { script, expected }) => Config::load(cli.config.as_deref())
+            .and_then(|config| simulate::run(&config, script, expected.as_deref())),
+        Some(Command::Stress {
+            duration,
+            patterns,
+            rate,
+            events,
+            seed,
+            save_script,
+        }) => Config::load(cli.config.as_deref()).and_then(|config| {
+            stress::run(
+                &config,
+                &stress::Options {
+                    duration: *duration,
+                    patterns,
+                    rate: *rate,
+                    events: *events as usize,
+                    seed: *seed,
+                    save_script,
+                },
+            )
+        }),
+        Some(Command::ParseLibinput { input, expected }) => {
+            simulate::parse_libinput(input, expected.as_deref())
+        }
+        Some(Command::Monitor { socket }) => monitor::run(socket.as_deref()),
+        Some(Command::Watch { socket, interval }) => monitor::watch(socket.as_deref(), *interval),
+        Some(Command::Inject { socket, command }) => inject::run(socket.as_deref(), command),
+        Some(Command::Ctl { socket, command }) => ctl::run(socket.as_deref(), command),
+        Some(Command::InitConfig { path, force }) => {
+            init_config::run(path.as_deref().or(cli.config.as_deref()), *force)
+        }
+        Some(Command::MigrateConfig { path }) => {
+            migrate::run(path.as_deref().or(cli.config.as_deref()))
+        }
+        Some(Command::Check) => {
+            Config::load(cli.config.as_deref()).and_then(|config| capabilities::check(&config))
+        }
+        Some(Command::Doctor { json }) => doctor::run(
+            Config::load(cli.config.as_deref()),
+            &cli.backend,
+            libinput_version(),
+            *json,
+        ),
+        Some(Command::ShowConfig) => Config::load(cli.config.as_deref()).map(|config| {
+            layers::show(&config, cli.config.as_deref());
+        }),
+        Some(Command::ListPresets) => presets::list(),
+        Some(Command::ListDevices) => {
+            diagnose::list_devices();
+            Ok(())
+        }
+        Some(Command::DumpDevice { path, grab }) => dump::run(path, *grab),
+        None => start(cli),
+    }
+}
+
+/// Run the daemon
+fn start(cli: &Cli) -> Result<()> {
+    // Resolved before daemonize moves us to /
+    let config = absolute(cli.config.as_deref(), "--config")?;
+    let log_file = absolute(cli.log_file.as_deref(), "--log-file")?;
+    // Forking has to happen before the runtime starts any threads
+    if cli.daemonize {
+        daemon::daemonize()?;
+    }
+    if let Some(path) = log_file {
+        logfile::start(logfile::Options {
+            path,
+            max_size: cli.log_max_size,
+            keep: usize::from(cli.log_files),
+        })?;
+    }
+    let runtime = tokio::runtime::Runtime::new().context("Failed to start runtime")?;
+    runtime.block_on(run(cli, config.as_deref()))
+}
+
+/// A path flag made absolute, so it still names the same file from /
+fn absolute(path: Option<&Path>, flag: &str) -> Result<Option<PathBuf>> {
+    path.map(std::path::absolute)
+        .transpose()
+        .with_context(|| format!("Failed to resolve {}", flag))
+}
+
+/// How long the keyboard monitor gets to clean up once another monitor died
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(2);
+
+/// `config` is `--config`, already made absolute
+async fn run(cli: &Cli, config: Option<&Path>) -> Result<()> {
+    let config = Config::load(config)?;
+    let config = Arc::new(config);
+
+    // Taken before any device is opened, so two instances never race for the grab
+    let _lock = InstanceLock::acquire(cli.replace)?;
 
-// Configuration
-const TOUCHPAD_PATH: &str = "/dev/input/by-path/platform-AMDI0010:03-event-mouse";
-const KEYBOARD_PATH: &str = "/dev/input/by-path/platform-i8042-serio-0-event-kbd";
-const DEBOUNCE_MS: u64 = 0;
-const VIRTUAL_KEYBOARD_NAME: &str = "my-virtual-keyboard";
+    device::set_own_names(config.own_device_names());
+    device::set_verbose(cli.verbose);
 
-#[tokio::main]
-async fn main() -> Result<()> {
     eprintln!("🚀 Starting touchpad-remap");
-    eprintln!("📁 Touchpad: {}", TOUCHPAD_PATH);
-    eprintln!("⌨️  Keyboard: {}", KEYBOARD_PATH);
-    eprintln!("🖱️  Virtual keyboard: {}", VIRTUAL_KEYBOARD_NAME);
+    if let Some(seat) = seat::get() {
+        eprintln!("💺 Seat: {}", seat);
+    }
+    if let Some(preset) = config.preset {
+        eprintln!("📝 Config: preset {}", preset);
+    } else if config.layers.is_empty() {
+        eprintln!("📝 Config: built-in defaults");
+    }
+    for path in &config.layers {
+        eprintln!("📝 Config: {}", path.display());
+    }
+    // Anything not listed is at its built-in default
+    for setting in &config.settings {
+        let from = match &setting.source {
+            Source::Env(var) => var.clone(),
+            Source::Preset(name) => format!("preset {}", name),
+            Source::File(path) if config.layers.len() > 1 => path.display().to_string(),
+            _ => "file".to_string(),
+        };
+        eprintln!("   {} = {}  [{}]", setting.key, setting.value, from);
+    }
+    eprintln!("📁 Touchpad: {}", config.touchpad.join(", "));
+    eprintln!("⌨️  Keyboard: {}", config.keyboard.join(", "));
+    eprintln!("🖱️  Virtual keyboard: {}", config.virtual_keyboard_name);
+    if let Some(mouse) = &config.external_mouse {
+        eprintln!(
+            "🖱️  External mouse: {} ({})",
+            mouse.candidates.join(", "),
+            if mouse.follow_activation {
+                "remapped while active"
+            } else {
+                "always remapped"
+            }
+        );
+    }
+    if let Some(buttons) = &config.touchpad_buttons {
+        eprintln!(
+            "🖱️  Touchpad buttons: remapped while active, through '{}'",
+            buttons.name
+        );
+    }
+    for (keysym, key) in &config.keysyms {
+        eprintln!("🔤 Keysym {} → {:?}", keysym, key);
+    }
+    for (i, set) in config.mapping_sets.iter().enumerate() {
+        eprintln!(
+            "🗂️  mapping_sets[{}]: {} ({} mapping(s))",
+            i,
+            set.candidates.join(", "),
+            set.table.mappings.len()
+        );
+    }
+    if let Some(mouse) = &config.activation_mouse {
+        eprintln!(
+            "🖱️  Activation mouse: {} (threshold {}, idle {}ms)",
+            mouse.candidates.join(", "),
+            mouse.threshold,
+            mouse.idle.as_millis()
+        );
+    }
+    eprintln!("🎯 Active when: {}", config.activate_when);
+    if config.sticky.enabled {
+        eprintln!(
+            "📌 Sticky mode: {}-finger swipe up latches, down unlatches",
+            config.sticky.fingers
+        );
+    }
+    if let Some(warp) = &config.warp {
+        eprintln!(
+            "🎯 Grid warp: hold {:?} for U I O / J K L / M , . ({}x{})",
+            warp.key, warp.width, warp.height
+        );
+    }
+    eprintln!(
+        "↕️  Key scrolling: {}",
+        if config.scroll.natural {
+            "natural"
+        } else {
+            "traditional"
+        }
+    );
+    eprintln!();
+
+    // keyd and friends may still be starting; `After=` alone doesn't wait for their device
+    device::wait_for_named("touchpad", &config.touchpad, config.device_wait);
+    device::wait_for_named("keyboard", &config.keyboard, config.device_wait);
+
+    let backend = backend::select(&cli.backend)?;
+    backend.check(&config);
+    preflight::run(&config, backend)?;
     eprintln!();
 
-    let touchpad_active = Arc::new(AtomicBool::new(false));
+    eprintln!("🔎 Looking for touchpad...");
+    let (touchpad, touchpad_path) = device::open_first(
+        DeviceKind::Touchpad,
+        &config.touchpad,
+        &config.touchpad_source,
+    )?;
+
+    // libinput reads the proxy instead, since the touchpad itself is grabbed
+    let proxy = match &config.touchpad_buttons {
+        Some(buttons) => Some(TouchpadProxy::create(buttons, &touchpad)?),
+        None => None,
+    };
+    let libinput_path = match &proxy {
+        Some((_, node)) => node.clone(),
+        None => Arc::new(Mutex::new(Some(touchpad_path.clone()))),
+    };
+
+    hooks::install(
+        config.on_activate.clone(),
+        config.on_deactivate.clone(),
+        cli.verbose,
+    );
+
+    report::emit(Record::Startup {
+        version: env!("CARGO_PKG_VERSION").into(),
+        config: config.layers.last().map(|path| path.display().to_string()),
+        touchpad: touchpad_path.display().to_string(),
+        virtual_keyboard: config.virtual_keyboard_name.clone(),
+    });
+
+    let mut persisted = if config.persist_state {
+        PersistedState::load()
+    } else {
+        PersistedState::default()
+    };
+    // A timed pause still running stays paused; one that ran out meanwhile is over
+    let mut paused_until = persisted
+        .paused_until
+        .filter(|_| !persisted.enabled)
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+    if paused_until.is_some_and(|until| until <= SystemTime::now()) {
+        eprintln!("▶️  The saved pause ran out while stopped");
+        persisted.enabled = true;
+        paused_until = None;
+    }
+    let armed = config.start_enabled && !cli.start_disabled;
+    if !armed {
+        eprintln!(
+            "⏸️  Starting disabled ({}); SIGUSR1 turns remapping on",
+            if cli.start_disabled {
+                "--start-disabled"
+            } else {
+                "start_enabled = false"
+            }
+        );
+        // The saved switch says what is in effect, as after a toggle
+        if config.persist_state && (persisted.enabled || paused_until.is_some()) {
+            let off = |state: &mut PersistedState| {
+                state.enabled = false;
+                state.paused_until = None;
+            };
+            if let Err(err) = PersistedState::update(off) {
+                eprintln!("⚠️  Failed to persist state: {:#}", err);
+            }
+        }
+        paused_until = None;
+    } else if let Some(until) = paused_until {
+        eprintln!(
+            "⏸️  Starting paused, {} remaining (restored from {})",
+            snooze::remaining(until.duration_since(SystemTime::now()).unwrap_or_default()),
+            state::state_path().display()
+        );
+    } else if !persisted.enabled {
+        eprintln!(
+            "⏸️  Starting disabled (restored from {})",
+            state::state_path().display()
+        );
+    }
+    let enabled_at_start = armed && persisted.enabled;
+    let enabled = Arc::new(AtomicBool::new(enabled_at_start));
+    state_output::init(enabled_at_start);
+
+    let trigger = config.deactivate_on.resolve(&touchpad);
+    if config.deactivate_on == Trigger::Auto {
+        eprintln!("👆 Deactivating on {} (detected)", trigger.name());
+    }
+    let activation = Arc::new(Mutex::new(Activation::new(
+        config.activate_when.clone(),
+        config.debounce,
+        &config.adaptive_debounce,
+        trigger,
+        config.mouse_idle(),
+        &config.hysteresis,
+    )));
+    if persisted.sticky && config.sticky.enabled {
+        eprintln!(
+            "📌 Sticky mode ON (restored from {})",
+            state::state_path().display()
+        );
+        if let Some(transition) = activation.lock().unwrap().sticky(Instant::now(), true) {
+            mode_changed(transition);
+        }
+    }
+    let shutdown = Arc::new(Shutdown::new()?);
+    let mut monitors = JoinSet::new();
+
+    let last_motion = Arc::new(LastMotion::new());
+    let fingers = if config.finger_layers.is_empty() {
+        None
+    } else {
+        Some(Arc::new(Fingers::new()?))
+    };
+    inject::init(&config.control)?;
+    snooze::init(paused_until)?;
+    watchdog::start(&config.watchdog)?;
+    // Removed again when this returns
+    let _control = control::serve(
+        &config.control,
+        control::Status {
+            activation: activation.clone(),
+            enabled: enabled.clone(),
+            last_motion: last_motion.clone(),
+        },
+    )?;
 
     // Spawn libinput monitor in blocking thread
-    let touchpad_active_clone = touchpad_active.clone();
-    tokio::task::spawn_blocking(move || monitor_libinput(touchpad_active_clone));
+    let config_clone = config.clone();
+    let activation_clone = activation.clone();
+    let last_motion_clone = last_motion.clone();
+    let proxied = proxy.is_some();
+    monitors.spawn_blocking(move || {
+        let result = monitor_libinput(
+            &config_clone,
+            &libinput_path,
+            proxied,
+            &activation_clone,
+            &last_motion_clone,
+        );
+        ("libinput monitor", result)
+    });
 
     // Spawn touchpad release monitor in blocking thread
-    let touchpad_active_clone = touchpad_active.clone();
-    tokio::task::spawn_blocking(move || monitor_evdev_release(touchpad_active_clone));
+    let activation_clone = activation.clone();
+    let enabled_clone = enabled.clone();
+    let shutdown_clone = shutdown.clone();
+    let config_clone = config.clone();
+    let fingers_clone = fingers.clone();
+    let last_motion_clone = last_motion.clone();
+    monitors.spawn_blocking(move || {
+        let result = monitor_evdev_release(
+            &config_clone,
+            (touchpad, touchpad_path),
+            trigger,
+            (&activation_clone, &last_motion_clone),
+            proxy,
+            fingers_clone.as_deref(),
+            &enabled_clone,
+            &shutdown_clone,
+        );
+        ("touchpad release monitor", result)
+    });
+
+    if config.activation_mouse.is_some() {
+        let activation_clone = activation.clone();
+        let config_clone = config.clone();
+        let last_motion_clone = last_motion.clone();
+        monitors.spawn_blocking(move || {
+            let result =
+                monitor_activation_mouse(&config_clone, &activation_clone, &last_motion_clone);
+            ("activation mouse monitor", result)
+        });
+    }
+
+    if config.external_mouse.is_some() {
+        let activation_clone = activation.clone();
+        let enabled_clone = enabled.clone();
+        let shutdown_clone = shutdown.clone();
+        let config_clone = config.clone();
+        monitors.spawn_blocking(move || {
+            let result = monitor_mouse(
+                &config_clone,
+                &activation_clone,
+                &enabled_clone,
+                &shutdown_clone,
+            );
+            ("mouse monitor", result)
+        });
+    }
 
     // Run keyboard monitor in blocking thread
-    tokio::task::spawn_blocking(move || monitor_keyboard(touchpad_active)).await??;
+    let shutdown_clone = shutdown.clone();
+    let enabled_clone = enabled.clone();
+    let config_clone = config.clone();
+    monitors.spawn_blocking(move || {
+        let result = monitor_keyboard(
+            &config_clone,
+            activation,
+            enabled_clone,
+            last_motion,
+            fingers.as_deref(),
+            backend,
+            &shutdown_clone,
+        );
+        ("keyboard monitor", result)
+    });
 
-    Ok(())
+    supervise(&config, monitors, &enabled, &shutdown).await
 }
 
-/// Monitor libinput for POINTER_MOTION
-fn monitor_libinput(touchpad_active: Arc<AtomicBool>) -> Result<()> {
-    eprintln!("📡 Starting libinput monitor...");
+/// Flip the administrative enabled flag (SIGUSR1); this also ends a pause
+fn toggle_enabled(config: &Config, enabled: &AtomicBool) {
+    snooze::clear();
+    let now = !enabled.load(Ordering::SeqCst);
+    eprintln!(
+        "{}",
+        if now {
+            "▶️  Layer enabled"
+        } else {
+            "⏸️  Layer disabled"
+        }
+    );
+    set_enabled(config, enabled, now, "sigusr1");
+}
 
-    let mut child = StdCommand::new("libinput")
-        .arg("debug-events")
-        .arg("--device")
-        .arg(TOUCHPAD_PATH)
-        .stdin(Stdio::null())
-        .stdout(Stdio::piped())
+/// Set the administrative enabled flag, saving it (and any pause) when persistence is on
+fn set_enabled(config: &Config, enabled: &AtomicBool, now: bool, reason: &str) {
+    if enabled.swap(now, Ordering::SeqCst) != now {
+        state_output::enabled_changed(now, reason);
+        report::emit(Record::EnabledChange { enabled: now });
+    }
+    notify_status(running_status());
+
+    if config.persist_state {
+        let saved = PersistedState::update(|state| {
+            state.enabled = now;
+            state.paused_until = snooze::until_unix();
+        });
+        if let Err(err) = saved {
+            eprintln!("⚠️  Failed to persist state: {:#}", err);
+        }
+    }
+}
+
+/// Apply a `ctl pause`/`resume`, or a pause running out, from the keyboard loop
+fn snoozed(config: &Config, enabled: &AtomicBool, change: snooze::Change) {
+    match change {
+        snooze::Change::Pause => {
+            match snooze::describe() {
+                Some(pause) => eprintln!("⏸️  Layer {}", pause),
+                None => eprintln!("⏸️  Layer paused"),
+            }
+            set_enabled(config, enabled, false, "pause");
+        }
+        snooze::Change::Resume => {
+            eprintln!("▶️  Layer resumed");
+            set_enabled(config, enabled, true, "resume");
+        }
+        snooze::Change::Expired => {
+            eprintln!("▶️  Pause over - layer enabled");
+            set_enabled(config, enabled, true, "pause_expired");
+        }
+    }
+}
+
+/// Handle signals until SIGTERM/SIGINT or until a monitor stops; none of them is meant to, so that is fatal
+async fn supervise(
+    config: &Config,
+    mut monitors: JoinSet<(&'static str, Result<()>)>,
+    enabled: &AtomicBool,
+    shutdown: &Shutdown,
+) -> Result<()> {
+    let mut sigterm =
+        signal(SignalKind::terminate()).context("Failed to install SIGTERM handler")?;
+    let mut sigint = signal(SignalKind::interrupt()).context("Failed to install SIGINT handler")?;
+    let mut sigusr1 =
+        signal(SignalKind::user_defined1()).context("Failed to install SIGUSR1 handler")?;
+    // Only taken over with --log-file; otherwise SIGHUP keeps its default
+    let mut sighup = if logfile::active() {
+        Some(signal(SignalKind::hangup()).context("Failed to install SIGHUP handler")?)
+    } else {
+        None
+    };
+
+    // Monitors holding a grab, waited for at shutdown
+    let mut grabbing = vec!["keyboard monitor"];
+    if config.external_mouse.is_some() {
+        grabbing.push("mouse monitor");
+    }
+    if config.touchpad_buttons.is_some() {
+        grabbing.push("touchpad release monitor");
+    }
+
+    let result = loop {
+        tokio::select! {
+            _ = sigusr1.recv() => toggle_enabled(config, enabled),
+            Some(_) = recv_optional(&mut sighup) => {
+                eprintln!("📝 SIGHUP received, reopening the log file");
+                logfile::reopen();
+            }
+            _ = sigterm.recv() => {
+                eprintln!("👋 SIGTERM received, shutting down");
+                break Ok(());
+            }
+            _ = sigint.recv() => {
+                eprintln!("👋 SIGINT received, shutting down");
+                break Ok(());
+            }
+            joined = monitors.join_next() => {
+                if let Some(Ok((name, _))) = &joined {
+                    grabbing.retain(|grab| grab != name);
+                }
+                let err = match joined {
+                    Some(Ok((name, Ok(())))) => anyhow::anyhow!("{} exited unexpectedly", name),
+                    Some(Ok((name, Err(err)))) => err.context(format!("{} failed", name)),
+                    Some(Err(err)) => exit::tag(
+                        exit::Class::Internal,
+                        anyhow::Error::new(err).context("Monitor panicked"),
+                    ),
+                    None => anyhow::anyhow!("No monitors running"),
+                };
+                eprintln!("💥 {:#}", err);
+                break Err(err);
+            }
+        }
+    };
+
+    // Let the grabbing monitors release held keys and their grab before we exit
+    shutdown.trigger();
+    let _ = tokio::time::timeout(SHUTDOWN_GRACE, async {
+        while !grabbing.is_empty() {
+            match monitors.join_next().await {
+                Some(Ok((name, _))) => grabbing.retain(|grab| *grab != name),
+                Some(Err(_)) => {}
+                None => break,
+            }
+        }
+    })
+    .await;
+    report::emit(report::stats());
+    quality::summarize();
+    result
+}
+
+/// The next `signal`, or never without one
+async fn recv_optional(signal: &mut Option<tokio::signal::unix::Signal>) -> Option<()> {
+    match signal {
+        Some(signal) => signal.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// `libinput --version`, e.g. `1.25.0`
+fn libinput_version() -> Option<String> {
+    let output = StdCommand::new("libinput")
+        .arg("--version")
         .stderr(Stdio::null())
-        .spawn()
+        .output()
+        .ok()?;
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Monitor libinput for POINTER_MOTION and hold gestures
+///
+/// The child is restarted with backoff when it exits or loses the touchpad,
+/// which may come back at another event node. With `proxied`, `node` is the
+/// touchpad proxy's, kept current by the release monitor.
+fn monitor_libinput(
+    config: &Config,
+    node: &Mutex<Option<PathBuf>>,
+    proxied: bool,
+    activation: &Mutex<Activation>,
+    last_motion: &LastMotion,
+) -> Result<()> {
+    eprintln!("📡 Starting libinput monitor...");
+    let hold = config.activate_when.mentions(Signal::Hold);
+    if hold && !libinput::has_hold_gestures(libinput_version().as_deref()) {
+        eprintln!("⚠️  libinput before 1.19 has no hold gestures; `hold` never turns the mode on");
+    }
+
+    let mut path = node
+        .lock()
+        .unwrap()
+        .clone()
+        .context("No touchpad node for libinput")?;
+    // Set from the moment libinput stops until a new child has the touchpad open
+    let mut backoff: Option<Backoff> = None;
+    loop {
+        // --device uses libinput's path backend, which takes the node as given:
+        // the seat was already applied when the touchpad was picked
+        let mut child = reaper::spawn(
+            StdCommand::new("libinput")
+                .arg("debug-events")
+                .arg("--device")
+                .arg(&path)
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null()),
+        )
         .context("Failed to spawn libinput")?;
 
-    let stdout = child.stdout.take().context("Failed to get stdout")?;
-    let reader = StdBufReader::new(stdout);
+        // Dropping child on an error below stops it too
+        let stdout = child.stdout().context("Failed to get stdout")?;
+        let removed = follow_libinput(
+            StdBufReader::new(stdout),
+            config.motion_threshold,
+            activation,
+            last_motion,
+            SwipeTracker::new(&config.sticky),
+            config.persist_state,
+            &mut backoff,
+        )?;
+        let status = child.stop();
 
-    for line in reader.lines() {
-        let line = line?;
-        let trimmed = line.trim();
+        let now = Instant::now();
+        let backoff = match &mut backoff {
+            Some(backoff) => {
+                backoff.failed(now);
+                backoff
+            }
+            None => {
+                match status {
+                    _ if removed => eprintln!("🔌 libinput lost the touchpad, restarting it..."),
+                    Ok(status) => eprintln!("⚠️  libinput exited ({}), restarting it...", status),
+                    Err(err) => eprintln!("⚠️  libinput exited ({}), restarting it...", err),
+                }
+                notify_status("Reconnecting libinput");
+                backoff.insert(Backoff::new("libinput", config.reconnect_max_delay, now))
+            }
+        };
+        path = loop {
+            thread::sleep(backoff.delay());
+            let found = if proxied {
+                node.lock().unwrap().clone()
+            } else {
+                device::reopen(DeviceKind::Touchpad, &config.touchpad).map(|(_, path)| path)
+            };
+            match found {
+                Some(path) => break path,
+                None => backoff.failed(Instant::now()),
+            }
+        };
+    }
+}
 
-        if trimmed.is_empty() {
+/// Act on libinput's events until it exits or removes the touchpad; true for the latter
+///
+/// Motion during a swipe `swipe` tracks never counts: the swipe is a command.
+fn follow_libinput(
+    reader: impl BufRead,
+    threshold: f64,
+    activation: &Mutex<Activation>,
+    last_motion: &LastMotion,
+    mut swipe: Option<SwipeTracker>,
+    persist: bool,
+    backoff: &mut Option<Backoff>,
+) -> Result<bool> {
+    let mut scanner = Scanner::new(reader);
+    let mut coalescer = Coalescer::default();
+    while scanner.advance(followed)? {
+        let Some(event) = scanner.line() else {
             continue;
+        };
+        match event.kind {
+            Kind::DeviceAdded => {
+                if let Some(backoff) = backoff.take() {
+                    backoff.recovered(Instant::now());
+                    notify_status(running_status());
+                }
+                continue;
+            }
+            Kind::DeviceRemoved => return Ok(true),
+            Kind::GestureHoldBegin | Kind::GestureHoldEnd => {
+                let begin = event.kind == Kind::GestureHoldBegin;
+                let transition = activation.lock().unwrap().hold(Instant::now(), begin);
+                if let Some(transition) = transition {
+                    eprintln!("✓ {} detected - mode ACTIVE", event.kind);
+                    mode_changed(transition);
+                }
+                continue;
+            }
+            Kind::GestureSwipeBegin => {
+                if let (Some(swipe), Some(fingers)) =
+                    (&mut swipe, libinput::gesture_fingers(event.payload))
+                {
+                    swipe.begin(Instant::now(), fingers);
+                }
+                continue;
+            }
+            Kind::GestureSwipeUpdate => {
+                if let (Some(swipe), Some((dx, dy))) =
+                    (&mut swipe, libinput::gesture_delta(event.payload))
+                {
+                    swipe.update(dx, dy);
+                }
+                continue;
+            }
+            Kind::GestureSwipeEnd => {
+                let cancelled = libinput::gesture_cancelled(event.payload);
+                let direction = swipe
+                    .as_mut()
+                    .and_then(|swipe| swipe.end(Instant::now(), cancelled));
+                if let Some(direction) = direction {
+                    sticky_swipe(direction, activation, persist);
+                }
+                continue;
+            }
+            Kind::PointerMotion if swipe.as_ref().is_some_and(SwipeTracker::in_progress) => {
+                continue
+            }
+            Kind::PointerMotion => {}
+            _ => continue,
         }
+        let size =
+            libinput::motion_delta(event.payload).map_or(0.0, |(dx, dy)| dx.abs() + dy.abs());
+        if threshold > 0.0 && size < threshold {
+            continue;
+        }
+        let transition = coalescer.motion(Instant::now(), size, activation, last_motion);
+        if let Some(transition) = transition {
+            eprintln!("✓ {} detected - mode ACTIVE", event.kind);
+            mode_changed(transition);
+        }
+    }
+
+    Ok(false)
+}
+
+/// The libinput lines `follow_libinput` acts on; the rest are skipped unread
+fn followed(kind: Kind) -> bool {
+    matches!(
+        kind,
+        Kind::DeviceAdded
+            | Kind::DeviceRemoved
+            | Kind::GestureHoldBegin
+            | Kind::GestureHoldEnd
+            | Kind::GestureSwipeBegin
+            | Kind::GestureSwipeUpdate
+            | Kind::GestureSwipeEnd
+            | Kind::PointerMotion
+    )
+}
 
-        if trimmed.contains("POINTER_MOTION") {
-            if !touchpad_active.load(Ordering::Relaxed) {
-                touchpad_active.store(true, Ordering::Relaxed);
-                eprintln!("✓ POINTER_MOTION detected - mode ACTIVE");
+/// Latch or unlatch sticky mode, saving the latch with `persist`
+fn sticky_swipe(direction: Direction, activation: &Mutex<Activation>, persist: bool) {
+    let latch = direction == Direction::Up;
+    let transition = activation.lock().unwrap().sticky(Instant::now(), latch);
+    eprintln!(
+        "📌 Swipe {} - sticky mode {}",
+        if latch { "up" } else { "down" },
+        if latch { "ON" } else { "off" }
+    );
+    if persist {
+        if let Err(err) = PersistedState::update(|state| state.sticky = latch) {
+            eprintln!("⚠️  Failed to persist state: {:#}", err);
+        }
+    }
+    if let Some(transition) = transition {
+        mode_changed(transition);
+    }
+}
+
+/// Arm `mouse_motion` from the activation mouse, and run its idle timer
+///
+/// Read without a grab: the mouse keeps working as usual.
+fn monitor_activation_mouse(
+    config: &Config,
+    activation: &Mutex<Activation>,
+    last_motion: &LastMotion,
+) -> Result<()> {
+    let mouse = config
+        .activation_mouse
+        .as_ref()
+        .expect("spawned only with an activation mouse");
+    let find = || device::reopen(DeviceKind::Mouse, &mouse.candidates);
+    eprintln!("🖱️  Opening activation mouse...");
+    let (mut device, _) = match find() {
+        Some(found) => found,
+        None => {
+            eprintln!("🖱️  Activation mouse not connected, waiting for it...");
+            retry_with_backoff("activation mouse", config.reconnect_max_delay, find)
+        }
+    };
+    let mut drops = DropFilter::default();
+    // |dx| + |dy| of the frame so far
+    let mut moved = 0u32;
+
+    loop {
+        let deadline = activation.lock().unwrap().next_deadline();
+        let readable = wait_readable(device.as_raw_fd(), -1, deadline)?;
+        polled(poll(activation, last_motion));
+        if !readable {
+            continue;
+        }
+
+        let gone = match device.fetch_events() {
+            Ok(events) => {
+                for event in events {
+                    if drops.feed(&event) != Feed::Pass {
+                        moved = 0;
+                        continue;
+                    }
+                    match event.kind() {
+                        InputEventKind::RelAxis(
+                            RelativeAxisType::REL_X | RelativeAxisType::REL_Y,
+                        ) => {
+                            moved = moved.saturating_add(event.value().unsigned_abs());
+                        }
+                        InputEventKind::Synchronization(Synchronization::SYN_REPORT) => {
+                            if moved > 0 && moved >= mouse.threshold {
+                                let transition =
+                                    activation.lock().unwrap().mouse_motion(Instant::now());
+                                if let Some(transition) = transition {
+                                    eprintln!("✓ Mouse motion detected - mode ACTIVE");
+                                    mode_changed(transition);
+                                }
+                            }
+                            moved = 0;
+                        }
+                        _ => {}
+                    }
+                }
+                false
             }
+            Err(err) => match ReadFailure::classify(err) {
+                ReadFailure::Retry => continue,
+                ReadFailure::Gone => true,
+                ReadFailure::Fatal(err) => {
+                    return Err(err).context("Failed to fetch activation mouse events")
+                }
+            },
+        };
+
+        if gone {
+            drops.reset();
+            moved = 0;
+            device = wait_for_device("activation mouse", config.reconnect_max_delay, || {
+                find().map(|(device, _)| device)
+            });
         }
     }
+}
 
-    Ok(())
+/// `[touchpad_buttons]`'s proxy, and its event node for libinput
+struct TouchpadProxy {
+    sink: Guarded,
+    /// None while the proxy is being recreated
+    node: Arc<Mutex<Option<PathBuf>>>,
+}
+
+/// The proxy before it is guarded, which happens on the monitor's thread
+type NewProxy = (MouseProxy, Arc<Mutex<Option<PathBuf>>>);
+
+impl TouchpadProxy {
+    fn create(buttons: &ExternalMouse, touchpad: &RawDevice) -> Result<NewProxy> {
+        let mut proxy = MouseProxy::create(buttons, touchpad)?;
+        let node = proxy.node()?;
+        let devices = proxy.devices();
+        eprintln!("🧩 Virtual devices: {}", devices.join(", "));
+        report::add_devices(devices);
+        Ok((proxy, Arc::new(Mutex::new(Some(node)))))
+    }
+
+    /// As `recover_proxy`, pointing libinput at the new node
+    fn recover(&mut self, buttons: &ExternalMouse, touchpad: &mut RawDevice) -> Result<()> {
+        if !self.sink.failed() {
+            return Ok(());
+        }
+        *self.node.lock().unwrap() = None;
+        let mut node = None;
+        let recovered = self.sink.recover("touchpad proxy", || {
+            let mut proxy = MouseProxy::create(buttons, touchpad)?;
+            node = Some(proxy.node()?);
+            Ok(Box::new(proxy) as Box<dyn EventSink>)
+        });
+        if recovered.is_err() {
+            let _ = touchpad.ungrab();
+            eprintln!("🔓 Released touchpad");
+        }
+        *self.node.lock().unwrap() = node;
+        recovered
+    }
 }
 
-/// Monitor evdev for BTN_TOOL_FINGER release
-fn monitor_evdev_release(touchpad_active: Arc<AtomicBool>) -> Result<()> {
+/// Monitor the touchpad for finger lifts, and run the deactivation timer
+///
+/// With `[touchpad_buttons]` the touchpad is grabbed and everything it sends
+/// goes out through the proxy, its buttons remapped while the mode is active.
+/// With finger layers it also counts the multitouch contacts into `fingers`.
+#[allow(clippy::too_many_arguments)]
+fn monitor_evdev_release(
+    config: &Config,
+    (mut touchpad, path): (RawDevice, PathBuf),
+    trigger: Trigger,
+    (activation, last_motion): (&Mutex<Activation>, &LastMotion),
+    proxy: Option<NewProxy>,
+    fingers: Option<&Fingers>,
+    enabled: &AtomicBool,
+    shutdown: &Shutdown,
+) -> Result<()> {
     eprintln!("👆 Starting touchpad release monitor...");
+    let mut contacts = Contacts::new(trigger);
+    // BTN_TOOL_DOUBLETAP and friends lag and flicker; only slots count fingers
+    let fingers = fingers.filter(|_| {
+        let slots = mt::has_slots(&touchpad);
+        if !slots {
+            eprintln!("⚠️  The touchpad has no multitouch slots; finger_layers never apply");
+        }
+        slots
+    });
+    let mut slots = SlotTracker::default();
+    let mut drops = DropFilter::default();
+    let buttons = config.touchpad_buttons.as_ref();
+    let mut remapper = buttons.map(|buttons| Remapper::for_mouse(config, buttons));
+    let mut proxy = proxy.map(|(proxy, node)| TouchpadProxy {
+        sink: Guarded::new(Box::new(proxy)),
+        node,
+    });
 
-    let mut touchpad = Device::open(TOUCHPAD_PATH).context("Failed to open touchpad")?;
+    if proxy.is_some() {
+        eprintln!("🔒 Grabbing touchpad...");
+        match grab_with_retry(&mut touchpad, "touchpad", config, shutdown) {
+            Ok(true) => {}
+            Ok(false) => return Ok(()),
+            Err(err) => {
+                preflight::explain_grab_failure(&path, &err);
+                return Err(anyhow::Error::new(AccessError::Grab(path)).context(err));
+            }
+        }
+    }
+    let active = || enabled.load(Ordering::Relaxed) && activation.lock().unwrap().is_active();
 
     loop {
-        let events = touchpad.fetch_events().context("Failed to fetch events")?;
-
-        for event in events {
-            if let InputEventKind::Key(Key::BTN_TOOL_FINGER) = event.kind() {
-                if event.value() == 0 {
-                    if touchpad_active.load(Ordering::Relaxed) {
-                        if DEBOUNCE_MS > 0 {
-                            eprintln!("⏱  BTN_TOOL_FINGER released - waiting {}ms", DEBOUNCE_MS);
-                            thread::sleep(Duration::from_millis(DEBOUNCE_MS));
-                        }
+        let deadline = [
+            activation.lock().unwrap().next_deadline(),
+            remapper.as_ref().and_then(Remapper::next_deadline),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        let readable = wait_readable(touchpad.as_raw_fd(), shutdown.fd(), deadline)?;
+
+        if shutdown.requested() {
+            if let (Some(remapper), Some(proxy)) = (&mut remapper, &mut proxy) {
+                remapper.release_all(&mut proxy.sink)?;
+                touchpad
+                    .ungrab()
+                    .context("Failed to release touchpad grab")?;
+                eprintln!("🔓 Released touchpad");
+            }
+            return Ok(());
+        }
+
+        polled(poll(activation, last_motion));
+        if let (Some(buttons), Some(remapper), Some(proxy)) = (buttons, &mut remapper, &mut proxy) {
+            remapper.tick(Instant::now(), active(), &mut proxy.sink)?;
+            expire_holds(remapper, &touchpad, "touchpad", &mut proxy.sink)?;
+            proxy.recover(buttons, &mut touchpad)?;
+        }
+        if !readable {
+            continue;
+        }
 
-                        touchpad_active.store(false, Ordering::Relaxed);
-                        eprintln!("✗ Mode DEACTIVATED");
+        let mut resync = false;
+        let gone = match touchpad.fetch_events() {
+            Ok(events) => {
+                for event in events {
+                    match drops.feed(&event) {
+                        Feed::Pass => {}
+                        Feed::Skip => continue,
+                        Feed::Resync => {
+                            resync = true;
+                            continue;
+                        }
+                    }
+                    contact_edge(contacts.feed(&event), trigger, activation);
+                    if let Some((fingers, count)) = fingers.zip(slots.feed(&event)) {
+                        fingers.set(count);
+                    }
+                    if let (Some(remapper), Some(proxy)) = (&mut remapper, &mut proxy) {
+                        remapper.handle(event, active(), Instant::now(), &mut proxy.sink)?;
                     }
                 }
+                false
+            }
+            Err(err) => match ReadFailure::classify(err) {
+                ReadFailure::Retry => continue,
+                ReadFailure::Gone => true,
+                ReadFailure::Fatal(err) => return Err(err).context("Failed to fetch events"),
+            },
+        };
+
+        if let (Some(buttons), Some(proxy), false) = (buttons, &mut proxy, gone) {
+            proxy.recover(buttons, &mut touchpad)?;
+        }
+
+        if resync && !gone {
+            let edge = contacts
+                .resync(&touchpad)
+                .context("Failed to re-read touchpad state")?;
+            resync::resynced("touchpad", "contacts");
+            contact_edge(edge, trigger, activation);
+            if let Some(fingers) = fingers {
+                slots
+                    .resync(&touchpad)
+                    .context("Failed to re-read touchpad contacts")?;
+                fingers.set(slots.count());
+            }
+            if let (Some(remapper), Some(proxy)) = (&mut remapper, &mut proxy) {
+                let pressed = touchpad
+                    .get_key_state()
+                    .context("Failed to re-read touchpad buttons")?;
+                let released = remapper.resync(&pressed, Instant::now(), &mut proxy.sink)?;
+                resync::resynced(
+                    "touchpad",
+                    &format!("{} stuck button(s) released", released),
+                );
             }
         }
+
+        if gone {
+            contacts.reset();
+            slots.reset();
+            if let Some(fingers) = fingers {
+                fingers.set(0);
+            }
+            drops.reset();
+            if let (Some(remapper), Some(proxy)) = (&mut remapper, &mut proxy) {
+                remapper.release_all(&mut proxy.sink)?;
+            }
+            touchpad = reconnect_touchpad(config, activation, proxy.is_some());
+        }
+    }
+}
+
+fn contact_edge(edge: Option<bool>, trigger: Trigger, activation: &Mutex<Activation>) {
+    match edge {
+        Some(true) => finger_down(trigger, activation),
+        Some(false) => finger_up(trigger, activation),
+        None => {}
     }
 }
 
-/// Create a virtual keyboard with custom name
-fn create_virtual_keyboard(keyboard: &Device, name: &str) -> Result<VirtualDevice> {
-    let mut builder = VirtualDeviceBuilder::new()
-        .context("Failed to create keyboard builder")?
-        .name(name);
+fn finger_down(trigger: Trigger, activation: &Mutex<Activation>) {
+    let (transition, cancelled) = {
+        let mut activation = activation.lock().unwrap();
+        let pending = activation.lift_pending();
+        let transition = activation.finger_down(Instant::now());
+        (transition, pending && !activation.lift_pending())
+    };
+    if cancelled {
+        eprintln!("↩️  {} down again - deactivation cancelled", trigger.name());
+    }
+    if let Some(transition) = transition {
+        eprintln!("✓ {} down - mode ACTIVE", trigger.name());
+        mode_changed(transition);
+    }
+}
 
-    if let Some(keys) = keyboard.supported_keys() {
-        builder = builder.with_keys(keys)?;
+/// Deactivate after a finger lift; with a debounce, the monitor loop does it once due
+fn finger_up(trigger: Trigger, activation: &Mutex<Activation>) {
+    let (transition, remaining) = {
+        let mut activation = activation.lock().unwrap();
+        let now = Instant::now();
+        (activation.finger_up(now), activation.lift_remaining(now))
+    };
+    deactivated(transition);
+    if let Some(remaining) = remaining.filter(|_| trigger != Trigger::TimeoutOnly) {
+        eprintln!(
+            "⏱  {} released - waiting {}ms",
+            trigger.name(),
+            remaining.as_millis()
+        );
     }
+}
 
-    if let Some(rel_axes) = keyboard.supported_relative_axes() {
-        builder = builder.with_relative_axes(rel_axes)?;
+fn deactivated(transition: Option<Transition>) {
+    if let Some(transition) = transition {
+        eprintln!("✗ Mode DEACTIVATED");
+        mode_changed(transition);
     }
+}
 
-    if let Some(switches) = keyboard.supported_switches() {
-        builder = builder.with_switches(switches)?;
+/// Run `activation`'s timers, after letting held-back motion push a pending lift
+fn poll(activation: &Mutex<Activation>, last_motion: &LastMotion) -> Option<Transition> {
+    let mut activation = activation.lock().unwrap();
+    if let Some(at) = last_motion.at() {
+        activation.motion_seen(at);
     }
+    activation.poll(Instant::now())
+}
 
-    builder.build().context("Failed to build virtual keyboard")
+/// A timer fired: a lift ran out, or a change held back by hysteresis went through
+fn polled(transition: Option<Transition>) {
+    match transition {
+        Some(transition) if transition.active => {
+            eprintln!("✓ Mode ACTIVE (held back by hysteresis)");
+            mode_changed(transition);
+        }
+        transition => deactivated(transition),
+    }
+}
+
+fn mode_changed(Transition { active, reason }: Transition) {
+    hooks::mode_changed(active, reason);
+    quality::mode_changed(active);
+    state_output::mode_changed(active, reason);
+    report::emit(Record::ModeChange {
+        active,
+        reason: reason.into(),
+    });
+}
+
+/// Report a human-readable state to systemd (no-op outside systemd)
+fn notify_status(status: &str) {
+    if daemon::is_daemon() {
+        return;
+    }
+    let _ = systemd::daemon::notify(false, [(systemd::daemon::STATE_STATUS, status)].iter());
+}
+
+/// STATUS while nothing is being waited for
+fn running_status() -> &'static str {
+    if snooze::current().is_some() {
+        "Running (paused)"
+    } else if state_output::enabled() {
+        "Running"
+    } else {
+        "Running (disabled)"
+    }
+}
+
+/// Wait for a device that went away, reporting progress
+fn wait_for_device<T>(label: &str, max: Duration, attempt: impl FnMut() -> Option<T>) -> T {
+    eprintln!("🔌 {} disappeared, waiting for it to return...", label);
+    report::emit(Record::DeviceDetached {
+        device: label.to_string(),
+    });
+    retry_with_backoff(label, max, attempt)
+}
+
+/// Retry `attempt` with backoff, pausing at most `max`, until it yields a device
+fn retry_with_backoff<T>(label: &str, max: Duration, mut attempt: impl FnMut() -> Option<T>) -> T {
+    notify_status(&format!("Reconnecting {}", label));
+
+    let mut backoff = Backoff::new(label, max, Instant::now());
+    loop {
+        thread::sleep(backoff.delay());
+
+        if let Some(found) = attempt() {
+            backoff.recovered(Instant::now());
+            report::emit(Record::DeviceAttached {
+                device: label.to_string(),
+            });
+            notify_status(running_status());
+            return found;
+        }
+
+        backoff.failed(Instant::now());
+        notify_status(&format!(
+            "Reconnecting {} ({} failed attempts)",
+            label,
+            backoff.attempts()
+        ));
+    }
+}
+
+/// Wait for the touchpad to come back after ENODEV, grabbing it if `grab`
+fn reconnect_touchpad(config: &Config, activation: &Mutex<Activation>, grab: bool) -> RawDevice {
+    // Nothing can deactivate the mode while the touchpad is gone
+    let transition = activation.lock().unwrap().touchpad_gone(Instant::now());
+    if let Some(transition) = transition {
+        eprintln!("✗ Mode DEACTIVATED (touchpad gone)");
+        mode_changed(transition);
+    }
+    wait_for_device("touchpad", config.reconnect_max_delay, || {
+        let (mut touchpad, path) = device::reopen(DeviceKind::Touchpad, &config.touchpad)?;
+        if grab {
+            if let Err(err) = touchpad.grab() {
+                eprintln!("   Found {} but grab failed: {}", path.display(), err);
+                return None;
+            }
+            eprintln!("🔒 Re-grabbed touchpad at {}", path.display());
+        }
+        Some(touchpad)
+    })
+}
+
+/// Grab `device`, trying again with a doubling pause while another process holds it (EBUSY)
+///
+/// Session startup has the compositor and portals probing devices for a
+/// moment. Other errors, and EBUSY on the last of `grab_attempts`, are
+/// returned. The pauses wait on `shutdown`; false means it was requested, and
+/// nothing was grabbed.
+fn grab_with_retry(
+    device: &mut RawDevice,
+    label: &str,
+    config: &Config,
+    shutdown: &Shutdown,
+) -> io::Result<bool> {
+    let mut delay = config.grab_retry;
+    for attempt in 1..=config.grab_attempts {
+        let err = match device.grab() {
+            Ok(()) => {
+                if attempt > 1 {
+                    eprintln!("🔒 Grabbed {} on attempt {}", label, attempt);
+                }
+                return Ok(true);
+            }
+            Err(err) if err.raw_os_error() == Some(libc::EBUSY) => err,
+            Err(err) => return Err(err),
+        };
+        if attempt == config.grab_attempts {
+            return Err(err);
+        }
+        if attempt == 1 {
+            eprintln!(
+                "⏳ {} is busy, retrying the grab up to {} times",
+                label,
+                config.grab_attempts - 1
+            );
+        } else if device::verbose() {
+            eprintln!(
+                "   {} still busy (attempt {}/{}), next in {}ms",
+                label,
+                attempt,
+                config.grab_attempts,
+                delay.as_millis()
+            );
+        }
+        // A failed poll only cuts the pause short
+        let _ = wait_readable(shutdown.fd(), -1, Some(Instant::now() + delay));
+        if shutdown.requested() {
+            return Ok(false);
+        }
+        delay = (delay * 2).min(config::MAX_GRAB_RETRY_DELAY);
+    }
+    unreachable!("grab_attempts is at least 1")
+}
+
+/// Wait for the same device to re-enumerate (e.g. after resume) and grab it again
+fn reconnect_grabbed(label: &str, identity: &device::Identity, max: Duration) -> RawDevice {
+    wait_for_device(label, max, || {
+        let (mut keyboard, path) = device::find_by_identity(identity)?;
+        match keyboard.grab() {
+            Ok(()) => {
+                eprintln!("🔒 Re-grabbed {} at {}", identity, path.display());
+                Some(keyboard)
+            }
+            Err(err) => {
+                eprintln!("   Found {} but grab failed: {}", identity, err);
+                None
+            }
+        }
+    })
+}
+
+/// Wait until `fd` or `wake` (ignored if negative) is readable or `deadline` passes; true if `fd` is readable
+fn wait_readable(fd: RawFd, wake: RawFd, deadline: Option<Instant>) -> Result<bool> {
+    let [readable, _] = wait_any([fd, wake], deadline)?;
+    Ok(readable)
+}
+
+/// Wait until any of `fds` (negative ones ignored) is readable or `deadline` passes
+fn wait_any<const N: usize>(fds: [RawFd; N], deadline: Option<Instant>) -> Result<[bool; N]> {
+    let timeout = match deadline {
+        Some(deadline) => {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            // Round up so we never wake just before the deadline and spin
+            remaining.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32
+        }
+        None => -1,
+    };
+
+    let mut pfds = fds.map(|fd| libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    });
+    // SAFETY: pfds is a valid pollfd array for the duration of the call
+    let ready = unsafe { libc::poll(pfds.as_mut_ptr(), pfds.len() as libc::nfds_t, timeout) };
+    if ready < 0 {
+        let err = io::Error::last_os_error();
+        if err.kind() == io::ErrorKind::Interrupted {
+            return Ok([false; N]);
+        }
+        return Err(err).context("Failed to poll input device");
+    }
+    Ok(pfds.map(|pfd| ready > 0 && pfd.revents != 0))
+}
+
+/// What is holding the layer off regardless of the touchpad
+#[derive(Debug, Clone, Copy, Default)]
+struct Paused {
+    tablet: bool,
+    locked: bool,
+    /// Between PrepareForSleep and resume, with `[suspend]`
+    asleep: bool,
+}
+
+impl Paused {
+    fn any(self) -> bool {
+        self.tablet || self.locked || self.asleep
+    }
+}
+
+/// Apply a change to `paused`, already recorded in it: held outputs are
+/// released, and the keyboard is handed back while a pause asking for that
+/// (`tablet_mode.action = "ungrab"`, `session_lock.ungrab`, suspend) lasts
+fn paused_changed(
+    config: &Config,
+    paused: Paused,
+    keyboard: &mut RawDevice,
+    grabbed: &mut bool,
+    remapper: &mut Remapper,
+    outputs: &mut dyn EventSink,
+) -> Result<()> {
+    if paused.any() {
+        remapper.release_all(outputs)?;
+    }
+    let release = (paused.tablet && config.tablet_mode.action == TabletAction::Ungrab)
+        || (paused.locked && config.session_lock.ungrab)
+        || paused.asleep;
+    if release && *grabbed {
+        keyboard
+            .ungrab()
+            .context("Failed to release keyboard grab")?;
+        *grabbed = false;
+        eprintln!("🔓 Released keyboard while paused");
+    } else if !release && !*grabbed {
+        keyboard.grab().context("Failed to re-grab keyboard")?;
+        *grabbed = true;
+        eprintln!("🔒 Re-grabbed keyboard");
+    }
+    notify_status(if paused.asleep {
+        "Paused: suspended"
+    } else if paused.locked {
+        "Paused: session locked"
+    } else if paused.tablet {
+        "Paused: tablet mode"
+    } else {
+        running_status()
+    });
+    Ok(())
+}
+
+fn log_tablet(engaged: bool) {
+    if engaged {
+        eprintln!("📱 Tablet mode - layer disabled");
+    } else {
+        eprintln!("💻 Tablet mode ended");
+    }
+}
+
+/// Recreate the keyboard's outputs if an event failed on them; past the
+/// attempts `Guarded` allows, release the grab and fail
+fn recover_outputs(
+    outputs: &mut Guarded,
+    backend: &Backend,
+    config: &Config,
+    keyboard: &mut RawDevice,
+    grabbed: bool,
+) -> Result<()> {
+    if !outputs.failed() {
+        return Ok(());
+    }
+    let recovered = outputs.recover("keyboard", || backend.create(config, keyboard));
+    if recovered.is_err() && grabbed {
+        let _ = keyboard.ungrab();
+        eprintln!("🔓 Released keyboard");
+    }
+    recovered
 }
 
 /// Handle keyboard input
-fn monitor_keyboard(touchpad_active: Arc<AtomicBool>) -> Result<()> {
+fn monitor_keyboard(
+    config: &Config,
+    activation: Arc<Mutex<Activation>>,
+    enabled: Arc<AtomicBool>,
+    last_motion: Arc<LastMotion>,
+    fingers: Option<&Fingers>,
+    backend: &Backend,
+    shutdown: &Shutdown,
+) -> Result<()> {
+    let mut typing = TypingDetector::new(&config.typing);
+    let typing_cooldown = Duration::from_millis(config.typing.cooldown_ms);
+    let mut drops = DropFilter::default();
+
     eprintln!("⌨️  Opening keyboard device...");
 
-    let mut keyboard = Device::open(KEYBOARD_PATH).context("Failed to open keyboard")?;
+    let (mut keyboard, keyboard_path) = device::open_first(
+        DeviceKind::Keyboard,
+        &config.keyboard,
+        &config.keyboard_source,
+    )?;
+    let identity = device::Identity::of(&keyboard);
+    let profile = config.mapping_source(&keyboard_path);
+    eprintln!("🗂️  Mappings for {}: {}", keyboard_path.display(), profile);
+    report::emit(Record::ProfileChange {
+        profile,
+        reason: "keyboard".into(),
+    });
+    let mapping_set = config.mapping_set_for(&keyboard_path);
+    let config = &config.for_keyboard(&keyboard_path);
+    capabilities::warn_unmatched(config, &keyboard, &keyboard_path);
+    // Before the grab, so a broken script never leaves the keyboard captured
+    let mut remapper = Remapper::new(config, last_motion)?;
+
+    // Outputs first: if they can't be created, the keyboard was never taken away
+    let mut outputs = Guarded::new(backend.create(config, &keyboard)?);
+    let devices = outputs.devices();
+    eprintln!("🧩 Virtual devices: {}", devices.join(", "));
+    report::add_devices(devices);
+    // Before the grab, so nothing new is pressed until a killed predecessor's outputs are up
+    match journal::Journal::open(&mut outputs) {
+        Ok(journal) => outputs.keep_journal(journal),
+        Err(err) => eprintln!("⚠️  {:#}; held outputs are not journaled", err),
+    }
+
+    inject::declare(inject::Declared::new(config, &keyboard));
+    live_map::init(
+        config,
+        mapping_set,
+        inject::Declared::new(config, &keyboard),
+    )?;
+    let mut injected = inject::Held::default();
+
+    let mut tablet = TabletSwitch::find(&config.tablet_mode, &keyboard, &keyboard_path);
+    let session_lock = SessionLock::watch(&config.session_lock);
+    let suspend = Suspend::watch(&config.suspend);
+    let mut paused = Paused::default();
 
     eprintln!("🔒 Grabbing keyboard...");
-    keyboard.grab().context("Failed to grab keyboard")?;
-
-    eprintln!("🖱️  Creating virtual mouse...");
-    let mut mouse_keys = evdev::AttributeSet::new();
-    mouse_keys.insert(Key::BTN_LEFT);
-    mouse_keys.insert(Key::BTN_RIGHT);
-    mouse_keys.insert(Key::BTN_MIDDLE);
-
-    let mut mouse_axes = evdev::AttributeSet::new();
-    mouse_axes.insert(evdev::RelativeAxisType::REL_X);
-    mouse_axes.insert(evdev::RelativeAxisType::REL_Y);
-    mouse_axes.insert(evdev::RelativeAxisType::REL_WHEEL);
-
-    let mut mouse = VirtualDeviceBuilder::new()
-        .context("Failed to create mouse builder")?
-        .name("rust-virtual-mouse")
-        .with_keys(&mouse_keys)?
-        .with_relative_axes(&mouse_axes)?
-        .build()
-        .context("Failed to build virtual mouse")?;
+    match grab_with_retry(&mut keyboard, "keyboard", config, shutdown) {
+        Ok(true) => {}
+        // Stopped while waiting: nothing was grabbed or forwarded yet
+        Ok(false) => return Ok(()),
+        Err(err) => {
+            preflight::explain_grab_failure(&keyboard_path, &err);
+            return Err(anyhow::Error::new(AccessError::Grab(keyboard_path)).context(err));
+        }
+    }
+    let mut grabbed = true;
+    // Dropped on every way out, so our duplicate never outlives the loop's grab
+    let mut watched = watchdog::watch(keyboard.as_raw_fd())?;
+    let tick = watchdog::tick(&config.watchdog);
+    if tablet.as_ref().is_some_and(TabletSwitch::engaged) {
+        log_tablet(true);
+        paused.tablet = true;
+        paused_changed(
+            config,
+            paused,
+            &mut keyboard,
+            &mut grabbed,
+            &mut remapper,
+            &mut outputs,
+        )?;
+    }
 
-    eprintln!(
-        "⌨️  Creating virtual keyboard '{}'...",
-        VIRTUAL_KEYBOARD_NAME
-    );
-    let mut virtual_kbd = create_virtual_keyboard(&keyboard, VIRTUAL_KEYBOARD_NAME)?;
+    // Only this thread: the touchpad monitors and hooks stay at normal priority
+    sched::boost_current_thread(&config.scheduling);
 
     eprintln!("✅ Ready! Monitoring keyboard events...");
 
     // Notify systemd that we're ready (virtual keyboard is created)
-    let _ = systemd::daemon::notify(false, [(systemd::daemon::STATE_READY, "1")].iter());
+    if daemon::is_daemon() {
+        daemon::notify_ready()?;
+    } else {
+        let _ = systemd::daemon::notify(false, [(systemd::daemon::STATE_READY, "1")].iter());
+    }
+    notify_status(running_status());
 
     eprintln!();
 
     loop {
-        let events = keyboard
-            .fetch_events()
-            .context("Failed to fetch keyboard events")?;
-
-        for event in events {
-            let mut handled = false;
-            let is_active = touchpad_active.load(Ordering::Relaxed);
-
-            if event.event_type() == EventType::KEY && is_active {
-                match event.kind() {
-                    InputEventKind::Key(Key::KEY_F) => {
-                        if event.value() == 1 {
-                            eprintln!("F → LEFT CLICK");
+        watchdog::beat(grabbed);
+        let switch_fd = tablet.as_ref().map_or(-1, TabletSwitch::fd);
+        let lock_fd = session_lock.as_ref().map_or(-1, |lock| lock.fd());
+        let sleep_fd = suspend.as_ref().map_or(-1, |suspend| suspend.sleeping.fd());
+        let deadline = [remapper.next_deadline(), snooze::deadline()]
+            .into_iter()
+            .flatten()
+            .min();
+        let [readable, switched, lock_changed, sleep_changed, injections, snooze_woken, edited, _, _] =
+            wait_any(
+                [
+                    keyboard.as_raw_fd(),
+                    switch_fd,
+                    lock_fd,
+                    sleep_fd,
+                    inject::fd(),
+                    snooze::fd(),
+                    live_map::fd(),
+                    fingers.map_or(-1, Fingers::fd),
+                    shutdown.fd(),
+                ],
+                watchdog::deadline(deadline, tick),
+            )?;
+
+        if shutdown.requested() {
+            injected.release_all(&mut outputs)?;
+            remapper.release_all(&mut outputs)?;
+            if grabbed {
+                keyboard
+                    .ungrab()
+                    .context("Failed to release keyboard grab")?;
+                eprintln!("🔓 Released keyboard");
+            }
+            return Ok(());
+        }
+
+        if let Some(change) = snooze::poll(snooze_woken) {
+            // Nothing stays held through a pause, whatever was pressed before it
+            if change == snooze::Change::Pause {
+                injected.release_all(&mut outputs)?;
+                remapper.release_all(&mut outputs)?;
+            }
+            snoozed(config, &enabled, change);
+        }
+
+        if edited {
+            for edit in live_map::take() {
+                remapper.set_mapping(edit.key, edit.mapping);
+            }
+        }
+
+        if injections {
+            for action in inject::take() {
+                // Nothing synthetic reaches a lock screen or a sleeping session
+                if paused.locked || paused.asleep {
+                    eprintln!("💉 Dropped injected {:?} while paused", action);
+                    injected.release_all(&mut outputs)?;
+                    continue;
+                }
+                injected.apply(action, &mut outputs)?;
+            }
+        }
+
+        if switched {
+            if let Some(engaged) = tablet.as_mut().and_then(TabletSwitch::read) {
+                log_tablet(engaged);
+                paused.tablet = engaged;
+                paused_changed(
+                    config,
+                    paused,
+                    &mut keyboard,
+                    &mut grabbed,
+                    &mut remapper,
+                    &mut outputs,
+                )?;
+            }
+        }
+
+        if let Some(lock) = session_lock.as_ref().filter(|_| lock_changed) {
+            lock.drain();
+            let locked = lock.get();
+            if locked != paused.locked {
+                if locked {
+                    eprintln!("🔐 Session locked - layer disabled");
+                } else {
+                    eprintln!("🔑 Session unlocked");
+                }
+                paused.locked = locked;
+                paused_changed(
+                    config,
+                    paused,
+                    &mut keyboard,
+                    &mut grabbed,
+                    &mut remapper,
+                    &mut outputs,
+                )?;
+            }
+        }
+
+        if let Some(suspend) = suspend.as_ref().filter(|_| sleep_changed) {
+            suspend.sleeping.drain();
+            let sleeping = suspend.sleeping.get();
+            if sleeping && !paused.asleep {
+                eprintln!("💤 Suspending - releasing keyboard");
+                paused.asleep = true;
+                let released = paused_changed(
+                    config,
+                    paused,
+                    &mut keyboard,
+                    &mut grabbed,
+                    &mut remapper,
+                    &mut outputs,
+                );
+                // Suspend goes ahead either way; don't hold it up on an error
+                suspend.released();
+                released?;
+            } else if !sleeping && paused.asleep {
+                eprintln!("⏰ Resumed - looking for the keyboard again");
+                // It may have been re-enumerated at another event node
+                drops.reset();
+                keyboard = reconnect_grabbed("keyboard", &identity, config.reconnect_max_delay);
+                watched.rewatch(keyboard.as_raw_fd())?;
+                grabbed = true;
+                paused.asleep = false;
+                if let Some(engaged) = tablet.as_mut().and_then(|t| t.resync(&keyboard)) {
+                    log_tablet(engaged);
+                }
+                paused.tablet = tablet.as_ref().is_some_and(TabletSwitch::engaged);
+                paused_changed(
+                    config,
+                    paused,
+                    &mut keyboard,
+                    &mut grabbed,
+                    &mut remapper,
+                    &mut outputs,
+                )?;
+            }
+        }
+
+        // Read once per wakeup, so a whole batch of keys sees the same layer
+        if let Some(fingers) = fingers {
+            fingers.drain();
+            remapper.set_fingers(fingers.get(), Instant::now(), &mut outputs)?;
+        }
+        let active = config.mapping_condition.applies(
+            enabled.load(Ordering::Relaxed) && !paused.any(),
+            activation.lock().unwrap().is_active(),
+        );
+        remapper.tick(Instant::now(), active, &mut outputs)?;
+        if grabbed {
+            expire_holds(&mut remapper, &keyboard, "keyboard", &mut outputs)?;
+        }
+        recover_outputs(&mut outputs, backend, config, &mut keyboard, grabbed)?;
+
+        if !readable {
+            continue;
+        }
+
+        let mut resync = false;
+        let mut switch = None;
+        let gone = match keyboard.fetch_events() {
+            Ok(events) => {
+                for event in events {
+                    match drops.feed(&event) {
+                        Feed::Pass => {}
+                        Feed::Skip => continue,
+                        Feed::Resync => {
+                            resync = true;
+                            continue;
                         }
-                        mouse.emit(&[evdev::InputEvent::new(
-                            EventType::KEY,
-                            Key::BTN_LEFT.code(),
-                            event.value(),
-                        )])?;
-                        handled = true;
                     }
-                    InputEventKind::Key(Key::KEY_D) => {
-                        if event.value() == 1 {
-                            eprintln!("D → RIGHT CLICK");
+                    // Applied after the batch, which borrows the keyboard; until
+                    // then the rest of it already sees the layer forced off
+                    if let Some(engaged) = tablet.as_mut().and_then(|t| t.observe(&event)) {
+                        switch = Some(engaged);
+                        paused.tablet = engaged;
+                    }
+                    // Handed back: the compositor reads the keyboard itself
+                    if !grabbed {
+                        continue;
+                    }
+                    let now = Instant::now();
+                    let mut active = config.mapping_condition.applies(
+                        enabled.load(Ordering::Relaxed) && !paused.any(),
+                        activation.lock().unwrap().is_active(),
+                    );
+                    if active
+                        && typing
+                            .as_mut()
+                            .is_some_and(|typing| typing.observe(&event, &remapper, now))
+                    {
+                        let transition = activation.lock().unwrap().typing(now, typing_cooldown);
+                        if let Some(transition) = transition {
+                            eprintln!("⌨️  Typing detected - mode DEACTIVATED");
+                            mode_changed(transition);
                         }
-                        mouse.emit(&[evdev::InputEvent::new(
-                            EventType::KEY,
-                            Key::BTN_RIGHT.code(),
-                            event.value(),
-                        )])?;
-                        handled = true;
+                        active = false;
                     }
-                    _ => {}
+                    remapper.handle(event, active, now, &mut outputs)?;
                 }
+                false
             }
+            Err(err) => match ReadFailure::classify(err) {
+                ReadFailure::Retry => continue,
+                ReadFailure::Gone => true,
+                ReadFailure::Fatal(err) => {
+                    return Err(err).context("Failed to fetch keyboard events")
+                }
+            },
+        };
 
-            if !handled {
-                virtual_kbd.emit(&[event])?;
+        if !gone {
+            recover_outputs(&mut outputs, backend, config, &mut keyboard, grabbed)?;
+        }
+
+        if let Some(engaged) = switch.filter(|_| !gone) {
+            log_tablet(engaged);
+            paused_changed(
+                config,
+                paused,
+                &mut keyboard,
+                &mut grabbed,
+                &mut remapper,
+                &mut outputs,
+            )?;
+        }
+
+        if resync && !gone && grabbed {
+            let pressed = keyboard
+                .get_key_state()
+                .context("Failed to re-read keyboard state")?;
+            let released = remapper.resync(&pressed, Instant::now(), &mut outputs)?;
+            resync::resynced("keyboard", &format!("{} stuck key(s) released", released));
+        }
+
+        if gone {
+            // Nothing will ever release what was held on the old device
+            drops.reset();
+            remapper.release_all(&mut outputs)?;
+            keyboard = reconnect_grabbed("keyboard", &identity, config.reconnect_max_delay);
+            watched.rewatch(keyboard.as_raw_fd())?;
+            if !grabbed {
+                keyboard
+                    .ungrab()
+                    .context("Failed to release keyboard grab")?;
+            }
+        }
+    }
+}
+
+/// `watchdog.max_button_hold_ms`: force up buttons held too long by keys that are up
+fn expire_holds(
+    remapper: &mut Remapper,
+    device: &RawDevice,
+    label: &str,
+    out: &mut dyn EventSink,
+) -> Result<()> {
+    let now = Instant::now();
+    if !remapper.hold_check_due(now) {
+        return Ok(());
+    }
+    let pressed = device
+        .get_key_state()
+        .with_context(|| format!("Failed to read {} key state", label))?;
+    remapper.expire_holds(&pressed, now, out)?;
+    Ok(())
+}
+
+/// As `recover_outputs`, for the external mouse's proxy
+fn recover_proxy(proxy: &mut Guarded, mouse: &ExternalMouse, device: &mut RawDevice) -> Result<()> {
+    if !proxy.failed() {
+        return Ok(());
+    }
+    let recovered = proxy.recover("external mouse", || {
+        Ok(Box::new(MouseProxy::create(mouse, device)?) as Box<dyn EventSink>)
+    });
+    if recovered.is_err() {
+        let _ = device.ungrab();
+        eprintln!("🔓 Released external mouse");
+    }
+    recovered
+}
+
+/// Remap the external mouse onto its proxy, waiting for it while unplugged
+fn monitor_mouse(
+    config: &Config,
+    activation: &Mutex<Activation>,
+    enabled: &AtomicBool,
+    shutdown: &Shutdown,
+) -> Result<()> {
+    let mouse: &ExternalMouse = config
+        .external_mouse
+        .as_ref()
+        .expect("spawned only with an external mouse");
+    let mut remapper = Remapper::for_mouse(config, mouse);
+    let mut drops = DropFilter::default();
+
+    eprintln!("🖱️  Opening external mouse...");
+    let find = || device::reopen(DeviceKind::Mouse, &mouse.candidates);
+    let (mut device, path) = match find() {
+        Some(found) => found,
+        None => {
+            // Travel mice come and go; not having one is no reason to stop
+            eprintln!("🖱️  External mouse not connected, waiting for it...");
+            retry_with_backoff("external mouse", config.reconnect_max_delay, find)
+        }
+    };
+    let identity = device::Identity::of(&device);
+
+    let mut proxy = Guarded::new(Box::new(MouseProxy::create(mouse, &device)?));
+    let devices = proxy.devices();
+    eprintln!("🧩 Virtual devices: {}", devices.join(", "));
+    report::add_devices(devices);
+
+    eprintln!("🔒 Grabbing external mouse...");
+    match grab_with_retry(&mut device, "external mouse", config, shutdown) {
+        Ok(true) => {}
+        Ok(false) => return Ok(()),
+        Err(err) => {
+            preflight::explain_grab_failure(&path, &err);
+            return Err(anyhow::Error::new(AccessError::Grab(path)).context(err));
+        }
+    }
+
+    loop {
+        let readable = wait_readable(device.as_raw_fd(), shutdown.fd(), remapper.next_deadline())?;
+
+        if shutdown.requested() {
+            remapper.release_all(&mut proxy)?;
+            device
+                .ungrab()
+                .context("Failed to release external mouse grab")?;
+            eprintln!("🔓 Released external mouse");
+            return Ok(());
+        }
+
+        let active = || {
+            !mouse.follow_activation
+                || (enabled.load(Ordering::Relaxed) && activation.lock().unwrap().is_active())
+        };
+        remapper.tick(Instant::now(), active(), &mut proxy)?;
+        expire_holds(&mut remapper, &device, "external mouse", &mut proxy)?;
+        recover_proxy(&mut proxy, mouse, &mut device)?;
+
+        if !readable {
+            continue;
+        }
+
+        let mut resync = false;
+        let gone = match device.fetch_events() {
+            Ok(events) => {
+                for event in events {
+                    match drops.feed(&event) {
+                        Feed::Pass => {}
+                        Feed::Skip => continue,
+                        Feed::Resync => {
+                            resync = true;
+                            continue;
+                        }
+                    }
+                    remapper.handle(event, active(), Instant::now(), &mut proxy)?;
+                }
+                false
             }
+            Err(err) => match ReadFailure::classify(err) {
+                ReadFailure::Retry => continue,
+                ReadFailure::Gone => true,
+                ReadFailure::Fatal(err) => {
+                    return Err(err).context("Failed to fetch external mouse events")
+                }
+            },
+        };
+
+        if !gone {
+            recover_proxy(&mut proxy, mouse, &mut device)?;
+        }
+
+        if resync && !gone {
+            let pressed = device
+                .get_key_state()
+                .context("Failed to re-read external mouse state")?;
+            let released = remapper.resync(&pressed, Instant::now(), &mut proxy)?;
+            resync::resynced(
+                "external mouse",
+                &format!("{} stuck button(s) released", released),
+            );
+        }
+
+        if gone {
+            drops.reset();
+            remapper.release_all(&mut proxy)?;
+            device = reconnect_grabbed("external mouse", &identity, config.reconnect_max_delay);
         }
     }
 }
@@ -0,0 +1,207 @@
+//! Config schema versions: `version = N` at the top of the file
+//!
+//! A file without `version` predates versioning and is version 1. Older
+//! versions are brought up to date step by step before validation, editing
+//! the TOML document so `migrate-config` can write it back with comments intact.
+
+use crate::config::Config;
+use crate::exit::{self, Class};
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::Path;
+use toml_edit::{value, DocumentMut};
+
+pub const CURRENT_VERSION: i64 = 1;
+
+/// The step from `from` to `from + 1`; returns one line per change made
+struct Migration {
+    from: i64,
+    apply: fn(&mut DocumentMut) -> Vec<String>,
+}
+
+/// Every step, in order; a rename adds one here and bumps CURRENT_VERSION
+const MIGRATIONS: &[Migration] = &[];
+
+pub struct Migrated {
+    /// At CURRENT_VERSION, with `version` set
+    pub doc: DocumentMut,
+    /// Version as written in the file (1 if absent)
+    pub from: i64,
+    /// Whether the file needs rewriting: old version, or no `version` at all
+    pub outdated: bool,
+    pub notes: Vec<String>,
+}
+
+pub fn migrate(text: &str) -> Result<Migrated> {
+    let mut doc: DocumentMut = text.parse()?;
+    let (from, versioned) = match doc.get("version") {
+        None => (1, false),
+        Some(item) => (
+            item.as_integer().context("version must be an integer")?,
+            true,
+        ),
+    };
+    if from > CURRENT_VERSION {
+        bail!(
+            "config version {} is newer than this build supports (up to {}); upgrade touch-layer",
+            from,
+            CURRENT_VERSION
+        );
+    }
+    if from < 1 {
+        bail!("config version {} is not valid; versions start at 1", from);
+    }
+
+    let mut notes = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|m| m.from >= from) {
+        for note in (migration.apply)(&mut doc) {
+            notes.push(format!(
+                "v{} → v{}: {}",
+                migration.from,
+                migration.from + 1,
+                note
+            ));
+        }
+    }
+    doc["version"] = value(CURRENT_VERSION);
+    Ok(Migrated {
+        doc,
+        from,
+        outdated: from < CURRENT_VERSION || !versioned,
+        notes,
+    })
+}
+
+/// `migrate-config`: rewrite the file at the current version, keeping a backup
+pub fn run(path: Option<&Path>) -> Result<()> {
+    let path = path.map_or_else(Config::path, Path::to_path_buf);
+    let text =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let migrated = migrate(&text)
+        .with_context(|| format!("Failed to parse config {}", path.display()))
+        .map_err(|err| exit::tag(Class::Config, err))?;
+    if !migrated.outdated {
+        eprintln!(
+            "✅ {} is already at version {}",
+            path.display(),
+            CURRENT_VERSION
+        );
+        return Ok(());
+    }
+
+    let updated = migrated.doc.to_string();
+    // Never write back something the daemon would refuse
+    Config::parse(&updated, &path).context("Migrated config does not validate")?;
+
+    let mut backup = path.clone().into_os_string();
+    backup.push(format!(".v{}.bak", migrated.from));
+    fs::copy(&path, &backup).with_context(|| format!("Failed to back up {}", path.display()))?;
+    fs::write(&path, updated).with_context(|| format!("Failed to write {}", path.display()))?;
+
+    for note in &migrated.notes {
+        eprintln!("   {}", note);
+    }
+    let backup = Path::new(&backup).display();
+    if migrated.from == CURRENT_VERSION {
+        eprintln!(
+            "📝 Marked {} as version {} (backup: {})",
+            path.display(),
+            CURRENT_VERSION,
+            backup
+        );
+    } else {
+        eprintln!(
+            "📝 Updated {} from version {} to {} (backup: {})",
+            path.display(),
+            migrated.from,
+            CURRENT_VERSION,
+            backup
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// (from, a file at that version, what it migrates to); one entry per step in MIGRATIONS
+    const STEPS: &[(i64, &str, &str)] = &[];
+
+    fn error(text: &str) -> String {
+        migrate(text).err().expect("migration fails").to_string()
+    }
+
+    #[test]
+    fn every_step_has_a_fixture_and_produces_it() {
+        for migration in MIGRATIONS {
+            assert!(
+                STEPS.iter().any(|&(from, _, _)| from == migration.from),
+                "migration from v{} has no test in STEPS",
+                migration.from
+            );
+        }
+        for &(from, old, expected) in STEPS {
+            let migrated = migrate(old).unwrap();
+            assert_eq!(migrated.from, from);
+            assert!(migrated.outdated);
+            assert_eq!(migrated.doc.to_string(), expected, "from v{}", from);
+            Config::parse(expected, Path::new("test.toml")).unwrap();
+        }
+    }
+
+    #[test]
+    fn an_unversioned_file_is_version_1_and_gets_marked() {
+        let old = "# my touchpad\ndebounce_ms = 200\n";
+        let migrated = migrate(old).unwrap();
+        assert_eq!(migrated.from, 1);
+        assert!(migrated.outdated);
+        assert!(migrated.notes.is_empty());
+        assert_eq!(
+            migrated.doc.to_string(),
+            "# my touchpad\ndebounce_ms = 200\nversion = 1\n"
+        );
+    }
+
+    #[test]
+    fn a_current_file_is_left_alone() {
+        let current = format!("version = {}\ndebounce_ms = 200\n", CURRENT_VERSION);
+        let migrated = migrate(&current).unwrap();
+        assert!(!migrated.outdated);
+        assert_eq!(migrated.doc.to_string(), current);
+    }
+
+    #[test]
+    fn a_future_version_is_rejected() {
+        let future = format!("version = {}\n", CURRENT_VERSION + 1);
+        let err = error(&future);
+        assert!(err.contains("newer than this build supports"), "{}", err);
+    }
+
+    #[test]
+    fn a_non_integer_or_zero_version_is_rejected() {
+        for text in ["version = \"2\"\n", "version = 1.5\n"] {
+            let err = error(text);
+            assert_eq!(err, "version must be an integer", "{}", text);
+        }
+        let err = error("version = 0\n");
+        assert!(err.contains("versions start at 1"), "{}", err);
+    }
+
+    #[test]
+    fn migrate_config_rewrites_the_file_and_keeps_a_backup() {
+        let dir =
+            std::env::temp_dir().join(format!("touch-layer-test-migrate-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        fs::write(&path, "debounce_ms = 200\n").unwrap();
+        run(Some(&path)).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "debounce_ms = 200\nversion = 1\n"
+        );
+        let backup = dir.join("config.toml.v1.bak");
+        assert_eq!(fs::read_to_string(backup).unwrap(), "debounce_ms = 200\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
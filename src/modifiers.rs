@@ -0,0 +1,235 @@
+//! Modifier conditions on mappings
+//!
+//! A condition says, per modifier group, whether it must be held, must be
+//! released, or doesn't matter. Modifier state comes from the keys being
+//! passed through, so it is exactly what applications see. When several
+//! variants of a mapping match, the one constraining the most groups wins,
+//! and the earlier one on a tie.
+
+use anyhow::{bail, Result};
+use evdev::Key;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Either key of a group counts as the group being held
+const GROUPS: [(&str, [Key; 2]); 4] = [
+    ("shift", [Key::KEY_LEFTSHIFT, Key::KEY_RIGHTSHIFT]),
+    ("ctrl", [Key::KEY_LEFTCTRL, Key::KEY_RIGHTCTRL]),
+    ("alt", [Key::KEY_LEFTALT, Key::KEY_RIGHTALT]),
+    ("super", [Key::KEY_LEFTMETA, Key::KEY_RIGHTMETA]),
+];
+
+/// Every combination of held groups, one bit per group
+const STATES: u8 = 1 << GROUPS.len();
+
+/// What a condition asks of one modifier group
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Requirement {
+    Held,
+    Released,
+    Any,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Condition {
+    /// Groups that must be held, one bit each in `GROUPS` order
+    held: u8,
+    /// Groups that must not be held
+    released: u8,
+}
+
+impl Condition {
+    /// From `{ shift = "held", ctrl = "released" }`; unnamed groups are `any`
+    pub fn parse(requirements: &HashMap<String, Requirement>) -> Result<Self> {
+        let mut condition = Condition::default();
+        for (name, requirement) in requirements {
+            let Some(bit) = GROUPS.iter().position(|(group, _)| group == name) else {
+                bail!(
+                    "unknown modifier '{}'; expected {}",
+                    name,
+                    GROUPS.map(|(group, _)| group).join(", ")
+                );
+            };
+            match requirement {
+                Requirement::Held => condition.held |= 1 << bit,
+                Requirement::Released => condition.released |= 1 << bit,
+                Requirement::Any => {}
+            }
+        }
+        Ok(condition)
+    }
+
+    pub fn matches(self, state: u8) -> bool {
+        state & self.held == self.held && state & self.released == 0
+    }
+
+    /// How many groups it constrains
+    pub fn specificity(self) -> u32 {
+        (self.held | self.released).count_ones()
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let parts: Vec<String> = GROUPS
+            .iter()
+            .enumerate()
+            .filter_map(|(bit, (group, _))| {
+                if self.held & 1 << bit != 0 {
+                    Some(format!("{} held", group))
+                } else if self.released & 1 << bit != 0 {
+                    Some(format!("{} released", group))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        if parts.is_empty() {
+            f.write_str("any modifiers")
+        } else {
+            f.write_str(&parts.join(", "))
+        }
+    }
+}
+
+/// Whether `key` is one of the modifier keys
+pub fn is_modifier(key: Key) -> bool {
+    GROUPS.iter().any(|(_, keys)| keys.contains(&key))
+}
+
+/// The held groups among `held` keys, as `Condition::matches` takes them
+pub fn state(held: &HashSet<Key>) -> u8 {
+    GROUPS
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, keys))| keys.iter().any(|key| held.contains(key)))
+        .fold(0, |state, (bit, _)| state | 1 << bit)
+}
+
+/// Index of the variant that applies in `state`: most specific, then earliest
+pub fn select(conditions: impl IntoIterator<Item = Condition>, state: u8) -> Option<usize> {
+    let mut best: Option<(usize, u32)> = None;
+    for (i, condition) in conditions.into_iter().enumerate() {
+        if !condition.matches(state) {
+            continue;
+        }
+        let specificity = condition.specificity();
+        if best.is_none_or(|(_, best)| specificity > best) {
+            best = Some((i, specificity));
+        }
+    }
+    best.map(|(i, _)| i)
+}
+
+/// Variants no modifier state can ever select, shadowed by others
+pub fn unreachable(conditions: &[Condition]) -> Vec<usize> {
+    let mut reached = vec![false; conditions.len()];
+    for state in 0..STATES {
+        if let Some(i) = select(conditions.iter().copied(), state) {
+            reached[i] = true;
+        }
+    }
+    (0..conditions.len()).filter(|&i| !reached[i]).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Requirement::{Any, Held, Released};
+
+    /// A condition from `("shift", Held), ...` pairs
+    fn when(requirements: &[(&str, Requirement)]) -> Condition {
+        let map = requirements
+            .iter()
+            .map(|&(name, requirement)| (name.to_string(), requirement))
+            .collect();
+        Condition::parse(&map).unwrap()
+    }
+
+    fn held(keys: &[Key]) -> u8 {
+        state(&keys.iter().copied().collect())
+    }
+
+    #[test]
+    fn the_most_specific_match_wins() {
+        let conditions = [
+            when(&[]),
+            when(&[("shift", Held)]),
+            when(&[("shift", Held), ("ctrl", Held)]),
+        ];
+        assert_eq!(select(conditions, held(&[])), Some(0));
+        assert_eq!(select(conditions, held(&[Key::KEY_LEFTSHIFT])), Some(1));
+        assert_eq!(
+            select(conditions, held(&[Key::KEY_RIGHTSHIFT, Key::KEY_LEFTCTRL])),
+            Some(2)
+        );
+        // Alt constrains nothing here, so shift alone still picks the shift variant
+        assert_eq!(
+            select(conditions, held(&[Key::KEY_LEFTSHIFT, Key::KEY_LEFTALT])),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn the_earliest_match_wins_a_tie() {
+        let conditions = [when(&[("shift", Held)]), when(&[("ctrl", Held)])];
+        let both = held(&[Key::KEY_LEFTSHIFT, Key::KEY_LEFTCTRL]);
+        assert_eq!(select(conditions, both), Some(0));
+        assert_eq!(select([conditions[1], conditions[0]], both), Some(0));
+        assert_eq!(select(conditions, held(&[Key::KEY_RIGHTCTRL])), Some(1));
+    }
+
+    #[test]
+    fn nothing_is_selected_when_no_variant_matches() {
+        let conditions = [when(&[("shift", Released)]), when(&[("super", Held)])];
+        assert_eq!(select(conditions, held(&[Key::KEY_LEFTSHIFT])), None);
+        assert_eq!(select([], 0), None);
+    }
+
+    #[test]
+    fn a_variant_shadowed_at_the_same_specificity_is_unreachable() {
+        let conditions = [
+            when(&[("shift", Held)]),
+            when(&[("shift", Held), ("alt", Any)]),
+            when(&[]),
+        ];
+        assert_eq!(unreachable(&conditions), [1]);
+    }
+
+    #[test]
+    fn a_more_specific_later_variant_stays_reachable() {
+        let conditions = [
+            when(&[]),
+            when(&[("shift", Held)]),
+            when(&[("ctrl", Released)]),
+        ];
+        assert!(unreachable(&conditions).is_empty());
+        // Shift held with ctrl up matches both at specificity 1: the first takes it,
+        // but shift held with ctrl down still reaches the shift variant alone
+        let conditions = [when(&[("ctrl", Released)]), when(&[("shift", Held)])];
+        assert!(unreachable(&conditions).is_empty());
+        let conditions = [
+            when(&[("ctrl", Released)]),
+            when(&[("ctrl", Released), ("shift", Any)]),
+        ];
+        assert_eq!(unreachable(&conditions), [1]);
+    }
+
+    #[test]
+    fn either_key_of_a_group_counts() {
+        let shift = when(&[("shift", Held), ("ctrl", Released)]);
+        assert!(shift.matches(held(&[Key::KEY_LEFTSHIFT])));
+        assert!(shift.matches(held(&[Key::KEY_RIGHTSHIFT, Key::KEY_A])));
+        assert!(!shift.matches(held(&[Key::KEY_LEFTSHIFT, Key::KEY_RIGHTCTRL])));
+        assert_eq!(shift.specificity(), 2);
+        assert_eq!(shift.to_string(), "shift held, ctrl released");
+        let mut bad = HashMap::new();
+        bad.insert("hyper".to_string(), Held);
+        assert_eq!(
+            Condition::parse(&bad).unwrap_err().to_string(),
+            "unknown modifier 'hyper'; expected shift, ctrl, alt, super"
+        );
+    }
+}
@@ -0,0 +1,359 @@
+//! `touchpad-remap monitor`: live view of a running daemon over its control socket
+//!
+//! Needs no device access, only permission to connect to the socket. On a
+//! terminal it draws a small TUI (quit with q); otherwise it prints one line
+//! per record. `touchpad-remap watch` always prints lines, status snapshots
+//! included, at the interval it asks for.
+
+use crate::control;
+use crate::report::{Line, Record};
+use crate::snooze;
+use anyhow::{bail, Context, Result};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Routing decisions kept for the scrolling log
+const LOG_LINES: usize = 200;
+
+pub fn run(socket: Option<&Path>) -> Result<()> {
+    let mut stream = control::connect(socket)?;
+    writeln!(stream, "subscribe").context("Failed to subscribe")?;
+    let lines = BufReader::new(stream).lines();
+
+    #[cfg(feature = "monitor")]
+    if std::io::IsTerminal::is_terminal(&std::io::stdout()) {
+        return tui::run(lines);
+    }
+    stream_text(lines, false)
+}
+
+/// `touchpad-remap watch`: print the event stream, with a status line every `interval` seconds
+pub fn watch(socket: Option<&Path>, interval: Option<u64>) -> Result<()> {
+    let mut stream = control::connect(socket)?;
+    match interval {
+        Some(seconds) => writeln!(stream, "subscribe {}", seconds),
+        None => writeln!(stream, "subscribe"),
+    }
+    .context("Failed to subscribe")?;
+    stream_text(BufReader::new(stream).lines(), true)
+}
+
+fn parse(line: &str) -> Result<Line> {
+    if let Some(message) = line.strip_prefix("error ") {
+        bail!("Daemon refused: {}", message);
+    }
+    serde_json::from_str(line).with_context(|| format!("Unexpected line from daemon: {}", line))
+}
+
+/// One line per record; status snapshots too if `statuses`
+fn stream_text(lines: impl Iterator<Item = std::io::Result<String>>, statuses: bool) -> Result<()> {
+    let mut state = State::default();
+    for line in lines {
+        let line = parse(&line.context("Lost the connection to the daemon")?)?;
+        let status = statuses && matches!(line.record, Record::Status { .. });
+        let clock = clock(&line.timestamp).to_string();
+        if let Some(text) = state.apply(line) {
+            println!("{}", text);
+        } else if status {
+            println!("{} {}", clock, state.status_line());
+        }
+    }
+    eprintln!("Daemon closed the connection");
+    Ok(())
+}
+
+/// Everything the view shows, rebuilt from the record stream
+#[derive(Default)]
+struct State {
+    active: bool,
+    enabled: bool,
+    finger_down: bool,
+    /// Time since motion as of the last status, and when that status arrived
+    since_motion: Option<(u64, Instant)>,
+    /// Mapped keys currently held, with what they are routed to
+    held: BTreeMap<String, String>,
+    counters: [u64; 6],
+    /// `ctl pause`: None when not paused, Some(None) until resumed, else the
+    /// seconds left as of the last status, and when that status arrived
+    pause: Option<Option<(u64, Instant)>>,
+    devices: Vec<String>,
+    log: VecDeque<String>,
+}
+
+impl State {
+    /// Fold one record in; returns a log line for anything but status updates
+    fn apply(&mut self, line: Line) -> Option<String> {
+        let text = match line.record {
+            Record::Status {
+                active,
+                enabled,
+                finger_down,
+                since_motion_ms,
+                activations,
+                remapped,
+                forwarded,
+                resyncs,
+                lost_events,
+                forced_releases,
+                paused,
+                pause_remaining_s,
+                devices,
+                ..
+            } => {
+                self.pause = paused.then(|| pause_remaining_s.map(|s| (s, Instant::now())));
+                self.active = active;
+                self.enabled = enabled;
+                self.finger_down = finger_down;
+                self.since_motion = since_motion_ms.map(|ms| (ms, Instant::now()));
+                self.counters = [
+                    activations,
+                    remapped,
+                    forwarded,
+                    resyncs,
+                    lost_events,
+                    forced_releases,
+                ];
+                self.devices = devices;
+                return None;
+            }
+            Record::ModeChange { active, reason } => {
+                self.active = active;
+                if active {
+                    self.since_motion = Some((0, Instant::now()));
+                }
+                format!(
+                    "mode {} ({})",
+                    if active { "ACTIVE" } else { "inactive" },
+                    reason
+                )
+            }
+            Record::EnabledChange { enabled } => {
+                self.enabled = enabled;
+                format!("layer {}", if enabled { "enabled" } else { "disabled" })
+            }
+            Record::ProfileChange { profile, reason } => {
+                format!("mappings now {} ({})", profile, reason)
+            }
+            Record::DeviceAttached { device } => format!("{} attached", device),
+            Record::DeviceDetached { device } => format!("{} detached", device),
+            Record::Remap { key, target, value } => {
+                if value == 0 {
+                    self.held.remove(&key);
+                } else {
+                    self.held.insert(key.clone(), target.clone());
+                }
+                format!("{} → {} {}", key, target, value)
+            }
+            Record::Forward { key, value } => format!("{} forwarded {}", key, value),
+            Record::Resync { device } => format!("{} resynced after SYN_DROPPED", device),
+            Record::OutputRecovered {
+                device,
+                lost,
+                replayed,
+            } => format!(
+                "{} output recreated ({} lost, {} replayed)",
+                device, lost, replayed
+            ),
+            Record::Error { message } => format!("error: {}", message),
+            Record::Startup { version, .. } => format!("daemon started (v{})", version),
+            Record::Stats { .. } => "daemon shutting down".into(),
+        };
+        let text = format!("{} {}", clock(&line.timestamp), text);
+        self.log.push_back(text.clone());
+        if self.log.len() > LOG_LINES {
+            self.log.pop_front();
+        }
+        Some(text)
+    }
+
+    /// The switch: enabled, disabled, or paused and for how long
+    fn layer(&self) -> String {
+        match self.pause {
+            None if self.enabled => "enabled".into(),
+            None => "disabled".into(),
+            Some(None) => "paused until resumed".into(),
+            Some(Some((secs, at))) => {
+                let left = Duration::from_secs(secs).saturating_sub(at.elapsed());
+                format!("paused, {} remaining", snooze::remaining(left))
+            }
+        }
+    }
+
+    /// The last status, on one line
+    fn status_line(&self) -> String {
+        let [activations, remapped, forwarded, resyncs, lost, forced] = self.counters;
+        format!(
+            "status: mode {}, layer {}, finger {}, {} activations, {} remapped, {} forwarded, {} resyncs, {} lost, {} forced up",
+            if self.active { "ACTIVE" } else { "inactive" },
+            self.layer(),
+            if self.finger_down { "down" } else { "up" },
+            activations,
+            remapped,
+            forwarded,
+            resyncs,
+            lost,
+            forced
+        )
+    }
+}
+
+/// HH:MM:SS.mmm out of an RFC 3339 timestamp
+fn clock(timestamp: &str) -> &str {
+    timestamp.get(11..23).unwrap_or(timestamp)
+}
+
+#[cfg(feature = "monitor")]
+mod tui {
+    use super::{parse, State};
+    use anyhow::Result;
+    use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+    use ratatui::layout::{Constraint, Layout};
+    use ratatui::style::{Style, Stylize};
+    use ratatui::text::Line as TextLine;
+    use ratatui::widgets::{Block, Paragraph};
+    use ratatui::Frame;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    /// How often the view redraws without new records
+    const REDRAW: Duration = Duration::from_millis(100);
+
+    pub fn run(
+        lines: impl Iterator<Item = std::io::Result<String>> + Send + 'static,
+    ) -> Result<()> {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for line in lines {
+                let line = line
+                    .map_err(anyhow::Error::from)
+                    .and_then(|line| parse(&line));
+                let failed = line.is_err();
+                if tx.send(line).is_err() || failed {
+                    return;
+                }
+            }
+        });
+
+        let mut terminal = ratatui::init();
+        let result = (|| -> Result<()> {
+            let mut state = State::default();
+            let mut connected = true;
+            loop {
+                loop {
+                    match rx.try_recv() {
+                        Ok(Ok(line)) => {
+                            state.apply(line);
+                        }
+                        Ok(Err(err)) => return Err(err),
+                        Err(mpsc::TryRecvError::Empty) => break,
+                        Err(mpsc::TryRecvError::Disconnected) => {
+                            connected = false;
+                            break;
+                        }
+                    }
+                }
+                terminal.draw(|frame| draw(frame, &state, connected))?;
+                if event::poll(REDRAW)? {
+                    if let Event::Key(key) = event::read()? {
+                        let ctrl_c = key.code == KeyCode::Char('c')
+                            && key.modifiers.contains(KeyModifiers::CONTROL);
+                        if key.kind == KeyEventKind::Press
+                            && (matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) || ctrl_c)
+                        {
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        })();
+        ratatui::restore();
+        result
+    }
+
+    /// Extrapolated from the last status, so it keeps counting between them
+    fn since_motion(state: &State) -> String {
+        match state.since_motion {
+            Some((ms, at)) => format!("{} ms ago", ms + at.elapsed().as_millis() as u64),
+            None => "never".into(),
+        }
+    }
+
+    fn draw(frame: &mut Frame, state: &State, connected: bool) {
+        let [status_area, log_area] =
+            Layout::vertical([Constraint::Length(9), Constraint::Min(3)]).areas(frame.area());
+
+        let on_off = |on: bool, yes: &'static str, no: &'static str| {
+            if on {
+                yes.green().bold()
+            } else {
+                no.dark_gray()
+            }
+        };
+        let held = if state.held.is_empty() {
+            "-".to_string()
+        } else {
+            state
+                .held
+                .iter()
+                .map(|(key, target)| format!("{} → {}", key, target))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let [activations, remapped, forwarded, resyncs, lost, forced] = state.counters;
+        let devices = if state.devices.is_empty() {
+            "-".to_string()
+        } else {
+            state.devices.join(", ")
+        };
+        let status = vec![
+            TextLine::from(vec![
+                "Mode:        ".into(),
+                on_off(state.active, "ACTIVE", "inactive"),
+            ]),
+            TextLine::from(vec![
+                "Layer:       ".into(),
+                match state.pause {
+                    Some(_) => state.layer().yellow().bold(),
+                    None => on_off(state.enabled, "enabled", "disabled"),
+                },
+            ]),
+            TextLine::from(vec![
+                "Finger:      ".into(),
+                on_off(state.finger_down, "down", "up"),
+            ]),
+            TextLine::from(format!("Last motion: {}", since_motion(state))),
+            TextLine::from(format!("Held:        {}", held)),
+            TextLine::from(format!(
+                "Counters:    {} activations, {} remapped, {} forwarded, {} resyncs, {} lost, {} forced up",
+                activations, remapped, forwarded, resyncs, lost, forced
+            )),
+            TextLine::from(format!("Devices:     {}", devices)),
+        ];
+        let title = if connected {
+            " touch-layer monitor (q to quit) "
+        } else {
+            " touch-layer monitor - daemon disconnected (q to quit) "
+        };
+        frame.render_widget(
+            Paragraph::new(status).block(Block::bordered().title(title)),
+            status_area,
+        );
+
+        let rows = log_area.height.saturating_sub(2) as usize;
+        let log: Vec<TextLine> = state
+            .log
+            .iter()
+            .skip(state.log.len().saturating_sub(rows))
+            .map(|line| TextLine::from(line.as_str()))
+            .collect();
+        frame.render_widget(
+            Paragraph::new(log)
+                .style(Style::default())
+                .block(Block::bordered().title(" Routing ")),
+            log_area,
+        );
+    }
+}
@@ -0,0 +1,198 @@
+use crate::activation::{Activation, Transition};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// While the mode is on, motion is passed to `Activation` at most this often
+pub const MOTION_REFRESH: Duration = Duration::from_millis(10);
+
+/// When the touchpad last reported pointer motion, shared between monitor threads
+///
+/// Recorded for every motion event, including those `Coalescer` holds back,
+/// so a pending lift can be pushed out by them (`Activation::motion_seen`).
+pub struct LastMotion {
+    epoch: Instant,
+    /// Nanoseconds after `epoch`, plus one; 0 means never
+    at: AtomicU64,
+}
+
+impl Default for LastMotion {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LastMotion {
+    pub fn new() -> Self {
+        Self {
+            epoch: Instant::now(),
+            at: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record(&self, now: Instant) {
+        let ns = now.saturating_duration_since(self.epoch).as_nanos() as u64 + 1;
+        self.at.store(ns, Ordering::Relaxed);
+    }
+
+    /// Time from the last motion to `now`, or None if there has been none yet
+    pub fn elapsed(&self, now: Instant) -> Option<Duration> {
+        match self.at.load(Ordering::Relaxed) {
+            0 => None,
+            ns => Some(
+                now.saturating_duration_since(self.epoch)
+                    .saturating_sub(Duration::from_nanos(ns - 1)),
+            ),
+        }
+    }
+
+    /// When the last motion was, or None if there has been none yet
+    pub fn at(&self) -> Option<Instant> {
+        match self.at.load(Ordering::Relaxed) {
+            0 => None,
+            ns => Some(self.epoch + Duration::from_nanos(ns - 1)),
+        }
+    }
+}
+
+/// Holds back touchpad motion while the mode is on, carrying its distance
+///
+/// Motion then only keeps the mode on and feeds the lift debounce, so one
+/// update per `MOTION_REFRESH` does, with the |dx| + |dy| in between added to
+/// it, and the `Activation` lock is taken that much less often. A window
+/// only opens while `Activation::coalesces_motion` says nothing would be lost.
+#[derive(Debug, Default)]
+pub struct Coalescer {
+    /// Until when motion is held back, and the distance so far
+    window: Option<(Instant, f64)>,
+}
+
+impl Coalescer {
+    /// Motion of `distance` at `now`: recorded in `last_motion` every time,
+    /// and passed to `activation` unless held back
+    pub fn motion(
+        &mut self,
+        now: Instant,
+        distance: f64,
+        activation: &Mutex<Activation>,
+        last_motion: &LastMotion,
+    ) -> Option<Transition> {
+        last_motion.record(now);
+        let distance = self.feed(now, distance)?;
+        let (transition, coalesce) = {
+            let mut activation = activation.lock().unwrap();
+            let transition = activation.motion(now, distance);
+            (transition, activation.coalesces_motion())
+        };
+        self.window = coalesce.then_some((now + MOTION_REFRESH, 0.0));
+        transition
+    }
+
+    /// The distance to pass on now, with what was held back, or None while
+    /// the window lasts
+    fn feed(&mut self, now: Instant, distance: f64) -> Option<f64> {
+        match &mut self.window {
+            Some((until, pending)) if now < *until => {
+                *pending += distance;
+                None
+            }
+            _ => Some(distance + self.window.take().map_or(0.0, |(_, pending)| pending)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::trigger::Trigger;
+    use std::path::Path;
+
+    const DEBOUNCE: Duration = Duration::from_millis(150);
+
+    fn activation() -> Mutex<Activation> {
+        let config = Config::parse("debounce_ms = 150", Path::new("test.toml")).unwrap();
+        Mutex::new(Activation::new(
+            config.activate_when.clone(),
+            config.debounce,
+            &config.adaptive_debounce,
+            Trigger::ToolFinger,
+            config.mouse_idle(),
+            &config.hysteresis,
+        ))
+    }
+
+    #[test]
+    fn active_motion_is_passed_on_once_per_refresh_with_its_distance() {
+        let activation = activation();
+        let last_motion = LastMotion::new();
+        let start = Instant::now();
+        activation.lock().unwrap().finger_down(start);
+        let mut coalescer = Coalescer::default();
+        let on = coalescer.motion(start, 1.0, &activation, &last_motion);
+        assert!(on.is_some_and(|transition| transition.active));
+
+        // One second of a 1kHz touchpad
+        let (mut passed, mut distance) = (0, 0.0);
+        for ms in 1..=1000 {
+            let now = start + Duration::from_millis(ms);
+            if let Some(size) = coalescer.feed(now, 0.5) {
+                passed += 1;
+                distance += size;
+                coalescer.window = Some((now + MOTION_REFRESH, 0.0));
+            }
+        }
+        assert!(passed <= 100, "{} updates for 1000 motion lines", passed);
+        let held = coalescer.window.map_or(0.0, |(_, pending)| pending);
+        assert_eq!(distance + held, 500.0, "no distance is lost");
+    }
+
+    #[test]
+    fn held_back_motion_after_a_lift_still_pushes_the_debounce() {
+        let activation = activation();
+        let last_motion = LastMotion::new();
+        let start = Instant::now();
+        activation.lock().unwrap().finger_down(start);
+        let mut coalescer = Coalescer::default();
+        coalescer.motion(start, 1.0, &activation, &last_motion);
+        assert!(activation.lock().unwrap().coalesces_motion());
+
+        let lift = start + Duration::from_millis(2);
+        activation.lock().unwrap().finger_up(lift);
+        // Inside the refresh window, so the activation never sees it
+        let late = start + Duration::from_millis(8);
+        assert!(coalescer
+            .motion(late, 1.0, &activation, &last_motion)
+            .is_none());
+        assert_eq!(
+            activation.lock().unwrap().next_deadline(),
+            Some(lift + DEBOUNCE)
+        );
+
+        let mut activation = activation.lock().unwrap();
+        activation.motion_seen(last_motion.at().unwrap());
+        assert_eq!(
+            activation.next_deadline(),
+            Some(late + DEBOUNCE),
+            "the lift waits from the last motion"
+        );
+        assert_eq!(activation.poll(lift + DEBOUNCE), None);
+        let off = activation.poll(late + DEBOUNCE);
+        assert!(off.is_some_and(|transition| !transition.active));
+    }
+
+    #[test]
+    fn motion_before_the_lift_does_not_push_it() {
+        let activation = activation();
+        let last_motion = LastMotion::new();
+        let start = Instant::now();
+        let mut activation = activation.lock().unwrap();
+        activation.finger_down(start);
+        activation.motion(start, 1.0);
+        last_motion.record(start);
+        let lift = start + Duration::from_millis(5);
+        activation.finger_up(lift);
+        activation.motion_seen(last_motion.at().unwrap());
+        assert_eq!(activation.next_deadline(), Some(lift + DEBOUNCE));
+    }
+}
@@ -0,0 +1,135 @@
+//! Virtual twin of the grabbed `[external_mouse]`, or of the touchpad with
+//! `[touchpad_buttons]`
+//!
+//! It declares everything the source has (keys, relative and absolute axes,
+//! misc events) plus the buttons and wheel its mappings send, so motion and
+//! unmapped buttons pass through unchanged. Forwarded events are written a
+//! whole frame at a time, keeping REL_X and REL_Y of one movement together.
+
+use crate::config::{ExternalMouse, Target, PHYS_SUFFIX};
+use crate::device::DeviceKind;
+use crate::output::{self, uinput_builder};
+use crate::scroll;
+use crate::sink::{EventSink, Frame};
+use anyhow::{Context, Result};
+use evdev::raw_stream::RawDevice;
+use evdev::uinput::VirtualDevice;
+use evdev::{AttributeSet, EventType, InputEvent, Key, RelativeAxisType};
+use std::path::PathBuf;
+
+pub struct MouseProxy {
+    device: VirtualDevice,
+    name: String,
+    /// "mouse" or "touchpad", for the status output
+    kind: &'static str,
+    /// Forwarded events of the frame in progress, written at its SYN_REPORT
+    frame: Frame,
+}
+
+impl MouseProxy {
+    pub fn create(mouse: &ExternalMouse, source: &RawDevice) -> Result<Self> {
+        let mut keys = AttributeSet::new();
+        for key in source.supported_keys().into_iter().flat_map(|k| k.iter()) {
+            keys.insert(key);
+        }
+        for button in mouse.mappings.values().filter_map(Target::button) {
+            keys.insert(button);
+        }
+        let wheel = mouse
+            .mappings
+            .values()
+            .any(|target| matches!(target, Target::Wheel(_)));
+        let touchpad = DeviceKind::Touchpad.reject_reason(source).is_none();
+        let mut axes = output::mouse_axes(wheel);
+        if touchpad {
+            // REL_X/REL_Y would get it tagged a mouse as well as a touchpad
+            axes.remove(RelativeAxisType::REL_X);
+            axes.remove(RelativeAxisType::REL_Y);
+        }
+        for axis in source
+            .supported_relative_axes()
+            .into_iter()
+            .flat_map(|a| a.iter())
+        {
+            axes.insert(axis);
+        }
+
+        let _building = output::BUILDING
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let before = output::uinput_fds();
+        let mut builder = uinput_builder()
+            .context("Failed to create mouse proxy builder")?
+            .name(&mouse.name)
+            .with_keys(&keys)?
+            .with_relative_axes(&axes)?
+            .with_properties(source.properties())?;
+        if let Some(misc) = source.misc_properties() {
+            builder = builder.with_msc(misc)?;
+        }
+        builder = output::with_abs_axes(builder, source)?;
+        // Its twin's phys, so rules matching the mouse by phys prefix still do
+        let phys = match source.physical_path().filter(|phys| !phys.is_empty()) {
+            Some(phys) => format!("{}{}", phys, PHYS_SUFFIX),
+            None => "touch-layer/proxy".into(),
+        };
+        output::set_phys(output::new_descriptor(&before), &phys)?;
+        let device = builder.build().context("Failed to build mouse proxy")?;
+        Ok(Self {
+            device,
+            name: mouse.name.clone(),
+            kind: if touchpad { "touchpad" } else { "mouse" },
+            frame: Frame::new(),
+        })
+    }
+
+    /// Its /dev/input event node, for readers that need one (libinput)
+    pub fn node(&mut self) -> Result<PathBuf> {
+        self.device
+            .enumerate_dev_nodes_blocking()
+            .context("Failed to list the proxy's device nodes")?
+            .filter_map(Result::ok)
+            .find(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with("event"))
+            })
+            .context("The proxy has no event node")
+    }
+
+    /// Write the pending frame; `emit` ends it with a SYN_REPORT of its own
+    fn flush(&mut self) -> Result<()> {
+        let device = &mut self.device;
+        self.frame.flush(|events| device.emit(events))
+    }
+}
+
+impl EventSink for MouseProxy {
+    fn forward(&mut self, event: InputEvent) -> Result<()> {
+        if self.frame.push(event) {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn key(&mut self, key: Key, value: i32) -> Result<()> {
+        self.button(key, value)
+    }
+
+    fn button(&mut self, button: Key, value: i32) -> Result<()> {
+        self.flush()?;
+        self.device
+            .emit(&[InputEvent::new(EventType::KEY, button.code(), value)])?;
+        Ok(())
+    }
+
+    fn wheel(&mut self, hi_res: i32, legacy: i32) -> Result<()> {
+        self.flush()?;
+        self.frame.extend(scroll::wheel_events(hi_res, legacy));
+        self.flush()
+    }
+
+    fn devices(&self) -> Vec<String> {
+        vec![format!("uinput {} proxy '{}'", self.kind, self.name)]
+    }
+}
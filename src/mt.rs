@@ -0,0 +1,302 @@
+//! Multitouch protocol B contact tracking
+//!
+//! ABS_MT_SLOT selects a slot and ABS_MT_TRACKING_ID gives it a contact (an ID
+//! of 0 or more) or frees it (-1). The contact set only counts as changed at
+//! SYN_REPORT: within a frame a pad may free one slot before filling another,
+//! and that half-done state is not a lift.
+
+use crate::shutdown;
+use anyhow::Result;
+use evdev::raw_stream::RawDevice;
+use evdev::{AbsoluteAxisType, InputEvent, InputEventKind, Synchronization};
+use std::collections::BTreeMap;
+use std::io;
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Whether `device` speaks protocol B, with slots and tracking IDs
+pub fn has_slots(device: &RawDevice) -> bool {
+    device.supported_absolute_axes().is_some_and(|axes| {
+        axes.contains(AbsoluteAxisType::ABS_MT_SLOT)
+            && axes.contains(AbsoluteAxisType::ABS_MT_TRACKING_ID)
+    })
+}
+
+#[derive(Debug, Default)]
+pub struct SlotTracker {
+    /// Slot the following ABS_MT events refer to
+    slot: i32,
+    /// Tracking ID of every occupied slot, as of the events so far
+    contacts: BTreeMap<i32, i32>,
+    /// Contact count as of the last SYN_REPORT
+    count: usize,
+}
+
+impl SlotTracker {
+    /// Contacts on the pad as of the last complete frame
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Some(count) at the end of a frame that changed the number of contacts
+    pub fn feed(&mut self, event: &InputEvent) -> Option<usize> {
+        match event.kind() {
+            InputEventKind::AbsAxis(AbsoluteAxisType::ABS_MT_SLOT) => {
+                self.slot = event.value();
+                None
+            }
+            InputEventKind::AbsAxis(AbsoluteAxisType::ABS_MT_TRACKING_ID) => {
+                if event.value() < 0 {
+                    self.contacts.remove(&self.slot);
+                } else {
+                    self.contacts.insert(self.slot, event.value());
+                }
+                None
+            }
+            InputEventKind::Synchronization(Synchronization::SYN_REPORT) => self.commit(),
+            _ => None,
+        }
+    }
+
+    /// Re-read every slot from the kernel, e.g. after SYN_DROPPED
+    pub fn resync(&mut self, device: &RawDevice) -> io::Result<Option<usize>> {
+        let abs = device.get_abs_state()?;
+        let slot_info = abs[AbsoluteAxisType::ABS_MT_SLOT.0 as usize];
+        let slots = slot_info.maximum.max(0) as usize + 1;
+        let ids = tracking_ids(device, slots)?;
+        Ok(self.load(slot_info.value, &ids))
+    }
+
+    /// Take the current slot and every slot's tracking ID as the kernel reports them
+    fn load(&mut self, slot: i32, ids: &[i32]) -> Option<usize> {
+        self.slot = slot;
+        self.contacts = ids
+            .iter()
+            .enumerate()
+            .filter(|&(_, &id)| id >= 0)
+            .map(|(slot, &id)| (slot as i32, id))
+            .collect();
+        self.commit()
+    }
+
+    fn commit(&mut self) -> Option<usize> {
+        let count = self.contacts.len();
+        if count == self.count {
+            return None;
+        }
+        self.count = count;
+        Some(count)
+    }
+}
+
+/// The tracking ID of every multitouch slot (EVIOCGMTSLOTS); -1 means free
+fn tracking_ids(device: &RawDevice, slots: usize) -> io::Result<Vec<i32>> {
+    // struct input_mt_request_layout { __u32 code; __s32 values[slots]; }
+    let mut request = vec![0i32; slots + 1];
+    request[0] = i32::from(AbsoluteAxisType::ABS_MT_TRACKING_ID.0);
+    let size = std::mem::size_of_val(request.as_slice()) as libc::c_ulong;
+    // _IOC(_IOC_READ, 'E', 0x0a, size)
+    let op = (2 << 30) | (size << 16) | (libc::c_ulong::from(b'E') << 8) | 0x0a;
+    // SAFETY: request is a writable buffer of exactly `size` bytes, laid out as the ioctl expects
+    if unsafe { libc::ioctl(device.as_raw_fd(), op as _, request.as_mut_ptr()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    request.remove(0);
+    Ok(request)
+}
+
+/// The touchpad's contact count, set by its monitor for the keyboard loop's
+/// finger layers, and a pipe that becomes readable when it changes
+pub struct Fingers {
+    count: AtomicUsize,
+    read: OwnedFd,
+    write: OwnedFd,
+}
+
+impl Fingers {
+    pub fn new() -> Result<Self> {
+        let (read, write) = shutdown::pipe("finger count")?;
+        Ok(Self {
+            count: AtomicUsize::new(0),
+            read,
+            write,
+        })
+    }
+
+    pub fn get(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    pub fn set(&self, count: usize) {
+        if self.count.swap(count, Ordering::SeqCst) != count {
+            // SAFETY: writing one byte from a valid buffer to our own pipe
+            unsafe { libc::write(self.write.as_raw_fd(), [1u8].as_ptr().cast(), 1) };
+        }
+    }
+
+    /// Descriptor that becomes readable when the count may have changed
+    pub fn fd(&self) -> RawFd {
+        self.read.as_raw_fd()
+    }
+
+    /// Empty the pipe after a wakeup
+    pub fn drain(&self) {
+        let mut buf = [0u8; 64];
+        // SAFETY: reading into a valid buffer from our own non-blocking pipe
+        while unsafe { libc::read(self.read.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) } > 0 {
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resync::{DropFilter, Feed};
+    use evdev::EventType;
+
+    fn slot(slot: i32) -> InputEvent {
+        abs(AbsoluteAxisType::ABS_MT_SLOT, slot)
+    }
+
+    fn id(id: i32) -> InputEvent {
+        abs(AbsoluteAxisType::ABS_MT_TRACKING_ID, id)
+    }
+
+    fn abs(axis: AbsoluteAxisType, value: i32) -> InputEvent {
+        InputEvent::new(EventType::ABSOLUTE, axis.0, value)
+    }
+
+    fn x(value: i32) -> InputEvent {
+        abs(AbsoluteAxisType::ABS_MT_POSITION_X, value)
+    }
+
+    fn syn(code: Synchronization) -> InputEvent {
+        InputEvent::new(EventType::SYNCHRONIZATION, code.0, 0)
+    }
+
+    fn report() -> InputEvent {
+        syn(Synchronization::SYN_REPORT)
+    }
+
+    /// The counts reported at each frame end, in order
+    fn changes(tracker: &mut SlotTracker, events: &[InputEvent]) -> Vec<usize> {
+        events
+            .iter()
+            .filter_map(|event| tracker.feed(event))
+            .collect()
+    }
+
+    #[test]
+    fn one_then_two_fingers_then_both_lift() {
+        let mut tracker = SlotTracker::default();
+        let events = [
+            // First finger in slot 0, with the position that comes with it
+            slot(0),
+            id(45),
+            x(1200),
+            report(),
+            x(1210),
+            report(),
+            // Second finger; the slot stays selected across frames
+            slot(1),
+            id(46),
+            x(3000),
+            report(),
+            slot(0),
+            id(-1),
+            report(),
+            slot(1),
+            id(-1),
+            report(),
+        ];
+        assert_eq!(changes(&mut tracker, &events), [1, 2, 1, 0]);
+        assert_eq!(tracker.count(), 0);
+    }
+
+    #[test]
+    fn motion_within_a_contact_reports_nothing() {
+        let mut tracker = SlotTracker::default();
+        assert_eq!(changes(&mut tracker, &[slot(0), id(7), report()]), [1]);
+        let moves: Vec<InputEvent> = (0..20).flat_map(|i| [x(100 + i), report()]).collect();
+        assert!(changes(&mut tracker, &moves).is_empty());
+    }
+
+    #[test]
+    fn a_slot_freed_and_refilled_in_one_frame_is_not_a_lift() {
+        // A quick re-tap: the pad ends one contact and starts the next in the same frame
+        let mut tracker = SlotTracker::default();
+        assert_eq!(changes(&mut tracker, &[slot(0), id(10), report()]), [1]);
+        let reuse = [slot(0), id(-1), id(11), x(500), report()];
+        assert!(changes(&mut tracker, &reuse).is_empty());
+        assert_eq!(tracker.count(), 1);
+    }
+
+    #[test]
+    fn a_lift_mid_frame_among_other_slots_counts_at_the_frame_end() {
+        // Going from one finger to two: slot 0 lifts while slots 1 and 2 fill
+        let mut tracker = SlotTracker::default();
+        assert_eq!(changes(&mut tracker, &[slot(0), id(20), report()]), [1]);
+        let frame = [slot(1), id(21), slot(0), id(-1), x(40), slot(2), id(22)];
+        assert!(
+            changes(&mut tracker, &frame).is_empty(),
+            "nothing before SYN_REPORT"
+        );
+        assert_eq!(tracker.count(), 1);
+        assert_eq!(changes(&mut tracker, &[report()]), [2]);
+    }
+
+    #[test]
+    fn freeing_an_empty_slot_changes_nothing() {
+        let mut tracker = SlotTracker::default();
+        assert!(changes(&mut tracker, &[slot(3), id(-1), report()]).is_empty());
+        assert_eq!(tracker.count(), 0);
+    }
+
+    #[test]
+    fn after_syn_dropped_the_kernel_state_replaces_ours() {
+        // Through the drop filter, as the touchpad monitor feeds it
+        let mut drops = DropFilter::default();
+        let mut tracker = SlotTracker::default();
+        let events = [
+            slot(0),
+            id(30),
+            report(),
+            slot(1),
+            id(31),
+            report(),
+            // Overflow: the lift of slot 0 and most of this frame are lost
+            syn(Synchronization::SYN_DROPPED),
+            slot(1),
+            id(-1),
+            report(),
+        ];
+        let mut counts = Vec::new();
+        let mut resync = false;
+        for event in &events {
+            match drops.feed(event) {
+                Feed::Pass => counts.extend(tracker.feed(event)),
+                Feed::Skip => {}
+                Feed::Resync => resync = true,
+            }
+        }
+        assert_eq!(counts, [1, 2]);
+        assert!(resync);
+        // EVIOCGMTSLOTS then says only slot 1 is still down, selected
+        assert_eq!(tracker.load(1, &[-1, 31, -1, -1, -1]), Some(1));
+        assert_eq!(changes(&mut tracker, &[id(-1), report()]), [0]);
+    }
+
+    #[test]
+    fn reset_forgets_every_contact() {
+        let mut tracker = SlotTracker::default();
+        changes(&mut tracker, &[slot(0), id(1), slot(1), id(2), report()]);
+        assert_eq!(tracker.count(), 2);
+        tracker.reset();
+        assert_eq!(tracker.count(), 0);
+        assert_eq!(changes(&mut tracker, &[slot(0), id(3), report()]), [1]);
+    }
+}
@@ -0,0 +1,646 @@
+use crate::config::{Config, MirrorConfig, WarpConfig};
+use crate::preflight::{self, AccessError};
+use crate::scroll;
+use crate::seat;
+use crate::sink::{EventSink, Frame};
+use anyhow::{Context, Result};
+use evdev::raw_stream::RawDevice;
+use evdev::uinput::{VirtualDevice, VirtualDeviceBuilder};
+use evdev::{
+    AbsInfo, AbsoluteAxisType, AttributeSet, EventType, InputEvent, Key, PropType,
+    RelativeAxisType, UinputAbsSetup,
+};
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Open /dev/uinput for a new virtual device, explaining permission problems
+pub fn uinput_builder<'a>() -> Result<VirtualDeviceBuilder<'a>> {
+    VirtualDeviceBuilder::new().map_err(|err| {
+        preflight::explain_uinput_failure(&err);
+        anyhow::Error::new(AccessError::Uinput).context(err)
+    })
+}
+
+/// Relative axes declared for pointer output; REL_X/REL_Y make it a pointer to libinput
+pub fn mouse_axes(wheel: bool) -> AttributeSet<RelativeAxisType> {
+    let mut axes = AttributeSet::new();
+    axes.insert(RelativeAxisType::REL_X);
+    axes.insert(RelativeAxisType::REL_Y);
+    if wheel {
+        axes.insert(RelativeAxisType::REL_WHEEL);
+        axes.insert(RelativeAxisType::REL_WHEEL_HI_RES);
+    }
+    axes
+}
+
+/// Name of the standalone virtual mouse
+pub const MOUSE_NAME: &str = "rust-virtual-mouse";
+
+/// `MOUSE_NAME`, suffixed with the seat
+pub fn mouse_name() -> String {
+    seat::suffixed(MOUSE_NAME)
+}
+
+/// Create the standalone virtual mouse
+pub fn create_virtual_mouse(
+    buttons: &AttributeSet<Key>,
+    wheel: bool,
+    phys: &str,
+    properties: &[PropType],
+) -> Result<VirtualDevice> {
+    let _building = BUILDING
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let name = mouse_name();
+    let before = uinput_fds();
+    let builder = uinput_builder()
+        .context("Failed to create mouse builder")?
+        .name(&name)
+        .with_keys(buttons)?
+        .with_relative_axes(&mouse_axes(wheel))?
+        .with_properties(&property_set(properties))?;
+    set_phys(new_descriptor(&before), phys)?;
+    builder.build().context("Failed to build virtual mouse")
+}
+
+/// `properties` as the builder takes them
+pub fn property_set(properties: &[PropType]) -> AttributeSet<PropType> {
+    let mut set = AttributeSet::new();
+    for &property in properties {
+        set.insert(property);
+    }
+    set
+}
+
+/// Name of the absolute device grid warping moves the pointer with
+pub const WARP_NAME: &str = "rust-virtual-tablet";
+
+/// `WARP_NAME`, suffixed with the seat
+pub fn warp_name() -> String {
+    seat::suffixed(WARP_NAME)
+}
+
+/// Create the absolute pointer for `[warp]`, its axes spanning the configured range
+///
+/// BTN_LEFT is declared but never pressed: with it and INPUT_PROP_POINTER,
+/// libinput takes the device for an absolute pointer, like a VM tablet.
+pub fn create_warp_device(warp: &WarpConfig, phys: &str) -> Result<VirtualDevice> {
+    let mut keys = AttributeSet::new();
+    keys.insert(Key::BTN_LEFT);
+    let axis = |axis, size: i32| UinputAbsSetup::new(axis, AbsInfo::new(0, 0, size - 1, 0, 0, 0));
+    let _building = BUILDING
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let name = warp_name();
+    let before = uinput_fds();
+    let builder = uinput_builder()
+        .context("Failed to create warp device builder")?
+        .name(&name)
+        .with_keys(&keys)?
+        .with_properties(&property_set(&[PropType::POINTER]))?
+        .with_absolute_axis(&axis(AbsoluteAxisType::ABS_X, warp.width))?
+        .with_absolute_axis(&axis(AbsoluteAxisType::ABS_Y, warp.height))?;
+    set_phys(new_descriptor(&before), phys)?;
+    builder.build().context("Failed to build the warp device")
+}
+
+/// Create a virtual keyboard with custom name, optionally also declaring the
+/// pointer buttons and whether to add wheel axes
+///
+/// Besides keys, relative axes and switches, it copies whatever `mirror` allows.
+/// `targets` are keys mappings type, declared even if `keyboard` has none.
+pub fn create_virtual_keyboard(
+    keyboard: &RawDevice,
+    targets: &AttributeSet<Key>,
+    name: &str,
+    pointer: Option<(&AttributeSet<Key>, bool)>,
+    mirror: &MirrorConfig,
+    phys: &str,
+    properties: &[PropType],
+) -> Result<VirtualDevice> {
+    let _building = BUILDING
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let before = uinput_fds();
+    let mut builder = uinput_builder()
+        .context("Failed to create keyboard builder")?
+        .name(name);
+
+    let mut keys = AttributeSet::new();
+    let mut rel_axes = AttributeSet::new();
+    for key in keyboard.supported_keys().into_iter().flat_map(|k| k.iter()) {
+        keys.insert(key);
+    }
+    let added: Vec<String> = targets
+        .iter()
+        .filter(|&key| !keys.contains(key))
+        .map(|key| format!("{:?}", key))
+        .collect();
+    if !added.is_empty() {
+        eprintln!(
+            "⌨️  Adding mapped key(s) the keyboard lacks: {}",
+            added.join(", ")
+        );
+    }
+    for key in targets.iter() {
+        keys.insert(key);
+    }
+    for axis in keyboard
+        .supported_relative_axes()
+        .into_iter()
+        .flat_map(|a| a.iter())
+    {
+        rel_axes.insert(axis);
+    }
+    if let Some((buttons, wheel)) = pointer {
+        for button in buttons.iter() {
+            keys.insert(button);
+        }
+        for axis in mouse_axes(wheel).iter() {
+            rel_axes.insert(axis);
+        }
+    }
+
+    if keys.iter().next().is_some() {
+        builder = builder.with_keys(&keys)?;
+    }
+
+    if rel_axes.iter().next().is_some() {
+        builder = builder.with_relative_axes(&rel_axes)?;
+    }
+
+    if let Some(switches) = keyboard.supported_switches() {
+        builder = builder.with_switches(switches)?;
+    }
+
+    if !properties.is_empty() {
+        builder = builder.with_properties(&property_set(properties))?;
+    }
+
+    if mirror.misc {
+        if let Some(misc) = keyboard.misc_properties() {
+            builder = builder.with_msc(misc)?;
+        }
+    }
+
+    if mirror.abs {
+        builder = with_abs_axes(builder, keyboard)?;
+    }
+
+    // evdev's builder has no phys, LED or EV_REP setup, and keeps its fd to itself
+    let fd = new_descriptor(&before);
+    set_phys(fd, phys)?;
+    let leds = keyboard.supported_leds().filter(|_| mirror.leds);
+    let repeat = keyboard.get_auto_repeat().filter(|_| mirror.repeat);
+    if leds.is_some() || repeat.is_some() {
+        match fd {
+            Some(fd) => {
+                if repeat.is_some() {
+                    set_bit(fd, UI_SET_EVBIT, EventType::REPEAT.0)?;
+                }
+                if let Some(leds) = leds {
+                    set_bit(fd, UI_SET_EVBIT, EventType::LED.0)?;
+                    for led in leds.iter() {
+                        set_bit(fd, UI_SET_LEDBIT, led.0)?;
+                    }
+                }
+            }
+            None => eprintln!(
+                "⚠️  Couldn't find the uinput descriptor; not mirroring LEDs and key repeat"
+            ),
+        }
+    }
+
+    let mut device = builder
+        .build()
+        .context("Failed to build virtual keyboard")?;
+    if let Some(repeat) = repeat {
+        // Written to uinput, EV_REP sets the device's delay and period
+        device.emit(&[
+            InputEvent::new(EventType::REPEAT, REP_DELAY, repeat.delay as i32),
+            InputEvent::new(EventType::REPEAT, REP_PERIOD, repeat.period as i32),
+        ])?;
+    }
+    Ok(device)
+}
+
+/// Declare every absolute axis of `device`, with its current ranges
+pub fn with_abs_axes<'a>(
+    mut builder: VirtualDeviceBuilder<'a>,
+    device: &RawDevice,
+) -> Result<VirtualDeviceBuilder<'a>> {
+    let Some(axes) = device.supported_absolute_axes() else {
+        return Ok(builder);
+    };
+    let state = device
+        .get_abs_state()
+        .context("Failed to read the source device's absolute axes")?;
+    for axis in axes.iter() {
+        let info = state[axis.0 as usize];
+        let info = AbsInfo::new(
+            info.value,
+            info.minimum,
+            info.maximum,
+            info.fuzz,
+            info.flat,
+            info.resolution,
+        );
+        builder = builder.with_absolute_axis(&UinputAbsSetup::new(axis, info))?;
+    }
+    Ok(builder)
+}
+
+/// _IOW('U', 100, int) and _IOW('U', 105, int) from linux/uinput.h
+const UI_SET_EVBIT: libc::c_ulong = 0x4004_5564;
+const UI_SET_LEDBIT: libc::c_ulong = 0x4004_5569;
+
+/// _IOW('U', 108, char *), sized by the pointer width
+const UI_SET_PHYS: libc::c_ulong = (1 << 30)
+    | ((std::mem::size_of::<*const libc::c_char>() as libc::c_ulong) << 16)
+    | (0x55 << 8)
+    | 108;
+
+/// REP_DELAY and REP_PERIOD from linux/input-event-codes.h
+const REP_DELAY: u16 = 0x00;
+const REP_PERIOD: u16 = 0x01;
+
+fn set_bit(fd: RawFd, request: libc::c_ulong, bit: u16) -> Result<()> {
+    // SAFETY: both requests take an int by value
+    if unsafe { libc::ioctl(fd, request, libc::c_int::from(bit)) } < 0 {
+        return Err(std::io::Error::last_os_error())
+            .context("Failed to set up the virtual keyboard");
+    }
+    Ok(())
+}
+
+/// Set the phys of the device `fd` is building; without the descriptor, it has none
+///
+/// uinput has no counterpart for `uniq`, so virtual devices never get one.
+pub fn set_phys(fd: Option<RawFd>, phys: &str) -> Result<()> {
+    let Some(fd) = fd else {
+        eprintln!(
+            "⚠️  Couldn't find the uinput descriptor; not setting phys {}",
+            phys
+        );
+        return Ok(());
+    };
+    let phys = CString::new(phys).context("phys must not contain NUL")?;
+    // SAFETY: UI_SET_PHYS copies the NUL-terminated string before returning
+    if unsafe { libc::ioctl(fd, UI_SET_PHYS, phys.as_ptr()) } < 0 {
+        return Err(std::io::Error::last_os_error())
+            .context("Failed to set the phys of a virtual device");
+    }
+    Ok(())
+}
+
+/// The /dev/uinput descriptor opened since `before`, if exactly one was
+pub fn new_descriptor(before: &HashSet<RawFd>) -> Option<RawFd> {
+    match uinput_fds().difference(before).collect::<Vec<_>>()[..] {
+        [&fd] => Some(fd),
+        _ => None,
+    }
+}
+
+/// Held while a device that needs its uinput descriptor is built
+pub static BUILDING: Mutex<()> = Mutex::new(());
+
+/// Descriptors open on /dev/uinput; while `BUILDING` is held no other thread
+/// opens any, so the one a new builder added is the difference
+pub fn uinput_fds() -> HashSet<RawFd> {
+    let Ok(entries) = fs::read_dir("/proc/self/fd") else {
+        return HashSet::new();
+    };
+    entries
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            fs::read_link(entry.path())
+                .is_ok_and(|target| target == Path::new(preflight::UINPUT_PATH))
+        })
+        .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+        .collect()
+}
+
+/// Where `Outputs` writes a frame; the device ends it with a SYN_REPORT
+pub trait Emit {
+    fn emit(&mut self, events: &[InputEvent]) -> io::Result<()>;
+}
+
+impl Emit for VirtualDevice {
+    fn emit(&mut self, events: &[InputEvent]) -> io::Result<()> {
+        VirtualDevice::emit(self, events)
+    }
+}
+
+/// Virtual output devices; pointer events go to the mouse when there is one
+pub struct Outputs<D = VirtualDevice> {
+    pub keyboard: D,
+    keyboard_name: String,
+    pub mouse: Option<D>,
+    /// Absolute pointer for `[warp]` jumps
+    pub warp: Option<D>,
+    /// The kernel repeats for the virtual keyboard, so forwarded repeats would double up
+    drop_repeats: bool,
+    /// Forwarded events of the frame in progress, written at its SYN_REPORT
+    frame: Frame,
+    /// The standalone mouse's frame, built and written in one call
+    pointer_frame: Frame,
+}
+
+impl Outputs {
+    /// Create the virtual devices for `keyboard`: a keyboard, plus a mouse when
+    /// something can click or scroll and pointer output doesn't go on the keyboard
+    pub fn create(config: &Config, keyboard: &RawDevice) -> Result<Self> {
+        let phys = config
+            .virtual_devices
+            .keyboard_phys(keyboard.physical_path().filter(|phys| !phys.is_empty()));
+        let mouse_keys = config.mouse_buttons();
+        let wheel = config.needs_wheel();
+        let pointer = config.needs_pointer();
+        let mouse = if !pointer {
+            eprintln!("🖱️  No button or wheel targets; not creating a virtual mouse");
+            None
+        } else if config.pointer_on_keyboard {
+            None
+        } else {
+            eprintln!("🖱️  Creating virtual mouse...");
+            Some(create_virtual_mouse(
+                &mouse_keys,
+                wheel,
+                &format!("{}/mouse", phys),
+                &config.virtual_devices.mouse_properties,
+            )?)
+        };
+
+        eprintln!(
+            "⌨️  Creating virtual keyboard '{}'...",
+            config.virtual_keyboard_name
+        );
+        if pointer && config.pointer_on_keyboard {
+            eprintln!("🖱️  Pointer buttons and axes go on the virtual keyboard");
+        }
+        let warp = match &config.warp {
+            Some(warp) => {
+                eprintln!("🎯 Creating warp device '{}'...", warp_name());
+                Some(create_warp_device(warp, &format!("{}/tablet", phys))?)
+            }
+            None => None,
+        };
+        let on_keyboard = pointer && config.pointer_on_keyboard;
+        let mut properties = config.virtual_devices.keyboard_properties.clone();
+        if on_keyboard {
+            properties.extend(&config.virtual_devices.mouse_properties);
+        }
+        let virtual_kbd = create_virtual_keyboard(
+            keyboard,
+            &config.key_targets(),
+            &config.virtual_keyboard_name,
+            on_keyboard.then_some((&mouse_keys, wheel)),
+            &config.mirror,
+            &phys,
+            &properties,
+        )?;
+        Ok(Self {
+            keyboard: virtual_kbd,
+            keyboard_name: config.virtual_keyboard_name.clone(),
+            mouse,
+            warp,
+            drop_repeats: config.mirror.repeat && keyboard.get_auto_repeat().is_some(),
+            frame: Frame::new(),
+            pointer_frame: Frame::new(),
+        })
+    }
+}
+
+impl<D: Emit> Outputs<D> {
+    /// Write the pending frame; `emit` ends it with a SYN_REPORT of its own
+    fn flush(&mut self) -> Result<()> {
+        let keyboard = &mut self.keyboard;
+        self.frame.flush(|events| keyboard.emit(events))
+    }
+
+    /// Write what `build` adds as one pointer frame; on the keyboard, after its pending frame
+    fn pointer(&mut self, build: impl FnOnce(&mut Frame)) -> Result<()> {
+        let Some(mouse) = &mut self.mouse else {
+            self.flush()?;
+            build(&mut self.frame);
+            return self.flush();
+        };
+        build(&mut self.pointer_frame);
+        self.pointer_frame.flush(|events| mouse.emit(events))
+    }
+}
+
+impl<D: Emit> EventSink for Outputs<D> {
+    /// Collected into frames as they came, so a frame goes out in one write
+    fn forward(&mut self, event: InputEvent) -> Result<()> {
+        if self.drop_repeats && event.event_type() == EventType::KEY && event.value() == 2 {
+            return Ok(());
+        }
+        if self.frame.push(event) {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Written at once, with whatever the frame in progress already holds
+    fn key(&mut self, key: Key, value: i32) -> Result<()> {
+        self.forward(InputEvent::new(EventType::KEY, key.code(), value))?;
+        self.flush()
+    }
+
+    fn button(&mut self, button: Key, value: i32) -> Result<()> {
+        self.pointer(|frame| {
+            frame.push(InputEvent::new(EventType::KEY, button.code(), value));
+        })
+    }
+
+    /// Empty frames are skipped
+    fn wheel(&mut self, hi_res: i32, legacy: i32) -> Result<()> {
+        self.pointer(|frame| frame.extend(scroll::wheel_events(hi_res, legacy)))
+    }
+
+    fn warp(&mut self, x: i32, y: i32) -> Result<()> {
+        if let Some(device) = &mut self.warp {
+            device.emit(&[
+                InputEvent::new(EventType::ABSOLUTE, AbsoluteAxisType::ABS_X.0, x),
+                InputEvent::new(EventType::ABSOLUTE, AbsoluteAxisType::ABS_Y.0, y),
+            ])?;
+        }
+        Ok(())
+    }
+
+    fn devices(&self) -> Vec<String> {
+        let mut devices = vec![format!("uinput keyboard '{}'", self.keyboard_name)];
+        if self.mouse.is_some() {
+            devices.push(format!("uinput mouse '{}'", mouse_name()));
+        }
+        if self.warp.is_some() {
+            devices.push(format!("uinput tablet '{}'", warp_name()));
+        }
+        devices
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::journal::Journal;
+    use crate::motion::LastMotion;
+    use crate::recover::Guarded;
+    use crate::remap::Remapper;
+    use evdev::MiscType;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    /// Counts the allocations of the current thread, so other tests don't add to them
+    struct Counting;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    fn count() {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+    }
+
+    // SAFETY: every call is passed straight to the system allocator
+    unsafe impl GlobalAlloc for Counting {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            count();
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            count();
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: Counting = Counting;
+
+    /// A uinput device that only counts the frames written to it
+    struct Device(Arc<AtomicUsize>);
+
+    impl Emit for Device {
+        fn emit(&mut self, events: &[InputEvent]) -> io::Result<()> {
+            assert!(!events.is_empty());
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    /// One key frame as a keyboard sends it: MSC_SCAN, the key, SYN_REPORT
+    fn frame(key: Key, value: i32) -> [InputEvent; 3] {
+        [
+            InputEvent::new(EventType::MISC, MiscType::MSC_SCAN.0, i32::from(key.code())),
+            InputEvent::new(EventType::KEY, key.code(), value),
+            InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
+        ]
+    }
+
+    /// Set in the re-executed test binary
+    const CHILD: &str = "TOUCH_LAYER_TEST_ALLOC";
+
+    #[test]
+    fn steady_state_frames_do_not_allocate() {
+        // The test harness captures stderr into a growing buffer, which the
+        // log line of each mapped press would count against; run uncaptured
+        if std::env::var_os(CHILD).is_none() {
+            let output = std::process::Command::new(std::env::current_exe().unwrap())
+                .args([
+                    "--exact",
+                    "output::tests::steady_state_frames_do_not_allocate",
+                    "--nocapture",
+                ])
+                .env(CHILD, "1")
+                .output()
+                .unwrap();
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            assert!(
+                output.status.success() && stdout.contains("1 passed"),
+                "{}\n{}",
+                stdout,
+                String::from_utf8_lossy(&output.stderr)
+            );
+            return;
+        }
+
+        let config = Config::parse(
+            "[mappings]\nKEY_F = \"BTN_LEFT\"\nKEY_J = { wheel = -1 }\nKEY_V = \"KEY_PLAYPAUSE\"\n",
+            Path::new("test.toml"),
+        )
+        .unwrap();
+        let (keyboard, mouse) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let outputs = Outputs {
+            keyboard: Device(keyboard.clone()),
+            keyboard_name: config.virtual_keyboard_name.clone(),
+            mouse: Some(Device(mouse.clone())),
+            warp: None,
+            drop_repeats: false,
+            frame: Frame::new(),
+            pointer_frame: Frame::new(),
+        };
+        let mut sink = Guarded::new(Box::new(outputs));
+        let path =
+            std::env::temp_dir().join(format!("touch-layer-test-alloc-{}", std::process::id()));
+        let journal = Journal::open_at(path.clone(), &mut sink).unwrap();
+        sink.keep_journal(journal);
+        let mut remapper = Remapper::new(&config, Arc::new(LastMotion::new())).unwrap();
+
+        // Typing with the layer off, then clicks, scrolls and a media key with it on
+        let mut stream = Vec::new();
+        for (keys, active) in [
+            (
+                &[Key::KEY_H, Key::KEY_E, Key::KEY_Y, Key::KEY_SPACE][..],
+                false,
+            ),
+            (&[Key::KEY_F, Key::KEY_J, Key::KEY_V, Key::KEY_F][..], true),
+        ] {
+            for &key in keys {
+                for value in [1, 0] {
+                    stream.extend(frame(key, value).map(|event| (event, active)));
+                }
+            }
+        }
+        let mut now = Instant::now();
+        let mut route = |sink: &mut Guarded| {
+            for &(event, active) in &stream {
+                now += Duration::from_millis(7);
+                remapper.handle(event, active, now, sink).unwrap();
+                remapper.tick(now, active, sink).unwrap();
+            }
+        };
+
+        // Warm up, so maps and buffers have their steady-state capacity
+        route(&mut sink);
+        let frames = keyboard.load(Ordering::Relaxed) + mouse.load(Ordering::Relaxed);
+        let before = ALLOCATIONS.with(Cell::get);
+        for _ in 0..100 {
+            route(&mut sink);
+        }
+        let allocations = ALLOCATIONS.with(Cell::get) - before;
+        let written = keyboard.load(Ordering::Relaxed) + mouse.load(Ordering::Relaxed) - frames;
+        assert_eq!(written, frames * 100, "every frame was written");
+        assert!(!sink.failed());
+        assert_eq!(
+            allocations, 0,
+            "{} frames allocated {} times",
+            written, allocations
+        );
+        drop(sink);
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,205 @@
+//! `TOUCH_LAYER_*` environment variables overriding single config values
+//!
+//! Each variable is the config key uppercased with `.` turned into `_`, so
+//! `scroll.kinetic.enabled` is `TOUCH_LAYER_SCROLL_KINETIC_ENABLED`. They are
+//! applied on top of the file (or the built-in defaults when there is none).
+
+use anyhow::{anyhow, bail, Result};
+use toml_edit::{value, Array, DocumentMut, Item};
+
+/// How a variable's text becomes a TOML value
+#[derive(Clone, Copy)]
+enum Kind {
+    Str,
+    Bool,
+    Int,
+    Float,
+    /// Comma-separated strings, e.g. device candidates
+    List,
+}
+
+/// Every overridable key; mappings only come from the file
+const KEYS: &[(&str, Kind)] = &[
+    ("touchpad", Kind::List),
+    ("keyboard", Kind::List),
+    ("device_wait_ms", Kind::Int),
+    ("reconnect_max_delay_ms", Kind::Int),
+    ("grab_attempts", Kind::Int),
+    ("grab_retry_ms", Kind::Int),
+    ("virtual_keyboard_name", Kind::Str),
+    ("pointer_on_keyboard", Kind::Bool),
+    ("mouse_buttons", Kind::List),
+    ("xkb_layout", Kind::Str),
+    ("mapping_keys", Kind::Str),
+    ("preset", Kind::Str),
+    ("xkb_variant", Kind::Str),
+    ("xkb_options", Kind::Str),
+    ("xkb_keymap", Kind::Str),
+    ("debounce_ms", Kind::Int),
+    ("adaptive_debounce.enabled", Kind::Bool),
+    ("adaptive_debounce.min_ms", Kind::Int),
+    ("adaptive_debounce.max_ms", Kind::Int),
+    ("adaptive_debounce.window_ms", Kind::Int),
+    ("adaptive_debounce.slow", Kind::Float),
+    ("adaptive_debounce.fast", Kind::Float),
+    ("chatter_ms", Kind::Int),
+    ("activate_when", Kind::Str),
+    ("motion_threshold", Kind::Float),
+    ("deactivate_on", Kind::Str),
+    ("persist_state", Kind::Bool),
+    ("start_enabled", Kind::Bool),
+    ("on_activate", Kind::Str),
+    ("on_deactivate", Kind::Str),
+    ("allow_commands", Kind::Bool),
+    ("mapping_condition", Kind::Str),
+    ("scroll.repeat_delay_ms", Kind::Int),
+    ("scroll.repeat_interval_ms", Kind::Int),
+    ("scroll.step", Kind::Int),
+    ("scroll.natural", Kind::Bool),
+    ("scroll.acceleration.enabled", Kind::Bool),
+    ("scroll.acceleration.initial_rate_hz", Kind::Int),
+    ("scroll.acceleration.max_rate_hz", Kind::Int),
+    ("scroll.acceleration.ramp_ms", Kind::Int),
+    ("scroll.acceleration.curve", Kind::Str),
+    ("scroll.kinetic.enabled", Kind::Bool),
+    ("scroll.kinetic.time_constant_ms", Kind::Int),
+    ("scroll.kinetic.min_velocity", Kind::Float),
+    ("scroll.kinetic.max_velocity", Kind::Float),
+    ("scroll.kinetic.tick_ms", Kind::Int),
+    ("script.path", Kind::Str),
+    ("script.max_operations", Kind::Int),
+    ("typing.presses", Kind::Int),
+    ("typing.window_ms", Kind::Int),
+    ("typing.cooldown_ms", Kind::Int),
+    ("typing.after_letter_ms", Kind::Int),
+    ("scheduling.policy", Kind::Str),
+    ("scheduling.priority", Kind::Int),
+    ("scheduling.nice", Kind::Int),
+    ("control.enabled", Kind::Bool),
+    ("control.group", Kind::Str),
+    ("control.inject", Kind::Bool),
+    ("control.inject_rate", Kind::Int),
+    ("control.map", Kind::Bool),
+    ("mirror.leds", Kind::Bool),
+    ("mirror.misc", Kind::Bool),
+    ("mirror.abs", Kind::Bool),
+    ("mirror.repeat", Kind::Bool),
+    ("virtual_devices.phys", Kind::Str),
+    ("virtual_devices.keyboard_properties", Kind::List),
+    ("virtual_devices.mouse_properties", Kind::List),
+    ("external_mouse.device", Kind::List),
+    ("external_mouse.name", Kind::Str),
+    ("external_mouse.follow_activation", Kind::Bool),
+    ("touchpad_buttons.name", Kind::Str),
+    ("activation_mouse.device", Kind::List),
+    ("activation_mouse.threshold", Kind::Int),
+    ("activation_mouse.idle_ms", Kind::Int),
+    ("tablet_mode.action", Kind::Str),
+    ("tablet_mode.device", Kind::List),
+    ("session_lock.enabled", Kind::Bool),
+    ("session_lock.ungrab", Kind::Bool),
+    ("suspend.enabled", Kind::Bool),
+    ("suspend.max_delay_ms", Kind::Int),
+    ("sticky.enabled", Kind::Bool),
+    ("sticky.fingers", Kind::Int),
+    ("sticky.min_distance", Kind::Float),
+    ("sticky.max_duration_ms", Kind::Int),
+    ("hysteresis.reactivate_after_ms", Kind::Int),
+    ("hysteresis.reactivate_motion", Kind::Float),
+    ("hysteresis.min_active_ms", Kind::Int),
+    ("watchdog.enabled", Kind::Bool),
+    ("watchdog.stall_ms", Kind::Int),
+    ("watchdog.max_button_hold_ms", Kind::Int),
+    ("warp.key", Kind::Str),
+    ("warp.width", Kind::Int),
+    ("warp.height", Kind::Int),
+];
+
+/// One variable that is set, already parsed
+pub struct Override {
+    pub var: String,
+    /// Dotted config key
+    pub key: &'static str,
+    item: Item,
+}
+
+impl Override {
+    /// The value as it would be written in the file
+    pub fn display(&self) -> String {
+        self.item
+            .as_value()
+            .map_or_else(String::new, |v| v.to_string().trim().to_string())
+    }
+}
+
+/// Variable name for a dotted key
+fn var_name(key: &str) -> String {
+    format!("TOUCH_LAYER_{}", key.replace('.', "_").to_uppercase())
+}
+
+/// Read every set variable, failing on the first that doesn't parse
+pub fn from_env() -> Result<Vec<Override>> {
+    let mut overrides = Vec::new();
+    for &(key, kind) in KEYS {
+        let name = var_name(key);
+        let Ok(text) = std::env::var(&name) else {
+            continue;
+        };
+        let item = parse(&text, kind).map_err(|err| anyhow!("{}={}: {}", name, text, err))?;
+        overrides.push(Override {
+            var: name,
+            key,
+            item,
+        });
+    }
+    Ok(overrides)
+}
+
+fn parse(text: &str, kind: Kind) -> Result<Item> {
+    let text = text.trim();
+    Ok(match kind {
+        Kind::Str => value(text),
+        Kind::Bool => match text {
+            "true" | "1" | "yes" | "on" => value(true),
+            "false" | "0" | "no" | "off" => value(false),
+            _ => bail!("expected true or false"),
+        },
+        Kind::Int => value(
+            text.parse::<i64>()
+                .map_err(|_| anyhow!("expected an integer"))?,
+        ),
+        Kind::Float => value(
+            text.parse::<f64>()
+                .map_err(|_| anyhow!("expected a number"))?,
+        ),
+        Kind::List => {
+            let items: Array = text
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .collect();
+            if items.is_empty() {
+                bail!("expected one or more comma-separated values");
+            }
+            value(items)
+        }
+    })
+}
+
+/// Write the overrides into `doc`, creating tables as needed
+pub fn apply(doc: &mut DocumentMut, overrides: &[Override]) -> Result<()> {
+    for o in overrides {
+        let mut parts: Vec<&str> = o.key.split('.').collect();
+        let leaf = parts.pop().expect("keys are never empty");
+        let mut table = doc.as_table_mut() as &mut dyn toml_edit::TableLike;
+        for part in parts {
+            table = table
+                .entry(part)
+                .or_insert_with(toml_edit::table)
+                .as_table_like_mut()
+                .ok_or_else(|| anyhow!("{}: `{}` in the config is not a table", o.var, part))?;
+        }
+        table.insert(leaf, o.item.clone());
+    }
+    Ok(())
+}
@@ -0,0 +1,395 @@
+use crate::backend::Backend;
+use crate::config::Config;
+use crate::device::{self, DeviceKind};
+use crate::diagnose::NodeAccess;
+use crate::exit::{
+    self, Class, EXIT_ALREADY_RUNNING, EXIT_GRAB_CONFLICT, EXIT_PERMISSION, EXIT_UINPUT_UNAVAILABLE,
+};
+use anyhow::{anyhow, Result};
+use std::ffi::CString;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+pub const UINPUT_PATH: &str = "/dev/uinput";
+
+/// A startup failure with a known cause, carried through anyhow so main can pick the exit code
+#[derive(Debug)]
+pub enum AccessError {
+    /// An event node exists but can't be read
+    EventNode(PathBuf),
+    /// EVIOCGRAB failed, usually because another process holds the grab
+    Grab(PathBuf),
+    /// /dev/uinput is missing or not writable
+    Uinput,
+    /// Another instance holds the PID file lock (its PID, if readable)
+    AlreadyRunning(Option<u32>),
+}
+
+impl AccessError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            AccessError::EventNode(_) => EXIT_PERMISSION,
+            AccessError::Grab(_) => EXIT_GRAB_CONFLICT,
+            AccessError::Uinput => EXIT_UINPUT_UNAVAILABLE,
+            AccessError::AlreadyRunning(_) => EXIT_ALREADY_RUNNING,
+        }
+    }
+}
+
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AccessError::EventNode(path) => write!(f, "No read access to {}", path.display()),
+            AccessError::Grab(path) => write!(f, "Could not grab {}", path.display()),
+            AccessError::Uinput => write!(f, "Cannot create virtual devices via {}", UINPUT_PATH),
+            AccessError::AlreadyRunning(Some(pid)) => write!(f, "Already running as PID {}", pid),
+            AccessError::AlreadyRunning(None) => write!(f, "Another instance is already running"),
+        }
+    }
+}
+
+impl std::error::Error for AccessError {}
+
+/// Outcome of a single preflight check
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: String,
+    pub ok: bool,
+    /// What was found, and how to fix it when not ok
+    pub details: Vec<String>,
+}
+
+fn access_ok(path: &Path, mode: libc::c_int) -> bool {
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // SAFETY: path is a valid NUL-terminated string
+    unsafe { libc::access(path.as_ptr(), mode) == 0 }
+}
+
+fn describe_node(path: &Path, details: &mut Vec<String>) {
+    if let Ok(access) = NodeAccess::of(path) {
+        details.extend(access_details(&access));
+    }
+}
+
+/// The node's mode and group, and the fix when this process lacks the group
+fn access_details(access: &NodeAccess) -> Vec<String> {
+    let mut details = vec![format!(
+        "mode {:o}, group '{}' (gid {}); this process is {}in that group",
+        access.mode,
+        access.group,
+        access.gid,
+        if access.member { "" } else { "not " }
+    )];
+    if !access.member {
+        details.push(format!(
+            "add the user to '{}' (then log in again) or run as root",
+            access.group
+        ));
+    }
+    details
+}
+
+/// Can we read the event node at `path`?
+pub fn check_event_node(path: &Path) -> Check {
+    let mut details = Vec::new();
+    let ok = access_ok(path, libc::R_OK);
+    if !ok {
+        if path.exists() {
+            describe_node(path, &mut details);
+        } else {
+            details.push("node does not exist".into());
+        }
+    }
+    Check {
+        name: format!("read {}", path.display()),
+        ok,
+        details,
+    }
+}
+
+/// Is the uinput module loaded and /dev/uinput writable?
+pub fn check_uinput() -> Check {
+    check_uinput_at(Path::new(UINPUT_PATH), Path::new("/sys/module/uinput"))
+}
+
+/// `check_uinput` on the node at `path`, with the module's sysfs entry at `module`
+fn check_uinput_at(path: &Path, module: &Path) -> Check {
+    let mut details = Vec::new();
+
+    let ok = if !path.exists() {
+        if module.exists() {
+            details.push("uinput module is loaded but the node is missing (check udev)".into());
+        } else {
+            details.push("uinput module not loaded: run `modprobe uinput`".into());
+        }
+        false
+    } else if !access_ok(path, libc::W_OK) {
+        describe_node(path, &mut details);
+        false
+    } else {
+        true
+    };
+
+    Check {
+        name: format!("write {}", path.display()),
+        ok,
+        details,
+    }
+}
+
+/// Processes holding `path` open; one of them likely owns the grab
+pub fn device_holders(path: &Path) -> Vec<(u32, String)> {
+    let Ok(target) = fs::canonicalize(path) else {
+        return Vec::new();
+    };
+    let own_pid = std::process::id();
+    let mut holders = Vec::new();
+
+    let Ok(procs) = fs::read_dir("/proc") else {
+        return holders;
+    };
+    for entry in procs.filter_map(|e| e.ok()) {
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|n| n.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        if pid == own_pid {
+            continue;
+        }
+        let Ok(fds) = fs::read_dir(entry.path().join("fd")) else {
+            continue;
+        };
+        let holds = fds
+            .filter_map(|fd| fd.ok())
+            .any(|fd| fs::read_link(fd.path()).is_ok_and(|link| link == target));
+        if holds {
+            let comm = fs::read_to_string(entry.path().join("comm")).unwrap_or_default();
+            holders.push((pid, comm.trim().to_string()));
+        }
+    }
+    holders
+}
+
+/// Explain a failed EVIOCGRAB
+pub fn explain_grab_failure(path: &Path, err: &io::Error) {
+    eprintln!("❌ Could not grab {}: {}", path.display(), err);
+    if err.raw_os_error() == Some(libc::EBUSY) {
+        eprintln!("   Another process already holds an exclusive grab on it");
+    }
+
+    let holders = device_holders(path);
+    if holders.is_empty() {
+        eprintln!("   No other process visibly has it open (run as root to see all)");
+    } else {
+        eprintln!("   Held open by:");
+        for (pid, comm) in holders {
+            eprintln!("     {} {}", pid, comm);
+        }
+    }
+}
+
+/// Explain a failed uinput device creation
+pub fn explain_uinput_failure(err: &io::Error) {
+    eprintln!("❌ Could not create a virtual device: {}", err);
+    print_check(&check_uinput());
+}
+
+/// Why a failed startup check fails, for the exit status
+enum Problem {
+    Missing,
+    Unreadable(PathBuf),
+    Uinput,
+}
+
+/// One device's candidates: fine if any node can be read
+fn check_candidates(kind: DeviceKind, candidates: &[String]) -> (Vec<Check>, Option<Problem>) {
+    let nodes = device::candidate_nodes(candidates);
+    if nodes.is_empty() {
+        let check = Check {
+            name: format!("find {}", kind.label()),
+            ok: false,
+            details: vec![format!("no node matches {}", candidates.join(", "))],
+        };
+        return (vec![check], Some(Problem::Missing));
+    }
+    let checks: Vec<Check> = nodes.iter().map(|node| check_event_node(node)).collect();
+    match checks.iter().find(|check| check.ok) {
+        // Unreadable fallbacks don't matter once one candidate works
+        Some(readable) => (vec![readable.clone()], None),
+        None => (checks, Some(Problem::Unreadable(nodes[0].clone()))),
+    }
+}
+
+/// Every check startup depends on, run and reported together before any
+/// device is opened, so a doomed start never grabs the keyboard
+pub fn run(config: &Config, backend: &Backend) -> Result<()> {
+    let (mut checks, mut problems) = (Vec::new(), Vec::new());
+    for (kind, candidates) in [
+        (DeviceKind::Touchpad, &config.touchpad),
+        (DeviceKind::Keyboard, &config.keyboard),
+    ] {
+        let (found, problem) = check_candidates(kind, candidates);
+        checks.extend(found);
+        problems.extend(problem);
+    }
+    // The external mouse proxy is a uinput device whatever the backend
+    if backend.name == "uinput" || config.external_mouse.is_some() {
+        let check = check_uinput();
+        if !check.ok {
+            problems.push(Problem::Uinput);
+        }
+        checks.push(check);
+    }
+
+    eprintln!("🩺 Startup checks:");
+    for check in &checks {
+        print_check(check);
+    }
+    let Some(first) = problems.first() else {
+        return Ok(());
+    };
+    let failed: Vec<&str> = checks
+        .iter()
+        .filter(|check| !check.ok)
+        .map(|check| check.name.as_str())
+        .collect();
+    let summary = format!(
+        "{} startup check{} failed: {}",
+        failed.len(),
+        if failed.len() == 1 { "" } else { "s" },
+        failed.join(", ")
+    );
+    // The first problem picks the exit status
+    Err(match first {
+        Problem::Missing => exit::tag(Class::DeviceNotFound, anyhow!(summary)),
+        Problem::Unreadable(path) => {
+            anyhow::Error::new(AccessError::EventNode(path.clone())).context(summary)
+        }
+        Problem::Uinput => anyhow::Error::new(AccessError::Uinput).context(summary),
+    })
+}
+
+pub fn print_check(check: &Check) {
+    eprintln!("   {} {}", if check.ok { "✓" } else { "✗" }, check.name);
+    for detail in &check.details {
+        eprintln!("       {}", detail);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh temp directory for fake nodes and sysfs entries
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "touch-layer-test-preflight-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn a_missing_uinput_node_says_whether_the_module_is_loaded() {
+        let dir = scratch("uinput");
+        let node = dir.join("uinput");
+        let module = dir.join("module");
+
+        let check = check_uinput_at(&node, &module);
+        assert!(!check.ok);
+        assert_eq!(check.name, format!("write {}", node.display()));
+        assert_eq!(
+            check.details,
+            ["uinput module not loaded: run `modprobe uinput`"]
+        );
+
+        fs::create_dir(&module).unwrap();
+        let check = check_uinput_at(&node, &module);
+        assert!(!check.ok);
+        assert_eq!(
+            check.details,
+            ["uinput module is loaded but the node is missing (check udev)"]
+        );
+
+        fs::write(&node, "").unwrap();
+        let check = check_uinput_at(&node, &module);
+        assert!(check.ok, "{:?}", check.details);
+        assert!(check.details.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_missing_input_group_says_how_to_join_it() {
+        let access = NodeAccess {
+            mode: 0o660,
+            gid: 104,
+            group: "input".to_string(),
+            member: false,
+        };
+        assert_eq!(
+            access_details(&access),
+            [
+                "mode 660, group 'input' (gid 104); this process is not in that group",
+                "add the user to 'input' (then log in again) or run as root",
+            ]
+        );
+        let access = NodeAccess {
+            member: true,
+            ..access
+        };
+        assert_eq!(
+            access_details(&access),
+            ["mode 660, group 'input' (gid 104); this process is in that group"]
+        );
+    }
+
+    #[test]
+    fn a_fake_node_is_described_by_its_real_mode_and_group() {
+        let dir = scratch("node");
+        let node = dir.join("event3");
+        fs::write(&node, "").unwrap();
+        fs::set_permissions(&node, std::os::unix::fs::PermissionsExt::from_mode(0o640)).unwrap();
+        let mut details = Vec::new();
+        describe_node(&node, &mut details);
+        // Created by this process, so its group is one we have
+        assert_eq!(details.len(), 1, "{:?}", details);
+        assert!(
+            details[0].starts_with("mode 640, group '"),
+            "{}",
+            details[0]
+        );
+        assert!(details[0].ends_with("this process is in that group"));
+
+        let check = check_event_node(&node);
+        assert!(check.ok);
+        let check = check_event_node(&dir.join("event4"));
+        assert!(!check.ok);
+        assert_eq!(check.details, ["node does not exist"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn each_access_error_has_its_own_exit_code() {
+        let codes = [
+            AccessError::EventNode(PathBuf::from("/dev/input/event3")).exit_code(),
+            AccessError::Grab(PathBuf::from("/dev/input/event3")).exit_code(),
+            AccessError::Uinput.exit_code(),
+            AccessError::AlreadyRunning(None).exit_code(),
+        ];
+        let mut unique = codes.to_vec();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), codes.len(), "{:?}", codes);
+    }
+}
@@ -0,0 +1,156 @@
+//! `--preset NAME` and `preset = "NAME"`: curated configs built into the binary
+//!
+//! A preset is an ordinary config file (see `presets/` in the source tree),
+//! compiled in and merged below every config layer, so anything a file sets
+//! overrides it, a mapping at a time. It goes through the same parsing and
+//! validation as the files on disk; `list-presets` builds each one to prove it
+//! still does, and checks its mapped keys against a typical keyboard.
+
+use crate::capabilities;
+use crate::config::Config;
+use anyhow::{bail, Context, Result};
+use evdev::{AttributeSet, Key};
+use std::sync::OnceLock;
+
+pub struct Preset {
+    pub name: &'static str,
+    /// One line for `list-presets`
+    pub summary: &'static str,
+    /// The config, as a file would hold it
+    pub text: &'static str,
+}
+
+pub const PRESETS: [Preset; 3] = [
+    Preset {
+        name: "classic",
+        summary: "F, D and S click left, right and middle",
+        text: include_str!("../presets/classic.toml"),
+    },
+    Preset {
+        name: "vim",
+        summary: "F/D click, J/K scroll a line, E/R three, H/L back and forward",
+        text: include_str!("../presets/vim.toml"),
+    },
+    Preset {
+        name: "reader",
+        summary: "Space and Shift-Space scroll a page, the arrows a line",
+        text: include_str!("../presets/reader.toml"),
+    },
+];
+
+/// Key codes KEY_ESC to KEY_COMPOSE: what a plain 105-key keyboard sends
+const TYPICAL_KEYS: std::ops::RangeInclusive<u16> = 1..=127;
+
+static CHOSEN: OnceLock<Option<&'static Preset>> = OnceLock::new();
+
+/// Every preset name, for `--preset`'s possible values
+pub fn names() -> Vec<&'static str> {
+    PRESETS.iter().map(|preset| preset.name).collect()
+}
+
+pub fn find(name: &str) -> Result<&'static Preset> {
+    match PRESETS.iter().find(|preset| preset.name == name) {
+        Some(preset) => Ok(preset),
+        None => bail!("unknown preset '{}'; expected {}", name, names().join(", ")),
+    }
+}
+
+/// Record `--preset`; call once, before any config is loaded
+pub fn choose(name: Option<&str>) -> Result<()> {
+    let preset = name.map(find).transpose()?;
+    let _ = CHOSEN.set(preset);
+    Ok(())
+}
+
+/// The preset given with `--preset`, if any
+pub fn chosen() -> Option<&'static Preset> {
+    CHOSEN.get().copied().flatten()
+}
+
+/// `list-presets`: each preset, validated, with what it maps
+pub fn list() -> Result<()> {
+    let typical: AttributeSet<Key> = TYPICAL_KEYS.map(Key::new).collect();
+    for (i, preset) in PRESETS.iter().enumerate() {
+        let config = Config::preset(preset)
+            .with_context(|| format!("Built-in preset {} is invalid", preset.name))?;
+        let missing = capabilities::unmatched_sources(&config, Some(&typical));
+        if !missing.is_empty() {
+            bail!(
+                "Built-in preset {} maps {:?}, which a typical keyboard lacks",
+                preset.name,
+                missing
+            );
+        }
+        if i > 0 {
+            println!();
+        }
+        println!("{}: {}", preset.name, preset.summary);
+        for (key, target) in table(&config) {
+            println!("  {:<12} → {}", format!("{:?}", key), target);
+        }
+    }
+    Ok(())
+}
+
+/// Mapped keys in code order, each with its target or its variants
+fn table(config: &Config) -> Vec<(Key, String)> {
+    let mut rows: Vec<(Key, String)> = config
+        .mappings
+        .iter()
+        .map(|(&key, target)| (key, target.to_string()))
+        .collect();
+    for (&key, variants) in &config.conditional {
+        let described: Vec<String> = variants
+            .iter()
+            .map(|variant| format!("{} ({})", variant.target, variant.when))
+            .collect();
+        rows.push((key, described.join(", else ")));
+    }
+    rows.sort_by_key(|(key, _)| key.code());
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn every_preset_validates_on_a_typical_keyboard() {
+        let typical: AttributeSet<Key> = TYPICAL_KEYS.map(Key::new).collect();
+        for preset in &PRESETS {
+            let config = Config::preset(preset)
+                .unwrap_or_else(|err| panic!("preset {}: {:#}", preset.name, err));
+            assert!(
+                !table(&config).is_empty(),
+                "preset {} maps nothing",
+                preset.name
+            );
+            let missing = capabilities::unmatched_sources(&config, Some(&typical));
+            assert!(missing.is_empty(), "preset {}: {:?}", preset.name, missing);
+            assert_eq!(config.preset, Some(preset.name));
+        }
+    }
+
+    #[test]
+    fn every_preset_can_be_picked_by_name_from_a_file() {
+        for name in names() {
+            let text = format!("preset = \"{}\"\n", name);
+            let config = Config::parse(&text, Path::new("test.toml"))
+                .unwrap_or_else(|err| panic!("preset {}: {:#}", name, err));
+            assert_eq!(config.preset, Some(name));
+        }
+    }
+
+    #[test]
+    fn names_are_unique_and_unknown_ones_are_listed() {
+        let mut names = names();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), PRESETS.len());
+        assert_eq!(
+            find("emacs").err().unwrap().to_string(),
+            "unknown preset 'emacs'; expected classic, vim, reader"
+        );
+    }
+}
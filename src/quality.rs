@@ -0,0 +1,357 @@
+//! How well activation matches use, for tuning the thresholds
+//!
+//! Each activation is classified when it ends: whether any mapped key was
+//! pressed during it, whether it lasted under `SHORT`, and whether it ended
+//! only to start again within `FLAP_WINDOW`. The time from activation to the
+//! first mapped press goes into a histogram. `Tracker` is fed the transitions
+//! and presses with their times and does no I/O; the daemon keeps one, puts
+//! its metrics in `status` and `stats` records and prints tuning hints from
+//! them at shutdown.
+
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Activations shorter than this are counted as short
+pub const SHORT: Duration = Duration::from_millis(500);
+
+/// A reactivation this soon after a deactivation makes that deactivation a flap
+pub const FLAP_WINDOW: Duration = Duration::from_millis(500);
+
+/// Upper bounds of the time-to-first-press buckets; one more bucket takes the rest
+pub const PRESS_BUCKETS_MS: [u64; 6] = [100, 250, 500, 1000, 2000, 5000];
+
+/// Finished activations needed before a hint is given
+const MIN_FOR_HINTS: u64 = 20;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Metrics {
+    /// Activations that have ended
+    pub activations: u64,
+    /// ...during which no mapped key was pressed
+    pub unused: u64,
+    /// ...that lasted under 500ms
+    pub short: u64,
+    /// Deactivations followed by a reactivation within 500ms
+    pub flaps: u64,
+    /// Activations by time to their first mapped press, in the buckets of
+    /// `PRESS_BUCKETS_MS` and then one for anything longer
+    pub first_press_ms: Vec<u64>,
+}
+
+/// An activation in progress
+struct Session {
+    started: Instant,
+    first_press: Option<Duration>,
+}
+
+pub struct Tracker {
+    session: Option<Session>,
+    /// When the last activation ended, until the flap window has passed
+    ended: Option<Instant>,
+    metrics: Metrics,
+}
+
+impl Default for Tracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tracker {
+    pub fn new() -> Self {
+        Self {
+            session: None,
+            ended: None,
+            metrics: Metrics {
+                first_press_ms: vec![0; PRESS_BUCKETS_MS.len() + 1],
+                ..Metrics::default()
+            },
+        }
+    }
+
+    pub fn activated(&mut self, at: Instant) {
+        if self.session.is_some() {
+            return;
+        }
+        if let Some(ended) = self.ended.take() {
+            if at.saturating_duration_since(ended) < FLAP_WINDOW {
+                self.metrics.flaps += 1;
+            }
+        }
+        self.session = Some(Session {
+            started: at,
+            first_press: None,
+        });
+    }
+
+    /// A mapped key went down; only the first of an activation counts
+    pub fn pressed(&mut self, at: Instant) {
+        if let Some(session) = &mut self.session {
+            if session.first_press.is_none() {
+                let after = at.saturating_duration_since(session.started);
+                session.first_press = Some(after);
+                let ms = after.as_millis() as u64;
+                let bucket = PRESS_BUCKETS_MS
+                    .iter()
+                    .position(|&bound| ms <= bound)
+                    .unwrap_or(PRESS_BUCKETS_MS.len());
+                self.metrics.first_press_ms[bucket] += 1;
+            }
+        }
+    }
+
+    pub fn deactivated(&mut self, at: Instant) {
+        let Some(session) = self.session.take() else {
+            return;
+        };
+        self.metrics.activations += 1;
+        if session.first_press.is_none() {
+            self.metrics.unused += 1;
+        }
+        if at.saturating_duration_since(session.started) < SHORT {
+            self.metrics.short += 1;
+        }
+        self.ended = Some(at);
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+}
+
+/// The bound under which half the activations with a press had their first
+/// press, or None without any
+pub fn median_first_press(metrics: &Metrics) -> Option<String> {
+    let total: u64 = metrics.first_press_ms.iter().sum();
+    if total == 0 {
+        return None;
+    }
+    let mut seen = 0;
+    for (i, count) in metrics.first_press_ms.iter().enumerate() {
+        seen += count;
+        if seen * 2 >= total {
+            return Some(match PRESS_BUCKETS_MS.get(i) {
+                Some(bound) => format!("within {}ms", bound),
+                None => format!("after more than {}ms", PRESS_BUCKETS_MS[i - 1]),
+            });
+        }
+    }
+    None
+}
+
+/// What the metrics suggest changing, one line each; nothing with too few activations
+pub fn hints(metrics: &Metrics) -> Vec<String> {
+    if metrics.activations < MIN_FOR_HINTS {
+        return Vec::new();
+    }
+    let percent = |count: u64| count * 100 / metrics.activations;
+    let mut hints = Vec::new();
+    if percent(metrics.unused) >= 50 {
+        hints.push(format!(
+            "{}% of activations saw no mapped key: consider raising motion_threshold or a stricter activate_when",
+            percent(metrics.unused)
+        ));
+    }
+    if percent(metrics.short) >= 30 {
+        hints.push(format!(
+            "{}% of activations lasted under {}ms: consider raising motion_threshold",
+            percent(metrics.short),
+            SHORT.as_millis()
+        ));
+    }
+    if percent(metrics.flaps) >= 20 {
+        hints.push(format!(
+            "{}% of deactivations were undone within {}ms: consider raising debounce_ms or hysteresis.reactivate_after_ms",
+            percent(metrics.flaps),
+            FLAP_WINDOW.as_millis()
+        ));
+    }
+    hints
+}
+
+static TRACKER: Mutex<Option<Tracker>> = Mutex::new(None);
+
+fn with<T>(f: impl FnOnce(&mut Tracker) -> T) -> T {
+    f(TRACKER.lock().unwrap().get_or_insert_with(Tracker::new))
+}
+
+/// The mode turned on or off
+pub fn mode_changed(active: bool) {
+    let now = Instant::now();
+    with(|tracker| {
+        if active {
+            tracker.activated(now);
+        } else {
+            tracker.deactivated(now);
+        }
+    });
+}
+
+/// A mapped key was pressed and routed
+pub fn pressed() {
+    let now = Instant::now();
+    with(|tracker| tracker.pressed(now));
+}
+
+pub fn current() -> Metrics {
+    with(|tracker| tracker.metrics().clone())
+}
+
+/// The shutdown summary on stderr, with hints if there are any
+pub fn summarize() {
+    let metrics = current();
+    if metrics.activations == 0 {
+        return;
+    }
+    let percent = |count: u64| count * 100 / metrics.activations;
+    eprintln!(
+        "📊 {} activation(s): {}% saw no mapped key, {}% under {}ms, {} flap(s); first press {}",
+        metrics.activations,
+        percent(metrics.unused),
+        percent(metrics.short),
+        SHORT.as_millis(),
+        metrics.flaps,
+        median_first_press(&metrics).map_or("never".to_string(), |median| format!(
+            "{} in half of those with one",
+            median
+        ))
+    );
+    for hint in hints(&metrics) {
+        eprintln!("💡 {}", hint);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy)]
+    enum Event {
+        On,
+        Press,
+        Off,
+    }
+    use Event::{Off, On, Press};
+
+    /// The metrics after `trace`, events at milliseconds from a common start
+    fn play(trace: &[(u64, Event)]) -> Metrics {
+        let start = Instant::now();
+        let mut tracker = Tracker::new();
+        for &(ms, event) in trace {
+            let at = start + Duration::from_millis(ms);
+            match event {
+                On => tracker.activated(at),
+                Press => tracker.pressed(at),
+                Off => tracker.deactivated(at),
+            }
+        }
+        tracker.metrics().clone()
+    }
+
+    #[test]
+    fn an_activation_is_classified_when_it_ends() {
+        // Used and long, then unused and short, then one still going
+        let metrics = play(&[
+            (0, On),
+            (300, Press),
+            (2_000, Off),
+            (5_000, On),
+            (5_499, Off),
+            (9_000, On),
+        ]);
+        assert_eq!(metrics.activations, 2);
+        assert_eq!(metrics.unused, 1);
+        assert_eq!(metrics.short, 1);
+        assert_eq!(metrics.flaps, 0);
+        assert_eq!(metrics.first_press_ms, [0, 0, 1, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn a_reactivation_inside_the_flap_window_is_a_flap() {
+        let metrics = play(&[
+            (0, On),
+            (1_000, Off),
+            (1_499, On),
+            (3_000, Off),
+            (3_500, On),
+        ]);
+        assert_eq!(metrics.flaps, 1, "499ms is a flap, 500ms is not");
+        assert_eq!(metrics.short, 0);
+    }
+
+    #[test]
+    fn only_the_first_press_of_an_activation_counts() {
+        let metrics = play(&[
+            (0, Press),
+            (10, On),
+            (110, Press),
+            (111, Press),
+            (700, Off),
+            (800, Press),
+        ]);
+        assert_eq!(metrics.first_press_ms, [1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(metrics.unused, 0);
+    }
+
+    #[test]
+    fn first_presses_fall_into_inclusive_buckets() {
+        let mut trace = Vec::new();
+        for (i, after) in [100, 101, 250, 5_000, 5_001, 60_000]
+            .into_iter()
+            .enumerate()
+        {
+            let start = i as u64 * 100_000;
+            trace.extend([(start, On), (start + after, Press), (start + 90_000, Off)]);
+        }
+        let metrics = play(&trace);
+        assert_eq!(metrics.first_press_ms, [1, 2, 0, 0, 0, 1, 2]);
+        assert_eq!(
+            median_first_press(&metrics).as_deref(),
+            Some("within 250ms")
+        );
+        assert_eq!(median_first_press(&play(&[(0, On), (10, Off)])), None);
+    }
+
+    #[test]
+    fn repeated_transitions_are_ignored() {
+        let metrics = play(&[(0, Off), (10, On), (20, On), (1_000, Off), (1_010, Off)]);
+        assert_eq!(metrics.activations, 1);
+        assert_eq!(metrics.flaps, 0);
+    }
+
+    #[test]
+    fn hints_need_enough_activations_and_clear_signals() {
+        let idle: Vec<_> = (0..20)
+            .flat_map(|i| [(i * 1_000, On), (i * 1_000 + 100, Off)])
+            .collect();
+        let metrics = play(&idle);
+        assert_eq!((metrics.unused, metrics.short), (20, 20));
+        // Each off at +100 is undone 900ms later: never a flap
+        assert_eq!(metrics.flaps, 0);
+        assert_eq!(
+            hints(&metrics),
+            [
+                "100% of activations saw no mapped key: consider raising motion_threshold or a stricter activate_when",
+                "100% of activations lasted under 500ms: consider raising motion_threshold",
+            ]
+        );
+        assert!(hints(&play(&idle[..38])).is_empty(), "19 activations");
+
+        let flaps: Vec<_> = (0..20)
+            .flat_map(|i| {
+                [
+                    (i * 1_000, On),
+                    (i * 1_000 + 200, Press),
+                    (i * 1_000 + 800, Off),
+                ]
+            })
+            .collect();
+        let metrics = play(&flaps);
+        assert_eq!(metrics.flaps, 19);
+        assert_eq!(
+            hints(&metrics),
+            ["95% of deactivations were undone within 500ms: consider raising debounce_ms or hysteresis.reactivate_after_ms"]
+        );
+    }
+}
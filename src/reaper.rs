@@ -0,0 +1,244 @@
+//! Child processes that must not outlive us, such as `libinput debug-events`
+//!
+//! `spawn` starts the child in its own process group and hands back a guard.
+//! Dropping the guard, or calling `stop`, sends the group SIGTERM, then
+//! SIGKILL if it is still there after `GRACE`, and reaps the child. That
+//! covers a monitor that returns or panics. At exit, the thread owning a
+//! guard may still be blocked reading from the child, so `stop_all` does the
+//! same for every child still running. The child also gets SIGTERM from the
+//! kernel if we are killed outright (PR_SET_PDEATHSIG).
+
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, ChildStdout, Command, ExitStatus};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a child gets to exit after SIGTERM before it is sent SIGKILL
+const GRACE: Duration = Duration::from_millis(500);
+const POLL: Duration = Duration::from_millis(10);
+
+/// PIDs, and so process groups, of the children whose guards are alive
+static RUNNING: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+pub struct Reaped {
+    child: Child,
+    /// Set once the child was reaped, or by `stop_all`
+    done: bool,
+}
+
+/// Start `command` as the leader of a new process group, guarded
+pub fn spawn(command: &mut Command) -> io::Result<Reaped> {
+    command.process_group(0);
+    // SAFETY: prctl is async-signal-safe and touches no memory of ours
+    unsafe {
+        command.pre_exec(|| {
+            // Sent when the spawning thread exits; the guard lives on that thread
+            if libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let child = command.spawn()?;
+    RUNNING.lock().unwrap().push(child.id());
+    Ok(Reaped { child, done: false })
+}
+
+impl Reaped {
+    pub fn stdout(&mut self) -> Option<ChildStdout> {
+        self.child.stdout.take()
+    }
+
+    /// Stop the child if it hasn't exited by itself, and reap it
+    pub fn stop(mut self) -> io::Result<ExitStatus> {
+        self.terminate()
+    }
+
+    fn terminate(&mut self) -> io::Result<ExitStatus> {
+        self.done = true;
+        let pid = self.child.id();
+        if !forget(pid) {
+            return Err(io::Error::other("already stopped at exit"));
+        }
+        if let Some(status) = self.child.try_wait()? {
+            return Ok(status);
+        }
+        signal(pid, libc::SIGTERM);
+        let deadline = Instant::now() + GRACE;
+        while Instant::now() < deadline {
+            if let Some(status) = self.child.try_wait()? {
+                return Ok(status);
+            }
+            thread::sleep(POLL);
+        }
+        signal(pid, libc::SIGKILL);
+        self.child.wait()
+    }
+}
+
+impl Drop for Reaped {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.terminate();
+        }
+    }
+}
+
+/// Take `pid` off the running list; false if `stop_all` already had it
+fn forget(pid: u32) -> bool {
+    let mut running = RUNNING.lock().unwrap();
+    let before = running.len();
+    running.retain(|&running| running != pid);
+    running.len() < before
+}
+
+/// Signal the process group `pid` leads
+fn signal(pid: u32, signal: libc::c_int) {
+    // SAFETY: kill has no memory-safety preconditions
+    unsafe { libc::kill(-(pid as libc::pid_t), signal) };
+}
+
+/// Whether `pid` has exited, reaping it if so
+fn reap(pid: u32, flags: libc::c_int) -> bool {
+    let mut status = 0;
+    // SAFETY: status is a valid c_int to write into
+    let reaped = unsafe { libc::waitpid(pid as libc::pid_t, &mut status, flags) };
+    // ECHILD: its guard reaped it meanwhile
+    reaped != 0
+}
+
+/// Stop and reap every guarded child still running, for the way out
+pub fn stop_all() {
+    let pids = std::mem::take(&mut *RUNNING.lock().unwrap());
+    if pids.is_empty() {
+        return;
+    }
+    for &pid in &pids {
+        signal(pid, libc::SIGTERM);
+    }
+    let mut left = pids;
+    let deadline = Instant::now() + GRACE;
+    loop {
+        left.retain(|&pid| !reap(pid, libc::WNOHANG));
+        if left.is_empty() || Instant::now() >= deadline {
+            break;
+        }
+        thread::sleep(POLL);
+    }
+    for pid in left {
+        signal(pid, libc::SIGKILL);
+        reap(pid, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    /// The running list is process-wide, and `stop_all` takes all of it
+    static SERIAL: Mutex<()> = Mutex::new(());
+
+    fn serial() -> std::sync::MutexGuard<'static, ()> {
+        SERIAL
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Whether anything still runs in the process group `pid` led
+    ///
+    /// Killed grandchildren are orphans, zombies until init reaps them, and
+    /// don't count.
+    fn group_alive(pid: u32) -> bool {
+        std::fs::read_dir("/proc").unwrap().flatten().any(|entry| {
+            let Ok(stat) = std::fs::read_to_string(entry.path().join("stat")) else {
+                return false;
+            };
+            // After "pid (comm)": state, ppid, pgrp
+            let Some((_, fields)) = stat.rsplit_once(')') else {
+                return false;
+            };
+            let fields: Vec<&str> = fields.split_whitespace().collect();
+            fields.get(2) == Some(&pid.to_string().as_str()) && fields[0] != "Z"
+        })
+    }
+
+    /// Whether the group has stopped within a second
+    fn group_gone(pid: u32) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while group_alive(pid) {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(POLL);
+        }
+        true
+    }
+
+    fn sh(script: &str) -> Reaped {
+        spawn(Command::new("sh").args(["-c", script])).unwrap()
+    }
+
+    #[test]
+    fn dropping_the_guard_ends_the_child() {
+        let _serial = serial();
+        let child = spawn(Command::new("sleep").arg("60")).unwrap();
+        let pid = child.child.id();
+        assert!(group_alive(pid));
+        drop(child);
+        // Reaped, not left a zombie: the pid is gone altogether
+        // SAFETY: signal 0 only checks for existence
+        assert_eq!(unsafe { libc::kill(pid as libc::pid_t, 0) }, -1);
+        assert_eq!(io::Error::last_os_error().raw_os_error(), Some(libc::ESRCH));
+        assert!(!RUNNING.lock().unwrap().contains(&pid));
+    }
+
+    #[test]
+    fn the_whole_group_is_stopped() {
+        let _serial = serial();
+        let child = sh("sleep 60 & sleep 60 & wait");
+        let pid = child.child.id();
+        thread::sleep(Duration::from_millis(50));
+        let status = child.stop().unwrap();
+        assert_eq!(status.signal(), Some(libc::SIGTERM));
+        assert!(group_gone(pid));
+    }
+
+    #[test]
+    fn a_child_ignoring_sigterm_is_killed_after_the_grace() {
+        let _serial = serial();
+        let child = sh("trap '' TERM; sleep 60");
+        thread::sleep(Duration::from_millis(50));
+        let started = Instant::now();
+        let pid = child.child.id();
+        let status = child.stop().unwrap();
+        assert_eq!(status.signal(), Some(libc::SIGKILL));
+        assert!(started.elapsed() >= GRACE);
+        assert!(group_gone(pid));
+    }
+
+    #[test]
+    fn a_child_that_exited_keeps_its_status() {
+        let _serial = serial();
+        let mut child = sh("exit 3");
+        child.child.wait().unwrap();
+        assert_eq!(child.stop().unwrap().code(), Some(3));
+    }
+
+    #[test]
+    fn stop_all_ends_every_child_and_their_guards_let_go() {
+        let _serial = serial();
+        let children = [sh("sleep 60"), sh("trap '' TERM; sleep 60")];
+        let pids: Vec<u32> = children.iter().map(|child| child.child.id()).collect();
+        thread::sleep(Duration::from_millis(50));
+        stop_all();
+        for &pid in &pids {
+            assert!(group_gone(pid), "{}", pid);
+        }
+        for child in children {
+            assert!(child.stop().is_err(), "already stopped at exit");
+        }
+    }
+}
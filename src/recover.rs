@@ -0,0 +1,191 @@
+//! Surviving virtual devices that stop accepting events
+//!
+//! A uinput node can vanish under us (a udev rule removing it, the module
+//! being reloaded), after which every emit fails. `Guarded` wraps a backend's
+//! sink and remembers which keys and buttons it has pressed. The first failed
+//! call marks it broken; that event and any later ones are counted as lost
+//! instead of failing the caller mid-route. The owning loop then calls
+//! `recover`, which builds fresh outputs and presses the held keys again.
+
+use crate::backoff;
+use crate::journal::{Journal, Output};
+use crate::report::{self, Record};
+use crate::sink::EventSink;
+use anyhow::{Error, Result};
+use evdev::{EventType, InputEvent, Key};
+use std::collections::HashSet;
+use std::thread;
+
+/// Rebuilds in a row, without a successful event in between, before giving up
+pub const MAX_ATTEMPTS: u32 = 5;
+
+pub struct Guarded {
+    inner: Box<dyn EventSink>,
+    /// Keys and buttons pressed through it and not yet released
+    keys: HashSet<Key>,
+    buttons: HashSet<Key>,
+    /// The error that broke the outputs, until they are recovered
+    failure: Option<Error>,
+    /// Events dropped since the failure
+    lost: u64,
+    /// Rebuilds since the last event that went out
+    attempts: u32,
+    /// Where synthetic presses and releases are noted for a successor, if anywhere
+    journal: Option<Journal>,
+}
+
+impl Guarded {
+    pub fn new(inner: Box<dyn EventSink>) -> Self {
+        Self {
+            inner,
+            keys: HashSet::new(),
+            buttons: HashSet::new(),
+            failure: None,
+            lost: 0,
+            attempts: 0,
+            journal: None,
+        }
+    }
+
+    /// Note every synthetic press and release from now on in `journal`
+    pub fn keep_journal(&mut self, journal: Journal) {
+        self.journal = Some(journal);
+    }
+
+    /// Whether an event failed since the outputs were last (re)built
+    pub fn failed(&self) -> bool {
+        self.failure.is_some()
+    }
+
+    /// Rebuild the outputs with `create` and press again what was held
+    ///
+    /// Gives up with the original error after `MAX_ATTEMPTS` rebuilds in a row
+    /// that failed or were followed by another failure.
+    pub fn recover(
+        &mut self,
+        label: &str,
+        mut create: impl FnMut() -> Result<Box<dyn EventSink>>,
+    ) -> Result<()> {
+        let Some(failure) = self.failure.take() else {
+            return Ok(());
+        };
+        eprintln!("⚠️  {} output failed: {:#}; recreating it", label, failure);
+        let mut delay = backoff::MIN_DELAY;
+        while self.attempts < MAX_ATTEMPTS {
+            self.attempts += 1;
+            match create().and_then(|inner| {
+                self.inner = inner;
+                self.replay()
+            }) {
+                Ok(replayed) => {
+                    eprintln!(
+                        "🩹 Recreated {} output: {} event(s) lost, {} held key(s) replayed",
+                        label, self.lost, replayed
+                    );
+                    report::emit(Record::OutputRecovered {
+                        device: label.to_string(),
+                        lost: self.lost,
+                        replayed,
+                    });
+                    self.lost = 0;
+                    return Ok(());
+                }
+                Err(err) => eprintln!(
+                    "   Attempt {}/{} failed: {:#}",
+                    self.attempts, MAX_ATTEMPTS, err
+                ),
+            }
+            thread::sleep(delay);
+            delay *= 2;
+        }
+        Err(failure.context(format!(
+            "Gave up recreating the {} output after {} attempts ({} event(s) lost)",
+            label, MAX_ATTEMPTS, self.lost
+        )))
+    }
+
+    /// Press every held key and button on fresh outputs; how many there were
+    fn replay(&mut self) -> Result<usize> {
+        for &key in &self.keys {
+            self.inner.key(key, 1)?;
+        }
+        for &button in &self.buttons {
+            self.inner.button(button, 1)?;
+        }
+        Ok(self.keys.len() + self.buttons.len())
+    }
+
+    fn note(&mut self, output: Output, key: Key, value: i32) {
+        if let Some(journal) = &mut self.journal {
+            journal.record(output, key, value);
+        }
+    }
+
+    fn send(&mut self, call: impl FnOnce(&mut dyn EventSink) -> Result<()>) -> Result<()> {
+        if self.failure.is_some() {
+            self.lost += 1;
+            return Ok(());
+        }
+        match call(self.inner.as_mut()) {
+            Ok(()) => self.attempts = 0,
+            Err(err) => {
+                self.lost += 1;
+                self.failure = Some(err);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Held state follows what was asked for, sent or not, so a replay restores it
+fn track(held: &mut HashSet<Key>, key: Key, value: i32) {
+    match value {
+        0 => {
+            held.remove(&key);
+        }
+        1 => {
+            held.insert(key);
+        }
+        _ => {}
+    }
+}
+
+impl EventSink for Guarded {
+    fn forward(&mut self, event: InputEvent) -> Result<()> {
+        if event.event_type() == EventType::KEY {
+            let key = Key::new(event.code());
+            // Typed input, so never journaled
+            track(&mut self.keys, key, event.value());
+        }
+        self.send(|inner| inner.forward(event))
+    }
+
+    fn key(&mut self, key: Key, value: i32) -> Result<()> {
+        track(&mut self.keys, key, value);
+        self.note(Output::Key, key, value);
+        self.send(|inner| inner.key(key, value))
+    }
+
+    fn button(&mut self, button: Key, value: i32) -> Result<()> {
+        track(&mut self.buttons, button, value);
+        self.note(Output::Button, button, value);
+        self.send(|inner| inner.button(button, value))
+    }
+
+    fn wheel(&mut self, hi_res: i32, legacy: i32) -> Result<()> {
+        self.send(|inner| inner.wheel(hi_res, legacy))
+    }
+
+    fn warp(&mut self, x: i32, y: i32) -> Result<()> {
+        self.send(|inner| inner.warp(x, y))
+    }
+
+    /// Not an output event, so a broken output doesn't stop it
+    fn command(&mut self, command: &str, env: &[(&str, String)]) -> Result<()> {
+        self.inner.command(command, env)
+    }
+
+    fn devices(&self) -> Vec<String> {
+        self.inner.devices()
+    }
+}
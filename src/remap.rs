@@ -0,0 +1,1424 @@
+use crate::config::{
+    self, ClickTarget, Config, ExternalMouse, MappingTable, ReleaseTarget, Target, TurboTarget,
+    Variant, WheelTarget, MIN_REPEAT_INTERVAL_MS,
+};
+use crate::keys;
+use crate::modifiers;
+use crate::motion::LastMotion;
+use crate::quality;
+use crate::report::{self, Record};
+use crate::script::{Decision, Input, ScriptHook};
+use crate::scroll::{release_velocity, Kinetic, WheelAccumulator};
+use crate::sink::EventSink;
+use crate::warp::{Outcome, Warp};
+use anyhow::Result;
+use evdev::{AttributeSetRef, EventType, InputEvent, InputEventKind, Key};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How a pressed key was routed, so its repeats and release follow the press
+enum Route {
+    Button(Key),
+    /// Typed as another key, which repeats with it
+    Key(Key),
+    /// Nothing sent yet; the click goes out on release
+    OnRelease {
+        target: ReleaseTarget,
+        pressed_at: Instant,
+    },
+    Turbo {
+        target: TurboTarget,
+        /// Whether the current click's press has gone out without its release
+        down: bool,
+        clicks: u32,
+        /// Next press or release; None once finished (max_clicks or mode off)
+        next: Option<Instant>,
+    },
+    Wheel {
+        target: WheelTarget,
+        pressed_at: Instant,
+        last_step: Instant,
+        next_step: Instant,
+        steps: u32,
+        /// Accelerated repeats: steps emitted so far, possibly fractional
+        repeated: f64,
+    },
+    /// Dropped by the script: repeats and the release are dropped too
+    Swallowed,
+    /// Started a click sequence, which finishes on its own; the release is dropped
+    Clicks,
+}
+
+impl Route {
+    /// Whether this is what `target` would route a press to, so it can stay held
+    fn follows(&self, target: Option<Target>) -> bool {
+        match (self, target) {
+            (Route::Button(button), Some(Target::Button(new)))
+            | (Route::Key(button), Some(Target::Key(new))) => *button == new,
+            (Route::OnRelease { target, .. }, Some(Target::OnRelease(new))) => *target == new,
+            (Route::Turbo { target, .. }, Some(Target::Turbo(new))) => *target == new,
+            (Route::Wheel { target, .. }, Some(Target::Wheel(new))) => *target == new,
+            // Holding nothing
+            (Route::Swallowed | Route::Clicks, _) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether this keeps `button` down right now
+    fn holds(&self, button: Key) -> bool {
+        match self {
+            Route::Button(held) => *held == button,
+            Route::Turbo {
+                target, down: true, ..
+            } => target.button == button,
+            _ => false,
+        }
+    }
+}
+
+/// A double/triple click in flight: the remaining frames, `gap` apart
+struct Sequence {
+    /// The key that started it
+    key: Key,
+    button: Key,
+    down: bool,
+    /// Press/release frames still to emit
+    frames: u32,
+    gap: Duration,
+    next: Instant,
+}
+
+/// Button changes kept for the warning when one is forced up
+const HISTORY: usize = 16;
+
+/// How soon a button past `watchdog.max_button_hold_ms` is checked again
+/// while one of its keys is still down, e.g. during a long drag
+const HOLD_RECHECK: Duration = Duration::from_secs(1);
+
+/// One press or release of an output button, for the routing history
+struct Step {
+    at: Instant,
+    /// The mapped key behind it
+    key: Key,
+    button: Key,
+    value: i32,
+    /// Presses holding the button afterwards
+    count: u32,
+}
+
+struct HeldButton {
+    count: u32,
+    since: Instant,
+    /// When the limit comes up for a check; None without one
+    check: Option<Instant>,
+}
+
+/// Output buttons held down, by how many presses hold each
+///
+/// Several keys may map to one button: it goes down with the first of them
+/// and up with the last, so letting go of one doesn't end another's drag.
+struct Buttons {
+    held: HashMap<Key, HeldButton>,
+    max_hold: Option<Duration>,
+    history: VecDeque<Step>,
+}
+
+impl Buttons {
+    fn new(max_hold: Option<Duration>) -> Self {
+        Self {
+            held: HashMap::new(),
+            max_hold,
+            history: VecDeque::with_capacity(HISTORY),
+        }
+    }
+
+    fn press(
+        &mut self,
+        key: Key,
+        button: Key,
+        now: Instant,
+        out: &mut dyn EventSink,
+    ) -> Result<()> {
+        let held = self.held.entry(button).or_insert(HeldButton {
+            count: 0,
+            since: now,
+            check: self.max_hold.map(|max| now + max),
+        });
+        held.count += 1;
+        let count = held.count;
+        self.record(key, button, 1, count, now);
+        if count == 1 {
+            out.button(button, 1)?;
+        }
+        Ok(())
+    }
+
+    /// A release of a button nothing holds still goes out; it changes nothing
+    fn release(
+        &mut self,
+        key: Key,
+        button: Key,
+        now: Instant,
+        out: &mut dyn EventSink,
+    ) -> Result<()> {
+        let count = match self.held.get_mut(&button) {
+            Some(held) if held.count > 1 => {
+                held.count -= 1;
+                held.count
+            }
+            Some(_) => {
+                self.held.remove(&button);
+                0
+            }
+            None => 0,
+        };
+        self.record(key, button, 0, count, now);
+        if count == 0 {
+            out.button(button, 0)?;
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, key: Key, button: Key, value: i32, count: u32, at: Instant) {
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(Step {
+            at,
+            key,
+            button,
+            value,
+            count,
+        });
+    }
+
+    fn next_check(&self) -> Option<Instant> {
+        self.held.values().filter_map(|held| held.check).min()
+    }
+
+    fn release_all(&mut self, out: &mut dyn EventSink) -> Result<()> {
+        for (button, _) in self.held.drain() {
+            out.button(button, 0)?;
+        }
+        Ok(())
+    }
+}
+
+/// A press held back until it outlasts its key's minimum press duration
+struct Deferred {
+    key: Key,
+    press: InputEvent,
+    active: bool,
+    pressed_at: Instant,
+    until: Instant,
+    /// Everything that arrived meanwhile, replayed in order once this resolves
+    backlog: Vec<(InputEvent, bool, Instant)>,
+}
+
+/// Routing state for a grabbed device, the keyboard or the external mouse
+pub struct Remapper {
+    mappings: HashMap<Key, Target>,
+    /// Mapped keys whose target depends on the modifiers being passed through
+    conditional: HashMap<Key, Vec<Variant>>,
+    /// Tables over the two above by finger count, and the one in effect (0: none)
+    layers: BTreeMap<usize, MappingTable>,
+    layer: usize,
+    /// Mapped keys currently held, by press-time route
+    routes: HashMap<Key, Route>,
+    /// Mapped keys released but held back for their chatter window, with its end
+    releasing: HashMap<Key, (Route, Instant)>,
+    chatter: HashMap<Key, Duration>,
+    min_press: HashMap<Key, Duration>,
+    /// Per-key typing guard windows, and the one click targets get otherwise
+    after_letter: HashMap<Key, Duration>,
+    after_letter_default: Duration,
+    /// When a letter last went out as typed
+    last_letter: Option<Instant>,
+    deferred: Option<Deferred>,
+    /// Keys passed through as pressed and not yet released
+    forwarded: HashSet<Key>,
+    /// Click sequences, at most one per button
+    sequences: Vec<Sequence>,
+    /// Every button press and release from routing goes through this
+    buttons: Buttons,
+    kinetic: Kinetic,
+    /// The vertical wheel axis, shared by every scroll key and the inertia
+    wheel: WheelAccumulator,
+    /// The mode as of the last event or tick, to notice it turning off
+    active: bool,
+    /// Grid warping, ahead of the mappings and the script
+    warp: Option<Warp>,
+    /// When each key's `command` last ran, for its cooldown
+    command_runs: HashMap<Key, Instant>,
+    hook: Option<ScriptHook>,
+    last_motion: Arc<LastMotion>,
+}
+
+impl Remapper {
+    pub fn new(config: &Config, last_motion: Arc<LastMotion>) -> Result<Self> {
+        let hook = ScriptHook::load(
+            &config.script,
+            config.mouse_buttons(),
+            Duration::from_millis(config.scroll.repeat_delay_ms),
+            Duration::from_millis(config.scroll.repeat_interval_ms),
+            config
+                .scroll
+                .ramp(Duration::from_millis(config.scroll.repeat_interval_ms)),
+            config.scroll.step,
+            config.scroll.natural,
+        )?;
+        Ok(Self {
+            mappings: config.mappings.clone(),
+            conditional: config.conditional.clone(),
+            layers: config.finger_layers.clone(),
+            layer: 0,
+            routes: HashMap::new(),
+            releasing: HashMap::new(),
+            chatter: config.chatter.clone(),
+            min_press: config.min_press.clone(),
+            after_letter: config.after_letter.clone(),
+            after_letter_default: Duration::from_millis(config.typing.after_letter_ms),
+            last_letter: None,
+            deferred: None,
+            forwarded: HashSet::new(),
+            sequences: Vec::new(),
+            buttons: Buttons::new(config.watchdog.max_button_hold()),
+            kinetic: Kinetic::new(config.scroll.kinetic.clone()),
+            wheel: WheelAccumulator::default(),
+            active: false,
+            warp: config.warp.map(Warp::new),
+            command_runs: HashMap::new(),
+            hook,
+            last_motion,
+        })
+    }
+
+    /// Routing for the external mouse: its own mappings and no script
+    pub fn for_mouse(config: &Config, mouse: &ExternalMouse) -> Self {
+        Self {
+            mappings: mouse.mappings.clone(),
+            conditional: HashMap::new(),
+            layers: BTreeMap::new(),
+            layer: 0,
+            routes: HashMap::new(),
+            releasing: HashMap::new(),
+            chatter: mouse.chatter.clone(),
+            min_press: mouse.min_press.clone(),
+            after_letter: HashMap::new(),
+            after_letter_default: Duration::ZERO,
+            last_letter: None,
+            deferred: None,
+            forwarded: HashSet::new(),
+            sequences: Vec::new(),
+            buttons: Buttons::new(config.watchdog.max_button_hold()),
+            kinetic: Kinetic::new(config.scroll.kinetic.clone()),
+            wheel: WheelAccumulator::default(),
+            active: false,
+            warp: None,
+            command_runs: HashMap::new(),
+            hook: None,
+            last_motion: Arc::new(LastMotion::new()),
+        }
+    }
+
+    pub fn is_mapped(&self, key: Key) -> bool {
+        self.mappings.contains_key(&key)
+            || self.conditional.contains_key(&key)
+            || self.layers.values().any(|layer| layer.maps(key))
+    }
+
+    /// Replace `key`'s entry in the base table (`ctl map`), or drop it (`ctl unmap`)
+    ///
+    /// A held key keeps its press-time route; the change applies from its next press.
+    pub fn set_mapping(&mut self, key: Key, mapping: Option<(Target, Option<Duration>)>) {
+        self.conditional.remove(&key);
+        self.min_press.remove(&key);
+        self.after_letter.remove(&key);
+        self.chatter.remove(&key);
+        match mapping {
+            Some((target, chatter)) => {
+                self.mappings.insert(key, target);
+                if let Some(window) = chatter {
+                    self.chatter.insert(key, window);
+                }
+            }
+            None => {
+                self.mappings.remove(&key);
+            }
+        }
+    }
+
+    /// `key`'s target in the current layer under the modifiers held right now;
+    /// None passes it through
+    fn target(&self, key: Key) -> Option<Target> {
+        let (mappings, conditional) = match self.layers.get(&self.layer).filter(|l| l.maps(key)) {
+            Some(layer) => (&layer.mappings, &layer.conditional),
+            None => (&self.mappings, &self.conditional),
+        };
+        let Some(variants) = conditional.get(&key) else {
+            return mappings.get(&key).copied();
+        };
+        let state = modifiers::state(&self.forwarded);
+        modifiers::select(variants.iter().map(|variant| variant.when), state)
+            .map(|i| variants[i].target)
+    }
+
+    /// Switch to the finger layer for `fingers` contacts on the touchpad
+    ///
+    /// Held keys keep their press-time route, but what one holds stays down
+    /// only if the new layer gives the key the same target. Otherwise it is let
+    /// go now, and the key's repeats and release are dropped.
+    pub fn set_fingers(
+        &mut self,
+        fingers: usize,
+        now: Instant,
+        out: &mut dyn EventSink,
+    ) -> Result<()> {
+        let layer = Some(fingers.min(3))
+            .filter(|layer| self.layers.contains_key(layer))
+            .unwrap_or(0);
+        if layer == self.layer {
+            return Ok(());
+        }
+        self.layer = layer;
+        let profile = match layer {
+            0 => "[mappings]".to_string(),
+            layer => format!("finger_layers.{}", config::layer_name(layer)),
+        };
+        eprintln!("🖐️  {} finger(s) - {}", fingers, profile);
+        report::emit(Record::ProfileChange {
+            profile,
+            reason: "fingers".into(),
+        });
+        let held: Vec<Key> = self.routes.keys().copied().collect();
+        for key in held {
+            if self.routes[&key].follows(self.target(key)) {
+                continue;
+            }
+            let route = self.routes.insert(key, Route::Swallowed);
+            self.let_go(key, route.expect("key just listed"), now, out)?;
+        }
+        // Already up, only waiting out their chatter window
+        let released: Vec<Key> = self
+            .releasing
+            .iter()
+            .filter(|(&key, (route, _))| !route.follows(self.target(key)))
+            .map(|(&key, _)| key)
+            .collect();
+        for key in released {
+            let (route, _) = self.releasing.remove(&key).expect("key just listed");
+            self.release(key, route, now, out)?;
+        }
+        Ok(())
+    }
+
+    /// End what a held key's route holds, without what its release would send
+    fn let_go(
+        &mut self,
+        key: Key,
+        route: Route,
+        now: Instant,
+        out: &mut dyn EventSink,
+    ) -> Result<()> {
+        eprintln!("{:?} let go: the layer changed under it", key);
+        match route {
+            Route::Button(button) => {
+                remapped(key, format_args!("{:?}", button), 0);
+                self.buttons.release(key, button, now, out)?;
+            }
+            Route::Key(target) => {
+                remapped(key, format_args!("{:?}", target), 0);
+                out.key(target, 0)?;
+            }
+            Route::Turbo { target, down, .. } => {
+                remapped(key, format_args!("turbo {:?}", target.button), 0);
+                if down {
+                    self.buttons.release(key, target.button, now, out)?;
+                }
+            }
+            // No click on release and no inertia
+            Route::Wheel { target, .. } => {
+                remapped(key, format_args!("wheel {:+}", target.detents), 0)
+            }
+            Route::OnRelease { .. } | Route::Swallowed | Route::Clicks => {}
+        }
+        Ok(())
+    }
+
+    /// Whether a key is holding a mouse button down right now
+    pub fn holding_button(&self) -> bool {
+        !self.sequences.is_empty()
+            || self
+                .routes
+                .values()
+                .chain(self.releasing.values().map(|(route, _)| route))
+                .any(|route| matches!(route, Route::Button(_) | Route::Turbo { .. }))
+    }
+
+    /// When the loop must wake up next even without input
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let repeat = self
+            .routes
+            .values()
+            .filter_map(|route| match route {
+                Route::Wheel { next_step, .. } => Some(*next_step),
+                Route::Turbo { next, .. } => *next,
+                Route::Button(_)
+                | Route::Key(_)
+                | Route::OnRelease { .. }
+                | Route::Swallowed
+                | Route::Clicks => None,
+            })
+            .min();
+        let release = self.releasing.values().map(|&(_, at)| at).min();
+        let clicks = self.sequences.iter().map(|sequence| sequence.next).min();
+        let deferred = self.deferred.as_ref().map(|deferred| deferred.until);
+        [
+            self.kinetic.next_deadline(),
+            repeat,
+            release,
+            clicks,
+            deferred,
+            self.buttons.next_check(),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+
+    /// Run timer-driven output: kinetic inertia, scroll repeat, turbo and click sequences
+    ///
+    /// Turbo stops as soon as this sees the mode inactive, at most half a
+    /// click interval after it turned off.
+    pub fn tick(&mut self, now: Instant, active: bool, out: &mut dyn EventSink) -> Result<()> {
+        self.track_mode(active);
+        self.commit_due(now, out)?;
+
+        // Chatter windows that ran out without a re-press: the release was real
+        while let Some(key) = self
+            .releasing
+            .iter()
+            .find(|(_, &(_, at))| at <= now)
+            .map(|(&key, _)| key)
+        {
+            let (route, _) = self.releasing.remove(&key).expect("key just found");
+            self.release(key, route, now, out)?;
+        }
+
+        // Sequences ignore the mode: once started they always complete
+        for sequence in &mut self.sequences {
+            if sequence.next <= now {
+                sequence.down = !sequence.down;
+                if sequence.down {
+                    self.buttons
+                        .press(sequence.key, sequence.button, now, out)?;
+                } else {
+                    self.buttons
+                        .release(sequence.key, sequence.button, now, out)?;
+                }
+                sequence.frames -= 1;
+                sequence.next = (sequence.next + sequence.gap).max(now);
+            }
+        }
+        self.sequences.retain(|sequence| sequence.frames > 0);
+
+        if let Some(hi_res) = self.kinetic.poll(now) {
+            let (hi_res, legacy) = self.wheel.add(hi_res);
+            out.wheel(hi_res, legacy)?;
+        }
+
+        // Scroll repeat runs on our own timers, independent of keyboard autorepeat
+        for (&key, route) in self.routes.iter_mut() {
+            match route {
+                Route::Wheel {
+                    target,
+                    pressed_at,
+                    last_step,
+                    next_step,
+                    steps,
+                    repeated,
+                } if *next_step <= now => {
+                    *last_step = now;
+                    *steps += 1;
+                    let Some(ramp) = target.acceleration else {
+                        emit_step(&mut self.wheel, out, f64::from(target.hi_res()))?;
+                        // Skip missed steps after a stall rather than bursting to catch up
+                        *next_step = (*next_step + target.repeat_interval).max(now);
+                        continue;
+                    };
+                    // The first repeat is one whole step, then the ramp's integral
+                    let into = now.saturating_duration_since(*pressed_at + target.repeat_delay);
+                    let due = 1.0 + ramp.steps(into) - *repeated;
+                    *repeated += due;
+                    emit_step(&mut self.wheel, out, due * f64::from(target.hi_res()))?;
+                    // Past 1/MIN_REPEAT_INTERVAL_MS, steps grow instead of coming faster
+                    let interval = Duration::from_secs_f64(1.0 / ramp.rate(into))
+                        .max(Duration::from_millis(MIN_REPEAT_INTERVAL_MS));
+                    *next_step = now + interval;
+                }
+                Route::Turbo {
+                    target,
+                    down,
+                    clicks,
+                    next,
+                } => {
+                    let Some(at) = *next else { continue };
+                    // Mode went off: finish the click in flight and stop
+                    if !active {
+                        if *down {
+                            self.buttons.release(key, target.button, now, out)?;
+                            *down = false;
+                        }
+                        *next = None;
+                        continue;
+                    }
+                    if at > now {
+                        continue;
+                    }
+                    if *down {
+                        self.buttons.release(key, target.button, now, out)?;
+                        *down = false;
+                        if target.max_clicks.is_some_and(|max| *clicks >= max) {
+                            *next = None;
+                            continue;
+                        }
+                    } else {
+                        self.buttons.press(key, target.button, now, out)?;
+                        *down = true;
+                        *clicks += 1;
+                    }
+                    *next = Some((at + target.interval / 2).max(now));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Route one physical keyboard event that arrived at `now`
+    ///
+    /// While a press is deferred for its minimum duration, later events queue
+    /// behind it so the output order matches the input order.
+    pub fn handle(
+        &mut self,
+        event: InputEvent,
+        active: bool,
+        now: Instant,
+        out: &mut dyn EventSink,
+    ) -> Result<()> {
+        self.track_mode(active);
+        self.commit_due(now, out)?;
+
+        if let Some(deferred) = &mut self.deferred {
+            if event.kind() != InputEventKind::Key(deferred.key) {
+                deferred.backlog.push((event, active, now));
+                return Ok(());
+            }
+            if event.value() != 0 {
+                return Ok(());
+            }
+            // Released before the threshold: the press never happened
+            let deferred = self.deferred.take().expect("deferred just matched");
+            eprintln!(
+                "{:?} dropped, pressed only {}ms",
+                deferred.key,
+                (now - deferred.pressed_at).as_millis()
+            );
+            return self.replay(deferred.backlog, out);
+        }
+
+        if let InputEventKind::Key(key) = event.kind() {
+            if let Some(&min) = self.min_press.get(&key) {
+                if event.value() == 1 && active && !self.releasing.contains_key(&key) {
+                    self.deferred = Some(Deferred {
+                        key,
+                        press: event,
+                        active,
+                        pressed_at: now,
+                        until: now + min,
+                        backlog: Vec::new(),
+                    });
+                    return Ok(());
+                }
+            }
+        }
+
+        self.route(event, active, now, out)
+    }
+
+    /// The deferred press lasted long enough: route it as of when it happened
+    fn commit_due(&mut self, now: Instant, out: &mut dyn EventSink) -> Result<()> {
+        // Replaying the backlog can defer another press that is already due
+        while let Some(deferred) = self.deferred.take_if(|deferred| deferred.until <= now) {
+            self.route(deferred.press, deferred.active, deferred.pressed_at, out)?;
+            self.replay(deferred.backlog, out)?;
+        }
+        Ok(())
+    }
+
+    fn replay(
+        &mut self,
+        backlog: Vec<(InputEvent, bool, Instant)>,
+        out: &mut dyn EventSink,
+    ) -> Result<()> {
+        for (event, active, at) in backlog {
+            self.handle(event, active, at, out)?;
+        }
+        Ok(())
+    }
+
+    fn route(
+        &mut self,
+        event: InputEvent,
+        active: bool,
+        now: Instant,
+        out: &mut dyn EventSink,
+    ) -> Result<()> {
+        let InputEventKind::Key(key) = event.kind() else {
+            return out.forward(event);
+        };
+
+        if let Some(warp) = &mut self.warp {
+            match warp.handle(key, event.value(), active) {
+                Outcome::Pass => {}
+                Outcome::Swallow => return Ok(()),
+                Outcome::Jump(x, y) => {
+                    eprintln!("🎯 {:?} → warp to {}, {}", key, x, y);
+                    return out.warp(x, y);
+                }
+            }
+        }
+
+        match event.value() {
+            // Press: route by the script, else by the mapping table while the mode is active
+            1 => {
+                // Re-pressed inside the chatter window: a bounce, so the press never ended
+                if let Some((route, _)) = self.releasing.remove(&key) {
+                    eprintln!("{:?} chatter suppressed", key);
+                    self.routes.insert(key, route);
+                    return Ok(());
+                }
+                let decision = match &mut self.hook {
+                    Some(hook) => hook.decide(Input {
+                        key,
+                        value: event.value(),
+                        active,
+                        held: &self.forwarded,
+                        since_motion: self.last_motion.elapsed(now),
+                    }),
+                    None => Decision::Default,
+                };
+                let target = match decision {
+                    Decision::Default => self
+                        .target(key)
+                        .filter(|_| active)
+                        .filter(|&target| !self.mid_word(key, target, now)),
+                    Decision::Map(target) => Some(target),
+                    Decision::Forward => None,
+                    Decision::Swallow => {
+                        self.routes.insert(key, Route::Swallowed);
+                        return Ok(());
+                    }
+                };
+                match target {
+                    Some(Target::Button(button)) => {
+                        self.kinetic.cancel();
+                        eprintln!("{:?} → {:?}", key, button);
+                        remapped(key, format_args!("{:?}", button), 1);
+                        self.buttons.press(key, button, now, out)?;
+                        self.routes.insert(key, Route::Button(button));
+                    }
+                    Some(Target::Key(target)) => {
+                        eprintln!("{:?} → {:?}", key, target);
+                        remapped(key, format_args!("{:?}", target), 1);
+                        out.key(target, 1)?;
+                        self.routes.insert(key, Route::Key(target));
+                    }
+                    Some(Target::OnRelease(target)) => {
+                        self.kinetic.cancel();
+                        self.routes.insert(
+                            key,
+                            Route::OnRelease {
+                                target,
+                                pressed_at: now,
+                            },
+                        );
+                    }
+                    Some(Target::Turbo(target)) => {
+                        self.kinetic.cancel();
+                        eprintln!("{:?} → {:?} (turbo)", key, target.button);
+                        remapped(key, format_args!("turbo {:?}", target.button), 1);
+                        self.buttons.press(key, target.button, now, out)?;
+                        self.routes.insert(
+                            key,
+                            Route::Turbo {
+                                target,
+                                down: true,
+                                clicks: 1,
+                                next: Some(now + target.interval / 2),
+                            },
+                        );
+                    }
+                    Some(Target::Clicks(target)) => {
+                        self.kinetic.cancel();
+                        eprintln!("{:?} → {:?} ×{}", key, target.button, target.count);
+                        remapped(
+                            key,
+                            format_args!("{:?} x{}", target.button, target.count),
+                            1,
+                        );
+                        self.start_clicks(key, target, now, out)?;
+                        self.routes.insert(key, Route::Clicks);
+                    }
+                    Some(Target::Command(target)) => {
+                        // Swallowed either way; repeats and the release follow the route
+                        self.routes.insert(key, Route::Swallowed);
+                        let last = self.command_runs.get(&key);
+                        if last
+                            .is_some_and(|&at| now.saturating_duration_since(at) < target.cooldown)
+                        {
+                            eprintln!("{:?} → command skipped (cooldown)", key);
+                            return Ok(());
+                        }
+                        self.command_runs.insert(key, now);
+                        eprintln!("{:?} → command: {}", key, target.command);
+                        remapped(key, format_args!("command {}", target.command), 1);
+                        let since_motion = self.last_motion.elapsed(now);
+                        out.command(
+                            target.command,
+                            &[
+                                ("TOUCH_LAYER_KEY", format!("{:?}", key)),
+                                (
+                                    "TOUCH_LAYER_STATE",
+                                    if active { "active" } else { "inactive" }.to_string(),
+                                ),
+                                (
+                                    "TOUCH_LAYER_SINCE_MOTION_MS",
+                                    since_motion.map_or(String::new(), |elapsed| {
+                                        elapsed.as_millis().to_string()
+                                    }),
+                                ),
+                            ],
+                        )?;
+                    }
+                    Some(Target::Wheel(target)) => {
+                        self.kinetic.cancel();
+                        remapped(key, format_args!("wheel {:+}", target.detents), 1);
+                        emit_step(&mut self.wheel, out, f64::from(target.hi_res()))?;
+                        self.routes.insert(
+                            key,
+                            Route::Wheel {
+                                target,
+                                pressed_at: now,
+                                last_step: now,
+                                next_step: now + target.repeat_delay,
+                                steps: 1,
+                                repeated: 0.0,
+                            },
+                        );
+                    }
+                    None => {
+                        if keys::is_letter(key) {
+                            self.last_letter = Some(now);
+                        } else if key == Key::KEY_ESC || modifiers::is_modifier(key) {
+                            // The word is over: a command or a modified click follows
+                            self.last_letter = None;
+                        }
+                        self.forwarded.insert(key);
+                        forwarded(key, 1);
+                        out.forward(event)?;
+                    }
+                }
+            }
+            // Repeat: mapped keys repeat on our timers (or not at all), key targets as typed
+            2 => match self.routes.get(&key) {
+                Some(&Route::Key(target)) => out.key(target, 2)?,
+                Some(_) => {}
+                None if self.releasing.contains_key(&key) => {}
+                None => {
+                    forwarded(key, 2);
+                    out.forward(event)?;
+                }
+            },
+            // Release: goes wherever the press went, even if the mode changed since
+            _ => match self.routes.remove(&key) {
+                Some(route @ (Route::Button(_) | Route::Key(_) | Route::Wheel { .. }))
+                    if self.chatter.contains_key(&key) =>
+                {
+                    self.releasing
+                        .insert(key, (route, now + self.chatter[&key]));
+                }
+                Some(route) => self.release(key, route, now, out)?,
+                None => {
+                    self.forwarded.remove(&key);
+                    forwarded(key, event.value());
+                    out.forward(event)?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Whether `key` comes too soon after a typed letter to be anything but
+    /// typing; click targets use typing.after_letter_ms unless the key sets its own
+    fn mid_word(&self, key: Key, target: Target, now: Instant) -> bool {
+        let window = match self.after_letter.get(&key) {
+            Some(&window) => window,
+            None if target.button().is_some() => self.after_letter_default,
+            None => Duration::ZERO,
+        };
+        let Some(since) = self
+            .last_letter
+            .map(|at| now.saturating_duration_since(at))
+            .filter(|&since| since < window)
+        else {
+            return false;
+        };
+        eprintln!("{:?} typed: {}ms after a letter", key, since.as_millis());
+        true
+    }
+
+    /// End a mapped press
+    fn release(
+        &mut self,
+        key: Key,
+        route: Route,
+        now: Instant,
+        out: &mut dyn EventSink,
+    ) -> Result<()> {
+        match route {
+            Route::Button(button) => {
+                remapped(key, format_args!("{:?}", button), 0);
+                self.buttons.release(key, button, now, out)?;
+            }
+            Route::Key(target) => {
+                remapped(key, format_args!("{:?}", target), 0);
+                out.key(target, 0)?;
+            }
+            Route::OnRelease { target, pressed_at } => {
+                let held = now - pressed_at;
+                if target.max_hold.is_some_and(|max| held > max) {
+                    eprintln!("{:?} held {}ms, click cancelled", key, held.as_millis());
+                    return Ok(());
+                }
+                eprintln!("{:?} → {:?} (on release)", key, target.button);
+                remapped(key, format_args!("{:?}", target.button), 1);
+                self.buttons.press(key, target.button, now, out)?;
+                remapped(key, format_args!("{:?}", target.button), 0);
+                self.buttons.release(key, target.button, now, out)?;
+            }
+            Route::Turbo { target, down, .. } => {
+                remapped(key, format_args!("turbo {:?}", target.button), 0);
+                if down {
+                    self.buttons.release(key, target.button, now, out)?;
+                }
+            }
+            Route::Wheel {
+                target,
+                pressed_at,
+                last_step,
+                steps,
+                ..
+            } => {
+                remapped(key, format_args!("wheel {:+}", target.detents), 0);
+                // Accelerated keys hand over the rate they had reached, not the average
+                let velocity = match target.acceleration {
+                    Some(ramp) if steps > 1 => {
+                        let into =
+                            last_step.saturating_duration_since(pressed_at + target.repeat_delay);
+                        Some(ramp.rate(into) * target.distance())
+                    }
+                    _ => release_velocity(target.distance(), steps, last_step - pressed_at),
+                };
+                self.kinetic.release(velocity, now);
+            }
+            Route::Swallowed | Route::Clicks => {}
+        }
+        Ok(())
+    }
+
+    /// Press now and schedule the rest; a sequence already running on the
+    /// button is extended instead, so the two never interleave
+    fn start_clicks(
+        &mut self,
+        key: Key,
+        target: ClickTarget,
+        now: Instant,
+        out: &mut dyn EventSink,
+    ) -> Result<()> {
+        let frames = 2 * target.count;
+        if let Some(sequence) = self
+            .sequences
+            .iter_mut()
+            .find(|sequence| sequence.button == target.button)
+        {
+            sequence.frames += frames;
+            return Ok(());
+        }
+        self.buttons.press(key, target.button, now, out)?;
+        self.sequences.push(Sequence {
+            key,
+            button: target.button,
+            down: true,
+            frames: frames - 1,
+            gap: target.gap,
+            next: now + target.gap,
+        });
+        Ok(())
+    }
+
+    /// Reconcile with the keys the kernel says are down after a SYN_DROPPED
+    ///
+    /// Keys we hold that are no longer down get their release now; presses we
+    /// missed are left alone rather than replayed. Returns how many were released.
+    pub fn resync(
+        &mut self,
+        pressed: &AttributeSetRef<Key>,
+        now: Instant,
+        out: &mut dyn EventSink,
+    ) -> Result<usize> {
+        // A deferred press is real if the key is still down, else a dropped graze
+        if let Some(deferred) = self.deferred.take() {
+            if pressed.contains(deferred.key) {
+                self.route(deferred.press, deferred.active, deferred.pressed_at, out)?;
+            }
+            self.replay(deferred.backlog, out)?;
+        }
+        let stale: Vec<Key> = self
+            .routes
+            .keys()
+            .chain(self.forwarded.iter())
+            .copied()
+            .filter(|&key| !pressed.contains(key))
+            .collect();
+        for &key in &stale {
+            let release = InputEvent::new(EventType::KEY, key.code(), 0);
+            self.handle(release, false, now, out)?;
+        }
+        Ok(stale.len())
+    }
+
+    /// Whether a button has been down for `watchdog.max_button_hold_ms` and
+    /// `expire_holds` should look at it, with the device's key state
+    pub fn hold_check_due(&self, now: Instant) -> bool {
+        self.buttons.next_check().is_some_and(|at| at <= now)
+    }
+
+    /// Force up each button past the hold limit that no key still down holds
+    ///
+    /// `pressed` is the device's key state, so a long drag with its key held
+    /// is left alone and looked at again in `HOLD_RECHECK`. Returns how many
+    /// buttons were forced up.
+    pub fn expire_holds(
+        &mut self,
+        pressed: &AttributeSetRef<Key>,
+        now: Instant,
+        out: &mut dyn EventSink,
+    ) -> Result<usize> {
+        let due: Vec<Key> = self
+            .buttons
+            .held
+            .iter()
+            .filter(|(_, held)| held.check.is_some_and(|at| at <= now))
+            .map(|(&button, _)| button)
+            .collect();
+        let mut forced = 0;
+        for button in due {
+            let holders: Vec<Key> = self
+                .routes
+                .iter()
+                .filter(|(_, route)| route.holds(button))
+                .map(|(&key, _)| key)
+                .collect();
+            if holders.iter().any(|&key| pressed.contains(key)) {
+                if let Some(held) = self.buttons.held.get_mut(&button) {
+                    held.check = Some(now + HOLD_RECHECK);
+                }
+                continue;
+            }
+            let Some(held) = self.buttons.held.remove(&button) else {
+                continue;
+            };
+            eprintln!(
+                "🚨 {:?} held for {}ms with no key down - forcing it up. Recent button routing:",
+                button,
+                (now - held.since).as_millis()
+            );
+            for step in &self.buttons.history {
+                eprintln!(
+                    "   -{}ms {:?} → {:?} {} (held by {})",
+                    (now - step.at).as_millis(),
+                    step.key,
+                    step.button,
+                    step.value,
+                    step.count
+                );
+            }
+            // Their keys' repeats and releases are dropped from now on
+            for key in holders {
+                remapped(key, format_args!("{:?}", button), 0);
+                self.routes.insert(key, Route::Swallowed);
+            }
+            self.releasing.retain(|_, (route, _)| !route.holds(button));
+            self.sequences.retain(|sequence| sequence.button != button);
+            out.button(button, 0)?;
+            report::count_forced_release();
+            forced += 1;
+        }
+        Ok(forced)
+    }
+
+    /// Notice the mode turning off: leftover wheel fractions belong to that session
+    fn track_mode(&mut self, active: bool) {
+        if self.active && !active {
+            self.wheel.reset();
+        }
+        self.active = active;
+    }
+
+    /// Release everything we are holding down on the virtual devices
+    pub fn release_all(&mut self, out: &mut dyn EventSink) -> Result<()> {
+        self.kinetic.cancel();
+        self.wheel.reset();
+        if let Some(warp) = &mut self.warp {
+            warp.reset();
+        }
+        // Nothing of a deferred press or its backlog has gone out yet
+        self.deferred = None;
+        let releasing = self.releasing.drain().map(|(key, (route, _))| (key, route));
+        for (_, route) in self.routes.drain().chain(releasing) {
+            if let Route::Key(target) = route {
+                out.key(target, 0)?;
+            }
+        }
+        // Buttons, whichever routes and click sequences held them, go up once each
+        self.sequences.clear();
+        self.buttons.release_all(out)?;
+        for key in self.forwarded.drain() {
+            out.key(key, 0)?;
+        }
+        Ok(())
+    }
+}
+
+/// Report a remap; its strings are only built if the record goes anywhere
+fn remapped(key: Key, target: fmt::Arguments, value: i32) {
+    if value == 1 {
+        quality::pressed();
+    }
+    if !report::wants_remap() {
+        report::count_remap();
+        return;
+    }
+    report::emit(Record::Remap {
+        key: format!("{:?}", key),
+        target: target.to_string(),
+        value,
+    });
+}
+
+/// Report a forwarded key, formatting it only for JSON output
+fn forwarded(key: Key, value: i32) {
+    if !report::enabled() {
+        report::count_forward();
+        return;
+    }
+    report::emit(Record::Forward {
+        key: format!("{:?}", key),
+        value,
+    });
+}
+
+/// Send `hi_res` units through the axis accumulator, omitting an empty frame
+fn emit_step(wheel: &mut WheelAccumulator, out: &mut dyn EventSink, hi_res: f64) -> Result<()> {
+    match wheel.add(hi_res) {
+        (0, 0) => Ok(()),
+        (hi_res, legacy) => out.wheel(hi_res, legacy),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    /// Every output, as `button BTN_LEFT 1`, `key KEY_A 0`, `forward KEY_F 1`, ...
+    #[derive(Default)]
+    struct Recorder(Vec<String>);
+
+    impl EventSink for Recorder {
+        fn forward(&mut self, event: InputEvent) -> Result<()> {
+            if let InputEventKind::Key(key) = event.kind() {
+                self.0.push(format!("forward {:?} {}", key, event.value()));
+            }
+            Ok(())
+        }
+
+        fn key(&mut self, key: Key, value: i32) -> Result<()> {
+            self.0.push(format!("key {:?} {}", key, value));
+            Ok(())
+        }
+
+        fn button(&mut self, button: Key, value: i32) -> Result<()> {
+            self.0.push(format!("button {:?} {}", button, value));
+            Ok(())
+        }
+
+        fn wheel(&mut self, hi_res: i32, legacy: i32) -> Result<()> {
+            self.0.push(format!("wheel {} {}", hi_res, legacy));
+            Ok(())
+        }
+
+        fn devices(&self) -> Vec<String> {
+            Vec::new()
+        }
+    }
+
+    impl Recorder {
+        fn take(&mut self) -> Vec<String> {
+            std::mem::take(&mut self.0)
+        }
+    }
+
+    /// A remapper over `config`, fed key events at milliseconds from `start`
+    struct Harness {
+        remapper: Remapper,
+        out: Recorder,
+        start: Instant,
+    }
+
+    impl Harness {
+        fn new(config: &str) -> Self {
+            let config = Config::parse(config, Path::new("test.toml")).unwrap();
+            Self {
+                remapper: Remapper::new(&config, Arc::new(LastMotion::new())).unwrap(),
+                out: Recorder::default(),
+                start: Instant::now(),
+            }
+        }
+
+        fn at(&self, ms: u64) -> Instant {
+            self.start + Duration::from_millis(ms)
+        }
+
+        fn key(&mut self, ms: u64, key: Key, value: i32, active: bool) {
+            let event = InputEvent::new(EventType::KEY, key.code(), value);
+            let now = self.at(ms);
+            self.remapper
+                .handle(event, active, now, &mut self.out)
+                .unwrap();
+        }
+
+        /// Run the timers at every deadline up to `ms`
+        fn tick(&mut self, ms: u64, active: bool) {
+            let until = self.at(ms);
+            while let Some(due) = self.remapper.next_deadline().filter(|&due| due <= until) {
+                self.remapper.tick(due, active, &mut self.out).unwrap();
+            }
+            self.remapper.tick(until, active, &mut self.out).unwrap();
+        }
+    }
+
+    const SHARED: &str = "[mappings]\nKEY_F = \"BTN_LEFT\"\nKEY_SPACE = \"BTN_LEFT\"\n";
+
+    #[test]
+    fn interleaved_holds_of_one_button_press_and_release_it_once() {
+        let mut h = Harness::new(SHARED);
+        h.key(0, Key::KEY_F, 1, true);
+        h.key(10, Key::KEY_SPACE, 1, true);
+        h.key(20, Key::KEY_F, 0, true);
+        assert_eq!(h.out.take(), ["button BTN_LEFT 1"], "still held by space");
+        assert!(h.remapper.holding_button());
+        h.key(30, Key::KEY_F, 1, true);
+        h.key(40, Key::KEY_SPACE, 0, true);
+        h.key(50, Key::KEY_F, 0, true);
+        assert_eq!(h.out.take(), ["button BTN_LEFT 0"]);
+        assert!(!h.remapper.holding_button());
+    }
+
+    #[test]
+    fn a_mode_change_while_both_are_held_keeps_the_one_button() {
+        let mut h = Harness::new(SHARED);
+        h.key(0, Key::KEY_F, 1, true);
+        h.key(10, Key::KEY_SPACE, 1, true);
+        // Releases follow the press-time route, whatever the mode is now
+        h.tick(20, false);
+        h.key(30, Key::KEY_SPACE, 0, false);
+        h.key(40, Key::KEY_F, 0, false);
+        assert_eq!(h.out.take(), ["button BTN_LEFT 1", "button BTN_LEFT 0"]);
+
+        // And a press with the mode off is typed
+        h.key(50, Key::KEY_F, 1, false);
+        h.key(60, Key::KEY_F, 0, false);
+        assert_eq!(h.out.take(), ["forward KEY_F 1", "forward KEY_F 0"]);
+    }
+
+    #[test]
+    fn release_all_lets_a_shared_button_go_once() {
+        let mut h = Harness::new(SHARED);
+        h.key(0, Key::KEY_F, 1, true);
+        h.key(10, Key::KEY_SPACE, 1, true);
+        h.remapper.release_all(&mut h.out).unwrap();
+        assert_eq!(h.out.take(), ["button BTN_LEFT 1", "button BTN_LEFT 0"]);
+        // The physical releases that follow find nothing held
+        h.key(20, Key::KEY_F, 0, true);
+        h.key(30, Key::KEY_SPACE, 0, true);
+        assert!(!h.remapper.holding_button());
+        assert!(h.out.take().iter().all(|out| !out.starts_with("button")));
+    }
+
+    const CHATTER: &str = "[mappings]\nKEY_F = { button = \"BTN_LEFT\", chatter_ms = 30 }\n";
+
+    /// A press and release of F at `down` and `up`, with the timers run first as the loop does
+    fn tap(h: &mut Harness, down: u64, up: u64) {
+        h.tick(down, true);
+        h.key(down, Key::KEY_F, 1, true);
+        h.tick(up, true);
+        h.key(up, Key::KEY_F, 0, true);
+    }
+
+    #[test]
+    fn a_bounce_inside_the_chatter_window_is_one_click() {
+        let mut h = Harness::new(CHATTER);
+        tap(&mut h, 0, 50);
+        // 29ms after the release: the switch bounced
+        tap(&mut h, 79, 120);
+        h.tick(149, true);
+        assert_eq!(
+            h.out.take(),
+            ["button BTN_LEFT 1"],
+            "held back for the window"
+        );
+        h.tick(150, true);
+        assert_eq!(h.out.take(), ["button BTN_LEFT 0"]);
+    }
+
+    #[test]
+    fn a_double_tap_past_the_chatter_window_is_two_clicks() {
+        let mut h = Harness::new(CHATTER);
+        tap(&mut h, 0, 50);
+        // 31ms after the release: a deliberate second tap
+        tap(&mut h, 81, 120);
+        h.tick(200, true);
+        assert_eq!(
+            h.out.take(),
+            [
+                "button BTN_LEFT 1",
+                "button BTN_LEFT 0",
+                "button BTN_LEFT 1",
+                "button BTN_LEFT 0"
+            ]
+        );
+    }
+
+    #[test]
+    fn the_release_is_delayed_by_the_window_only() {
+        let mut h = Harness::new(CHATTER);
+        tap(&mut h, 0, 50);
+        h.tick(79, true);
+        assert_eq!(h.out.take(), ["button BTN_LEFT 1"]);
+        assert_eq!(h.remapper.next_deadline(), Some(h.at(80)));
+        h.tick(80, true);
+        assert_eq!(h.out.take(), ["button BTN_LEFT 0"]);
+        assert!(!h.remapper.holding_button());
+    }
+
+    const CLICKS: &str = "[mappings]\nKEY_W = { double_click = \"BTN_LEFT\" }\n\
+                          KEY_Q = { triple_click = \"BTN_LEFT\", gap_ms = 20 }\n";
+
+    /// Every frame up to `ms`, each with the millisecond its timer fired at
+    fn timed(h: &mut Harness, ms: u64, active: bool) -> Vec<(u64, String)> {
+        let mut frames: Vec<(u64, String)> = h.out.take().into_iter().map(|out| (0, out)).collect();
+        let until = h.at(ms);
+        while let Some(due) = h.remapper.next_deadline().filter(|&due| due <= until) {
+            h.remapper.tick(due, active, &mut h.out).unwrap();
+            let at = (due - h.start).as_millis() as u64;
+            frames.extend(h.out.take().into_iter().map(|out| (at, out)));
+        }
+        frames
+    }
+
+    fn frames(expected: &[(u64, &str)]) -> Vec<(u64, String)> {
+        expected
+            .iter()
+            .map(|&(at, out)| (at, out.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn a_double_click_is_four_frames_a_gap_apart() {
+        let mut h = Harness::new(CLICKS);
+        h.key(0, Key::KEY_W, 1, true);
+        h.key(10, Key::KEY_W, 0, true);
+        assert_eq!(
+            timed(&mut h, 500, true),
+            frames(&[
+                (0, "button BTN_LEFT 1"),
+                (30, "button BTN_LEFT 0"),
+                (60, "button BTN_LEFT 1"),
+                (90, "button BTN_LEFT 0"),
+            ])
+        );
+        assert!(!h.remapper.holding_button());
+    }
+
+    #[test]
+    fn a_triple_click_completes_after_the_mode_turns_off() {
+        let mut h = Harness::new(CLICKS);
+        h.key(0, Key::KEY_Q, 1, true);
+        h.key(5, Key::KEY_Q, 0, true);
+        assert_eq!(
+            timed(&mut h, 500, false),
+            frames(&[
+                (0, "button BTN_LEFT 1"),
+                (20, "button BTN_LEFT 0"),
+                (40, "button BTN_LEFT 1"),
+                (60, "button BTN_LEFT 0"),
+                (80, "button BTN_LEFT 1"),
+                (100, "button BTN_LEFT 0"),
+            ])
+        );
+        assert_eq!(h.remapper.next_deadline(), None);
+    }
+
+    const KINETIC: &str = "[mappings]\nKEY_F = \"BTN_LEFT\"\nKEY_J = { wheel = -1 }\n\
+                           [scroll]\nrepeat_delay_ms = 100\nrepeat_interval_ms = 40\n\
+                           [scroll.kinetic]\nenabled = true\n";
+
+    fn wheel_frames(out: &[String]) -> usize {
+        out.iter().filter(|out| out.starts_with("wheel")).count()
+    }
+
+    /// Hold J long enough to repeat, then let go: inertia is running
+    fn coasting() -> Harness {
+        let mut h = Harness::new(KINETIC);
+        h.key(0, Key::KEY_J, 1, true);
+        h.tick(500, true);
+        h.key(500, Key::KEY_J, 0, true);
+        h.out.take();
+        h
+    }
+
+    #[test]
+    fn a_scroll_key_release_coasts() {
+        let mut h = coasting();
+        h.tick(600, true);
+        assert!(wheel_frames(&h.out.take()) > 0);
+        h.tick(5000, true);
+        h.out.take();
+        h.tick(6000, true);
+        assert_eq!(wheel_frames(&h.out.take()), 0, "the coast ended");
+    }
+
+    #[test]
+    fn a_click_cancels_the_coast() {
+        let mut h = coasting();
+        h.tick(520, true);
+        h.key(520, Key::KEY_F, 1, true);
+        h.out.take();
+        h.tick(2000, true);
+        assert_eq!(wheel_frames(&h.out.take()), 0);
+    }
+}
@@ -0,0 +1,402 @@
+//! JSON-lines records for `--output json` and control socket subscribers
+//!
+//! These structs are the schema: one `Line` per stdout line, the record's
+//! variant name (snake_case) in its `type` field. Human logs stay on stderr.
+
+use crate::control;
+use crate::live_map;
+use crate::quality::{self, Metrics};
+use crate::snooze;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static ACTIVATIONS: AtomicU64 = AtomicU64::new(0);
+static REMAPPED: AtomicU64 = AtomicU64::new(0);
+static FORWARDED: AtomicU64 = AtomicU64::new(0);
+static RESYNCS: AtomicU64 = AtomicU64::new(0);
+/// Output events dropped while a virtual device was being recreated
+static LOST: AtomicU64 = AtomicU64::new(0);
+static FORCED: AtomicU64 = AtomicU64::new(0);
+
+/// Virtual devices the monitors created
+static DEVICES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// One output line
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Line {
+    /// Wall clock, RFC 3339 / ISO 8601 in UTC with milliseconds
+    pub timestamp: String,
+    /// CLOCK_MONOTONIC in milliseconds, comparable to evdev event times
+    pub monotonic_ms: u64,
+    #[serde(flatten)]
+    pub record: Record,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Record {
+    Startup {
+        version: String,
+        /// Topmost config layer in use, absent with built-in defaults
+        config: Option<String>,
+        touchpad: String,
+        virtual_keyboard: String,
+    },
+    ModeChange {
+        active: bool,
+        /// What caused it, e.g. "pointer_motion", "finger_up", "signal"
+        reason: String,
+    },
+    /// The layer was switched on or off (SIGUSR1)
+    EnabledChange {
+        enabled: bool,
+    },
+    /// The mapping table in use changed
+    ProfileChange {
+        /// E.g. "[mappings]", "mapping_sets[1] (...)", "finger_layers.2"
+        profile: String,
+        /// "keyboard" when the keyboard's table was picked, "fingers" for a finger layer
+        reason: String,
+    },
+    /// An input device was found, at startup or after it went away
+    DeviceAttached {
+        /// "keyboard", "touchpad", "external mouse", ...
+        device: String,
+    },
+    /// An input device went away; we wait for it to return
+    DeviceDetached {
+        device: String,
+    },
+    /// A mapped key pressed (value 1) or released (value 0) while routed to a pointer target
+    Remap {
+        key: String,
+        target: String,
+        value: i32,
+    },
+    /// A key passed through to the virtual keyboard
+    Forward {
+        key: String,
+        value: i32,
+    },
+    Error {
+        message: String,
+    },
+    /// The kernel overflowed a device's event buffer and state was re-read
+    Resync {
+        /// "keyboard", "touchpad" or "external mouse"
+        device: String,
+    },
+    /// Virtual devices stopped taking events and were recreated
+    OutputRecovered {
+        /// "keyboard" or "external mouse"
+        device: String,
+        /// Events dropped meanwhile
+        lost: u64,
+        /// Held keys and buttons pressed again on the new devices
+        replayed: usize,
+    },
+    /// Snapshot sent to control socket subscribers on connect and periodically
+    Status {
+        active: bool,
+        enabled: bool,
+        finger_down: bool,
+        /// Absent until the first motion
+        since_motion_ms: Option<u64>,
+        activations: u64,
+        remapped: u64,
+        forwarded: u64,
+        resyncs: u64,
+        #[serde(default)]
+        lost_events: u64,
+        /// Buttons the watchdog forced up
+        #[serde(default)]
+        forced_releases: u64,
+        /// Whether `ctl pause` turned the layer off
+        #[serde(default)]
+        paused: bool,
+        /// Seconds left of a timed pause
+        #[serde(default)]
+        pause_remaining_s: Option<u64>,
+        /// Whether `ctl map`/`unmap` changed the mappings since they were loaded or saved
+        #[serde(default)]
+        mappings_modified: bool,
+        /// Virtual devices in existence, empty before the keyboard is grabbed
+        #[serde(default)]
+        devices: Vec<String>,
+        /// How activations went, see `quality`
+        #[serde(default)]
+        quality: Metrics,
+    },
+    /// Counters since startup, emitted on shutdown
+    Stats {
+        activations: u64,
+        remapped: u64,
+        forwarded: u64,
+        resyncs: u64,
+        #[serde(default)]
+        lost_events: u64,
+        #[serde(default)]
+        forced_releases: u64,
+        #[serde(default)]
+        quality: Metrics,
+    },
+}
+
+/// Turn JSON output on; before this every `emit` is a no-op
+pub fn enable() {
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Count the record and, in JSON mode, write it as one line on stdout
+///
+/// Control socket subscribers get it too, except `forward`: what was typed
+/// never leaves the process.
+pub fn emit(record: Record) {
+    let counter = match &record {
+        Record::ModeChange { active: true, .. } => Some(&ACTIVATIONS),
+        Record::Remap { .. } => Some(&REMAPPED),
+        Record::Forward { .. } => Some(&FORWARDED),
+        Record::Resync { .. } => Some(&RESYNCS),
+        _ => None,
+    };
+    if let Some(counter) = counter {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+    if let Record::OutputRecovered { lost, .. } = &record {
+        LOST.fetch_add(*lost, Ordering::Relaxed);
+    }
+
+    let subscribed = control::has_subscribers() && !matches!(record, Record::Forward { .. });
+    if !enabled() && !subscribed {
+        return;
+    }
+    let Some(json) = to_json(record) else {
+        return;
+    };
+    if enabled() {
+        // A closed stdout must not take the daemon down
+        let _ = writeln!(std::io::stdout().lock(), "{}", json);
+    }
+    if subscribed {
+        control::broadcast(&json);
+    }
+}
+
+/// Whether a `remap` record would reach stdout or a subscriber
+///
+/// The per-event path checks this before formatting one, and calls
+/// `count_remap` instead when it wouldn't, so the counters stay right.
+pub fn wants_remap() -> bool {
+    enabled() || control::has_subscribers()
+}
+
+pub fn count_remap() {
+    REMAPPED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// A button held past watchdog.max_button_hold_ms was forced up
+pub fn count_forced_release() {
+    FORCED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// As `count_remap`, for `forward` records, which only ever go to stdout
+pub fn count_forward() {
+    FORWARDED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// One output line for `record`, stamped now
+pub fn to_json(record: Record) -> Option<String> {
+    let line = Line {
+        timestamp: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+        monotonic_ms: monotonic_ms(),
+        record,
+    };
+    serde_json::to_string(&line).ok()
+}
+
+pub fn status(
+    active: bool,
+    enabled: bool,
+    finger_down: bool,
+    since_motion_ms: Option<u64>,
+) -> Record {
+    let pause = snooze::current();
+    Record::Status {
+        active,
+        enabled,
+        finger_down,
+        since_motion_ms,
+        activations: ACTIVATIONS.load(Ordering::Relaxed),
+        remapped: REMAPPED.load(Ordering::Relaxed),
+        forwarded: FORWARDED.load(Ordering::Relaxed),
+        resyncs: RESYNCS.load(Ordering::Relaxed),
+        lost_events: LOST.load(Ordering::Relaxed),
+        forced_releases: FORCED.load(Ordering::Relaxed),
+        paused: pause.is_some(),
+        pause_remaining_s: pause.flatten().map(|until| {
+            until
+                .duration_since(SystemTime::now())
+                .map_or(0, |left| left.as_secs())
+        }),
+        mappings_modified: live_map::modified(),
+        devices: DEVICES.lock().unwrap().clone(),
+        quality: quality::current(),
+    }
+}
+
+/// Record virtual devices as each monitor creates its own
+pub fn add_devices(devices: Vec<String>) {
+    DEVICES.lock().unwrap().extend(devices);
+}
+
+pub fn stats() -> Record {
+    Record::Stats {
+        activations: ACTIVATIONS.load(Ordering::Relaxed),
+        remapped: REMAPPED.load(Ordering::Relaxed),
+        forwarded: FORWARDED.load(Ordering::Relaxed),
+        resyncs: RESYNCS.load(Ordering::Relaxed),
+        lost_events: LOST.load(Ordering::Relaxed),
+        forced_releases: FORCED.load(Ordering::Relaxed),
+        quality: quality::current(),
+    }
+}
+
+/// CLOCK_MONOTONIC in milliseconds
+pub fn monotonic_ms() -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: ts is a valid timespec to write into
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    ts.tv_sec as u64 * 1000 + ts.tv_nsec as u64 / 1_000_000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> Line {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn sample_lines_deserialize_into_the_schema() {
+        let line = parse(
+            r#"{"timestamp":"2026-01-05T09:30:00.125Z","monotonic_ms":81234,"type":"mode_change","active":true,"reason":"pointer_motion"}"#,
+        );
+        assert_eq!(line.timestamp, "2026-01-05T09:30:00.125Z");
+        assert_eq!(line.monotonic_ms, 81234);
+        assert_eq!(
+            line.record,
+            Record::ModeChange {
+                active: true,
+                reason: "pointer_motion".to_string()
+            }
+        );
+
+        let line = parse(
+            r#"{"timestamp":"2026-01-05T09:30:00.250Z","monotonic_ms":81359,"type":"remap","key":"KEY_F","target":"BTN_LEFT","value":1}"#,
+        );
+        assert_eq!(
+            line.record,
+            Record::Remap {
+                key: "KEY_F".to_string(),
+                target: "BTN_LEFT".to_string(),
+                value: 1
+            }
+        );
+
+        let line = parse(
+            r#"{"timestamp":"2026-01-05T09:29:58.000Z","monotonic_ms":79109,"type":"startup","version":"0.1.0","config":null,"touchpad":"SYNA8004:00 06CB:CD8B Touchpad","virtual_keyboard":"my-virtual-keyboard"}"#,
+        );
+        assert_eq!(
+            line.record,
+            Record::Startup {
+                version: "0.1.0".to_string(),
+                config: None,
+                touchpad: "SYNA8004:00 06CB:CD8B Touchpad".to_string(),
+                virtual_keyboard: "my-virtual-keyboard".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn a_status_from_before_the_newer_fields_still_parses() {
+        let line = parse(
+            r#"{"timestamp":"2026-01-05T09:30:01.000Z","monotonic_ms":82109,"type":"status","active":false,"enabled":true,"finger_down":false,"since_motion_ms":null,"activations":3,"remapped":12,"forwarded":40,"resyncs":0}"#,
+        );
+        let Record::Status {
+            activations,
+            remapped,
+            since_motion_ms,
+            paused,
+            devices,
+            quality,
+            ..
+        } = line.record
+        else {
+            panic!("not a status: {:?}", line.record);
+        };
+        assert_eq!((activations, remapped), (3, 12));
+        assert_eq!(since_motion_ms, None);
+        assert!(!paused);
+        assert!(devices.is_empty());
+        assert_eq!(quality, Metrics::default());
+    }
+
+    #[test]
+    fn every_record_round_trips_through_its_line() {
+        let records = [
+            Record::EnabledChange { enabled: false },
+            Record::ProfileChange {
+                profile: "finger_layers.2".to_string(),
+                reason: "fingers".to_string(),
+            },
+            Record::DeviceAttached {
+                device: "keyboard".to_string(),
+            },
+            Record::DeviceDetached {
+                device: "touchpad".to_string(),
+            },
+            Record::Forward {
+                key: "KEY_A".to_string(),
+                value: 0,
+            },
+            Record::Error {
+                message: "Failed to grab".to_string(),
+            },
+            Record::Resync {
+                device: "keyboard".to_string(),
+            },
+            Record::OutputRecovered {
+                device: "keyboard".to_string(),
+                lost: 4,
+                replayed: 1,
+            },
+            stats(),
+        ];
+        for record in records {
+            let json = to_json(record.clone()).unwrap();
+            let line = parse(&json);
+            assert_eq!(line.record, record, "{}", json);
+            assert!(
+                humantime::parse_rfc3339(&line.timestamp).is_ok(),
+                "{}",
+                json
+            );
+            assert!(line.monotonic_ms > 0);
+        }
+        let json = to_json(Record::EnabledChange { enabled: true }).unwrap();
+        assert!(json.contains(r#""type":"enabled_change""#), "{}", json);
+    }
+}
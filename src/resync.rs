@@ -0,0 +1,54 @@
+use crate::report::{self, Record};
+use evdev::{InputEvent, InputEventKind, Synchronization};
+
+/// What to do with one raw event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feed {
+    Pass,
+    /// Part of a frame the kernel overflowed: drop it
+    Skip,
+    /// The dropped frame just ended: re-read device state before trusting ours
+    Resync,
+}
+
+/// Tracks SYN_DROPPED on a raw evdev stream
+///
+/// After SYN_DROPPED the kernel's contract is to discard events up to and
+/// including the next SYN_REPORT, then query state with the EVIOCG* ioctls.
+#[derive(Debug, Default)]
+pub struct DropFilter {
+    dropping: bool,
+}
+
+impl DropFilter {
+    pub fn feed(&mut self, event: &InputEvent) -> Feed {
+        match event.kind() {
+            InputEventKind::Synchronization(Synchronization::SYN_DROPPED) => {
+                self.dropping = true;
+                Feed::Skip
+            }
+            InputEventKind::Synchronization(Synchronization::SYN_REPORT) if self.dropping => {
+                self.dropping = false;
+                Feed::Resync
+            }
+            _ if self.dropping => Feed::Skip,
+            _ => Feed::Pass,
+        }
+    }
+
+    /// Forget a half-dropped frame, e.g. after reopening the device
+    pub fn reset(&mut self) {
+        self.dropping = false;
+    }
+}
+
+/// Log and count a resync of `device`
+pub fn resynced(device: &str, detail: &str) {
+    eprintln!(
+        "⚠️  Kernel dropped {} events (SYN_DROPPED); resynced {}",
+        device, detail
+    );
+    report::emit(Record::Resync {
+        device: device.into(),
+    });
+}
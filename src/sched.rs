@@ -0,0 +1,63 @@
+use crate::config::{SchedConfig, SchedPolicy};
+use std::io;
+
+/// Raise the calling thread's scheduling priority as configured
+///
+/// Linux applies both calls to the calling thread only, so the other monitors
+/// keep normal priority. Either way SCHED_RESET_ON_FORK is set, so threads and
+/// commands started from the keyboard thread go back to normal priority.
+/// Without CAP_SYS_NICE this warns and carries on.
+pub fn boost_current_thread(config: &SchedConfig) {
+    let result = match config.policy {
+        SchedPolicy::Normal => return,
+        SchedPolicy::Fifo => set_scheduler(libc::SCHED_FIFO, config.priority),
+        SchedPolicy::Nice => match set_scheduler(libc::SCHED_OTHER, 0) {
+            0 => {
+                // SAFETY: gettid has no preconditions
+                let tid = unsafe { libc::gettid() };
+                // SAFETY: plain syscall on our own thread id
+                unsafe { libc::setpriority(libc::PRIO_PROCESS, tid as libc::id_t, config.nice) }
+            }
+            failed => failed,
+        },
+    };
+
+    let what = match config.policy {
+        SchedPolicy::Fifo => format!("SCHED_FIFO priority {}", config.priority),
+        _ => format!("nice {}", config.nice),
+    };
+    if result == 0 {
+        eprintln!("⚡ Keyboard thread running at {}", what);
+        return;
+    }
+    let err = io::Error::last_os_error();
+    eprintln!(
+        "⚠️  Could not set {} for the keyboard thread: {}",
+        what, err
+    );
+    if matches!(err.raw_os_error(), Some(libc::EPERM | libc::EACCES)) {
+        eprintln!("   Needs CAP_SYS_NICE (e.g. AmbientCapabilities=CAP_SYS_NICE) or root");
+    }
+}
+
+/// The calling thread's policy, with SCHED_RESET_ON_FORK
+fn set_scheduler(policy: libc::c_int, priority: libc::c_int) -> libc::c_int {
+    let param = libc::sched_param {
+        sched_priority: priority,
+    };
+    // SAFETY: pid 0 is the calling thread; param is a valid sched_param
+    unsafe { libc::sched_setscheduler(0, policy | libc::SCHED_RESET_ON_FORK, &param) }
+}
+
+/// Normal policy and nice 0, for a child about to exec
+///
+/// Async-signal-safe, for `pre_exec`. SCHED_RESET_ON_FORK already covers
+/// children of the boosted thread; this also covers a boost from elsewhere.
+pub fn reset_for_child() {
+    let param = libc::sched_param { sched_priority: 0 };
+    // SAFETY: plain syscalls on the calling process; param is a valid sched_param
+    unsafe {
+        libc::sched_setscheduler(0, libc::SCHED_OTHER, &param);
+        libc::setpriority(libc::PRIO_PROCESS, 0, 0);
+    }
+}
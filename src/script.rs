@@ -0,0 +1,396 @@
+use crate::config::{ScriptConfig, Target, WheelTarget, MAX_WHEEL_STEP};
+use crate::keys;
+use crate::scroll::Ramp;
+use anyhow::{anyhow, bail, Result};
+use evdev::{AttributeSet, Key};
+use rhai::{CallFnOptions, Dynamic, Engine, Map, Scope, AST};
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+/// Minimum gap between repeated script-failure warnings
+const WARN_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Modifier keys reported to the script when held
+const MODIFIERS: [Key; 8] = [
+    Key::KEY_LEFTSHIFT,
+    Key::KEY_RIGHTSHIFT,
+    Key::KEY_LEFTCTRL,
+    Key::KEY_RIGHTCTRL,
+    Key::KEY_LEFTALT,
+    Key::KEY_RIGHTALT,
+    Key::KEY_LEFTMETA,
+    Key::KEY_RIGHTMETA,
+];
+
+/// What the script decided for one key press
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// Use the static mapping table
+    Default,
+    Forward,
+    Swallow,
+    Map(Target),
+}
+
+/// Everything the script gets to see about a press
+pub struct Input<'a> {
+    pub key: Key,
+    pub value: i32,
+    pub active: bool,
+    /// Keys currently held down (modifiers are picked out of these)
+    pub held: &'a HashSet<Key>,
+    pub since_motion: Option<Duration>,
+}
+
+/// A compiled `route(event)` script, run synchronously for every press
+pub struct ScriptHook {
+    engine: Engine,
+    ast: AST,
+    /// Script globals, kept between calls
+    scope: Scope<'static>,
+    /// Buttons the virtual devices declare, the only ones a script may map to
+    buttons: AttributeSet<Key>,
+    wheel_delay: Duration,
+    wheel_interval: Duration,
+    wheel_acceleration: Option<Ramp>,
+    /// scroll.step, the hi-res units per detent of a script wheel step
+    wheel_step: i32,
+    /// scroll.natural, applied to script wheel steps like to mapped ones
+    natural: bool,
+    last_warning: Option<Instant>,
+    suppressed: u32,
+}
+
+impl ScriptHook {
+    pub fn load(
+        config: &ScriptConfig,
+        buttons: AttributeSet<Key>,
+        wheel_delay: Duration,
+        wheel_interval: Duration,
+        wheel_acceleration: Option<Ramp>,
+        wheel_step: i32,
+        natural: bool,
+    ) -> Result<Option<Self>> {
+        let Some(path) = &config.path else {
+            return Ok(None);
+        };
+
+        let mut engine = Engine::new();
+        // The budget is what keeps a runaway script from stalling the keyboard loop
+        engine.set_max_operations(config.max_operations);
+        engine.set_max_call_levels(16);
+        engine.set_max_expr_depths(64, 32);
+        engine.set_max_string_size(1024);
+        engine.set_max_array_size(256);
+        engine.set_max_map_size(256);
+        engine.on_print(|text| eprintln!("📜 {}", text));
+
+        let ast = engine
+            .compile_file(path.clone())
+            .map_err(|err| anyhow!("Failed to compile script {}: {}", path.display(), err))?;
+        if !ast
+            .iter_functions()
+            .any(|f| f.name == "route" && f.params.len() == 1)
+        {
+            bail!("Script {} does not define route(event)", path.display());
+        }
+
+        // Top-level statements run once, to set up globals
+        let mut scope = Scope::new();
+        engine
+            .run_ast_with_scope(&mut scope, &ast)
+            .map_err(|err| anyhow!("Script {} failed to start: {}", path.display(), err))?;
+
+        eprintln!("📜 Routing script: {}", path.display());
+        Ok(Some(Self {
+            engine,
+            ast,
+            scope,
+            buttons,
+            wheel_delay,
+            wheel_interval,
+            wheel_acceleration,
+            wheel_step,
+            natural,
+            last_warning: None,
+            suppressed: 0,
+        }))
+    }
+
+    /// Ask the script; any failure falls back to the static table
+    pub fn decide(&mut self, input: Input) -> Decision {
+        let key = input.key;
+        match self.call(input) {
+            Ok(decision) => decision,
+            Err(err) => {
+                self.warn(key, &err);
+                Decision::Default
+            }
+        }
+    }
+
+    fn call(&mut self, input: Input) -> Result<Decision> {
+        let modifiers: rhai::Array = MODIFIERS
+            .iter()
+            .filter(|key| input.held.contains(key))
+            .map(|key| Dynamic::from(format!("{:?}", key)))
+            .collect();
+
+        let mut event = Map::new();
+        event.insert("key".into(), format!("{:?}", input.key).into());
+        event.insert("value".into(), (input.value as rhai::INT).into());
+        event.insert("active".into(), input.active.into());
+        event.insert("modifiers".into(), modifiers.into());
+        let since_motion = input
+            .since_motion
+            .map_or(-1, |elapsed| elapsed.as_millis() as rhai::INT);
+        event.insert("since_motion_ms".into(), since_motion.into());
+
+        let options = CallFnOptions::new().eval_ast(false);
+        let result: Dynamic = self
+            .engine
+            .call_fn_with_options(options, &mut self.scope, &self.ast, "route", (event,))
+            .map_err(|err| anyhow!("{}", err))?;
+        self.parse(result)
+    }
+
+    /// `()`/"default", "forward", "swallow", a button name, or `#{ wheel: n }`
+    fn parse(&self, result: Dynamic) -> Result<Decision> {
+        if result.is_unit() {
+            return Ok(Decision::Default);
+        }
+        if let Some(map) = result.clone().try_cast::<Map>() {
+            let detents = map
+                .get("wheel")
+                .and_then(|w| w.as_int().ok())
+                .filter(|&w| w != 0)
+                .ok_or_else(|| anyhow!("map result needs a non-zero integer `wheel`"))?;
+            if detents.abs() > i64::from(MAX_WHEEL_STEP) {
+                bail!("wheel step {} is over {} detents", detents, MAX_WHEEL_STEP);
+            }
+            return Ok(Decision::Map(Target::Wheel(WheelTarget {
+                detents: if self.natural {
+                    -(detents as i32)
+                } else {
+                    detents as i32
+                },
+                step: self.wheel_step,
+                repeat_delay: self.wheel_delay,
+                repeat_interval: self.wheel_interval,
+                acceleration: self.wheel_acceleration,
+            })));
+        }
+
+        let text = result
+            .into_immutable_string()
+            .map_err(|ty| anyhow!("route() returned a {}", ty))?;
+        match text.as_str() {
+            "default" => Ok(Decision::Default),
+            "forward" => Ok(Decision::Forward),
+            "swallow" => Ok(Decision::Swallow),
+            name => match keys::resolve(name) {
+                Ok(button) if self.buttons.contains(button) => {
+                    Ok(Decision::Map(Target::Button(button)))
+                }
+                _ => bail!("'{}' is not a button the virtual devices declare", name),
+            },
+        }
+    }
+
+    fn warn(&mut self, key: Key, err: &anyhow::Error) {
+        let now = Instant::now();
+        if self
+            .last_warning
+            .is_some_and(|last| now.duration_since(last) < WARN_INTERVAL)
+        {
+            self.suppressed += 1;
+            return;
+        }
+        eprintln!("⚠️  Script failed for {:?}, using mappings: {:#}", key, err);
+        if self.suppressed > 0 {
+            eprintln!("   ({} similar failures suppressed)", self.suppressed);
+        }
+        self.last_warning = Some(now);
+        self.suppressed = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// `source` written to a temp file, loaded with `max_operations` and BTN_LEFT declared
+    fn load(name: &str, source: &str, max_operations: u64, natural: bool) -> Result<ScriptHook> {
+        let path = std::env::temp_dir().join(format!(
+            "touch-layer-test-script-{}-{}.rhai",
+            name,
+            std::process::id()
+        ));
+        std::fs::write(&path, source).unwrap();
+        let config = ScriptConfig {
+            path: Some(path.clone()),
+            max_operations,
+        };
+        let buttons: AttributeSet<Key> = [Key::BTN_LEFT].into_iter().collect();
+        let hook = ScriptHook::load(
+            &config,
+            buttons,
+            Duration::from_millis(250),
+            Duration::from_millis(50),
+            None,
+            120,
+            natural,
+        );
+        std::fs::remove_file(&path).unwrap();
+        hook.map(|hook| hook.expect("a path was given"))
+    }
+
+    fn press<'a>(key: Key, held: &'a HashSet<Key>) -> Input<'a> {
+        Input {
+            key,
+            value: 1,
+            active: true,
+            held,
+            since_motion: Some(Duration::from_millis(40)),
+        }
+    }
+
+    #[test]
+    fn a_script_that_does_not_compile_or_route_is_refused() {
+        let error = load("syntax", "fn route(event) { ", 10_000, false)
+            .err()
+            .unwrap();
+        assert!(
+            error.to_string().starts_with("Failed to compile script"),
+            "{}",
+            error
+        );
+        let error = load("noroute", "fn other(event) { () }", 10_000, false)
+            .err()
+            .unwrap();
+        assert!(
+            error.to_string().ends_with("does not define route(event)"),
+            "{}",
+            error
+        );
+        let error = load(
+            "start",
+            "throw \"no\"; fn route(event) { () }",
+            10_000,
+            false,
+        )
+        .err()
+        .unwrap();
+        assert!(error.to_string().contains("failed to start"), "{}", error);
+    }
+
+    #[test]
+    fn a_script_error_falls_back_to_the_mappings() {
+        let mut hook = load(
+            "throw",
+            "fn route(event) { throw \"broken\"; }",
+            10_000,
+            false,
+        )
+        .unwrap();
+        let held = HashSet::new();
+        let error = hook.call(press(Key::KEY_F, &held)).unwrap_err();
+        assert!(error.to_string().contains("broken"), "{}", error);
+        assert_eq!(hook.decide(press(Key::KEY_F, &held)), Decision::Default);
+    }
+
+    #[test]
+    fn a_runaway_script_is_stopped_by_the_operation_limit() {
+        let mut hook = load("loop", "fn route(event) { loop {} }", 1_000, false).unwrap();
+        let held = HashSet::new();
+        let started = Instant::now();
+        let error = hook.call(press(Key::KEY_F, &held)).unwrap_err();
+        assert!(error.to_string().contains("operations"), "{}", error);
+        assert_eq!(hook.decide(press(Key::KEY_F, &held)), Decision::Default);
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn returned_actions_become_decisions() {
+        let source = r#"
+            fn route(event) {
+                switch event.key {
+                    "KEY_A" => "forward",
+                    "KEY_B" => "swallow",
+                    "KEY_C" => "BTN_LEFT",
+                    "KEY_D" => #{ wheel: 2 },
+                    "KEY_E" => "default",
+                    "KEY_G" => "BTN_RIGHT",
+                    "KEY_H" => #{ wheel: 0 },
+                    "KEY_I" => 7,
+                    _ => (),
+                }
+            }
+        "#;
+        let mut hook = load("actions", source, 10_000, false).unwrap();
+        let held = HashSet::new();
+        let mut decide = |key| hook.call(press(key, &held));
+        assert_eq!(decide(Key::KEY_A).unwrap(), Decision::Forward);
+        assert_eq!(decide(Key::KEY_B).unwrap(), Decision::Swallow);
+        assert_eq!(
+            decide(Key::KEY_C).unwrap(),
+            Decision::Map(Target::Button(Key::BTN_LEFT))
+        );
+        let Decision::Map(Target::Wheel(wheel)) = decide(Key::KEY_D).unwrap() else {
+            panic!("not a wheel");
+        };
+        assert_eq!((wheel.detents, wheel.step), (2, 120));
+        assert_eq!(decide(Key::KEY_E).unwrap(), Decision::Default);
+        assert_eq!(decide(Key::KEY_F).unwrap(), Decision::Default);
+        // Buttons the devices don't declare, and malformed results, are errors
+        assert!(decide(Key::KEY_G).is_err());
+        assert!(decide(Key::KEY_H).is_err());
+        assert!(decide(Key::KEY_I).is_err());
+
+        let mut natural = load("natural", source, 10_000, true).unwrap();
+        let Decision::Map(Target::Wheel(wheel)) = natural.call(press(Key::KEY_D, &held)).unwrap()
+        else {
+            panic!("not a wheel");
+        };
+        assert_eq!(wheel.detents, -2, "natural scrolling flips script steps");
+    }
+
+    #[test]
+    fn the_script_sees_the_event_and_keeps_its_globals() {
+        let source = r#"
+            let presses = 0;
+            fn route(event) {
+                presses += 1;
+                if presses == 2 && event.modifiers == ["KEY_LEFTSHIFT"]
+                    && event.since_motion_ms == 40 && event.active && event.value == 1 {
+                    return "swallow";
+                }
+                ()
+            }
+        "#;
+        let mut hook = load("globals", source, 10_000, false).unwrap();
+        let held: HashSet<Key> = [Key::KEY_LEFTSHIFT, Key::KEY_F].into_iter().collect();
+        assert_eq!(hook.decide(press(Key::KEY_F, &held)), Decision::Default);
+        assert_eq!(hook.decide(press(Key::KEY_F, &held)), Decision::Swallow);
+        assert_eq!(hook.decide(press(Key::KEY_F, &held)), Decision::Default);
+    }
+
+    #[test]
+    fn no_path_means_no_hook() {
+        let config = ScriptConfig {
+            path: None::<PathBuf>,
+            max_operations: 10_000,
+        };
+        let hook = ScriptHook::load(
+            &config,
+            AttributeSet::new(),
+            Duration::ZERO,
+            Duration::ZERO,
+            None,
+            120,
+            false,
+        );
+        assert!(hook.unwrap().is_none());
+    }
+}
@@ -0,0 +1,437 @@
+use crate::config::KineticConfig;
+use evdev::{EventType, InputEvent, RelativeAxisType};
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+/// Hi-res wheel units per detent (see REL_WHEEL_HI_RES in the kernel docs)
+pub const HI_RES_PER_DETENT: i32 = 120;
+
+/// Exponentially decaying velocity: v(t) = v0 * e^(-t / tau)
+#[derive(Debug, Clone, Copy)]
+pub struct Decay {
+    /// Initial velocity in detents per second (signed)
+    pub v0: f64,
+    /// Time constant in seconds
+    pub tau: f64,
+}
+
+impl Decay {
+    /// Distance travelled between t1 and t2, integrated exactly so tick jitter doesn't matter
+    pub fn distance(&self, t1: f64, t2: f64) -> f64 {
+        self.v0 * self.tau * ((-t1 / self.tau).exp() - (-t2 / self.tau).exp())
+    }
+
+    /// Time at which |v| drops to `min_velocity`
+    pub fn stop_time(&self, min_velocity: f64) -> f64 {
+        if self.v0.abs() <= min_velocity {
+            return 0.0;
+        }
+        self.tau * (self.v0.abs() / min_velocity).ln()
+    }
+}
+
+/// Release velocity from the key's repeat history: detents emitted over the hold
+pub fn release_velocity(detents: f64, steps: u32, held: Duration) -> Option<f64> {
+    // A single tap has no repeat rate to carry over
+    if steps < 2 || held.is_zero() {
+        return None;
+    }
+    let rate = f64::from(steps - 1) / held.as_secs_f64();
+    Some(rate * detents)
+}
+
+/// Shape of the climb from the initial to the maximum repeat rate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Curve {
+    Linear,
+    /// Slow at first, so short holds stay precise
+    Quadratic,
+    Cubic,
+}
+
+impl Curve {
+    fn exponent(self) -> i32 {
+        match self {
+            Curve::Linear => 1,
+            Curve::Quadratic => 2,
+            Curve::Cubic => 3,
+        }
+    }
+}
+
+/// Repeat rate rising with hold time: r(t) = r0 + (r_max - r0) * min(t / ramp, 1)^n
+///
+/// `t` counts from the first repeat step; rates are in steps per second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ramp {
+    pub initial_hz: u32,
+    pub max_hz: u32,
+    pub ramp: Duration,
+    pub curve: Curve,
+}
+
+impl Ramp {
+    /// Repeat rate `t` into the repeat
+    pub fn rate(&self, t: Duration) -> f64 {
+        let (r0, r_max) = (f64::from(self.initial_hz), f64::from(self.max_hz));
+        r0 + (r_max - r0) * self.progress(t).powi(self.curve.exponent())
+    }
+
+    /// Steps repeated from 0 to `t`, integrated exactly so tick jitter doesn't matter
+    pub fn steps(&self, t: Duration) -> f64 {
+        let (r0, r_max) = (f64::from(self.initial_hz), f64::from(self.max_hz));
+        let (t, ramp) = (t.as_secs_f64(), self.ramp.as_secs_f64());
+        let n = self.curve.exponent();
+        let climbing = t.min(ramp);
+        let mut steps = r0 * climbing;
+        if ramp > 0.0 {
+            steps += (r_max - r0) * ramp * (climbing / ramp).powi(n + 1) / f64::from(n + 1);
+        }
+        steps + r_max * (t - climbing)
+    }
+
+    fn progress(&self, t: Duration) -> f64 {
+        if self.ramp.is_zero() {
+            return 1.0;
+        }
+        (t.as_secs_f64() / self.ramp.as_secs_f64()).min(1.0)
+    }
+}
+
+/// Splits wheel movement into whole hi-res units and whole legacy detents
+///
+/// REL_WHEEL only fires once a full detent's worth of hi-res units has gone
+/// out, whichever key or coast they came from. Wheel targets are vertical
+/// only, so there is one, for REL_WHEEL; a horizontal axis would need its own.
+/// Turning around drops what was left over, so it never cancels out against
+/// the new direction.
+#[derive(Debug, Default)]
+pub struct WheelAccumulator {
+    /// Hi-res units not yet emitted
+    fraction: f64,
+    /// Hi-res units emitted since the last legacy detent
+    legacy: i32,
+}
+
+impl WheelAccumulator {
+    /// Returns (hi_res, legacy) deltas to emit for `hi_res` units of movement
+    pub fn add(&mut self, hi_res: f64) -> (i32, i32) {
+        let left = self.fraction + f64::from(self.legacy);
+        if left * hi_res < 0.0 {
+            self.reset();
+        }
+        self.fraction += hi_res;
+        let hi_res = self.fraction.trunc() as i32;
+        self.fraction -= f64::from(hi_res);
+
+        self.legacy += hi_res;
+        let legacy = self.legacy / HI_RES_PER_DETENT;
+        self.legacy -= legacy * HI_RES_PER_DETENT;
+
+        (hi_res, legacy)
+    }
+
+    /// Drop leftover fractions, so they don't carry into the next session
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// A wheel frame, omitting axes with no movement
+pub fn wheel_events(hi_res: i32, legacy: i32) -> impl Iterator<Item = InputEvent> {
+    [
+        (RelativeAxisType::REL_WHEEL_HI_RES, hi_res),
+        (RelativeAxisType::REL_WHEEL, legacy),
+    ]
+    .into_iter()
+    .filter(|&(_, value)| value != 0)
+    .map(|(axis, value)| InputEvent::new(EventType::RELATIVE, axis.0, value))
+}
+
+/// Inertia in progress after a scroll key release
+#[derive(Debug)]
+struct Coast {
+    decay: Decay,
+    started: Instant,
+    elapsed: f64,
+    stop: f64,
+    next_tick: Instant,
+}
+
+/// Kinetic scrolling state driven by the keyboard loop's timer
+#[derive(Debug)]
+pub struct Kinetic {
+    config: KineticConfig,
+    coast: Option<Coast>,
+}
+
+impl Kinetic {
+    pub fn new(config: KineticConfig) -> Self {
+        Self {
+            config,
+            coast: None,
+        }
+    }
+
+    /// Start coasting after a scroll key release at `velocity` detents per second
+    pub fn release(&mut self, velocity: Option<f64>, now: Instant) {
+        self.coast = None;
+        if !self.config.enabled {
+            return;
+        }
+
+        let Some(velocity) = velocity else {
+            return;
+        };
+        let v0 = velocity.clamp(-self.config.max_velocity, self.config.max_velocity);
+        let decay = Decay {
+            v0,
+            tau: Duration::from_millis(self.config.time_constant_ms).as_secs_f64(),
+        };
+        let stop = decay.stop_time(self.config.min_velocity);
+        if stop > 0.0 {
+            eprintln!("🛝 Kinetic scroll at {:.1} detents/s", v0);
+            self.coast = Some(Coast {
+                decay,
+                started: now,
+                elapsed: 0.0,
+                stop,
+                next_tick: now + self.tick(),
+            });
+        }
+    }
+
+    /// Stop any inertia immediately
+    pub fn cancel(&mut self) {
+        self.coast = None;
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.coast.as_ref().map(|c| c.next_tick)
+    }
+
+    fn tick(&self) -> Duration {
+        Duration::from_millis(self.config.tick_ms)
+    }
+
+    /// Advance the coast, returning the hi-res units due now
+    pub fn poll(&mut self, now: Instant) -> Option<f64> {
+        let tick = self.tick();
+        let coast = self.coast.as_mut()?;
+        if now < coast.next_tick {
+            return None;
+        }
+
+        let t = now
+            .duration_since(coast.started)
+            .as_secs_f64()
+            .min(coast.stop);
+        let distance = coast.decay.distance(coast.elapsed, t);
+        coast.elapsed = t;
+        coast.next_tick = now + tick;
+
+        if t >= coast.stop {
+            self.coast = None;
+        }
+        Some(distance * f64::from(HI_RES_PER_DETENT))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Legacy and hi-res totals over `presses` steps of `step` hi-res units
+    fn totals(wheel: &mut WheelAccumulator, step: f64, presses: usize) -> (i32, i32) {
+        (0..presses).fold((0, 0), |(hi_res, legacy), _| {
+            let (h, l) = wheel.add(step);
+            (hi_res + h, legacy + l)
+        })
+    }
+
+    #[test]
+    fn steps_of_40_carry_a_detent_every_third_press() {
+        let mut wheel = WheelAccumulator::default();
+        let legacy: Vec<i32> = (0..6).map(|_| wheel.add(40.0).1).collect();
+        assert_eq!(legacy, [0, 0, 1, 0, 0, 1]);
+        assert_eq!(totals(&mut wheel, 40.0, 30), (1200, 10));
+    }
+
+    #[test]
+    fn steps_of_180_alternate_one_and_two_detents() {
+        let mut wheel = WheelAccumulator::default();
+        let legacy: Vec<i32> = (0..4).map(|_| wheel.add(180.0).1).collect();
+        assert_eq!(legacy, [1, 2, 1, 2]);
+        assert_eq!(totals(&mut wheel, 180.0, 20), (3600, 30));
+    }
+
+    #[test]
+    fn legacy_detents_follow_the_hi_res_total_downwards_too() {
+        let mut wheel = WheelAccumulator::default();
+        for step in [-40.0, -180.0, -0.5, -33.3] {
+            let (hi_res, legacy) = totals(&mut wheel, step, 36);
+            assert_eq!(legacy, hi_res / HI_RES_PER_DETENT, "step {}", step);
+            wheel.reset();
+        }
+    }
+
+    #[test]
+    fn fractions_add_up_to_whole_units() {
+        let mut wheel = WheelAccumulator::default();
+        assert_eq!(wheel.add(0.4), (0, 0));
+        assert_eq!(wheel.add(0.4), (0, 0));
+        assert_eq!(wheel.add(0.4), (1, 0));
+    }
+
+    #[test]
+    fn a_direction_change_drops_the_remainder() {
+        let mut wheel = WheelAccumulator::default();
+        assert_eq!(wheel.add(100.5), (100, 0));
+        // Without the reset, 100 units up would cancel most of this
+        assert_eq!(wheel.add(-120.0), (-120, -1));
+        assert_eq!(wheel.add(-40.0), (-40, 0));
+    }
+
+    #[test]
+    fn reset_drops_the_remainder() {
+        let mut wheel = WheelAccumulator::default();
+        wheel.add(100.0);
+        wheel.reset();
+        assert_eq!(wheel.add(40.0), (40, 0));
+    }
+
+    fn kinetic() -> Kinetic {
+        Kinetic::new(KineticConfig {
+            enabled: true,
+            ..KineticConfig::default()
+        })
+    }
+
+    /// Poll at every deadline until the coast ends; (ticks, hi-res units)
+    fn coast(kinetic: &mut Kinetic) -> (u32, f64) {
+        let (mut ticks, mut units) = (0, 0.0);
+        while let Some(at) = kinetic.next_deadline() {
+            units += kinetic.poll(at).expect("due at its deadline");
+            ticks += 1;
+            assert!(ticks < 1000, "the coast never stops");
+        }
+        (ticks, units)
+    }
+
+    #[test]
+    fn decay_covers_v0_tau_in_total() {
+        let decay = Decay { v0: 20.0, tau: 0.3 };
+        assert!((decay.distance(0.0, 100.0) - 6.0).abs() < 1e-9);
+        // Piecewise sums to the same, whatever the tick boundaries
+        let pieces: f64 = [0.0, 0.013, 0.05, 0.2, 0.7, 100.0]
+            .windows(2)
+            .map(|w| decay.distance(w[0], w[1]))
+            .sum();
+        assert!((pieces - 6.0).abs() < 1e-9);
+        assert!(
+            Decay {
+                v0: -20.0,
+                tau: 0.3
+            }
+            .distance(0.0, 1.0)
+                < 0.0
+        );
+    }
+
+    #[test]
+    fn decay_reaches_the_stop_velocity() {
+        let decay = Decay {
+            v0: 40.0,
+            tau: 0.325,
+        };
+        let stop = decay.stop_time(2.0);
+        let velocity = decay.v0 * (-stop / decay.tau).exp();
+        assert!((velocity - 2.0).abs() < 1e-9);
+        assert_eq!(Decay { v0: 1.5, tau: 0.3 }.stop_time(2.0), 0.0);
+        assert_eq!(
+            Decay {
+                v0: -40.0,
+                tau: 0.325
+            }
+            .stop_time(2.0),
+            stop
+        );
+    }
+
+    #[test]
+    fn release_velocity_is_the_repeat_rate_times_the_step() {
+        // 11 steps over 400ms: 10 repeats, 25 steps/s, of 2 detents each
+        let velocity = release_velocity(2.0, 11, Duration::from_millis(400)).unwrap();
+        assert!((velocity - 50.0).abs() < 1e-9);
+        assert!(release_velocity(-1.0, 5, Duration::from_millis(100)).unwrap() < 0.0);
+        // A tap has no rate
+        assert_eq!(release_velocity(1.0, 1, Duration::from_millis(300)), None);
+        assert_eq!(release_velocity(1.0, 5, Duration::ZERO), None);
+    }
+
+    #[test]
+    fn a_coast_stops_in_bounded_ticks_having_covered_the_decay() {
+        let mut kinetic = kinetic();
+        kinetic.release(Some(30.0), Instant::now());
+        let (ticks, units) = coast(&mut kinetic);
+        let decay = Decay {
+            v0: 30.0,
+            tau: 0.325,
+        };
+        let stop = decay.stop_time(2.0);
+        // One frame per 16ms tick up to the stop time, give or take the last
+        let expected = (stop / 0.016).ceil() as u32;
+        assert!(
+            ticks.abs_diff(expected) <= 1,
+            "{} ticks for {}",
+            ticks,
+            expected
+        );
+        let distance = decay.distance(0.0, stop) * f64::from(HI_RES_PER_DETENT);
+        assert!((units - distance).abs() < 1e-6);
+        assert_eq!(kinetic.next_deadline(), None);
+    }
+
+    #[test]
+    fn release_velocity_is_capped() {
+        let mut capped = kinetic();
+        capped.release(Some(500.0), Instant::now());
+        let mut at_cap = kinetic();
+        at_cap.release(Some(60.0), Instant::now());
+        let (capped, at_cap) = (coast(&mut capped).1, coast(&mut at_cap).1);
+        assert!((capped - at_cap).abs() < 1e-6);
+    }
+
+    #[test]
+    fn nothing_coasts_when_slow_disabled_or_a_tap() {
+        let mut kinetic = kinetic();
+        kinetic.release(Some(1.0), Instant::now());
+        assert_eq!(kinetic.next_deadline(), None);
+        kinetic.release(None, Instant::now());
+        assert_eq!(kinetic.next_deadline(), None);
+        let mut disabled = Kinetic::new(KineticConfig::default());
+        disabled.release(Some(30.0), Instant::now());
+        assert_eq!(disabled.next_deadline(), None);
+    }
+
+    #[test]
+    fn a_coast_is_cancelled_at_once() {
+        let mut kinetic = kinetic();
+        let start = Instant::now();
+        kinetic.release(Some(30.0), start);
+        let first = kinetic.next_deadline().unwrap();
+        assert!(kinetic.poll(first).is_some());
+        kinetic.cancel();
+        assert_eq!(kinetic.next_deadline(), None);
+        assert_eq!(kinetic.poll(first + Duration::from_millis(16)), None);
+    }
+
+    #[test]
+    fn polling_early_emits_nothing() {
+        let mut kinetic = kinetic();
+        let start = Instant::now();
+        kinetic.release(Some(30.0), start);
+        assert_eq!(kinetic.poll(start + Duration::from_millis(5)), None);
+    }
+}
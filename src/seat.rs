@@ -0,0 +1,146 @@
+//! `--seat NAME`: one instance per seat on a multi-seat machine
+//!
+//! A device's seat is udev's ID_SEAT property, read straight from the udev
+//! database (/run/udev/data/c<major>:<minor>) so no libudev is linked; a
+//! device without one is on seat0, as logind assigns it. With a seat set,
+//! auto-detection only considers that seat's devices. Virtual device names,
+//! the PID file, control socket and state file get the seat as a suffix, so
+//! instances on different seats never collide.
+
+use anyhow::{bail, Result};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Where udevd keeps each device's properties
+pub const UDEV_DATA: &str = "/run/udev/data";
+
+/// The seat of devices udev didn't assign to one
+pub const DEFAULT_SEAT: &str = "seat0";
+
+static SEAT: OnceLock<Option<String>> = OnceLock::new();
+
+/// Record `--seat`; call once, before anything names a device or file
+pub fn set(seat: Option<String>) -> Result<()> {
+    if let Some(seat) = &seat {
+        // logind's own rule for seat names
+        let valid = seat.strip_prefix("seat").is_some_and(|rest| {
+            !rest.is_empty()
+                && rest
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        });
+        if !valid {
+            bail!(
+                "--seat {}: seat names are 'seat' followed by letters, digits, '-' or '_'",
+                seat
+            );
+        }
+    }
+    let _ = SEAT.set(seat);
+    Ok(())
+}
+
+/// The seat given with `--seat`, if any
+pub fn get() -> Option<&'static str> {
+    SEAT.get().and_then(|seat| seat.as_deref())
+}
+
+/// `name` with `-SEAT` appended when a seat is set
+pub fn suffixed(name: &str) -> String {
+    match get() {
+        Some(seat) => format!("{}-{}", name, seat),
+        None => name.to_string(),
+    }
+}
+
+/// `stem.extension`, with the seat between them when one is set
+pub fn file_name(stem: &str, extension: &str) -> String {
+    format!("{}.{}", suffixed(stem), extension)
+}
+
+/// Whether the event node at `path` may be auto-detected: always without a seat
+pub fn accepts(path: &Path) -> bool {
+    match get() {
+        Some(seat) => seat_of(Path::new(UDEV_DATA), path) == seat,
+        None => true,
+    }
+}
+
+/// ID_SEAT of the character device at `path`, as recorded under `udev_data`
+pub fn seat_of(udev_data: &Path, path: &Path) -> String {
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return DEFAULT_SEAT.to_string();
+    };
+    let rdev = metadata.rdev();
+    let entry = udev_data.join(format!("c{}:{}", libc::major(rdev), libc::minor(rdev)));
+    std::fs::read_to_string(entry)
+        .ok()
+        .and_then(|data| id_seat(&data).map(str::to_string))
+        .unwrap_or_else(|| DEFAULT_SEAT.to_string())
+}
+
+/// The ID_SEAT among a udev database entry's `E:KEY=VALUE` lines
+pub fn id_seat(data: &str) -> Option<&str> {
+    data.lines()
+        .find_map(|line| line.strip_prefix("E:ID_SEAT="))
+        .filter(|seat| !seat.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// A udev database holding `entry` for /dev/null (c1:3), if given
+    fn udev_data(name: &str, entry: Option<&str>) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "touch-layer-test-udev-{}-{}",
+            name,
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        if let Some(entry) = entry {
+            fs::write(dir.join("c1:3"), entry).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn the_seat_comes_from_the_device_entry() {
+        let dir = udev_data(
+            "hit",
+            Some("S:input/by-path/platform-i8042-serio-0-event-kbd\nE:ID_INPUT=1\nE:ID_SEAT=seat1\nG:seat\n"),
+        );
+        assert_eq!(seat_of(&dir, Path::new("/dev/null")), "seat1");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn devices_without_a_seat_are_on_seat0() {
+        let dir = udev_data("none", Some("E:ID_INPUT=1\nE:ID_INPUT_KEYBOARD=1\n"));
+        assert_eq!(seat_of(&dir, Path::new("/dev/null")), DEFAULT_SEAT);
+        fs::write(dir.join("c1:3"), "E:ID_SEAT=\n").unwrap();
+        assert_eq!(seat_of(&dir, Path::new("/dev/null")), DEFAULT_SEAT);
+        fs::remove_dir_all(&dir).unwrap();
+
+        // No entry at all, or no device
+        let dir = udev_data("missing", None);
+        assert_eq!(seat_of(&dir, Path::new("/dev/null")), DEFAULT_SEAT);
+        assert_eq!(
+            seat_of(&dir, Path::new("/dev/input/no-such-event")),
+            DEFAULT_SEAT
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn only_the_id_seat_property_counts() {
+        assert_eq!(
+            id_seat("E:ID_SEAT_OLD=seat9\nE:ID_SEAT=seat-2\n"),
+            Some("seat-2")
+        );
+        assert_eq!(id_seat("S:ID_SEAT=seat3\n"), None);
+        assert_eq!(id_seat(""), None);
+    }
+}
@@ -0,0 +1,325 @@
+//! logind session locks and suspend
+//!
+//! A grabbed keyboard keeps flowing through us while the screen is locked, so
+//! a brushed touchpad could still click on the lock screen. A thread follows
+//! the session's Lock/Unlock signals and its LockedHint property on the
+//! system bus and wakes the keyboard loop through a pipe, which then forces
+//! the layer off. Another, with `[suspend]`, follows PrepareForSleep so the
+//! loop can let go of the keyboard before the system sleeps and take it back,
+//! possibly at a new event node, after resume. Without a system bus or logind
+//! nothing is followed, and startup never waits on either.
+//!
+//! The session is our own (GetSessionByPID), else logind's "auto" one (a user
+//! service's display session), else the one active on seat0 when we start.
+
+use crate::config::{SessionLockConfig, SuspendConfig};
+use crate::shutdown;
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// A state set by a bus thread, and a pipe that becomes readable on changes
+pub struct Flag {
+    on: AtomicBool,
+    read: OwnedFd,
+    write: OwnedFd,
+}
+
+impl Flag {
+    fn new(what: &str) -> Option<Self> {
+        match shutdown::pipe(what) {
+            Ok((read, write)) => Some(Self {
+                on: AtomicBool::new(false),
+                read,
+                write,
+            }),
+            Err(err) => {
+                eprintln!("⚠️  {:#}; not following the {}", err, what);
+                None
+            }
+        }
+    }
+
+    pub fn get(&self) -> bool {
+        self.on.load(Ordering::SeqCst)
+    }
+
+    /// Descriptor that becomes readable when the state may have changed
+    pub fn fd(&self) -> RawFd {
+        self.read.as_raw_fd()
+    }
+
+    /// Empty the pipe after a wakeup
+    pub fn drain(&self) {
+        let mut buf = [0u8; 64];
+        // SAFETY: reading into a valid buffer from our own non-blocking pipe
+        while unsafe { libc::read(self.read.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) } > 0 {
+        }
+    }
+
+    #[cfg_attr(not(feature = "logind"), allow(dead_code))]
+    fn set(&self, on: bool) {
+        if self.on.swap(on, Ordering::SeqCst) != on {
+            // SAFETY: writing one byte from a valid buffer to our own pipe
+            unsafe { libc::write(self.write.as_raw_fd(), [1u8].as_ptr().cast(), 1) };
+        }
+    }
+}
+
+/// Whether the session is locked
+pub struct SessionLock;
+
+impl SessionLock {
+    /// Start following the session, or None with `enabled = false`
+    pub fn watch(config: &SessionLockConfig) -> Option<Arc<Flag>> {
+        if !config.enabled {
+            return None;
+        }
+        let lock = Arc::new(Flag::new("session lock")?);
+        spawn("session locks", lock.clone(), |lock| logind::follow(&lock));
+        Some(lock)
+    }
+}
+
+/// Whether the system is about to sleep, under a logind delay inhibitor
+///
+/// The inhibitor is held while awake. On PrepareForSleep the flag goes up,
+/// and the lock is let go once the keyboard loop has released its grab
+/// (`released`) or after `max_delay_ms`, whichever comes first.
+pub struct Suspend {
+    pub sleeping: Flag,
+    released: Mutex<bool>,
+    condvar: Condvar,
+    max_delay: Duration,
+}
+
+impl Suspend {
+    /// Start following sleep, or None unless `[suspend] enabled`
+    pub fn watch(config: &SuspendConfig) -> Option<Arc<Self>> {
+        if !config.enabled {
+            return None;
+        }
+        let suspend = Arc::new(Self {
+            sleeping: Flag::new("suspend")?,
+            released: Mutex::new(false),
+            condvar: Condvar::new(),
+            max_delay: Duration::from_millis(config.max_delay_ms),
+        });
+        spawn("suspend", suspend.clone(), |suspend| {
+            logind::follow_sleep(&suspend)
+        });
+        Some(suspend)
+    }
+
+    /// The keyboard loop is ready for sleep: outputs and grab released
+    pub fn released(&self) {
+        *self.released.lock().unwrap() = true;
+        self.condvar.notify_all();
+    }
+
+    /// Announce sleep and wait, up to `max_delay`, for `released`; false on timeout
+    #[cfg_attr(not(feature = "logind"), allow(dead_code))]
+    fn prepare(&self) -> bool {
+        *self.released.lock().unwrap() = false;
+        self.sleeping.set(true);
+        let released = self.released.lock().unwrap();
+        let (released, _) = self
+            .condvar
+            .wait_timeout_while(released, self.max_delay, |released| !*released)
+            .unwrap();
+        *released
+    }
+}
+
+#[cfg(feature = "logind")]
+fn spawn<T: Send + Sync + 'static>(
+    what: &'static str,
+    state: Arc<T>,
+    follow: impl FnOnce(Arc<T>) -> anyhow::Result<()> + Send + 'static,
+) {
+    std::thread::spawn(move || {
+        if let Err(err) = follow(state) {
+            eprintln!("🔐 Not following {}: {:#}", what, err);
+        }
+    });
+}
+
+/// Built without logind support: nothing is ever locked or asleep
+#[cfg(not(feature = "logind"))]
+fn spawn<T>(_what: &str, _state: Arc<T>, _follow: impl FnOnce(Arc<T>) -> anyhow::Result<()>) {}
+
+/// Stand-ins so `watch` reads the same either way
+#[cfg(not(feature = "logind"))]
+mod logind {
+    pub fn follow(_lock: &super::Flag) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    pub fn follow_sleep(_suspend: &super::Suspend) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "logind")]
+mod logind {
+    use super::{Flag, Suspend};
+    use anyhow::{Context, Result};
+    use std::collections::HashMap;
+    use zbus::blocking::{Connection, MessageIterator};
+    use zbus::message::Type;
+    use zbus::zvariant::{DynamicType, OwnedFd, OwnedObjectPath, OwnedValue};
+    use zbus::MatchRule;
+
+    const SERVICE: &str = "org.freedesktop.login1";
+    const MANAGER_PATH: &str = "/org/freedesktop/login1";
+    const MANAGER: &str = "org.freedesktop.login1.Manager";
+    const SESSION: &str = "org.freedesktop.login1.Session";
+    const SEAT: &str = "org.freedesktop.login1.Seat";
+    const PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+
+    /// PropertiesChanged: interface, changed values, invalidated names
+    type Changed = (String, HashMap<String, OwnedValue>, Vec<String>);
+
+    /// Follow the session until the bus goes away
+    pub fn follow(lock: &Flag) -> Result<()> {
+        let bus = Connection::system().context("no system bus")?;
+        let session = find_session(&bus).context("no logind session")?;
+
+        // Subscribed before the initial read, so no change falls in between
+        let rule = MatchRule::builder()
+            .msg_type(Type::Signal)
+            .sender(SERVICE)?
+            .path(session.as_str())?
+            .build();
+        let signals = MessageIterator::for_match_rule(rule, &bus, None)
+            .context("Failed to subscribe to session signals")?;
+
+        let hint = property(&bus, session.as_str(), SESSION, "LockedHint")?;
+        eprintln!("🔐 Following session locks on {}", session.as_str());
+        lock.set(bool::try_from(hint).unwrap_or(false));
+
+        for message in signals {
+            let message = message.context("Failed to read from the system bus")?;
+            let header = message.header();
+            match header.member().map(|member| member.as_str()) {
+                Some("Lock") => lock.set(true),
+                Some("Unlock") => lock.set(false),
+                Some("PropertiesChanged") => {
+                    let Ok((_, changed, _)) = message.body().deserialize::<Changed>() else {
+                        continue;
+                    };
+                    if let Some(hint) = changed.get("LockedHint") {
+                        if let Ok(locked) = bool::try_from(hint) {
+                            lock.set(locked);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Follow PrepareForSleep, holding a delay inhibitor while awake
+    pub fn follow_sleep(suspend: &Suspend) -> Result<()> {
+        let bus = Connection::system().context("no system bus")?;
+        let rule = MatchRule::builder()
+            .msg_type(Type::Signal)
+            .sender(SERVICE)?
+            .path(MANAGER_PATH)?
+            .interface(MANAGER)?
+            .member("PrepareForSleep")?
+            .build();
+        let signals = MessageIterator::for_match_rule(rule, &bus, None)
+            .context("Failed to subscribe to PrepareForSleep")?;
+        let mut inhibitor = Some(inhibit(&bus)?);
+        eprintln!("💤 Releasing the keyboard around suspend");
+
+        for message in signals {
+            let message = message.context("Failed to read from the system bus")?;
+            let Ok(sleeping) = message.body().deserialize::<bool>() else {
+                continue;
+            };
+            if sleeping {
+                if !suspend.prepare() {
+                    eprintln!(
+                        "⚠️  Keyboard not released within {}ms; letting suspend proceed",
+                        suspend.max_delay.as_millis()
+                    );
+                }
+                // Dropping the descriptor is what lets logind go ahead
+                inhibitor = None;
+            } else {
+                suspend.sleeping.set(false);
+                if inhibitor.is_none() {
+                    inhibitor = inhibit(&bus).map_err(|err| eprintln!("⚠️  {:#}", err)).ok();
+                }
+            }
+        }
+        drop(inhibitor);
+        Ok(())
+    }
+
+    /// A delay lock on sleep, held for as long as the descriptor stays open
+    fn inhibit(bus: &Connection) -> Result<OwnedFd> {
+        let reply = bus
+            .call_method(
+                Some(SERVICE),
+                MANAGER_PATH,
+                Some(MANAGER),
+                "Inhibit",
+                &(
+                    "sleep",
+                    "touch-layer",
+                    "Release the keyboard grab before suspend",
+                    "delay",
+                ),
+            )
+            .context("Failed to take a sleep inhibitor")?;
+        let fd: OwnedFd = reply.body().deserialize()?;
+        Ok(fd)
+    }
+
+    fn find_session(bus: &Connection) -> Result<OwnedObjectPath> {
+        if let Some(path) = manager_session(bus, "GetSessionByPID", &(std::process::id(),)) {
+            return Ok(path);
+        }
+        if let Some(path) = manager_session(bus, "GetSession", &("auto",)) {
+            return Ok(path);
+        }
+        let active = property(
+            bus,
+            "/org/freedesktop/login1/seat/seat0",
+            SEAT,
+            "ActiveSession",
+        )?;
+        let (_, path) = <(String, OwnedObjectPath)>::try_from(active)
+            .context("Unexpected ActiveSession on seat0")?;
+        Ok(path)
+    }
+
+    /// A session path returned by a Manager method, if it has one for us
+    fn manager_session<B>(bus: &Connection, method: &str, body: &B) -> Option<OwnedObjectPath>
+    where
+        B: serde::Serialize + DynamicType,
+    {
+        let reply = bus
+            .call_method(Some(SERVICE), MANAGER_PATH, Some(MANAGER), method, body)
+            .ok()?;
+        reply.body().deserialize().ok()
+    }
+
+    fn property(bus: &Connection, path: &str, interface: &str, name: &str) -> Result<OwnedValue> {
+        let reply = bus
+            .call_method(
+                Some(SERVICE),
+                path,
+                Some(PROPERTIES),
+                "Get",
+                &(interface, name),
+            )
+            .with_context(|| format!("Failed to read {}.{}", interface, name))?;
+        Ok(reply.body().deserialize()?)
+    }
+}
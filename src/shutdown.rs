@@ -0,0 +1,50 @@
+use anyhow::{Context, Result};
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Self-pipe that wakes the grabbing loops out of poll() so they can release outputs and exit
+pub struct Shutdown {
+    read: OwnedFd,
+    write: OwnedFd,
+    requested: AtomicBool,
+}
+
+/// A non-blocking pipe, read end first
+pub fn pipe(what: &str) -> Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    // SAFETY: fds has room for the two descriptors pipe2 writes
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error()).context(format!("Failed to create {} pipe", what));
+    }
+    // SAFETY: pipe2 just returned these descriptors and nothing else owns them
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+impl Shutdown {
+    pub fn new() -> Result<Self> {
+        let (read, write) = pipe("shutdown")?;
+        Ok(Self {
+            read,
+            write,
+            requested: AtomicBool::new(false),
+        })
+    }
+
+    /// Ask the grabbing loops to stop; safe to call more than once
+    pub fn trigger(&self) {
+        if !self.requested.swap(true, Ordering::SeqCst) {
+            // SAFETY: writing one byte from a valid buffer to our own pipe
+            unsafe { libc::write(self.write.as_raw_fd(), [1u8].as_ptr().cast(), 1) };
+        }
+    }
+
+    pub fn requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Descriptor that becomes readable once shutdown is triggered
+    pub fn fd(&self) -> RawFd {
+        self.read.as_raw_fd()
+    }
+}
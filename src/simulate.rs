@@ -0,0 +1,623 @@
+//! `simulate SCRIPT`: run activation and routing against timed synthetic events
+//!
+//! Script lines are `+<offset> <event>`, offsets counted from the start
+//! (`ms`, `s`, or `us` as `stress` writes them) and non-decreasing:
+//!
+//! ```text
+//! +0ms    finger down
+//! +0ms    touchpad motion
+//! +50ms   mouse motion
+//! +100ms  key KEY_F 1
+//! +150ms  key KEY_F 0
+//! +200ms  finger up
+//! ```
+//!
+//! `hold begin` and `hold end` stand for libinput's hold gesture,
+//! `swipe begin 3`, `swipe update 0 -40` and `swipe end [cancelled]` for its
+//! swipes (`[sticky]`), `touchpad motion 12` gives the motion a distance
+//! for `adaptive_debounce`, and `fingers 2` sets the contact count that picks
+//! a finger layer.
+//! Output uses the same offsets: `mode active`, `key KEY_X 1`,
+//! `button BTN_LEFT 1`, `wheel <hi_res> <legacy>`, `warp <x> <y>`. No
+//! devices are opened.
+//!
+//! `parse-libinput FILE` shares the `--assert` comparison: it prints how each
+//! captured debug-events line parses.
+
+use crate::activation::{Activation, Transition};
+use crate::config::{Config, MappingCondition};
+use crate::keys;
+use crate::libinput;
+use crate::motion::LastMotion;
+use crate::remap::Remapper;
+use crate::sink::EventSink;
+use crate::swipe::{Direction, SwipeTracker};
+use crate::trigger::Trigger;
+use crate::typing::TypingDetector;
+use anyhow::{anyhow, bail, Context, Result};
+use evdev::{AttributeSet, EventType, InputEvent, InputEventKind, Key};
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How long timers keep running after the last scripted event
+const DRAIN_LIMIT: Duration = Duration::from_secs(5);
+
+/// Guard against a timer that never moves forward
+const MAX_TIMER_STEPS: usize = 100_000;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Step {
+    /// Touchpad motion of this |dx| + |dy|, 0 unless given
+    Motion(f64),
+    /// Activation mouse motion past its threshold
+    MouseMotion,
+    /// libinput hold gesture begin (true) or end
+    Hold(bool),
+    /// libinput swipe gesture with this many fingers
+    SwipeBegin(u32),
+    SwipeUpdate(f64, f64),
+    /// Swipe end, cancelled or not
+    SwipeEnd(bool),
+    FingerDown,
+    FingerUp,
+    /// Multitouch contacts, for `finger_layers`
+    Fingers(usize),
+    Key(Key, i32),
+}
+
+impl fmt::Display for Step {
+    /// As a script line writes it, after the offset
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Step::Motion(0.0) => write!(f, "touchpad motion"),
+            Step::Motion(distance) => write!(f, "touchpad motion {}", distance),
+            Step::MouseMotion => write!(f, "mouse motion"),
+            Step::Hold(true) => write!(f, "hold begin"),
+            Step::Hold(false) => write!(f, "hold end"),
+            Step::SwipeBegin(fingers) => write!(f, "swipe begin {}", fingers),
+            Step::SwipeUpdate(dx, dy) => write!(f, "swipe update {} {}", dx, dy),
+            Step::SwipeEnd(false) => write!(f, "swipe end"),
+            Step::SwipeEnd(true) => write!(f, "swipe end cancelled"),
+            Step::FingerDown => write!(f, "finger down"),
+            Step::FingerUp => write!(f, "finger up"),
+            Step::Fingers(count) => write!(f, "fingers {}", count),
+            Step::Key(key, value) => write!(f, "key {:?} {}", key, value),
+        }
+    }
+}
+
+/// A script of `steps`, as `simulate` reads it
+pub fn format_script(steps: &[(Duration, Step)]) -> String {
+    steps
+        .iter()
+        .map(|(at, step)| match at.subsec_micros() % 1000 {
+            0 => format!("+{}ms {}\n", at.as_millis(), step),
+            _ => format!("+{}us {}\n", at.as_micros(), step),
+        })
+        .collect()
+}
+
+fn parse_offset(text: &str) -> Option<Duration> {
+    let text = text.strip_prefix('+')?;
+    if let Some(us) = text.strip_suffix("us") {
+        return us.parse().ok().map(Duration::from_micros);
+    }
+    if let Some(ms) = text.strip_suffix("ms") {
+        return ms.parse().ok().map(Duration::from_millis);
+    }
+    let secs: f64 = text.strip_suffix('s')?.parse().ok()?;
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+fn parse_step(words: &[&str]) -> Result<Step> {
+    match words {
+        ["touchpad", "motion"] => Ok(Step::Motion(0.0)),
+        ["touchpad", "motion", distance] => match distance.parse::<f64>() {
+            Ok(distance) if distance >= 0.0 && distance.is_finite() => {
+                Ok(Step::Motion(distance))
+            }
+            _ => bail!("motion distance must be a non-negative number"),
+        },
+        ["mouse", "motion"] => Ok(Step::MouseMotion),
+        ["hold", "begin"] => Ok(Step::Hold(true)),
+        ["hold", "end"] => Ok(Step::Hold(false)),
+        ["swipe", "begin", fingers] => Ok(Step::SwipeBegin(
+            fingers.parse().context("swipe finger count must be a number")?,
+        )),
+        ["swipe", "update", dx, dy] => match (dx.parse::<f64>(), dy.parse::<f64>()) {
+            (Ok(dx), Ok(dy)) if dx.is_finite() && dy.is_finite() => Ok(Step::SwipeUpdate(dx, dy)),
+            _ => bail!("swipe deltas must be numbers"),
+        },
+        ["swipe", "end"] => Ok(Step::SwipeEnd(false)),
+        ["swipe", "end", "cancelled"] => Ok(Step::SwipeEnd(true)),
+        ["finger", "down"] => Ok(Step::FingerDown),
+        ["finger", "up"] => Ok(Step::FingerUp),
+        ["fingers", count] => Ok(Step::Fingers(
+            count.parse().context("finger count must be a number")?,
+        )),
+        ["key", name, value] => {
+            let key = keys::resolve(name)?;
+            let value = match *value {
+                "0" | "1" | "2" => value.parse()?,
+                _ => bail!("key value must be 0, 1 or 2"),
+            };
+            Ok(Step::Key(key, value))
+        }
+        _ => bail!(
+            "expected 'touchpad motion [DISTANCE]', 'mouse motion', 'hold begin|end', 'swipe begin FINGERS', 'swipe update DX DY', 'swipe end [cancelled]', 'finger down|up', 'fingers COUNT' or 'key NAME VALUE'"
+        ),
+    }
+}
+
+fn parse_script(text: &str) -> Result<Vec<(Duration, Step)>> {
+    let mut steps = Vec::new();
+    let mut last = Duration::ZERO;
+    for (index, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let parsed = parse_offset(words[0])
+            .ok_or_else(|| anyhow!("expected an offset like +100ms"))
+            .and_then(|at| {
+                if at < last {
+                    bail!("offsets must not go backwards");
+                }
+                Ok((at, parse_step(&words[1..])?))
+            })
+            .with_context(|| format!("line {}: {}", index + 1, line))?;
+        last = parsed.0;
+        steps.push(parsed);
+    }
+    Ok(steps)
+}
+
+/// What came out, for checks that need more than the text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    Key(Key, i32),
+    Button(Key, i32),
+    Mode(bool),
+}
+
+/// Collects output as text lines stamped with the simulated time
+struct Recorder {
+    start: Instant,
+    now: Instant,
+    /// Print each line as it comes, as `simulate` does
+    echo: bool,
+    lines: Vec<String>,
+    outputs: Vec<Output>,
+}
+
+impl Recorder {
+    fn log(&mut self, what: String) {
+        let line = format!("+{}ms {}", (self.now - self.start).as_millis(), what);
+        if self.echo {
+            println!("{}", line);
+        }
+        self.lines.push(line);
+    }
+}
+
+impl EventSink for Recorder {
+    fn forward(&mut self, event: InputEvent) -> Result<()> {
+        match event.kind() {
+            InputEventKind::Key(key) => self.key(key, event.value()),
+            _ => {
+                self.log(format!(
+                    "event {:?} {} {}",
+                    event.event_type(),
+                    event.code(),
+                    event.value()
+                ));
+                Ok(())
+            }
+        }
+    }
+
+    fn key(&mut self, key: Key, value: i32) -> Result<()> {
+        self.outputs.push(Output::Key(key, value));
+        self.log(format!("key {:?} {}", key, value));
+        Ok(())
+    }
+
+    fn button(&mut self, button: Key, value: i32) -> Result<()> {
+        self.outputs.push(Output::Button(button, value));
+        self.log(format!("button {:?} {}", button, value));
+        Ok(())
+    }
+
+    fn wheel(&mut self, hi_res: i32, legacy: i32) -> Result<()> {
+        if hi_res != 0 || legacy != 0 {
+            self.log(format!("wheel {} {}", hi_res, legacy));
+        }
+        Ok(())
+    }
+
+    fn warp(&mut self, x: i32, y: i32) -> Result<()> {
+        self.log(format!("warp {} {}", x, y));
+        Ok(())
+    }
+
+    /// Logged, never run
+    fn command(&mut self, command: &str, _env: &[(&str, String)]) -> Result<()> {
+        self.log(format!("command {}", command));
+        Ok(())
+    }
+
+    fn devices(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// Activation state as the two touchpad monitors would drive it
+pub struct Simulation {
+    remapper: Remapper,
+    out: Recorder,
+    last_motion: Arc<LastMotion>,
+    activation: Activation,
+    swipe: Option<SwipeTracker>,
+    typing: Option<TypingDetector>,
+    typing_cooldown: Duration,
+    condition: MappingCondition,
+    /// Scripted keys down, standing in for the keyboard's key state
+    pressed: AttributeSet<Key>,
+}
+
+impl Simulation {
+    /// A fresh run of `config` starting now; `echo` prints output lines as they come
+    pub fn new(config: &Config, echo: bool) -> Result<Self> {
+        let last_motion = Arc::new(LastMotion::new());
+        let start = Instant::now();
+        Ok(Self {
+            remapper: Remapper::new(config, last_motion.clone())?,
+            out: Recorder {
+                start,
+                now: start,
+                echo,
+                lines: Vec::new(),
+                outputs: Vec::new(),
+            },
+            last_motion,
+            activation: Activation::new(
+                config.activate_when.clone(),
+                config.debounce,
+                &config.adaptive_debounce,
+                // There is no device to detect from; a scripted lift is the tool_finger kind
+                match config.deactivate_on {
+                    Trigger::Auto => Trigger::ToolFinger,
+                    trigger => trigger,
+                },
+                config.mouse_idle(),
+                &config.hysteresis,
+            ),
+            swipe: SwipeTracker::new(&config.sticky),
+            typing: TypingDetector::new(&config.typing),
+            typing_cooldown: Duration::from_millis(config.typing.cooldown_ms),
+            condition: config.mapping_condition,
+            pressed: AttributeSet::new(),
+        })
+    }
+
+    /// Play `steps`, offsets from the start, then let timers run out
+    pub fn play(&mut self, steps: &[(Duration, Step)]) -> Result<()> {
+        let start = self.out.start;
+        let mut end = start;
+        for &(at, step) in steps {
+            end = start + at;
+            self.advance(end)?;
+            self.step(step)?;
+        }
+        self.advance(end + DRAIN_LIMIT)
+    }
+
+    /// Everything sent to the virtual devices so far, in order
+    pub fn outputs(&self) -> &[Output] {
+        &self.out.outputs
+    }
+
+    /// Whether a mapped key is still holding a button
+    pub fn holding_button(&self) -> bool {
+        self.remapper.holding_button()
+    }
+
+    /// Whether keys are remapped now; the simulation is always enabled
+    fn remapping(&self) -> bool {
+        self.condition.applies(true, self.activation.is_active())
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        [
+            self.remapper.next_deadline(),
+            self.activation.next_deadline(),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+
+    /// Fire every timer due up to `until`
+    fn advance(&mut self, until: Instant) -> Result<()> {
+        for _ in 0..MAX_TIMER_STEPS {
+            let Some(deadline) = self.next_deadline().filter(|&d| d <= until) else {
+                self.out.now = until;
+                return Ok(());
+            };
+            self.out.now = deadline;
+            let transition = self.activation.poll(deadline);
+            self.log_transition(transition);
+            self.remapper
+                .tick(deadline, self.remapping(), &mut self.out)?;
+            if self.remapper.hold_check_due(deadline) {
+                self.remapper
+                    .expire_holds(&self.pressed, deadline, &mut self.out)?;
+            }
+        }
+        bail!("timers did not settle")
+    }
+
+    fn step(&mut self, step: Step) -> Result<()> {
+        let now = self.out.now;
+        match step {
+            // As in the libinput monitor, a swipe's motion is not pointer motion
+            Step::Motion(_) if self.swipe.as_ref().is_some_and(SwipeTracker::in_progress) => {}
+            Step::Motion(distance) => {
+                self.last_motion.record(now);
+                let transition = self.activation.motion(now, distance);
+                self.log_transition(transition);
+            }
+            Step::MouseMotion => {
+                let transition = self.activation.mouse_motion(now);
+                self.log_transition(transition);
+            }
+            Step::Hold(begin) => {
+                let transition = self.activation.hold(now, begin);
+                self.log_transition(transition);
+            }
+            Step::SwipeBegin(fingers) => {
+                if let Some(swipe) = &mut self.swipe {
+                    swipe.begin(now, fingers);
+                }
+            }
+            Step::SwipeUpdate(dx, dy) => {
+                if let Some(swipe) = &mut self.swipe {
+                    swipe.update(dx, dy);
+                }
+            }
+            Step::SwipeEnd(cancelled) => {
+                let direction = self
+                    .swipe
+                    .as_mut()
+                    .and_then(|swipe| swipe.end(now, cancelled));
+                if let Some(direction) = direction {
+                    let transition = self.activation.sticky(now, direction == Direction::Up);
+                    self.log_transition(transition);
+                }
+            }
+            Step::FingerDown => {
+                let transition = self.activation.finger_down(now);
+                self.log_transition(transition);
+            }
+            Step::FingerUp => {
+                let transition = self.activation.finger_up(now);
+                self.log_transition(transition);
+            }
+            Step::Fingers(count) => self.remapper.set_fingers(count, now, &mut self.out)?,
+            Step::Key(key, value) => {
+                let event = InputEvent::new(EventType::KEY, key.code(), value);
+                if value == 0 {
+                    self.pressed.remove(key);
+                } else {
+                    self.pressed.insert(key);
+                }
+                let typing = self.remapping()
+                    && self
+                        .typing
+                        .as_mut()
+                        .is_some_and(|typing| typing.observe(&event, &self.remapper, now));
+                if typing {
+                    let transition = self.activation.typing(now, self.typing_cooldown);
+                    self.log_transition(transition);
+                }
+                self.remapper
+                    .handle(event, self.remapping(), now, &mut self.out)?;
+            }
+        }
+        self.advance(now)
+    }
+
+    fn log_transition(&mut self, transition: Option<Transition>) {
+        if let Some(Transition { active, reason }) = transition {
+            self.out.outputs.push(Output::Mode(active));
+            let state = if active { "active" } else { "inactive" };
+            self.out.log(format!("mode {} ({})", state, reason));
+        }
+    }
+}
+
+/// Run `script` and print the resulting output; with `expected`, compare against it
+pub fn run(config: &Config, script: &Path, expected: Option<&Path>) -> Result<()> {
+    let text = std::fs::read_to_string(script)
+        .with_context(|| format!("Failed to read {}", script.display()))?;
+    let steps = parse_script(&text).with_context(|| format!("In {}", script.display()))?;
+
+    let mut sim = Simulation::new(config, true)?;
+    sim.play(&steps)?;
+
+    match expected {
+        Some(path) => compare("Simulation", &sim.out.lines, path),
+        None => Ok(()),
+    }
+}
+
+/// Print how each line of captured `libinput debug-events` output parses
+pub fn parse_libinput(input: &Path, expected: Option<&Path>) -> Result<()> {
+    let text = if input == Path::new("-") {
+        std::io::read_to_string(std::io::stdin()).context("Failed to read stdin")?
+    } else {
+        std::fs::read_to_string(input)
+            .with_context(|| format!("Failed to read {}", input.display()))?
+    };
+    let lines: Vec<String> = text.lines().filter_map(libinput::describe).collect();
+    for line in &lines {
+        println!("{}", line);
+    }
+    match expected {
+        Some(path) => compare("Parsed", &lines, path),
+        None => Ok(()),
+    }
+}
+
+fn compare(what: &str, actual: &[String], path: &Path) -> Result<()> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let expected: Vec<&str> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+
+    let mismatch = (0..actual.len().max(expected.len()))
+        .find(|&i| actual.get(i).map(String::as_str) != expected.get(i).copied());
+    if let Some(i) = mismatch {
+        eprintln!(
+            "❌ Output differs from {} at line {}",
+            path.display(),
+            i + 1
+        );
+        eprintln!(
+            "   expected: {}",
+            expected.get(i).unwrap_or(&"(end of output)")
+        );
+        eprintln!(
+            "   actual:   {}",
+            actual.get(i).map_or("(end of output)", String::as_str)
+        );
+        bail!("{} output does not match {}", what, path.display());
+    }
+    eprintln!("✅ Output matches {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The output lines of `script` played against `config`
+    fn play(config: &Config, script: &str) -> Vec<String> {
+        let mut sim = Simulation::new(config, false).unwrap();
+        sim.play(&parse_script(script).unwrap()).unwrap();
+        assert!(!sim.holding_button(), "every click was released");
+        sim.out.lines
+    }
+
+    fn config(text: &str) -> Config {
+        Config::parse(text, Path::new("test.toml")).unwrap()
+    }
+
+    const INVERTED: &str = "mapping_condition = \"inactive\"\n[mappings]\nKEY_F = \"BTN_LEFT\"\n";
+
+    #[test]
+    fn a_key_typed_across_the_mode_turning_off_is_released_as_typed() {
+        let lines = play(
+            &config(INVERTED),
+            "+0ms finger down\n+0ms touchpad motion\n+100ms key KEY_F 1\n\
+             +150ms finger up\n+200ms key KEY_F 0\n+300ms key KEY_F 1\n+350ms key KEY_F 0\n",
+        );
+        assert_eq!(
+            lines,
+            [
+                "+0ms mode active (pointer_motion)",
+                "+100ms key KEY_F 1",
+                "+150ms mode inactive (finger_up)",
+                "+200ms key KEY_F 0",
+                "+300ms button BTN_LEFT 1",
+                "+350ms button BTN_LEFT 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn a_click_held_across_the_mode_turning_on_is_released_as_a_click() {
+        let lines = play(
+            &config(INVERTED),
+            "+0ms key KEY_F 1\n+50ms finger down\n+50ms touchpad motion\n\
+             +100ms key KEY_F 0\n+150ms key KEY_F 1\n+200ms key KEY_F 0\n",
+        );
+        assert_eq!(
+            lines,
+            [
+                "+0ms button BTN_LEFT 1",
+                "+50ms mode active (pointer_motion)",
+                "+100ms button BTN_LEFT 0",
+                "+150ms key KEY_F 1",
+                "+200ms key KEY_F 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn an_always_table_ignores_the_mode() {
+        let lines = play(
+            &config("mapping_condition = \"always\"\n[mappings]\nKEY_F = \"BTN_LEFT\"\n"),
+            "+0ms key KEY_F 1\n+50ms key KEY_F 0\n+100ms finger down\n+100ms touchpad motion\n\
+             +150ms key KEY_F 1\n+200ms key KEY_F 0\n",
+        );
+        assert_eq!(
+            lines,
+            [
+                "+0ms button BTN_LEFT 1",
+                "+50ms button BTN_LEFT 0",
+                "+100ms mode active (pointer_motion)",
+                "+150ms button BTN_LEFT 1",
+                "+200ms button BTN_LEFT 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn a_mapping_set_brings_its_own_condition() {
+        let device =
+            std::env::temp_dir().join(format!("touch-layer-test-set-{}", std::process::id()));
+        std::fs::write(&device, "").unwrap();
+        let config = config(&format!(
+            "[mappings]\nKEY_F = \"BTN_LEFT\"\n\
+             [[mapping_sets]]\ndevice = \"{}\"\ncondition = \"inactive\"\n\
+             mappings = {{ KEY_F = \"BTN_RIGHT\" }}\n",
+            device.display()
+        ));
+        let script = "+0ms key KEY_F 1\n+50ms key KEY_F 0\n";
+        assert_eq!(
+            play(&config, script),
+            ["+0ms key KEY_F 1", "+50ms key KEY_F 0"]
+        );
+        let set = config.for_keyboard(&device);
+        assert_eq!(set.mapping_condition, MappingCondition::Inactive);
+        assert_eq!(
+            play(&set, script),
+            ["+0ms button BTN_RIGHT 1", "+50ms button BTN_RIGHT 0"]
+        );
+        std::fs::remove_file(&device).unwrap();
+    }
+
+    #[test]
+    fn no_table_applies_while_disengaged() {
+        for condition in [
+            MappingCondition::Active,
+            MappingCondition::Inactive,
+            MappingCondition::Always,
+        ] {
+            assert!(!condition.applies(false, true), "{:?}", condition);
+            assert!(!condition.applies(false, false), "{:?}", condition);
+        }
+        assert!(MappingCondition::Active.applies(true, true));
+        assert!(!MappingCondition::Active.applies(true, false));
+        assert!(!MappingCondition::Inactive.applies(true, true));
+        assert!(MappingCondition::Inactive.applies(true, false));
+        assert!(MappingCondition::Always.applies(true, false));
+    }
+}
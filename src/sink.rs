@@ -0,0 +1,79 @@
+use crate::hooks;
+use anyhow::Result;
+use evdev::{InputEvent, InputEventKind, Key, Synchronization};
+use std::io;
+
+/// Events a frame has room for before its buffer grows; keyboards send a few
+const FRAME_CAPACITY: usize = 64;
+
+/// Where remapped and forwarded input goes; uinput by default, see `--backend`
+pub trait EventSink {
+    /// Pass a physical keyboard event through unchanged
+    fn forward(&mut self, event: InputEvent) -> Result<()>;
+
+    fn key(&mut self, key: Key, value: i32) -> Result<()>;
+
+    /// Pointer button press (1) or release (0)
+    fn button(&mut self, button: Key, value: i32) -> Result<()>;
+
+    /// One vertical wheel frame: 120ths of a detent plus whole legacy detents
+    fn wheel(&mut self, hi_res: i32, legacy: i32) -> Result<()>;
+
+    /// Jump the pointer to absolute coordinates in the `[warp]` range
+    ///
+    /// Backends without an absolute device drop it; `Backend::check` warns.
+    fn warp(&mut self, _x: i32, _y: i32) -> Result<()> {
+        Ok(())
+    }
+
+    /// Run a `command` mapping with `env` set, without waiting for it
+    ///
+    /// Not input, but it goes through here so a simulation can log it instead.
+    fn command(&mut self, command: &str, env: &[(&str, String)]) -> Result<()> {
+        hooks::spawn_detached(command, env);
+        Ok(())
+    }
+
+    /// The virtual devices it created, for the status output
+    fn devices(&self) -> Vec<String>;
+}
+
+/// Forwarded events of the frame in progress, written in one go at its SYN_REPORT
+///
+/// The buffer is allocated once and reused, so forwarding doesn't allocate.
+pub struct Frame(Vec<InputEvent>);
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Frame {
+    pub fn new() -> Self {
+        Self(Vec::with_capacity(FRAME_CAPACITY))
+    }
+
+    /// Add a forwarded event; true at its SYN_REPORT, which the writer adds itself
+    pub fn push(&mut self, event: InputEvent) -> bool {
+        if event.kind() == InputEventKind::Synchronization(Synchronization::SYN_REPORT) {
+            return true;
+        }
+        self.0.push(event);
+        false
+    }
+
+    pub fn extend(&mut self, events: impl IntoIterator<Item = InputEvent>) {
+        self.0.extend(events);
+    }
+
+    /// Hand the pending events to `write`, unless there are none, and start a new frame
+    pub fn flush(&mut self, write: impl FnOnce(&[InputEvent]) -> io::Result<()>) -> Result<()> {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+        let result = write(&self.0);
+        self.0.clear();
+        Ok(result?)
+    }
+}
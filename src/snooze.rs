@@ -0,0 +1,179 @@
+//! `ctl pause [DURATION]` and `ctl resume`: the layer off for a while
+//!
+//! A pause turns the enabled switch off, as SIGUSR1 would, and remembers
+//! when to turn it back on: after DURATION, or never for a plain `pause`,
+//! which lasts until `resume` (or SIGUSR1). A later pause replaces the
+//! deadline. The socket thread only queues the request; the keyboard loop
+//! applies it, so held outputs are released there, and it owns the timer.
+//! The deadline is wall-clock time, so it runs through a suspend and, with
+//! `persist_state`, a restart.
+
+use crate::shutdown;
+use anyhow::{bail, Result};
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Longest timed pause
+const MAX_PAUSE: Duration = Duration::from_secs(7 * 24 * 3600);
+
+/// How long the keyboard loop may sleep on a deadline before looking at the
+/// wall clock again, which its monotonic waits lose track of across a suspend
+const RECHECK: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request {
+    /// Until the time given, or until resumed
+    Pause(Option<SystemTime>),
+    Resume,
+}
+
+/// What the keyboard loop should do with the enabled switch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Pause,
+    Resume,
+    /// The pause's time is up
+    Expired,
+}
+
+struct Snooze {
+    /// Queued by the socket thread, taken by the keyboard loop
+    pending: Mutex<Option<Request>>,
+    /// The pause in effect: None when not paused, Some(None) until resumed
+    until: Mutex<Option<Option<SystemTime>>>,
+    read: OwnedFd,
+    write: OwnedFd,
+}
+
+static SNOOZE: OnceLock<Snooze> = OnceLock::new();
+
+/// `30m`, `1h30m`, `90s`; none is until resumed
+pub fn parse(duration: Option<&str>) -> Result<Request> {
+    let Some(text) = duration else {
+        return Ok(Request::Pause(None));
+    };
+    let duration = humantime::parse_duration(text)
+        .map_err(|err| anyhow::anyhow!("bad duration '{}': {} (try 30m or 1h30m)", text, err))?;
+    if duration.is_zero() || duration > MAX_PAUSE {
+        bail!("pause for up to {} days", MAX_PAUSE.as_secs() / 86_400);
+    }
+    Ok(Request::Pause(Some(SystemTime::now() + duration)))
+}
+
+/// Accept requests from now on; `restored` is a saved pause still running
+pub fn init(restored: Option<SystemTime>) -> Result<()> {
+    let (read, write) = shutdown::pipe("pause")?;
+    let _ = SNOOZE.set(Snooze {
+        pending: Mutex::new(None),
+        until: Mutex::new(restored.map(Some)),
+        read,
+        write,
+    });
+    Ok(())
+}
+
+/// Queue a request for the keyboard loop; a newer one replaces one not applied yet
+pub fn submit(request: Request) -> Result<()> {
+    let Some(snooze) = SNOOZE.get() else {
+        bail!("pausing is not available yet");
+    };
+    *snooze.pending.lock().unwrap() = Some(request);
+    // SAFETY: writing one byte from a valid buffer to our own pipe
+    unsafe { libc::write(snooze.write.as_raw_fd(), [1u8].as_ptr().cast(), 1) };
+    Ok(())
+}
+
+/// Descriptor that becomes readable when a request is queued, or -1 (which poll ignores)
+pub fn fd() -> RawFd {
+    SNOOZE.get().map_or(-1, |snooze| snooze.read.as_raw_fd())
+}
+
+/// When the keyboard loop must wake up to look at the deadline
+pub fn deadline() -> Option<Instant> {
+    let until = (*SNOOZE.get()?.until.lock().unwrap())??;
+    let left = until
+        .duration_since(SystemTime::now())
+        .unwrap_or(Duration::ZERO);
+    Some(Instant::now() + left.min(RECHECK))
+}
+
+/// Apply what was queued, or the deadline passing; the keyboard loop acts on the result
+///
+/// `woken` is whether `fd` was readable; only then is the pipe read.
+pub fn poll(woken: bool) -> Option<Change> {
+    let snooze = SNOOZE.get()?;
+    if woken {
+        let (fd, mut buf) = (snooze.read.as_raw_fd(), [0u8; 64]);
+        // SAFETY: reading into a valid buffer from our own non-blocking pipe
+        while unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) } > 0 {}
+    }
+    let mut until = snooze.until.lock().unwrap();
+    match snooze.pending.lock().unwrap().take() {
+        Some(Request::Pause(at)) => {
+            *until = Some(at);
+            return Some(Change::Pause);
+        }
+        Some(Request::Resume) => {
+            *until = None;
+            return Some(Change::Resume);
+        }
+        None => {}
+    }
+    let expired = until
+        .and_then(|at| at)
+        .is_some_and(|at| at <= SystemTime::now());
+    if expired {
+        *until = None;
+        return Some(Change::Expired);
+    }
+    None
+}
+
+/// Forget the pause without changing the switch, as when SIGUSR1 flips it
+pub fn clear() {
+    if let Some(snooze) = SNOOZE.get() {
+        *snooze.until.lock().unwrap() = None;
+    }
+}
+
+/// The pause in effect: None when there is none, Some(None) until resumed
+pub fn current() -> Option<Option<SystemTime>> {
+    *SNOOZE.get()?.until.lock().unwrap()
+}
+
+/// A timed pause's end as Unix seconds, for the state file
+pub fn until_unix() -> Option<u64> {
+    let until = current()??;
+    until
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|since| since.as_secs())
+}
+
+/// `paused, 12m remaining`, `paused until resumed`, or None when not paused
+pub fn describe() -> Option<String> {
+    Some(match current()? {
+        Some(until) => {
+            let left = until
+                .duration_since(SystemTime::now())
+                .unwrap_or(Duration::ZERO);
+            format!("paused, {} remaining", remaining(left))
+        }
+        None => "paused until resumed".to_string(),
+    })
+}
+
+/// Whole minutes, rounded up, or seconds under one
+pub fn remaining(left: Duration) -> String {
+    let secs = left.as_secs();
+    if secs < 60 {
+        return format!("{}s", secs.max(1));
+    }
+    let minutes = secs.div_ceil(60);
+    match (minutes / 60, minutes % 60) {
+        (0, m) => format!("{}m", m),
+        (h, 0) => format!("{}h", h),
+        (h, m) => format!("{}h{}m", h, m),
+    }
+}
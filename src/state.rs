@@ -0,0 +1,100 @@
+use crate::seat;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+
+/// User-intent state that survives restarts (never the touchpad-driven mode)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PersistedState {
+    /// Administrative on/off switch, toggled with SIGUSR1
+    pub enabled: bool,
+    /// When a timed `ctl pause` ends, in Unix seconds; the switch is off until then
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paused_until: Option<u64>,
+    /// Sticky mode latched by a swipe (`[sticky]`)
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub sticky: bool,
+}
+
+impl Default for PersistedState {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            paused_until: None,
+            sticky: false,
+        }
+    }
+}
+
+/// /var/lib for the system service, $XDG_STATE_HOME (or ~/.local/state) as a user
+pub fn state_path() -> PathBuf {
+    // SAFETY: geteuid has no preconditions
+    let dir = if unsafe { libc::geteuid() } == 0 {
+        PathBuf::from("/var/lib/touch-layer")
+    } else if let Some(dir) = std::env::var_os("XDG_STATE_HOME").filter(|d| !d.is_empty()) {
+        PathBuf::from(dir).join("touch-layer")
+    } else if let Some(home) = std::env::var_os("HOME") {
+        PathBuf::from(home).join(".local/state/touch-layer")
+    } else {
+        PathBuf::from("/var/lib/touch-layer")
+    };
+    dir.join(seat::file_name("state", "toml"))
+}
+
+impl PersistedState {
+    /// Last saved state; missing or unreadable files quietly give the defaults
+    pub fn load() -> Self {
+        fs::read_to_string(state_path())
+            .ok()
+            .and_then(|text| toml::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    /// Change the saved state with `change`, keeping what it doesn't touch
+    pub fn update(change: impl FnOnce(&mut Self)) -> Result<()> {
+        let mut state = Self::load();
+        change(&mut state);
+        state.save()
+    }
+
+    /// Write via a temp file and rename, so a crash never leaves a torn file
+    pub fn save(&self) -> Result<()> {
+        let path = state_path();
+        let dir = path.parent().context("State path has no directory")?;
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create state directory {}", dir.display()))?;
+
+        let tmp = path.with_extension("toml.tmp");
+        let text = toml::to_string(self).context("Failed to serialize state")?;
+        let mut file = File::create(&tmp)
+            .with_context(|| format!("Failed to write state file {}", tmp.display()))?;
+        file.write_all(text.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp, &path)
+            .with_context(|| format!("Failed to replace state file {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_sticky_latch_round_trips_and_defaults_off() {
+        let state = PersistedState {
+            enabled: true,
+            paused_until: None,
+            sticky: true,
+        };
+        let text = toml::to_string(&state).unwrap();
+        assert_eq!(toml::from_str::<PersistedState>(&text).unwrap(), state);
+        // A file from before the latch was saved
+        let old: PersistedState = toml::from_str("enabled = false").unwrap();
+        assert!(!old.sticky);
+        let unlatched = toml::to_string(&PersistedState::default()).unwrap();
+        assert!(!unlatched.contains("sticky"));
+    }
+}
@@ -0,0 +1,129 @@
+//! `STATE` lines on stdout, one per transition, for `touch-layer | while read ...`
+//!
+//! The format is stable; fields may be added at the end but never reordered:
+//!
+//! ```text
+//! STATE active=1 enabled=1 source=touchpad reason=pointer_motion ts=12345.678
+//! ```
+//!
+//! `source` is `touchpad`, `keyboard` (typing detection), `signal`
+//! (SIGUSR1) or `control` (`ctl pause`/`resume`, and a pause running out);
+//! `ts` is CLOCK_MONOTONIC seconds with milliseconds.
+
+use crate::report;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ON: AtomicBool = AtomicBool::new(true);
+
+/// Latest value of each flag, so every line carries the whole state
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Stop printing (`--no-state-output`, or stdout taken by JSON)
+pub fn disable() {
+    ON.store(false, Ordering::SeqCst);
+}
+
+/// Record the enabled flag at startup without printing
+pub fn init(enabled: bool) {
+    ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+/// The enabled flag as of the last change
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+pub fn mode_changed(active: bool, reason: &str) {
+    mode_changed_to(
+        &mut std::io::stdout().lock(),
+        active,
+        reason,
+        report::monotonic_ms(),
+    );
+}
+
+/// `reason` is `sigusr1`, `pause`, `resume` or `pause_expired`
+pub fn enabled_changed(enabled: bool, reason: &str) {
+    enabled_changed_to(
+        &mut std::io::stdout().lock(),
+        enabled,
+        reason,
+        report::monotonic_ms(),
+    );
+}
+
+fn mode_changed_to(out: &mut impl Write, active: bool, reason: &str, ms: u64) {
+    ACTIVE.store(active, Ordering::SeqCst);
+    let source = match reason {
+        "typing" => "keyboard",
+        "mouse_motion" | "mouse_idle" => "mouse",
+        _ => "touchpad",
+    };
+    print(out, source, reason, ms);
+}
+
+fn enabled_changed_to(out: &mut impl Write, enabled: bool, reason: &str, ms: u64) {
+    ENABLED.store(enabled, Ordering::SeqCst);
+    let source = match reason {
+        "sigusr1" => "signal",
+        _ => "control",
+    };
+    print(out, source, reason, ms);
+}
+
+/// One line stamped `ms`, CLOCK_MONOTONIC milliseconds
+fn print(out: &mut impl Write, source: &str, reason: &str, ms: u64) {
+    if !ON.load(Ordering::SeqCst) {
+        return;
+    }
+    // A closed stdout must not take the daemon down
+    let _ = writeln!(
+        out,
+        "STATE active={} enabled={} source={} reason={} ts={}.{:03}",
+        u8::from(ACTIVE.load(Ordering::SeqCst)),
+        u8::from(ENABLED.load(Ordering::SeqCst)),
+        source,
+        reason,
+        ms / 1000,
+        ms % 1000
+    );
+    let _ = out.flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One test, since the flags are process-wide
+    #[test]
+    fn every_transition_is_one_line_with_the_whole_state() {
+        init(true);
+        let mut out = Vec::new();
+        mode_changed_to(&mut out, true, "pointer_motion", 12_345_678);
+        mode_changed_to(&mut out, false, "typing", 12_346_005);
+        enabled_changed_to(&mut out, false, "sigusr1", 12_350_040);
+        mode_changed_to(&mut out, true, "mouse_motion", 12_351_000);
+        enabled_changed_to(&mut out, true, "pause_expired", 12_352_009);
+        mode_changed_to(&mut out, false, "finger_up", 7);
+        assert_eq!(
+            String::from_utf8(out).unwrap().lines().collect::<Vec<_>>(),
+            [
+                "STATE active=1 enabled=1 source=touchpad reason=pointer_motion ts=12345.678",
+                "STATE active=0 enabled=1 source=keyboard reason=typing ts=12346.005",
+                "STATE active=0 enabled=0 source=signal reason=sigusr1 ts=12350.040",
+                "STATE active=1 enabled=0 source=mouse reason=mouse_motion ts=12351.000",
+                "STATE active=1 enabled=1 source=control reason=pause_expired ts=12352.009",
+                "STATE active=0 enabled=1 source=touchpad reason=finger_up ts=0.007",
+            ]
+        );
+
+        // Turned off, nothing is written, but the state still follows
+        disable();
+        let mut out = Vec::new();
+        enabled_changed_to(&mut out, false, "pause", 1000);
+        assert!(out.is_empty());
+        assert!(!enabled());
+    }
+}
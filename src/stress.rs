@@ -0,0 +1,328 @@
+//! `stress`: soak activation and routing with high-rate synthetic input
+//!
+//! Each round generates a random script from the chosen patterns: bursts of
+//! overlapping key presses and releases, the touchpad flapping between
+//! active and inactive, and touchpad motion interleaved with both. It runs
+//! through the same engine as `simulate`, in simulated time and without
+//! devices, and what came out on the virtual outputs is checked:
+//!
+//! - every key and button pressed is released, never twice or while up
+//! - keys that nothing maps or types come out exactly as they went in, in
+//!   the same order and as many times
+//! - once the input is all released and the timers have run out, nothing is
+//!   held down and no mapped key holds a button
+//!
+//! Rounds repeat until `--duration` of wall-clock time is up. A round that
+//! breaks an invariant stops the run with what was expected against what
+//! came out, and its script is saved for `simulate` to replay: rounds are
+//! deterministic, so the same script (or `--seed`) reproduces it.
+
+use crate::cli::Pattern;
+use crate::config::Config;
+use crate::simulate::{self, Output, Simulation, Step};
+use anyhow::{bail, Context, Result};
+use evdev::Key;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Keys pressed at once in a burst, at most
+const MAX_HELD: usize = 4;
+
+/// Unmapped keys stood in for typing; mapped ones and key targets are left out
+const TYPED: [Key; 12] = [
+    Key::KEY_A,
+    Key::KEY_E,
+    Key::KEY_I,
+    Key::KEY_N,
+    Key::KEY_O,
+    Key::KEY_R,
+    Key::KEY_T,
+    Key::KEY_SPACE,
+    Key::KEY_DOT,
+    Key::KEY_COMMA,
+    Key::KEY_1,
+    Key::KEY_LEFTSHIFT,
+];
+
+/// Violations listed per round before the rest are only counted
+const MAX_LISTED: usize = 10;
+
+pub struct Options<'a> {
+    pub duration: Duration,
+    pub patterns: &'a [Pattern],
+    /// Input events per simulated second
+    pub rate: u32,
+    /// Input events per round
+    pub events: usize,
+    pub seed: Option<u64>,
+    pub save_script: &'a Path,
+}
+
+/// xorshift64*: enough for test input, and the same sequence for the same seed
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Zero would stay zero
+        Self(seed ^ 0x9e37_79b9_7f4a_7c15)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn chance(&mut self, percent: u64) -> bool {
+        self.next() % 100 < percent
+    }
+}
+
+/// Keys a round presses: the mapped ones, and typed ones nothing maps or types
+struct Keys {
+    mapped: Vec<Key>,
+    typed: Vec<Key>,
+    /// Whether typed keys must come out unchanged; not with a script or warp
+    /// grid, which may take any key
+    check_typed: bool,
+}
+
+impl Keys {
+    fn of(config: &Config) -> Self {
+        let mut mapped: Vec<Key> = config.mapped_keys().copied().collect();
+        mapped.sort_by_key(|key| key.code());
+        mapped.dedup();
+        let targets = config.key_targets();
+        let typed = TYPED
+            .into_iter()
+            .filter(|key| !mapped.contains(key) && !targets.contains(*key))
+            .collect();
+        Self {
+            mapped,
+            typed,
+            check_typed: config.script.path.is_none() && config.warp.is_none(),
+        }
+    }
+
+    fn all(&self) -> impl Iterator<Item = &Key> {
+        self.mapped.iter().chain(&self.typed)
+    }
+}
+
+/// One round's input: `events` steps of the chosen patterns, then everything let go
+fn generate(rng: &mut Rng, keys: &Keys, options: &Options, layers: bool) -> Vec<(Duration, Step)> {
+    let gap = Duration::from_secs(1) / options.rate;
+    let pool: Vec<Key> = keys.all().copied().collect();
+    let mut steps = Vec::with_capacity(options.events + MAX_HELD + 2);
+    let mut at = Duration::ZERO;
+    let mut held: Vec<Key> = Vec::new();
+    let mut finger = false;
+    let mut push = |steps: &mut Vec<(Duration, Step)>, rng: &mut Rng, step| {
+        // Up to twice the mean gap, and sometimes several at the same instant
+        // Whole microseconds, which a saved script can give exactly
+        if !rng.chance(10) {
+            at += Duration::from_micros(gap.as_micros() as u64 * (rng.next() % 2000) / 1000);
+        }
+        steps.push((at, step));
+    };
+    while steps.len() < options.events {
+        match options.patterns[rng.below(options.patterns.len())] {
+            Pattern::Burst if !pool.is_empty() => {
+                let press = held.is_empty() || (held.len() < MAX_HELD && rng.chance(55));
+                if press {
+                    let key = pool[rng.below(pool.len())];
+                    if held.contains(&key) {
+                        push(&mut steps, rng, Step::Key(key, 2));
+                    } else {
+                        held.push(key);
+                        push(&mut steps, rng, Step::Key(key, 1));
+                    }
+                } else {
+                    let key = held.swap_remove(rng.below(held.len()));
+                    push(&mut steps, rng, Step::Key(key, 0));
+                }
+            }
+            Pattern::Burst => {}
+            Pattern::Flap if finger => {
+                finger = false;
+                push(&mut steps, rng, Step::FingerUp);
+                if layers {
+                    push(&mut steps, rng, Step::Fingers(0));
+                }
+            }
+            Pattern::Flap => {
+                finger = true;
+                push(&mut steps, rng, Step::FingerDown);
+                push(&mut steps, rng, Step::Motion(0.0));
+                if layers {
+                    let fingers = 1 + rng.below(3);
+                    push(&mut steps, rng, Step::Fingers(fingers));
+                }
+            }
+            Pattern::Motion => {
+                let distance = (rng.next() % 200) as f64 / 10.0;
+                push(&mut steps, rng, Step::Motion(distance));
+            }
+        }
+    }
+    for key in held.drain(..) {
+        push(&mut steps, rng, Step::Key(key, 0));
+    }
+    if finger {
+        push(&mut steps, rng, Step::FingerUp);
+        if layers {
+            push(&mut steps, rng, Step::Fingers(0));
+        }
+    }
+    steps
+}
+
+/// Everything a round's output got wrong, one line each
+fn verify(keys: &Keys, steps: &[(Duration, Step)], sim: &Simulation) -> Vec<String> {
+    let mut violations = Vec::new();
+    let outputs = sim.outputs();
+
+    // Press and release pairing, per output device and code
+    let mut down: HashMap<(&str, Key), bool> = HashMap::new();
+    for (index, output) in outputs.iter().enumerate() {
+        let (device, key, value) = match *output {
+            Output::Key(key, value) => ("keyboard", key, value),
+            Output::Button(button, value) => ("pointer", button, value),
+            Output::Mode(_) => continue,
+        };
+        let was = down.insert((device, key), value != 0).unwrap_or(false);
+        let problem = match (was, value) {
+            (true, 1) => "pressed again without a release",
+            (false, 0) => "released while up",
+            (false, 2) => "repeated while up",
+            _ => continue,
+        };
+        violations.push(format!(
+            "output #{}: {} {:?} {}",
+            index + 1,
+            device,
+            key,
+            problem
+        ));
+    }
+    let mut stuck: Vec<String> = down
+        .iter()
+        .filter(|(_, &held)| held)
+        .map(|((device, key), _)| format!("{} {:?}", device, key))
+        .collect();
+    stuck.sort();
+    for held in stuck {
+        violations.push(format!("{} still down at the end", held));
+    }
+    if sim.holding_button() {
+        violations.push("a mapped key still holds a button at the end".to_string());
+    }
+
+    if keys.check_typed {
+        let typed: HashSet<Key> = keys.typed.iter().copied().collect();
+        let sent: Vec<(Key, i32)> = steps
+            .iter()
+            .filter_map(|(_, step)| match *step {
+                Step::Key(key, value) if typed.contains(&key) => Some((key, value)),
+                _ => None,
+            })
+            .collect();
+        let came: Vec<(Key, i32)> = outputs
+            .iter()
+            .filter_map(|output| match *output {
+                Output::Key(key, value) if typed.contains(&key) => Some((key, value)),
+                _ => None,
+            })
+            .collect();
+        let describe = |event: Option<&(Key, i32)>| {
+            event.map_or("(end)".to_string(), |(key, value)| {
+                format!("{:?} {}", key, value)
+            })
+        };
+        if let Some(i) = (0..sent.len().max(came.len())).find(|&i| sent.get(i) != came.get(i)) {
+            violations.push(format!(
+                "typed key #{} of {} in, {} out: expected {}, got {}",
+                i + 1,
+                sent.len(),
+                came.len(),
+                describe(sent.get(i)),
+                describe(came.get(i))
+            ));
+        }
+    }
+    violations
+}
+
+pub fn run(config: &Config, options: &Options) -> Result<()> {
+    let keys = Keys::of(config);
+    if keys.mapped.is_empty() {
+        eprintln!("⚠️  Nothing is mapped: only forwarding is exercised");
+    }
+    if !keys.check_typed {
+        eprintln!("⚠️  A script or warp grid may take any key: typed keys are not checked");
+    }
+    let first_seed = options.seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_nanos() as u64)
+    });
+    println!(
+        "Stressing for {} with {} mapped and {} typed keys, seed {}",
+        humantime::format_duration(options.duration),
+        keys.mapped.len(),
+        keys.typed.len(),
+        first_seed
+    );
+
+    let started = Instant::now();
+    let (mut rounds, mut sent, mut came, mut simulated) = (0u64, 0usize, 0usize, Duration::ZERO);
+    while rounds == 0 || started.elapsed() < options.duration {
+        let seed = first_seed.wrapping_add(rounds);
+        let mut rng = Rng::new(seed);
+        let steps = generate(&mut rng, &keys, options, !config.finger_layers.is_empty());
+        let mut sim = Simulation::new(config, false)?;
+        sim.play(&steps)
+            .with_context(|| format!("Round {} (seed {})", rounds + 1, seed))?;
+        let violations = verify(&keys, &steps, &sim);
+        if !violations.is_empty() {
+            println!(
+                "❌ Round {} (seed {}) broke {} invariant(s):",
+                rounds + 1,
+                seed,
+                violations.len()
+            );
+            for violation in violations.iter().take(MAX_LISTED) {
+                println!("   {}", violation);
+            }
+            if violations.len() > MAX_LISTED {
+                println!("   ... and {} more", violations.len() - MAX_LISTED);
+            }
+            fs::write(options.save_script, simulate::format_script(&steps))
+                .with_context(|| format!("Failed to write {}", options.save_script.display()))?;
+            bail!(
+                "stress found {} violation(s); replay with `simulate {}`",
+                violations.len(),
+                options.save_script.display()
+            );
+        }
+        rounds += 1;
+        sent += steps.len();
+        came += sim.outputs().len();
+        simulated += steps.last().map_or(Duration::ZERO, |(at, _)| *at);
+    }
+    println!(
+        "✅ {} round(s), {} events in, {} out, {} simulated: every invariant held",
+        rounds,
+        sent,
+        came,
+        humantime::format_duration(Duration::from_millis(simulated.as_millis() as u64))
+    );
+    Ok(())
+}
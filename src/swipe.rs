@@ -0,0 +1,84 @@
+//! Swipes that latch the layer (`[sticky]`)
+//!
+//! libinput reports a multi-finger swipe as GESTURE_SWIPE_BEGIN, UPDATEs with
+//! deltas, and END. The deltas are summed over the gesture, and at the end a
+//! swipe of the configured finger count counts if it was quick enough and
+//! mostly vertical: upward latches, downward unlatches. Cancelled gestures
+//! (libinput's `cancelled`, e.g. a finger added) never count.
+
+use crate::config::StickyConfig;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Gesture {
+    fingers: u32,
+    began: Instant,
+    dx: f64,
+    dy: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct SwipeTracker {
+    fingers: u32,
+    min_distance: f64,
+    max_duration: Duration,
+    gesture: Option<Gesture>,
+}
+
+impl SwipeTracker {
+    /// None unless `[sticky]` is enabled
+    pub fn new(config: &StickyConfig) -> Option<Self> {
+        config.enabled.then(|| Self {
+            fingers: config.fingers,
+            min_distance: config.min_distance,
+            max_duration: Duration::from_millis(config.max_duration_ms),
+            gesture: None,
+        })
+    }
+
+    /// Inside a swipe, whose motion must not also activate the layer
+    pub fn in_progress(&self) -> bool {
+        self.gesture.is_some()
+    }
+
+    pub fn begin(&mut self, now: Instant, fingers: u32) {
+        self.gesture = Some(Gesture {
+            fingers,
+            began: now,
+            dx: 0.0,
+            dy: 0.0,
+        });
+    }
+
+    pub fn update(&mut self, dx: f64, dy: f64) {
+        if let Some(gesture) = &mut self.gesture {
+            gesture.dx += dx;
+            gesture.dy += dy;
+        }
+    }
+
+    /// The swipe ended; its direction if it latches or unlatches
+    pub fn end(&mut self, now: Instant, cancelled: bool) -> Option<Direction> {
+        let gesture = self.gesture.take()?;
+        if cancelled
+            || gesture.fingers != self.fingers
+            || now.saturating_duration_since(gesture.began) > self.max_duration
+            || gesture.dy.abs() < self.min_distance
+            || gesture.dy.abs() <= gesture.dx.abs()
+        {
+            return None;
+        }
+        // libinput's y grows downwards
+        Some(if gesture.dy < 0.0 {
+            Direction::Up
+        } else {
+            Direction::Down
+        })
+    }
+}
@@ -0,0 +1,164 @@
+//! SW_TABLET_MODE: a convertible folded into a tablet
+//!
+//! The firmware or compositor disables the built-in keyboard then, but a
+//! grabbed keyboard keeps flowing through us, and a half-disabled one can
+//! still leak keys. The switch is read from the keyboard itself when it has
+//! one, else from `tablet_mode.device` or the first other event node that
+//! reports it (often "Intel HID switches"), which is never grabbed.
+
+use crate::config::TabletModeConfig;
+use crate::device::{self, ReadFailure};
+use crate::seat;
+use evdev::raw_stream::RawDevice;
+use evdev::{InputEvent, InputEventKind, SwitchType};
+use serde::Deserialize;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+
+/// What tablet mode does to the layer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TabletAction {
+    /// Keep remapping, e.g. for an external keyboard in tablet mode
+    Ignore,
+    /// Force the layer off and release whatever it holds
+    #[default]
+    Disable,
+    /// As `disable`, and give the keyboard back until tablet mode ends
+    Ungrab,
+}
+
+pub struct TabletSwitch {
+    /// A separate switch device; None when the keyboard reports the switch
+    device: Option<(RawDevice, PathBuf)>,
+    engaged: bool,
+}
+
+fn has_switch(device: &RawDevice) -> bool {
+    device
+        .supported_switches()
+        .is_some_and(|switches| switches.contains(SwitchType::SW_TABLET_MODE))
+}
+
+fn switch_state(device: &RawDevice) -> bool {
+    device
+        .get_switch_state()
+        .is_ok_and(|state| state.contains(SwitchType::SW_TABLET_MODE))
+}
+
+impl TabletSwitch {
+    /// The switch for the keyboard at `keyboard_path`, or None if there is none
+    /// (or `action = "ignore"`)
+    pub fn find(
+        config: &TabletModeConfig,
+        keyboard: &RawDevice,
+        keyboard_path: &Path,
+    ) -> Option<Self> {
+        if config.action == TabletAction::Ignore {
+            return None;
+        }
+        if config.candidates.is_empty() && has_switch(keyboard) {
+            eprintln!("📱 Tablet mode switch: on the keyboard");
+            return Some(Self {
+                device: None,
+                engaged: switch_state(keyboard),
+            });
+        }
+
+        let keyboard_node = std::fs::canonicalize(keyboard_path).ok();
+        let nodes: Vec<(PathBuf, RawDevice)> = if config.candidates.is_empty() {
+            device::event_nodes()
+        } else {
+            device::candidate_nodes(&config.candidates)
+                .into_iter()
+                .filter_map(|path| Some((path.clone(), RawDevice::open(&path).ok()?)))
+                .collect()
+        };
+        let found = nodes.into_iter().find(|(path, device)| {
+            has_switch(device)
+                && !device::is_own(device)
+                && (!config.candidates.is_empty() || seat::accepts(path))
+                && std::fs::canonicalize(path).ok() != keyboard_node
+        });
+        match found {
+            Some((path, device)) => {
+                eprintln!(
+                    "📱 Tablet mode switch: {} ({})",
+                    path.display(),
+                    device.name().unwrap_or("unnamed")
+                );
+                Some(Self {
+                    engaged: switch_state(&device),
+                    device: Some((device, path)),
+                })
+            }
+            None => {
+                if !config.candidates.is_empty() {
+                    eprintln!(
+                        "⚠️  No SW_TABLET_MODE among {}; tablet mode is not tracked",
+                        config.candidates.join(", ")
+                    );
+                }
+                None
+            }
+        }
+    }
+
+    pub fn engaged(&self) -> bool {
+        self.engaged
+    }
+
+    /// The separate device to poll, or -1 (which poll ignores)
+    pub fn fd(&self) -> RawFd {
+        self.device
+            .as_ref()
+            .map_or(-1, |(device, _)| device.as_raw_fd())
+    }
+
+    /// A keyboard event; the new state if it flips a switch on the keyboard
+    pub fn observe(&mut self, event: &InputEvent) -> Option<bool> {
+        if self.device.is_some()
+            || event.kind() != InputEventKind::Switch(SwitchType::SW_TABLET_MODE)
+        {
+            return None;
+        }
+        self.set(event.value() != 0)
+    }
+
+    /// Drain the separate device; the new state if it changed
+    ///
+    /// The state is read back from the kernel rather than the events, so a
+    /// SYN_DROPPED can't leave it wrong. A device that goes away stops tracking.
+    pub fn read(&mut self) -> Option<bool> {
+        let (device, path) = self.device.as_mut()?;
+        let failure = device.fetch_events().err().map(ReadFailure::classify);
+        if let Some(ReadFailure::Gone | ReadFailure::Fatal(_)) = failure {
+            eprintln!(
+                "⚠️  Tablet mode switch {} went away; tablet mode is not tracked",
+                path.display()
+            );
+            // Dropped, or poll would keep reporting the dead descriptor
+            self.device = None;
+            return self.set(false);
+        }
+        let engaged = switch_state(device);
+        self.set(engaged)
+    }
+
+    /// Re-read the switch, as after resume; the new state if it changed
+    pub fn resync(&mut self, keyboard: &RawDevice) -> Option<bool> {
+        let engaged = match &self.device {
+            Some((device, _)) => switch_state(device),
+            None => switch_state(keyboard),
+        };
+        self.set(engaged)
+    }
+
+    fn set(&mut self, engaged: bool) -> Option<bool> {
+        if self.engaged == engaged {
+            return None;
+        }
+        self.engaged = engaged;
+        Some(engaged)
+    }
+}
@@ -0,0 +1,125 @@
+use crate::mt::{self, SlotTracker};
+use evdev::raw_stream::RawDevice;
+use evdev::{InputEvent, InputEventKind, Key};
+use serde::Deserialize;
+use std::io;
+
+/// Which touchpad signal counts as the finger going down and lifting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+    /// Pick from what the device advertises
+    Auto,
+    ToolFinger,
+    Touch,
+    /// Every multitouch slot released
+    AllContactsUp,
+    /// Lifts are ignored; the mode ends `debounce_ms` after the last motion
+    TimeoutOnly,
+}
+
+impl Trigger {
+    pub fn name(self) -> &'static str {
+        match self {
+            Trigger::Auto => "auto",
+            Trigger::ToolFinger => "tool_finger",
+            Trigger::Touch => "touch",
+            Trigger::AllContactsUp => "all_contacts_up",
+            Trigger::TimeoutOnly => "timeout_only",
+        }
+    }
+
+    /// Replace Auto with a concrete choice for `device`
+    ///
+    /// Protocol B slots say exactly which contacts are down, so they win;
+    /// otherwise BTN_TOUCH, which drops at lift-off on most pads where
+    /// BTN_TOOL_FINGER can lag.
+    pub fn resolve(self, device: &RawDevice) -> Trigger {
+        if self != Trigger::Auto {
+            return self;
+        }
+        let keys = device.supported_keys();
+        let has = |key| keys.is_some_and(|keys| keys.contains(key));
+        if mt::has_slots(device) {
+            Trigger::AllContactsUp
+        } else if has(Key::BTN_TOUCH) {
+            Trigger::Touch
+        } else if has(Key::BTN_TOOL_FINGER) {
+            Trigger::ToolFinger
+        } else {
+            Trigger::TimeoutOnly
+        }
+    }
+}
+
+/// Turns touchpad events into finger down/up edges for a trigger
+pub struct Contacts {
+    trigger: Trigger,
+    /// Whether the finger is down as far as the trigger signal goes
+    down: bool,
+    slots: SlotTracker,
+}
+
+impl Contacts {
+    pub fn new(trigger: Trigger) -> Self {
+        Self {
+            trigger,
+            down: false,
+            slots: SlotTracker::default(),
+        }
+    }
+
+    /// Forget contacts, e.g. after the device went away
+    pub fn reset(&mut self) {
+        self.down = false;
+        self.slots.reset();
+    }
+
+    /// Some(true) when a finger goes down, Some(false) when it lifts
+    pub fn feed(&mut self, event: &InputEvent) -> Option<bool> {
+        if self.trigger == Trigger::AllContactsUp {
+            let count = self.slots.feed(event)?;
+            return self.set(count > 0);
+        }
+        match (self.trigger, event.kind()) {
+            (
+                Trigger::ToolFinger | Trigger::TimeoutOnly,
+                InputEventKind::Key(Key::BTN_TOOL_FINGER),
+            )
+            | (Trigger::Touch, InputEventKind::Key(Key::BTN_TOUCH)) => match event.value() {
+                0 => self.set(false),
+                1 => self.set(true),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Re-read the trigger signal from the kernel after a SYN_DROPPED
+    pub fn resync(&mut self, device: &RawDevice) -> io::Result<Option<bool>> {
+        match self.trigger {
+            Trigger::Auto => Ok(None),
+            Trigger::ToolFinger | Trigger::TimeoutOnly | Trigger::Touch => {
+                let key = if self.trigger == Trigger::Touch {
+                    Key::BTN_TOUCH
+                } else {
+                    Key::BTN_TOOL_FINGER
+                };
+                let down = device.get_key_state()?.contains(key);
+                Ok(self.set(down))
+            }
+            Trigger::AllContactsUp => {
+                self.slots.resync(device)?;
+                Ok(self.set(self.slots.count() > 0))
+            }
+        }
+    }
+
+    fn set(&mut self, down: bool) -> Option<bool> {
+        if self.down == down {
+            return None;
+        }
+        self.down = down;
+        Some(down)
+    }
+}
@@ -0,0 +1,67 @@
+use crate::config::TypingConfig;
+use crate::remap::Remapper;
+use evdev::{InputEvent, InputEventKind, Key};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Keys that accompany pointer use (ctrl-click and friends) and never count as typing
+const MODIFIERS: [Key; 8] = [
+    Key::KEY_LEFTSHIFT,
+    Key::KEY_RIGHTSHIFT,
+    Key::KEY_LEFTCTRL,
+    Key::KEY_RIGHTCTRL,
+    Key::KEY_LEFTALT,
+    Key::KEY_RIGHTALT,
+    Key::KEY_LEFTMETA,
+    Key::KEY_RIGHTMETA,
+];
+
+/// Spots sustained typing while the mode is active: `presses` ordinary key
+/// presses within `window`
+pub struct TypingDetector {
+    presses: usize,
+    window: Duration,
+    recent: VecDeque<Instant>,
+}
+
+impl TypingDetector {
+    /// None when the feature is off
+    pub fn new(config: &TypingConfig) -> Option<Self> {
+        (config.presses > 0).then(|| Self {
+            presses: config.presses,
+            window: Duration::from_millis(config.window_ms),
+            recent: VecDeque::with_capacity(config.presses),
+        })
+    }
+
+    /// Feed an event seen while active; true once it tips over into typing
+    ///
+    /// Mapped keys and modifiers don't count, and nothing counts while a
+    /// mapped button is held, so a drag with the keyboard is never cut short.
+    pub fn observe(&mut self, event: &InputEvent, remapper: &Remapper, now: Instant) -> bool {
+        let InputEventKind::Key(key) = event.kind() else {
+            return false;
+        };
+        if event.value() != 1
+            || MODIFIERS.contains(&key)
+            || remapper.is_mapped(key)
+            || remapper.holding_button()
+        {
+            return false;
+        }
+
+        while self
+            .recent
+            .front()
+            .is_some_and(|&at| now.saturating_duration_since(at) >= self.window)
+        {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(now);
+        if self.recent.len() < self.presses {
+            return false;
+        }
+        self.recent.clear();
+        true
+    }
+}
@@ -0,0 +1,145 @@
+//! Recent touchpad speed, and the debounce it calls for
+//!
+//! The window keeps the motion of the last `window_ms`, and the debounce is a
+//! pure function of it: average speed over the window, mapped linearly from
+//! `slow..fast` onto `min_ms..max_ms` and clamped. Motion is libinput's
+//! |dx| + |dy| per event, the same measure `motion_threshold` uses.
+
+use crate::config::AdaptiveDebounceConfig;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Motion distances of the last `span`, newest last
+#[derive(Debug, Clone)]
+pub struct MotionWindow {
+    span: Duration,
+    samples: VecDeque<(Instant, f64)>,
+}
+
+impl MotionWindow {
+    pub fn new(span: Duration) -> Self {
+        Self {
+            span,
+            samples: VecDeque::new(),
+        }
+    }
+
+    pub fn record(&mut self, now: Instant, distance: f64) {
+        self.samples.push_back((now, distance));
+        while self
+            .samples
+            .front()
+            .is_some_and(|&(at, _)| now.saturating_duration_since(at) > self.span)
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Average speed over the `span` before `now`, in distance per second
+    pub fn speed(&self, now: Instant) -> f64 {
+        let distance: f64 = self
+            .samples
+            .iter()
+            .filter(|&&(at, _)| now.saturating_duration_since(at) <= self.span)
+            .map(|&(_, distance)| distance)
+            .sum();
+        distance / self.span.as_secs_f64()
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+}
+
+/// The debounce for a finger lifting after moving at `speed`
+pub fn debounce(config: &AdaptiveDebounceConfig, speed: f64) -> Duration {
+    let t = ((speed - config.slow) / (config.fast - config.slow)).clamp(0.0, 1.0);
+    let ms = config.min_ms as f64 + t * (config.max_ms - config.min_ms) as f64;
+    Duration::from_millis(ms.round() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The debounce after `trace`, (ms, distance) pairs from `start`, lifting at `lift` ms
+    fn after(trace: &[(u64, f64)], lift: u64) -> Duration {
+        let config = AdaptiveDebounceConfig::default();
+        let start = Instant::now();
+        let mut window = MotionWindow::new(Duration::from_millis(config.window_ms));
+        for &(ms, distance) in trace {
+            window.record(start + Duration::from_millis(ms), distance);
+        }
+        debounce(&config, window.speed(start + Duration::from_millis(lift)))
+    }
+
+    /// An event every 10ms from `from` to `to`, each moving `distance`
+    fn steady(from: u64, to: u64, distance: f64) -> Vec<(u64, f64)> {
+        (from / 10..=to / 10).map(|i| (i * 10, distance)).collect()
+    }
+
+    #[test]
+    fn slow_motion_gets_the_shortest_debounce() {
+        // 20 events of 1 in 200ms: 100 per second, under `slow`
+        assert_eq!(after(&steady(10, 200, 1.0), 200), Duration::ZERO);
+        assert_eq!(after(&[], 200), Duration::ZERO);
+    }
+
+    #[test]
+    fn a_fast_flick_gets_the_longest_debounce() {
+        assert_eq!(
+            after(&steady(10, 200, 25.0), 200),
+            Duration::from_millis(300)
+        );
+        // Far past `fast` is clamped
+        assert_eq!(
+            after(&steady(10, 200, 500.0), 200),
+            Duration::from_millis(300)
+        );
+    }
+
+    #[test]
+    fn speeds_in_between_map_linearly() {
+        // 1100 per second, halfway from 200 to 2000
+        assert_eq!(
+            after(&steady(10, 200, 11.0), 200),
+            Duration::from_millis(150)
+        );
+        // 650 per second, a quarter of the way
+        assert_eq!(after(&steady(10, 200, 6.5), 200), Duration::from_millis(75));
+    }
+
+    #[test]
+    fn only_motion_inside_the_window_counts() {
+        // A flick that ended 300ms before the lift, then slow motion
+        let mut trace = steady(10, 200, 25.0);
+        trace.extend(steady(310, 500, 1.0));
+        assert_eq!(after(&trace, 500), Duration::ZERO);
+        // The same flick late in the window still counts
+        let mut trace = steady(10, 200, 1.0);
+        trace.extend(steady(210, 300, 50.0));
+        assert_eq!(after(&trace, 300), Duration::from_millis(300));
+        // Or with no motion since, it ages out by the lift
+        assert_eq!(after(&steady(10, 200, 25.0), 1000), Duration::ZERO);
+    }
+
+    #[test]
+    fn faster_traces_never_get_a_shorter_debounce() {
+        let mut last = Duration::ZERO;
+        for distance in 0..40 {
+            let debounce = after(&steady(10, 200, distance as f64), 200);
+            assert!(debounce >= last, "{} per event", distance);
+            last = debounce;
+        }
+    }
+
+    #[test]
+    fn clear_forgets_the_trace() {
+        let start = Instant::now();
+        let mut window = MotionWindow::new(Duration::from_millis(200));
+        window.record(start, 400.0);
+        assert_eq!(window.speed(start), 2000.0);
+        window.clear();
+        assert_eq!(window.speed(start), 0.0);
+    }
+}
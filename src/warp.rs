@@ -0,0 +1,145 @@
+//! Grid pointer warping, warpd-style
+//!
+//! While `warp.key` is held with the mode active, the screen is a 3x3 grid on
+//! U I O / J K L / M , . and each press jumps the pointer to the center of
+//! that cell, then narrows the grid to it, so a few presses reach any point.
+//! REL events can only nudge the pointer, so the jumps go out as ABS_X/ABS_Y
+//! on a separate absolute device. Grid keys are only read while the warp key
+//! is held; otherwise they type or click as usual.
+
+use crate::config::WarpConfig;
+use evdev::Key;
+use std::collections::HashSet;
+
+/// The 3x3 grid, row by row from the top left
+pub const GRID_KEYS: [Key; 9] = [
+    Key::KEY_U,
+    Key::KEY_I,
+    Key::KEY_O,
+    Key::KEY_J,
+    Key::KEY_K,
+    Key::KEY_L,
+    Key::KEY_M,
+    Key::KEY_COMMA,
+    Key::KEY_DOT,
+];
+
+/// Axis range when `warp.width`/`height` are unset; the compositor scales it
+/// to the screen either way
+pub const NORMALIZED_RANGE: i32 = 1 << 16;
+
+/// The part of the screen the next grid key divides
+#[derive(Debug, Clone, Copy)]
+struct Cell {
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+}
+
+impl Cell {
+    fn center(&self) -> (i32, i32) {
+        (
+            (self.x + self.width / 2.0) as i32,
+            (self.y + self.height / 2.0) as i32,
+        )
+    }
+
+    /// The `index`th of its nine cells, row by row
+    fn narrow(&self, index: usize) -> Cell {
+        let (width, height) = (self.width / 3.0, self.height / 3.0);
+        Cell {
+            x: self.x + width * (index % 3) as f64,
+            y: self.y + height * (index / 3) as f64,
+            width,
+            height,
+        }
+    }
+}
+
+/// What became of a key event
+pub enum Outcome {
+    /// Not the grid's; route it as usual
+    Pass,
+    /// The grid's, with nothing to send
+    Swallow,
+    /// Move the pointer to these absolute coordinates
+    Jump(i32, i32),
+}
+
+pub struct Warp {
+    config: WarpConfig,
+    /// The cell being narrowed, while the warp key is held
+    cell: Option<Cell>,
+    /// Grid keys whose press was taken, so their release is too
+    taken: HashSet<Key>,
+}
+
+impl Warp {
+    pub fn new(config: WarpConfig) -> Self {
+        Self {
+            config,
+            cell: None,
+            taken: HashSet::new(),
+        }
+    }
+
+    fn screen(&self) -> Cell {
+        Cell {
+            x: 0.0,
+            y: 0.0,
+            width: f64::from(self.config.width),
+            height: f64::from(self.config.height),
+        }
+    }
+
+    /// Look at one key event; the warp key only starts a grid while `active`
+    pub fn handle(&mut self, key: Key, value: i32, active: bool) -> Outcome {
+        if key == self.config.key {
+            return match value {
+                1 if active => {
+                    self.cell = Some(self.screen());
+                    Outcome::Swallow
+                }
+                // Pressed while inactive, it went through and so does the rest
+                _ if self.cell.is_none() => Outcome::Pass,
+                2 => Outcome::Swallow,
+                _ => {
+                    self.cell = None;
+                    Outcome::Swallow
+                }
+            };
+        }
+
+        if value != 1 {
+            let taken = if value == 0 {
+                self.taken.remove(&key)
+            } else {
+                self.taken.contains(&key)
+            };
+            return if taken {
+                Outcome::Swallow
+            } else {
+                Outcome::Pass
+            };
+        }
+        let (Some(cell), Some(index)) = (self.cell, GRID_KEYS.iter().position(|&k| k == key))
+        else {
+            return Outcome::Pass;
+        };
+        self.taken.insert(key);
+        // Narrowing stops at one unit, where further presses stay put
+        let next = cell.narrow(index);
+        if next.width >= 1.0 && next.height >= 1.0 {
+            self.cell = Some(next);
+        }
+        let (x, y) = next.center();
+        Outcome::Jump(x, y)
+    }
+
+    /// Forget the grid, as when every held key is released
+    pub fn reset(&mut self) {
+        self.cell = None;
+        self.taken.clear();
+    }
+}
@@ -0,0 +1,157 @@
+//! Deadman switch for the keyboard loop (`[watchdog]`)
+//!
+//! While the loop holds the exclusive grab, a stall in it (a deadlock, or a
+//! uinput write that never returns) swallows every keystroke. The loop beats
+//! on each iteration, and its wait wakes at least every quarter of `stall_ms`
+//! so an idle loop beats too. A thread checks the beat; if it is older than
+//! `stall_ms` while the keyboard has events waiting, the thread ungrabs the
+//! keyboard through its own duplicate of the descriptor (the grab belongs to
+//! the open file, which the duplicate shares), says so loudly and aborts, so
+//! the service manager restarts us.
+
+use crate::config::WatchdogConfig;
+use crate::report::monotonic_ms;
+use anyhow::{Context, Result};
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// EVIOCGRAB, _IOW('E', 0x90, int)
+const EVIOCGRAB: libc::c_ulong = 0x4004_4590;
+
+/// CLOCK_MONOTONIC milliseconds of the last beat
+static BEAT: AtomicU64 = AtomicU64::new(0);
+/// Whether the keyboard was grabbed as of the last beat
+static GRABBED: AtomicBool = AtomicBool::new(false);
+/// Our own descriptor for the grabbed keyboard; None while there is none
+static KEYBOARD: Mutex<Option<OwnedFd>> = Mutex::new(None);
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+/// How often an idle keyboard loop must beat
+pub fn tick(config: &WatchdogConfig) -> Option<Duration> {
+    config
+        .enabled
+        .then(|| Duration::from_millis(config.stall_ms / 4))
+}
+
+/// Start the watchdog thread, if enabled
+pub fn start(config: &WatchdogConfig) -> Result<()> {
+    if !config.enabled {
+        return Ok(());
+    }
+    let stall = config.stall_ms;
+    thread::Builder::new()
+        .name("watchdog".into())
+        .spawn(move || run(stall))
+        .context("Failed to start the watchdog")?;
+    STARTED.store(true, Ordering::Relaxed);
+    eprintln!("🐕 Watchdog: armed, {}ms", stall);
+    Ok(())
+}
+
+/// Watches the keyboard it was made for until dropped
+///
+/// Dropping it matters: our duplicate keeps the open file, and with it the
+/// grab, alive after the loop lets go of the keyboard.
+pub struct Watched(Option<RawFd>);
+
+impl Drop for Watched {
+    fn drop(&mut self) {
+        let mut keyboard = KEYBOARD.lock().unwrap();
+        if self.0.is_some() && keyboard.as_ref().map(AsRawFd::as_raw_fd) == self.0 {
+            *keyboard = None;
+        }
+    }
+}
+
+impl Watched {
+    /// Watch the keyboard at `fd` instead, after it was reopened
+    pub fn rewatch(&mut self, fd: RawFd) -> Result<()> {
+        // The old duplicate is already replaced when the old guard drops
+        *self = watch(fd)?;
+        Ok(())
+    }
+}
+
+/// Watch the keyboard at `fd` from now on
+pub fn watch(fd: RawFd) -> Result<Watched> {
+    if !STARTED.load(Ordering::Relaxed) {
+        return Ok(Watched(None));
+    }
+    // SAFETY: fcntl only reads the descriptor
+    let dup = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+    if dup < 0 {
+        return Err(io::Error::last_os_error())
+            .context("Failed to duplicate the keyboard descriptor for the watchdog");
+    }
+    // SAFETY: dup is a fresh descriptor nothing else owns
+    *KEYBOARD.lock().unwrap() = Some(unsafe { OwnedFd::from_raw_fd(dup) });
+    Ok(Watched(Some(dup)))
+}
+
+/// The keyboard loop is alive; `grabbed` is whether it holds the grab
+pub fn beat(grabbed: bool) {
+    GRABBED.store(grabbed, Ordering::Relaxed);
+    BEAT.store(monotonic_ms(), Ordering::Relaxed);
+}
+
+/// The earlier of `deadline` and the next beat an idle loop owes
+pub fn deadline(deadline: Option<Instant>, tick: Option<Duration>) -> Option<Instant> {
+    let beat = tick.map(|tick| Instant::now() + tick);
+    [deadline, beat].into_iter().flatten().min()
+}
+
+fn run(stall: u64) {
+    let interval = Duration::from_millis((stall / 4).max(1));
+    loop {
+        thread::sleep(interval);
+        let beat = BEAT.load(Ordering::Relaxed);
+        if beat == 0 || !GRABBED.load(Ordering::Relaxed) {
+            continue;
+        }
+        let age = monotonic_ms().saturating_sub(beat);
+        if age <= stall {
+            continue;
+        }
+        let keyboard = KEYBOARD.lock().unwrap();
+        let Some(fd) = keyboard.as_ref().map(AsRawFd::as_raw_fd) else {
+            continue;
+        };
+        if !pending(fd) {
+            continue;
+        }
+        eprintln!(
+            "🚨 Keyboard loop stalled for {}ms with input waiting - releasing the keyboard and aborting",
+            age
+        );
+        if let Err(err) = ungrab(fd) {
+            eprintln!("🚨 Failed to release the keyboard grab: {}", err);
+        }
+        std::process::abort();
+    }
+}
+
+/// Whether the keyboard has events the loop hasn't read; a removed device has none that matter
+fn pending(fd: RawFd) -> bool {
+    let mut pfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: pfd is a valid pollfd for the duration of the call
+    let ready = unsafe { libc::poll(&mut pfd, 1, 0) };
+    ready > 0
+        && pfd.revents & libc::POLLIN != 0
+        && pfd.revents & (libc::POLLHUP | libc::POLLERR) == 0
+}
+
+fn ungrab(fd: RawFd) -> io::Result<()> {
+    // SAFETY: EVIOCGRAB takes an int by value
+    if unsafe { libc::ioctl(fd, EVIOCGRAB, 0 as libc::c_int) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
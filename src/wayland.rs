@@ -0,0 +1,222 @@
+//! Output over the wlroots virtual-pointer and virtual-keyboard protocols,
+//! for systems where /dev/uinput is not available.
+
+use crate::sink::EventSink;
+use anyhow::{anyhow, Context, Result};
+use evdev::{InputEvent, InputEventKind, Key};
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::io::{AsFd, FromRawFd};
+use std::time::Instant;
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::wl_pointer::{Axis, AxisSource, ButtonState};
+use wayland_client::protocol::{wl_registry, wl_seat::WlSeat};
+use wayland_client::{delegate_noop, Connection, Dispatch, EventQueue, QueueHandle};
+use wayland_protocols_misc::zwp_virtual_keyboard_v1::client::zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1;
+use wayland_protocols_misc::zwp_virtual_keyboard_v1::client::zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1;
+use wayland_protocols_wlr::virtual_pointer::v1::client::zwlr_virtual_pointer_manager_v1::ZwlrVirtualPointerManagerV1;
+use wayland_protocols_wlr::virtual_pointer::v1::client::zwlr_virtual_pointer_v1::ZwlrVirtualPointerV1;
+
+/// wl_keyboard.keymap_format.xkb_v1
+const KEYMAP_FORMAT_XKB_V1: u32 = 1;
+
+/// Scroll distance the compositor gets per wheel detent (libinput's default)
+const AXIS_PER_DETENT: f64 = 15.0;
+
+/// Real-modifier masks; xkbcommon always puts the eight core modifiers first
+const SHIFT: u32 = 1 << 0;
+const LOCK: u32 = 1 << 1;
+const CONTROL: u32 = 1 << 2;
+const MOD1: u32 = 1 << 3;
+const MOD2: u32 = 1 << 4;
+const MOD4: u32 = 1 << 6;
+
+struct State;
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for State {
+    fn event(
+        _: &mut Self,
+        _: &wl_registry::WlRegistry,
+        _: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+delegate_noop!(State: ignore WlSeat);
+delegate_noop!(State: ZwlrVirtualPointerManagerV1);
+delegate_noop!(State: ZwlrVirtualPointerV1);
+delegate_noop!(State: ZwpVirtualKeyboardManagerV1);
+delegate_noop!(State: ZwpVirtualKeyboardV1);
+
+pub struct WaylandSink {
+    conn: Connection,
+    _queue: EventQueue<State>,
+    pointer: ZwlrVirtualPointerV1,
+    keyboard: ZwpVirtualKeyboardV1,
+    epoch: Instant,
+    /// Modifiers must be sent explicitly; the compositor doesn't derive them from keys
+    depressed: u32,
+    locked: u32,
+}
+
+impl WaylandSink {
+    /// Connect to $WAYLAND_DISPLAY and create a virtual pointer and keyboard
+    pub fn connect(xkb_layout: &str) -> Result<Self> {
+        let conn = Connection::connect_to_env().context("Failed to connect to Wayland display")?;
+        let (globals, queue) =
+            registry_queue_init::<State>(&conn).context("Failed to read Wayland globals")?;
+        let qh = queue.handle();
+
+        let missing = |name: &str| anyhow!("Compositor does not offer {}", name);
+        let seat: WlSeat = globals
+            .bind(&qh, 1..=1, ())
+            .map_err(|_| missing("wl_seat"))?;
+        let pointer_manager: ZwlrVirtualPointerManagerV1 = globals
+            .bind(&qh, 1..=1, ())
+            .map_err(|_| missing("zwlr_virtual_pointer_manager_v1"))?;
+        let keyboard_manager: ZwpVirtualKeyboardManagerV1 = globals
+            .bind(&qh, 1..=1, ())
+            .map_err(|_| missing("zwp_virtual_keyboard_manager_v1"))?;
+
+        let pointer = pointer_manager.create_virtual_pointer(Some(&seat), &qh, ());
+        let keyboard = keyboard_manager.create_virtual_keyboard(&seat, &qh, ());
+
+        // Keys are sent as evdev codes, so the keymap only has to name the layout
+        let keymap = keymap_file(xkb_layout)?;
+        let size = keymap.metadata()?.len() as u32;
+        keyboard.keymap(KEYMAP_FORMAT_XKB_V1, keymap.as_fd(), size);
+
+        conn.flush().context("Failed to talk to the compositor")?;
+        eprintln!("🪟 Wayland output ready (layout '{}')", xkb_layout);
+        Ok(Self {
+            conn,
+            _queue: queue,
+            pointer,
+            keyboard,
+            epoch: Instant::now(),
+            depressed: 0,
+            locked: 0,
+        })
+    }
+
+    fn time(&self) -> u32 {
+        self.epoch.elapsed().as_millis() as u32
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.conn
+            .flush()
+            .context("Lost connection to the compositor")
+    }
+
+    fn update_modifiers(&mut self, key: Key, value: i32) {
+        let held = match key {
+            Key::KEY_LEFTSHIFT | Key::KEY_RIGHTSHIFT => SHIFT,
+            Key::KEY_LEFTCTRL | Key::KEY_RIGHTCTRL => CONTROL,
+            Key::KEY_LEFTALT | Key::KEY_RIGHTALT => MOD1,
+            Key::KEY_LEFTMETA | Key::KEY_RIGHTMETA => MOD4,
+            Key::KEY_CAPSLOCK if value == 1 => {
+                self.locked ^= LOCK;
+                0
+            }
+            Key::KEY_NUMLOCK if value == 1 => {
+                self.locked ^= MOD2;
+                0
+            }
+            _ => return,
+        };
+        match value {
+            1 => self.depressed |= held,
+            0 => self.depressed &= !held,
+            _ => return,
+        }
+        self.keyboard.modifiers(self.depressed, 0, self.locked, 0);
+    }
+}
+
+impl EventSink for WaylandSink {
+    fn forward(&mut self, event: InputEvent) -> Result<()> {
+        // Only key events have a protocol equivalent; MSC/LED/SYN are dropped
+        match event.kind() {
+            InputEventKind::Key(key) => self.key(key, event.value()),
+            _ => Ok(()),
+        }
+    }
+
+    fn key(&mut self, key: Key, value: i32) -> Result<()> {
+        // The compositor runs its own repeat
+        if value == 2 {
+            return Ok(());
+        }
+        self.keyboard
+            .key(self.time(), key.code() as u32, value as u32);
+        self.update_modifiers(key, value);
+        self.flush()
+    }
+
+    fn button(&mut self, button: Key, value: i32) -> Result<()> {
+        let state = if value == 0 {
+            ButtonState::Released
+        } else {
+            ButtonState::Pressed
+        };
+        self.pointer
+            .button(self.time(), button.code() as u32, state);
+        self.pointer.frame();
+        self.flush()
+    }
+
+    /// No hi-res wheel in this protocol: whole detents go out as discrete steps,
+    /// fractional ones as plain axis motion
+    fn wheel(&mut self, hi_res: i32, legacy: i32) -> Result<()> {
+        if hi_res == 0 && legacy == 0 {
+            return Ok(());
+        }
+        let time = self.time();
+        // Wayland's vertical axis grows downwards, REL_WHEEL upwards
+        let value = -(hi_res as f64) / 120.0 * AXIS_PER_DETENT;
+        self.pointer.axis_source(AxisSource::Wheel);
+        if legacy != 0 {
+            self.pointer
+                .axis_discrete(time, Axis::VerticalScroll, value, -legacy);
+        } else {
+            self.pointer.axis(time, Axis::VerticalScroll, value);
+        }
+        self.pointer.frame();
+        self.flush()
+    }
+
+    fn devices(&self) -> Vec<String> {
+        vec![
+            "wayland virtual keyboard".into(),
+            "wayland virtual pointer".into(),
+        ]
+    }
+}
+
+/// Keymap text in a memfd, for the compositor to compile against its own XKB data
+fn keymap_file(layout: &str) -> Result<File> {
+    let text = format!(
+        "xkb_keymap {{\n\
+         \txkb_keycodes {{ include \"evdev+aliases(qwerty)\" }};\n\
+         \txkb_types {{ include \"complete\" }};\n\
+         \txkb_compat {{ include \"complete\" }};\n\
+         \txkb_symbols {{ include \"pc+{}+inet(evdev)\" }};\n\
+         }};\n",
+        layout
+    );
+    // SAFETY: the name is a valid NUL-terminated string
+    let fd = unsafe { libc::memfd_create(c"touch-layer-keymap".as_ptr(), libc::MFD_CLOEXEC) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error()).context("memfd_create failed");
+    }
+    // SAFETY: memfd_create just returned this descriptor and nothing else owns it
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    file.write_all(text.as_bytes())?;
+    // The size sent includes the terminating NUL
+    file.write_all(&[0])?;
+    Ok(file)
+}
@@ -0,0 +1,108 @@
+//! Mapping sources named by XKB keysym (`mapping_keys = "keysym"`)
+//!
+//! The keymap is the compiled one in `xkb_keymap` if set (e.g. the session's,
+//! saved with `xkbcli dump-keymap-wayland`), otherwise the one built from
+//! `xkb_layout`, `xkb_variant` and `xkb_options`. A keysym resolves to the key
+//! typing it on the first layout: unshifted before shifted, then lowest code.
+
+use anyhow::{anyhow, bail, Context, Result};
+use evdev::Key;
+use std::path::Path;
+use xkbcommon::xkb;
+
+/// xkb keycodes are evdev codes shifted by 8, a leftover of X11
+const EVDEV_OFFSET: u32 = 8;
+
+/// Where the keymap comes from
+pub enum KeymapSource<'a> {
+    File(&'a Path),
+    Names {
+        layout: &'a str,
+        variant: &'a str,
+        options: &'a str,
+    },
+}
+
+pub struct Resolver {
+    keymap: xkb::Keymap,
+    /// For messages: the file, or `layout(variant)`
+    description: String,
+}
+
+impl Resolver {
+    pub fn new(source: KeymapSource) -> Result<Self> {
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let (keymap, description) = match source {
+            KeymapSource::File(path) => {
+                let text = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read keymap {}", path.display()))?;
+                let keymap = xkb::Keymap::new_from_string(
+                    &context,
+                    text,
+                    xkb::KEYMAP_FORMAT_TEXT_V1,
+                    xkb::KEYMAP_COMPILE_NO_FLAGS,
+                )
+                .ok_or_else(|| anyhow!("{} is not a valid XKB keymap", path.display()))?;
+                (keymap, path.display().to_string())
+            }
+            KeymapSource::Names {
+                layout,
+                variant,
+                options,
+            } => {
+                let description = if variant.is_empty() {
+                    layout.to_string()
+                } else {
+                    format!("{}({})", layout, variant)
+                };
+                let keymap = xkb::Keymap::new_from_names(
+                    &context,
+                    "",
+                    "",
+                    layout,
+                    variant,
+                    (!options.is_empty()).then(|| options.to_string()),
+                    xkb::KEYMAP_COMPILE_NO_FLAGS,
+                )
+                .ok_or_else(|| anyhow!("xkbcommon could not compile layout '{}'", description))?;
+                (keymap, description)
+            }
+        };
+        Ok(Self {
+            keymap,
+            description,
+        })
+    }
+
+    /// The key that types keysym `name` (`f`, `semicolon`, `Super_L`)
+    pub fn resolve(&self, name: &str) -> Result<Key> {
+        let name = name.trim();
+        let mut keysym = xkb::keysym_from_name(name, xkb::KEYSYM_NO_FLAGS);
+        if keysym == xkb::Keysym::NoSymbol {
+            keysym = xkb::keysym_from_name(name, xkb::KEYSYM_CASE_INSENSITIVE);
+        }
+        if keysym == xkb::Keysym::NoSymbol {
+            bail!("'{}' is not an XKB keysym name", name);
+        }
+
+        let min = self.keymap.min_keycode().raw().max(EVDEV_OFFSET);
+        let max = self.keymap.max_keycode().raw();
+        let max_levels = (min..=max)
+            .map(|code| self.keymap.num_levels_for_key(code.into(), 0))
+            .max()
+            .unwrap_or(0);
+        for level in 0..max_levels {
+            for code in min..=max {
+                let syms = self.keymap.key_get_syms_by_level(code.into(), 0, level);
+                if syms.contains(&keysym) {
+                    return Ok(Key::new((code - EVDEV_OFFSET) as u16));
+                }
+            }
+        }
+        bail!(
+            "no key types '{}' on layout {}",
+            xkb::keysym_get_name(keysym),
+            self.description
+        )
+    }
+}
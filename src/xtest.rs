@@ -0,0 +1,412 @@
+//! Output through the X11 XTEST extension, for plain Xorg sessions where
+//! creating uinput devices needs root.
+
+use crate::sink::EventSink;
+use anyhow::{bail, Context, Result};
+use evdev::{InputEvent, InputEventKind, Key};
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{
+    ConnectionExt as _, Window, BUTTON_PRESS_EVENT, BUTTON_RELEASE_EVENT, KEY_PRESS_EVENT,
+    KEY_RELEASE_EVENT,
+};
+use x11rb::protocol::xtest::ConnectionExt as _;
+use x11rb::rust_connection::RustConnection;
+
+/// Distance between evdev scancodes and X keycodes under the evdev/libinput drivers
+const EVDEV_OFFSET: u32 = 8;
+
+/// Core-protocol wheel buttons
+const WHEEL_UP: u8 = 4;
+const WHEEL_DOWN: u8 = 5;
+
+pub struct XtestSink {
+    conn: RustConnection,
+    root: Window,
+    keymap: Keymap,
+    /// Whether the server numbers keys evdev + 8, so keys can go out by position
+    evdev: bool,
+}
+
+impl XtestSink {
+    /// Connect to $DISPLAY and check for XTEST
+    pub fn connect() -> Result<Self> {
+        if std::env::var_os("DISPLAY").is_none_or(|d| d.is_empty()) {
+            bail!("--backend xtest needs an X display, but DISPLAY is not set");
+        }
+        let (conn, screen) = x11rb::connect(None).context("Failed to connect to the X server")?;
+        let root = conn.setup().roots[screen].root;
+        let (min_keycode, max_keycode) = (conn.setup().min_keycode, conn.setup().max_keycode);
+
+        conn.xtest_get_version(2, 2)
+            .context("Failed to query XTEST")?
+            .reply()
+            .context("X server does not support the XTEST extension")?;
+
+        let mapping = conn
+            .get_keyboard_mapping(min_keycode, max_keycode - min_keycode + 1)?
+            .reply()
+            .context("Failed to read the X keyboard mapping")?;
+        let keymap = Keymap {
+            min_keycode,
+            per: mapping.keysyms_per_keycode as usize,
+            keysyms: mapping.keysyms,
+        };
+        let evdev = keymap.is_evdev();
+        if !evdev {
+            eprintln!(
+                "⚠️  X server keycodes aren't evdev + 8; keys go out by their US-layout symbol instead"
+            );
+        }
+        eprintln!("🪟 XTEST output ready");
+        Ok(Self {
+            conn,
+            root,
+            keymap,
+            evdev,
+        })
+    }
+
+    fn fake(&self, kind: u8, detail: u8) -> Result<()> {
+        self.conn
+            .xtest_fake_input(kind, detail, x11rb::CURRENT_TIME, self.root, 0, 0, 0)
+            .context("Lost connection to the X server")?;
+        Ok(())
+    }
+
+    fn click(&self, button: u8) -> Result<()> {
+        self.fake(BUTTON_PRESS_EVENT, button)?;
+        self.fake(BUTTON_RELEASE_EVENT, button)
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.conn.flush().context("Lost connection to the X server")
+    }
+}
+
+/// The server's keyboard mapping, as GetKeyboardMapping returns it
+struct Keymap {
+    min_keycode: u8,
+    per: usize,
+    /// `per` keysyms for every keycode from `min_keycode` up
+    keysyms: Vec<u32>,
+}
+
+impl Keymap {
+    /// Unshifted keysym on a keycode
+    fn first_sym(&self, keycode: u32) -> Option<u32> {
+        let index = keycode.checked_sub(self.min_keycode as u32)? as usize * self.per;
+        self.keysyms.get(index).copied().filter(|&sym| sym != 0)
+    }
+
+    /// Lowest keycode whose unshifted keysym is `keysym`
+    fn keycode_of(&self, keysym: u32) -> Option<u8> {
+        let index = self
+            .keysyms
+            .chunks(self.per.max(1))
+            .position(|syms| syms.first() == Some(&keysym))?;
+        u8::try_from(index + self.min_keycode as usize).ok()
+    }
+
+    /// Keys are sent by keycode and the keymap turns them into symbols, which
+    /// only keeps them in place if the keycodes follow the evdev numbering. The
+    /// old kbd driver agrees on the main block but not on arrows or Super.
+    fn is_evdev(&self) -> bool {
+        [Key::KEY_ESC, Key::KEY_ENTER, Key::KEY_UP, Key::KEY_LEFTMETA]
+            .into_iter()
+            .all(|key| self.first_sym(key.code() as u32 + EVDEV_OFFSET) == us_keysym(key))
+    }
+
+    /// X keycode to send for `key`: evdev + 8 when the server numbers keys that
+    /// way, so the active layout applies, else the keycode carrying its symbol
+    fn keycode(&self, key: Key, evdev: bool) -> Option<u8> {
+        if evdev {
+            let keycode = key.code() as u32 + EVDEV_OFFSET;
+            let max = self.min_keycode as usize + self.keysyms.len() / self.per.max(1);
+            return u8::try_from(keycode)
+                .ok()
+                .filter(|&code| code >= self.min_keycode && (code as usize) < max);
+        }
+        self.keycode_of(us_keysym(key)?)
+    }
+}
+
+/// Keysym an evdev key carries on a US layout, for servers without evdev keycodes
+fn us_keysym(key: Key) -> Option<u32> {
+    // Latin-1 keysyms are the character itself; rows start at these evdev codes
+    const ROWS: [(u16, &str); 4] = [
+        (2, "1234567890-="),
+        (16, "qwertyuiop[]"),
+        (30, "asdfghjkl;'`"),
+        (43, "\\zxcvbnm,./"),
+    ];
+    let code = key.code();
+    for (start, row) in ROWS {
+        if let Some(ch) = code
+            .checked_sub(start)
+            .and_then(|i| row.chars().nth(i as usize))
+        {
+            return Some(ch as u32);
+        }
+    }
+    let sym = match key {
+        Key::KEY_SPACE => 0x0020,
+        Key::KEY_ESC => 0xff1b,
+        Key::KEY_BACKSPACE => 0xff08,
+        Key::KEY_TAB => 0xff09,
+        Key::KEY_ENTER => 0xff0d,
+        Key::KEY_LEFTSHIFT => 0xffe1,
+        Key::KEY_RIGHTSHIFT => 0xffe2,
+        Key::KEY_LEFTCTRL => 0xffe3,
+        Key::KEY_RIGHTCTRL => 0xffe4,
+        Key::KEY_CAPSLOCK => 0xffe5,
+        Key::KEY_LEFTALT => 0xffe9,
+        Key::KEY_RIGHTALT => 0xffea,
+        Key::KEY_LEFTMETA => 0xffeb,
+        Key::KEY_RIGHTMETA => 0xffec,
+        Key::KEY_HOME => 0xff50,
+        Key::KEY_LEFT => 0xff51,
+        Key::KEY_UP => 0xff52,
+        Key::KEY_RIGHT => 0xff53,
+        Key::KEY_DOWN => 0xff54,
+        Key::KEY_PAGEUP => 0xff55,
+        Key::KEY_PAGEDOWN => 0xff56,
+        Key::KEY_END => 0xff57,
+        Key::KEY_INSERT => 0xff63,
+        Key::KEY_DELETE => 0xffff,
+        Key::KEY_F11 => 0xffc8,
+        Key::KEY_F12 => 0xffc9,
+        // F1-F10 are evdev 59-68 and keysyms 0xffbe-0xffc7
+        _ if (59..=68).contains(&code) => 0xffbe + (code as u32 - 59),
+        _ => return None,
+    };
+    Some(sym)
+}
+
+/// Core-protocol button number for a BTN_* code
+fn x_button(button: Key) -> Option<u8> {
+    match button {
+        Key::BTN_LEFT => Some(1),
+        Key::BTN_MIDDLE => Some(2),
+        Key::BTN_RIGHT => Some(3),
+        Key::BTN_SIDE | Key::BTN_BACK => Some(8),
+        Key::BTN_EXTRA | Key::BTN_FORWARD => Some(9),
+        _ => None,
+    }
+}
+
+impl EventSink for XtestSink {
+    fn forward(&mut self, event: InputEvent) -> Result<()> {
+        match event.kind() {
+            InputEventKind::Key(key) => self.key(key, event.value()),
+            _ => Ok(()),
+        }
+    }
+
+    fn key(&mut self, key: Key, value: i32) -> Result<()> {
+        let kind = match value {
+            0 => KEY_RELEASE_EVENT,
+            1 => KEY_PRESS_EVENT,
+            // The X server autorepeats on its own
+            _ => return Ok(()),
+        };
+        let Some(keycode) = self.keymap.keycode(key, self.evdev) else {
+            return Ok(());
+        };
+        self.fake(kind, keycode)?;
+        self.flush()
+    }
+
+    fn button(&mut self, button: Key, value: i32) -> Result<()> {
+        let Some(number) = x_button(button) else {
+            return Ok(());
+        };
+        let kind = if value == 0 {
+            BUTTON_RELEASE_EVENT
+        } else {
+            BUTTON_PRESS_EVENT
+        };
+        self.fake(kind, number)?;
+        self.flush()
+    }
+
+    /// The core protocol only knows whole detents, one button 4/5 click each
+    fn wheel(&mut self, _hi_res: i32, legacy: i32) -> Result<()> {
+        let button = if legacy > 0 { WHEEL_UP } else { WHEEL_DOWN };
+        for _ in 0..legacy.unsigned_abs() {
+            self.click(button)?;
+        }
+        self.flush()
+    }
+
+    /// XTEST injects through the server's own XTEST devices
+    fn devices(&self) -> Vec<String> {
+        vec!["X server XTEST devices".into()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHIFT_L: u32 = 0xffe1;
+    const CONTROL_L: u32 = 0xffe3;
+    const CONTROL_R: u32 = 0xffe4;
+    const ALT_L: u32 = 0xffe9;
+    const ALT_R: u32 = 0xffea;
+    const SUPER_L: u32 = 0xffeb;
+    const UP: u32 = 0xff52;
+
+    /// Main block shared by the evdev and kbd drivers, as `xmodmap -pke` lists
+    /// it for a US layout: runs of characters from a start keycode, then
+    /// single named keysyms
+    const MAIN: [(u8, &str); 5] = [
+        (10, "1234567890-="),
+        (24, "qwertyuiop[]"),
+        (38, "asdfghjkl;'`"),
+        (51, "\\zxcvbnm,./"),
+        (65, " "),
+    ];
+    const MAIN_NAMED: [(u8, u32); 9] = [
+        (9, 0xff1b),
+        (22, 0xff08),
+        (23, 0xff09),
+        (36, 0xff0d),
+        (37, CONTROL_L),
+        (50, SHIFT_L),
+        (62, 0xffe2),
+        (64, ALT_L),
+        (66, 0xffe5),
+    ];
+    /// Where the two drivers differ
+    const EVDEV_EXTRA: [(u8, u32); 5] = [
+        (105, CONTROL_R),
+        (108, ALT_R),
+        (111, UP),
+        (133, SUPER_L),
+        (134, 0xffec),
+    ];
+    const KBD_EXTRA: [(u8, u32); 5] = [
+        (109, CONTROL_R),
+        (113, ALT_R),
+        (98, UP),
+        (115, SUPER_L),
+        (116, 0xffec),
+    ];
+
+    /// Keycodes 8-255 with two keysyms each (plain, shifted), the way
+    /// GetKeyboardMapping returns them
+    fn keymap(runs: &[(u8, &str)], named: &[(u8, u32)]) -> Keymap {
+        let mut keysyms = vec![0; 248 * 2];
+        let mut set = |keycode: u8, plain: u32, shifted: u32| {
+            let index = (keycode as usize - 8) * 2;
+            keysyms[index] = plain;
+            keysyms[index + 1] = shifted;
+        };
+        for &(start, run) in runs {
+            for (i, ch) in run.chars().enumerate() {
+                set(start + i as u8, ch as u32, ch.to_ascii_uppercase() as u32);
+            }
+        }
+        for &(keycode, sym) in named {
+            set(keycode, sym, sym);
+        }
+        Keymap {
+            min_keycode: 8,
+            per: 2,
+            keysyms,
+        }
+    }
+
+    fn evdev_keymap() -> Keymap {
+        keymap(&MAIN, &[&MAIN_NAMED[..], &EVDEV_EXTRA[..]].concat())
+    }
+
+    fn kbd_keymap() -> Keymap {
+        keymap(&MAIN, &[&MAIN_NAMED[..], &KBD_EXTRA[..]].concat())
+    }
+
+    /// Letters, digits and every modifier
+    fn typed_keys() -> Vec<Key> {
+        let letters = (16..=25).chain(30..=38).chain(44..=50);
+        let digits = 2..=11;
+        let modifiers = [
+            Key::KEY_LEFTSHIFT,
+            Key::KEY_RIGHTSHIFT,
+            Key::KEY_LEFTCTRL,
+            Key::KEY_RIGHTCTRL,
+            Key::KEY_LEFTALT,
+            Key::KEY_RIGHTALT,
+            Key::KEY_LEFTMETA,
+            Key::KEY_RIGHTMETA,
+            Key::KEY_CAPSLOCK,
+        ];
+        letters
+            .chain(digits)
+            .map(Key::new)
+            .chain(modifiers)
+            .collect()
+    }
+
+    #[test]
+    fn the_keycode_numbering_is_recognised() {
+        assert!(evdev_keymap().is_evdev());
+        // kbd agrees on Esc and Enter, but not on Up or Super
+        assert!(!kbd_keymap().is_evdev());
+        assert!(!keymap(&[], &[]).is_evdev());
+    }
+
+    #[test]
+    fn letters_and_modifiers_round_trip_on_an_evdev_server() {
+        let keymap = evdev_keymap();
+        for key in typed_keys() {
+            let keycode = keymap.keycode(key, true).unwrap();
+            assert_eq!(keycode as u16, key.code() + 8, "{:?}", key);
+            assert_eq!(
+                keymap.first_sym(keycode as u32),
+                us_keysym(key),
+                "{:?}",
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn letters_and_modifiers_round_trip_by_symbol_elsewhere() {
+        let keymap = kbd_keymap();
+        for key in typed_keys() {
+            let keycode = keymap.keycode(key, false).unwrap();
+            assert_eq!(
+                keymap.first_sym(keycode as u32),
+                us_keysym(key),
+                "{:?}",
+                key
+            );
+        }
+        assert_eq!(keymap.keycode(Key::KEY_A, false), Some(38));
+        assert_eq!(keymap.keycode(Key::KEY_UP, false), Some(98));
+        assert_eq!(keymap.keycode(Key::KEY_RIGHTCTRL, false), Some(109));
+        assert_eq!(keymap.keycode(Key::KEY_LEFTMETA, false), Some(115));
+    }
+
+    #[test]
+    fn an_evdev_server_keeps_keys_in_place_under_other_layouts() {
+        // Dvorak puts ' where a US layout has q; the layout should still apply
+        let dvorak = keymap(
+            &[(24, "',.pyfgcrl/=")],
+            &[&MAIN_NAMED[..], &EVDEV_EXTRA[..]].concat(),
+        );
+        assert!(dvorak.is_evdev());
+        assert_eq!(dvorak.keycode(Key::KEY_Q, true), Some(24));
+        assert_eq!(dvorak.first_sym(24), Some('\'' as u32));
+    }
+
+    #[test]
+    fn keys_without_a_place_are_dropped() {
+        // X keycodes stop at 255
+        assert_eq!(evdev_keymap().keycode(Key::BTN_TRIGGER_HAPPY1, true), None);
+        // No US symbol, or one the keymap lacks
+        assert_eq!(us_keysym(Key::KEY_F13), None);
+        assert_eq!(kbd_keymap().keycode(Key::KEY_F13, false), None);
+        assert_eq!(kbd_keymap().keycode(Key::KEY_F1, false), None);
+    }
+}